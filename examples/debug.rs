@@ -0,0 +1,252 @@
+extern crate wasm;
+
+use std::{env, str, process};
+use std::fs::File;
+use std::io::{self, Read, BufRead, Write};
+use std::collections::HashMap;
+
+use wasm::{Dynamic, Breakpoint, DebugHandler, Pause, PauseReason, Step, Access, Watchpoint};
+
+// The interpreter logs every op to stdout, so the debugger talks on stderr;
+// run with stdout redirected to keep the session readable.
+macro_rules! say {
+    ($($arg:tt)*) => { writeln!(io::stderr(), $($arg)*).unwrap() }
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() < 3 {
+        println!("Usage: {} somefile.wasm export [args...] >/dev/null", args[0]);
+        return;
+    }
+
+    let mut contents = Vec::new();
+    File::open(&args[1]).expect("readable file").read_to_end(&mut contents).expect("read succeeds");
+
+    let module = wasm::Module::parse(&contents);
+
+    let func = module.find(args[2].as_bytes()).unwrap_or_else(|| {
+        say!("no export named {}", args[2]);
+        process::exit(1);
+    });
+    let ty = &module.types[module.functions[func.0].0];
+    let params = ty.param_types;
+    if params.len() != args.len() - 3 {
+        say!("{} takes {} arguments", args[2], params.len());
+        process::exit(1);
+    }
+    let call_args = params.iter().zip(&args[3..]).map(|(&t, a)| parse_arg(t, a)).collect::<Vec<_>>();
+
+    // Imports aren't available here, so calls to them trap.
+    let mut import_table = HashMap::new();
+    for i in &module.imports {
+        import_table.insert(i.module_name, Box::new(TrapImports) as Box<dyn wasm::BoundInstance>);
+    }
+    let mut inst = wasm::Instance::new(&module, import_table);
+
+    let names = (0..module.functions.len())
+        .map(|i| module.find_name(wasm::FunctionIndex(i))
+            .and_then(|n| str::from_utf8(n).ok())
+            .map(|n| n.to_owned())
+            .unwrap_or_else(|| format!("func[{}]", i)))
+        .collect::<Vec<_>>();
+
+    let mut debugger = wasm::Debugger::new(Box::new(Repl { names }));
+    debugger.set_step(Step::Into);
+    inst.debugger = Some(debugger);
+
+    match inst.invoke(func, &call_args) {
+        wasm::InterpResult::Value(Some(v)) => say!("returned {}", v),
+        wasm::InterpResult::Value(None) => say!("returned"),
+        wasm::InterpResult::Trap => say!("trapped"),
+    }
+}
+
+fn parse_arg(ty: u8, text: &str) -> Dynamic {
+    let bad = || -> ! {
+        say!("bad argument {}", text);
+        process::exit(1)
+    };
+    match ty {
+        1 => Dynamic::from_i32(text.parse().unwrap_or_else(|_| bad())),
+        2 => Dynamic::from_i64(text.parse().unwrap_or_else(|_| bad())),
+        3 => Dynamic::Float32(text.parse().unwrap_or_else(|_| bad())),
+        4 => Dynamic::Float64(text.parse().unwrap_or_else(|_| bad())),
+        _ => bad()
+    }
+}
+
+struct TrapImports;
+
+impl wasm::BoundInstance for TrapImports {
+    fn invoke_export(&mut self, _func: wasm::ExportIndex, _args: &[Dynamic]) -> wasm::InterpResult {
+        wasm::InterpResult::Trap
+    }
+    fn export_by_name_and_type(&self, _name: &[u8], _ty: wasm::FunctionType<&[u8]>) -> wasm::ExportIndex {
+        wasm::ExportIndex(0)
+    }
+}
+
+struct Repl {
+    names: Vec<String>,
+}
+
+const HELP: &'static str = "\
+commands:
+  s, step             step into calls
+  n, next             step over calls
+  f, finish           run until the current function returns
+  c, continue         run until the next breakpoint
+  b FUNC[+OFFSET]     break at a function (by name or index), optionally at an op offset
+  d N                 delete breakpoint N
+  w ADDR LEN [r|w|rw] pause on memory reads and/or writes (default: writes)
+  dw N                delete watchpoint N
+  i                   list breakpoints and watchpoints
+  l, locals           show locals
+  st, stack           show the operand stack
+  bt                  show the call stack
+  x ADDR [LEN]        dump memory
+  q, quit             exit";
+
+impl Repl {
+    fn function_index(&self, text: &str) -> Option<wasm::FunctionIndex> {
+        match text.parse::<usize>() {
+            Ok(i) if i < self.names.len() => Some(wasm::FunctionIndex(i)),
+            Ok(_) => None,
+            Err(_) => self.names.iter().position(|n| n == text).map(wasm::FunctionIndex),
+        }
+    }
+
+    fn show_breakpoint(&self, b: &Breakpoint) -> String {
+        match b.offset {
+            Some(offset) => format!("{}+{}", self.names[b.function.0], offset),
+            None => self.names[b.function.0].clone(),
+        }
+    }
+
+    /// Runs one command. Returns how to resume, or `None` to stay paused.
+    fn command(&mut self, pause: &mut Pause, line: &str) -> Option<Step> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.first().cloned().unwrap_or("") {
+            "s" | "step" => return Some(Step::Into),
+            "n" | "next" => return Some(Step::Over),
+            "f" | "finish" => return Some(Step::Out),
+            "c" | "continue" => return Some(Step::Continue),
+            "q" | "quit" => process::exit(0),
+            "b" | "break" if words.len() == 2 => {
+                let mut parts = words[1].splitn(2, '+');
+                let func = self.function_index(parts.next().unwrap());
+                let offset = parts.next().map(|o| o.parse::<usize>());
+                match (func, offset) {
+                    (Some(function), None) => pause.breakpoints.push(Breakpoint { function, offset: None }),
+                    (Some(function), Some(Ok(offset))) =>
+                        pause.breakpoints.push(Breakpoint { function, offset: Some(offset) }),
+                    _ => say!("bad breakpoint {}", words[1]),
+                }
+            }
+            "d" | "delete" if words.len() == 2 => {
+                match words[1].parse::<usize>() {
+                    Ok(n) if n < pause.breakpoints.len() => { pause.breakpoints.remove(n); }
+                    _ => say!("no breakpoint {}", words[1]),
+                }
+            }
+            "w" | "watch" if words.len() == 3 || words.len() == 4 => {
+                let access = match words.get(3).cloned().unwrap_or("w") {
+                    "r" => Some(Access::Read),
+                    "w" => Some(Access::Write),
+                    "rw" => Some(Access::ReadWrite),
+                    _ => None,
+                };
+                match (words[1].parse::<usize>(), words[2].parse::<usize>(), access) {
+                    (Ok(start), Ok(len), Some(access)) => pause.watchpoints.push(Watchpoint::new(start, len, access)),
+                    _ => say!("bad watchpoint"),
+                }
+            }
+            "dw" if words.len() == 2 => {
+                match words[1].parse::<usize>() {
+                    Ok(n) if n < pause.watchpoints.len() => { pause.watchpoints.remove(n); }
+                    _ => say!("no watchpoint {}", words[1]),
+                }
+            }
+            "i" | "info" => {
+                for (n, b) in pause.breakpoints.iter().enumerate() {
+                    say!("breakpoint {}: {}", n, self.show_breakpoint(b));
+                }
+                for (n, w) in pause.watchpoints.iter().enumerate() {
+                    say!("watchpoint {}: {:?} {}..{}", n, w.access, w.start, w.start + w.len);
+                }
+            }
+            "l" | "locals" => {
+                for (i, v) in pause.frame.locals.iter().enumerate() {
+                    match pause.local_name(i).and_then(|n| str::from_utf8(n).ok()) {
+                        Some(name) => say!("  {} ${} = {}", i, name, v),
+                        None => say!("  {} = {}", i, v),
+                    }
+                }
+            }
+            "st" | "stack" => {
+                for v in pause.frame.stack {
+                    match *v {
+                        Some(v) => say!("  {}", v),
+                        None => say!("  -"),
+                    }
+                }
+            }
+            "bt" => {
+                for frame in pause.backtrace.iter().rev() {
+                    say!("  {}+{}", self.names[frame.function.0], frame.offset);
+                }
+            }
+            "x" if words.len() >= 2 => {
+                let addr = words[1].parse::<usize>();
+                let len = words.get(2).map_or(Ok(16), |l| l.parse::<usize>());
+                match (addr, len) {
+                    (Ok(addr), Ok(len)) if addr.checked_add(len).map_or(false, |end| end <= pause.frame.memory.len()) => {
+                        let mut buf = vec![0; len];
+                        pause.frame.memory.read(addr, &mut buf);
+                        for (i, row) in buf.chunks(16).enumerate() {
+                            let hex = row.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>();
+                            say!("  {:08x}  {}", addr + i * 16, hex.join(" "));
+                        }
+                    }
+                    _ => say!("bad memory range"),
+                }
+            }
+            "" => {}
+            _ => say!("{}", HELP),
+        }
+        None
+    }
+}
+
+impl DebugHandler for Repl {
+    fn pause(&mut self, pause: &mut Pause) -> Step {
+        let location = format!("{}+{}", self.names[pause.function().0], pause.offset());
+        match pause.reason {
+            PauseReason::Breakpoint => say!("breakpoint at {}: {}", location, pause.frame.op),
+            PauseReason::Step => say!("{}: {}", location, pause.frame.op),
+            PauseReason::Watchpoint => {
+                let access = pause.access.unwrap();
+                match access.new {
+                    Some(new) => say!("watchpoint at {}: {} writes {:#x} over {:#x} at {} ({} bytes)",
+                        location, pause.frame.op, new, access.old, access.address, access.width),
+                    None => say!("watchpoint at {}: {} reads {:#x} at {} ({} bytes)",
+                        location, pause.frame.op, access.old, access.address, access.width),
+                }
+            }
+            PauseReason::Trap => say!("trap at {}: {}", location, pause.frame.op),
+        }
+
+        let stdin = io::stdin();
+        loop {
+            write!(io::stderr(), "(wasm) ").unwrap();
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap() == 0 {
+                return Step::Continue;
+            }
+            if let Some(step) = self.command(pause, line.trim()) {
+                return step;
+            }
+        }
+    }
+}
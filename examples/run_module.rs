@@ -2,15 +2,22 @@ extern crate wasm;
 
 use std::{env, str};
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 use std::collections::HashMap;
 
 use wasm::Dynamic;
 
 fn main() {
-    let args = env::args().collect::<Vec<_>>();
+    let mut args = env::args().collect::<Vec<_>>();
+    let profile_path = take_option(&mut args, "--profile");
+    let coverage_path = take_option(&mut args, "--coverage");
+    let trace_path = take_option(&mut args, "--trace");
+    let record_path = take_option(&mut args, "--record");
+    let replay_path = take_option(&mut args, "--replay");
+    let heap_path = take_option(&mut args, "--heap");
     if args.len() != 2 {
-        println!("Usage: {} somefile.wasm", args[0]);
+        println!("Usage: {} [--profile out.folded] [--coverage out.info] [--trace out.jsonl] \
+                  [--record out.jsonl | --replay in.jsonl] [--heap out.txt] somefile.wasm", args[0]);
         return;
     }
 
@@ -19,10 +26,38 @@ fn main() {
 
     let module = wasm::Module::parse(&contents);
 
-    let mut import_table = HashMap::new();
-    import_table.insert(&b"env"[..], Box::new(EmscriptenEnvModule) as Box<wasm::BoundInstance>);
-    import_table.insert(&b"asm2wasm"[..], Box::new(EmscriptenAsm2WasmModule) as Box<wasm::BoundInstance>);
+    let import_table = if replay_path.is_some() {
+        wasm::replay_imports(&module)
+    } else {
+        let mut import_table = HashMap::new();
+        import_table.insert(&b"env"[..], Box::new(EmscriptenEnvModule) as Box<dyn wasm::BoundInstance>);
+        import_table.insert(&b"asm2wasm"[..], Box::new(EmscriptenAsm2WasmModule) as Box<dyn wasm::BoundInstance>);
+        import_table
+    };
     let mut inst = wasm::Instance::new(&module, import_table);
+    if profile_path.is_some() {
+        inst.profiler = Some(wasm::Profiler::new());
+    }
+    if coverage_path.is_some() {
+        inst.coverage = Some(wasm::Coverage::new());
+    }
+    if let Some(ref path) = trace_path {
+        let out = io::BufWriter::new(File::create(path).expect("writable trace file"));
+        inst.tracer = Some(wasm::Tracer::new(Box::new(out)));
+    }
+    if heap_path.is_some() {
+        inst.heap = wasm::HeapProfiler::new(&module, b"_malloc", b"_free");
+        if inst.heap.is_none() {
+            println!("module doesn't export _malloc and _free; not profiling the heap");
+        }
+    }
+    if record_path.is_some() {
+        inst.recorder = Some(wasm::Recorder::new());
+    }
+    if let Some(ref path) = replay_path {
+        let input = io::BufReader::new(File::open(path).expect("readable replay file"));
+        inst.replayer = Some(wasm::Replayer::load(input).expect("valid replay file"));
+    }
 
     let esp = module.find(b"establishStackSpace").unwrap();
     let main = module.find(b"_main").unwrap();
@@ -32,6 +67,47 @@ fn main() {
     let res = inst.invoke(main, &[Dynamic::from_u32(0), Dynamic::from_u32(0)]);
 
     // println!("{}", Pr(res));
+
+    if let (Some(path), Some(profiler)) = (profile_path, inst.profiler.as_ref()) {
+        profiler.write_table(&module, &mut io::stdout()).expect("write profile table");
+        let mut out = File::create(&path).expect("writable profile file");
+        profiler.write_folded(&module, &mut out).expect("write folded stacks");
+    }
+
+    if let (Some(path), Some(coverage)) = (coverage_path, inst.coverage.as_ref()) {
+        let mut out = File::create(&path).expect("writable coverage file");
+        coverage.report(&module).write_lcov(&mut out).expect("write coverage report");
+    }
+
+    if let (Some(path), Some(report)) = (heap_path, inst.heap.as_ref().and_then(|h| h.last_report())) {
+        let mut out = File::create(&path).expect("writable heap report file");
+        report.write(&module, &mut out).expect("write heap report");
+    }
+
+    if let (Some(path), Some(recorder)) = (record_path, inst.recorder.as_ref()) {
+        let mut out = io::BufWriter::new(File::create(&path).expect("writable record file"));
+        recorder.save(&mut out).expect("write recorded calls");
+    }
+
+    if let Some(replayer) = inst.replayer.as_ref() {
+        match replayer.divergence() {
+            Some(divergence) => println!("replay diverged at {}", divergence),
+            None if !replayer.remaining().is_empty() =>
+                println!("replay ended with {} recorded calls unmade", replayer.remaining().len()),
+            None => println!("replay matched the recording"),
+        }
+    }
+}
+
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    match args.iter().position(|a| a == name) {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Some(value)
+        }
+        _ => None
+    }
 }
 
 struct EmscriptenEnvModule;
@@ -119,11 +195,11 @@ struct EmscriptenAsm2WasmModule;
 impl wasm::BoundInstance for EmscriptenAsm2WasmModule {
     fn invoke_export(&mut self, func: wasm::ExportIndex, args: &[Dynamic]) -> wasm::InterpResult {
         for a in args {
-            match a {
-                &Dynamic::Int32(v) => println!("print: {}", v),
-                &Dynamic::Int64(v) => println!("print: {}", v),
-                &Dynamic::Float32(v) => println!("print: {}", v),
-                &Dynamic::Float64(v) => println!("print: {}", v),
+            match *a {
+                Dynamic::Int32(v) => println!("print: {}", v),
+                Dynamic::Int64(v) => println!("print: {}", v),
+                Dynamic::Float32(v) => println!("print: {}", v),
+                Dynamic::Float64(v) => println!("print: {}", v),
             }
         }
         panic!();
@@ -3,19 +3,54 @@ extern crate wasm;
 use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::process;
 
 
 fn main() {
     let args = env::args().collect::<Vec<_>>();
-    if args.len() != 2 {
-        println!("Usage: {} somefile.wast", args[0]);
+    let (path, junit, json) = match args.len() {
+        2 => (&args[1], None, None),
+        4 if args[2] == "--junit" => (&args[1], Some(&args[3]), None),
+        4 if args[2] == "--json" => (&args[1], None, Some(&args[3])),
+        4 if args[2] == "--to-json" => (&args[1], None, None),
+        _ => {
+            println!("Usage: {} somefile.wast [--junit out.xml | --json out.json]", args[0]);
+            println!("       {} somefile.json [--junit out.xml | --json out.json]", args[0]);
+            println!("       {} somefile.wast --to-json outdir", args[0]);
+            return;
+        }
+    };
+
+    let test = if path.ends_with(".json") {
+        wasm::TestCase::parse_json_commands(Path::new(path)).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    } else {
+        let mut contents = Vec::new();
+        File::open(path).expect("readable file").read_to_end(&mut contents).expect("read succeeds");
+        wasm::TestCase::parse(&contents)
+    };
+
+    if args.len() == 4 && args[2] == "--to-json" {
+        let stem = Path::new(path).file_stem().and_then(|s| s.to_str()).expect("file name");
+        test.write_json_commands(Path::new(&args[3]), stem).unwrap_or_else(|e| panic!("{}: {}", path, e));
         return;
     }
 
-    let mut contents = Vec::new();
-    File::open(&args[1]).expect("readable file").read_to_end(&mut contents).expect("read succeeds");
+    let report = test.run();
 
-    let test = wasm::TestCase::parse(&contents);
+    for o in report.outcomes.iter().filter(|o| o.status == wasm::Status::Fail) {
+        println!("{}:{}: {}: expected {}, got {}", path, o.line, o.command, o.expected, o.actual);
+    }
+    println!("{}: {} passed, {} failed, {} skipped", path, report.passed(), report.failed(), report.skipped());
 
-    test.run_all();
+    if let Some(out) = junit {
+        report.write_junit(path, &mut File::create(out).expect("writable file")).expect("write succeeds");
+    }
+    if let Some(out) = json {
+        report.write_json(&mut File::create(out).expect("writable file")).expect("write succeeds");
+    }
+
+    if report.failed() > 0 {
+        process::exit(1);
+    }
 }
@@ -0,0 +1,133 @@
+use module::FunctionIndex;
+use watch::Access;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AlignmentError {
+    /// The effective address isn't a multiple of the declared alignment.
+    Misaligned,
+    /// The declared alignment is wider than the access itself, which the
+    /// binary format doesn't allow.
+    HintTooWide,
+}
+
+/// A load or store that broke its alignment hint.
+#[derive(Copy, Clone)]
+pub struct AlignmentFault {
+    pub function: FunctionIndex,
+    pub offset: usize,
+    pub address: usize,
+    pub width: usize,
+    pub log_of_alignment: u32,
+    /// `Access::Read` or `Access::Write`.
+    pub kind: Access,
+    pub error: AlignmentError,
+}
+
+pub trait AlignmentHandler {
+    fn fault(&mut self, fault: &AlignmentFault);
+}
+
+/// Checks every load and store against its `MemImm::log_of_alignment` while
+/// attached to an `Instance` (see `Instance::alignment`). Faults go to the
+/// handler if there is one, and trap if `trap` is set.
+pub struct AlignmentChecker {
+    pub trap: bool,
+    handler: Option<Box<dyn AlignmentHandler>>,
+}
+
+impl AlignmentChecker {
+    /// Traps on the first fault.
+    pub fn trapping() -> AlignmentChecker {
+        AlignmentChecker {
+            trap: true,
+            handler: None,
+        }
+    }
+
+    /// Reports every fault to `handler` and carries on.
+    pub fn reporting(handler: Box<dyn AlignmentHandler>) -> AlignmentChecker {
+        AlignmentChecker {
+            trap: false,
+            handler: Some(handler),
+        }
+    }
+
+    pub fn check(address: usize, width: usize, log_of_alignment: u32) -> Option<AlignmentError> {
+        match 1usize.checked_shl(log_of_alignment) {
+            Some(alignment) if alignment <= width => {
+                if address % alignment == 0 {
+                    None
+                } else {
+                    Some(AlignmentError::Misaligned)
+                }
+            }
+            _ => Some(AlignmentError::HintTooWide),
+        }
+    }
+
+    /// Passes a fault to the handler. Returns true if the access should trap.
+    pub fn report(&mut self, fault: &AlignmentFault) -> bool {
+        if let Some(ref mut handler) = self.handler {
+            handler.fault(fault);
+        }
+        self.trap
+    }
+}
+
+#[test]
+fn test_alignment_check() {
+    assert_eq!(AlignmentChecker::check(8, 4, 2), None);
+    assert_eq!(AlignmentChecker::check(6, 4, 2), Some(AlignmentError::Misaligned));
+    assert_eq!(AlignmentChecker::check(6, 4, 1), None);
+    assert_eq!(AlignmentChecker::check(7, 4, 0), None);
+    assert_eq!(AlignmentChecker::check(8, 4, 3), Some(AlignmentError::HintTooWide));
+    assert_eq!(AlignmentChecker::check(0, 8, 40), Some(AlignmentError::HintTooWide));
+}
+
+#[test]
+fn test_alignment_in_instance() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use interp::{Instance, InterpResult};
+    use types::Dynamic;
+
+    struct Log(Rc<RefCell<Vec<(usize, usize, usize, u32, Access, AlignmentError)>>>);
+    impl AlignmentHandler for Log {
+        fn fault(&mut self, fault: &AlignmentFault) {
+            self.0.borrow_mut().push((fault.function.0, fault.address, fault.width, fault.log_of_alignment,
+                fault.kind, fault.error));
+        }
+    }
+
+    let m = ::wat::parse_module("(module (memory 1)
+        (func (param $a i32) (result i32) (i32.load (get_local $a)))
+        (func (param $a i32) (result i32) (i32.load offset=2 (get_local $a)))
+        (func (param $a i32) (i32.store16 offset=1 (get_local $a) (i32.const 7))))").unwrap();
+    let arg = |a: u32| [Dynamic::from_u32(a)];
+    let value = InterpResult::Value(Some(Dynamic::from_u32(0)));
+
+    let mut instance = Instance::new(&m, HashMap::new());
+    instance.alignment = Some(AlignmentChecker::trapping());
+    assert_eq!(instance.invoke(FunctionIndex(0), &arg(4)), value);
+    assert_eq!(instance.invoke(FunctionIndex(0), &arg(2)), InterpResult::Trap);
+    assert_eq!(instance.trap_message.as_ref().map(|m| &m[..]), Some("unaligned memory access"));
+    // The offset is part of the effective address that has to be aligned.
+    assert_eq!(instance.invoke(FunctionIndex(1), &arg(2)), value);
+    assert_eq!(instance.invoke(FunctionIndex(1), &arg(4)), InterpResult::Trap);
+    assert_eq!(instance.invoke(FunctionIndex(2), &arg(1)), InterpResult::Value(None));
+    assert_eq!(instance.invoke(FunctionIndex(2), &arg(0)), InterpResult::Trap);
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut instance = Instance::new(&m, HashMap::new());
+    instance.alignment = Some(AlignmentChecker::reporting(Box::new(Log(log.clone()))));
+    assert_eq!(instance.invoke(FunctionIndex(0), &arg(2)), value);
+    assert_eq!(instance.invoke(FunctionIndex(1), &arg(4)), value);
+    assert_eq!(instance.invoke(FunctionIndex(2), &arg(0)), InterpResult::Value(None));
+    assert_eq!(instance.trap_message, None);
+    assert_eq!(*log.borrow(), vec![
+        (0, 2, 4, 2, Access::Read, AlignmentError::Misaligned),
+        (1, 6, 4, 2, Access::Read, AlignmentError::Misaligned),
+        (2, 1, 2, 1, Access::Write, AlignmentError::Misaligned),
+    ]);
+}
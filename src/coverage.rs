@@ -0,0 +1,332 @@
+use std::{io, str};
+use std::io::{BufRead, Write};
+use std::collections::{BTreeMap, HashMap};
+
+use module::{AsBytes, Module, FunctionIndex};
+use ops::{BlockOp, Block, NormalOp};
+
+#[derive(Default)]
+struct FunctionCounts {
+    calls: u64,
+    ops: HashMap<usize, u64>,
+    branches: HashMap<(usize, usize), u64>,
+}
+
+/// Records which ops and branch directions run while attached to an
+/// `Instance` (see `Instance::coverage`).
+///
+/// Ops are identified by their byte offset within the function body. Branch
+/// directions are numbered per branching op: for `if`, 0 is the then arm and
+/// 1 the else arm; for `br_if`, 0 is fall through and 1 is taken; for
+/// `br_table`, the index of the target used, with the default last.
+#[derive(Default)]
+pub struct Coverage {
+    functions: Vec<FunctionCounts>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    fn function(&mut self, func: FunctionIndex) -> &mut FunctionCounts {
+        while self.functions.len() <= func.0 {
+            self.functions.push(FunctionCounts::default());
+        }
+        &mut self.functions[func.0]
+    }
+
+    pub fn record_call(&mut self, func: FunctionIndex) {
+        self.function(func).calls += 1;
+    }
+
+    pub fn record_op(&mut self, func: FunctionIndex, offset: usize) {
+        *self.function(func).ops.entry(offset).or_insert(0) += 1;
+    }
+
+    pub fn record_branch(&mut self, func: FunctionIndex, offset: usize, direction: usize) {
+        *self.function(func).branches.entry((offset, direction)).or_insert(0) += 1;
+    }
+
+    /// Adds the counts from another run of the same module.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (i, counts) in other.functions.iter().enumerate() {
+            let f = self.function(FunctionIndex(i));
+            f.calls += counts.calls;
+            for (&k, &v) in &counts.ops {
+                *f.ops.entry(k).or_insert(0) += v;
+            }
+            for (&k, &v) in &counts.branches {
+                *f.branches.entry(k).or_insert(0) += v;
+            }
+        }
+    }
+
+    /// Builds a report covering every function in `module`, including the
+    /// ops and branches that never ran.
+    pub fn report<B: AsBytes>(&self, module: &Module<B>) -> CoverageReport {
+        let empty = FunctionCounts::default();
+        let mut report = CoverageReport::new();
+
+        for (i, body) in module.code.iter().enumerate() {
+            let counts = self.functions.get(i).unwrap_or(&empty);
+            let name = match module.find_name(FunctionIndex(i)).and_then(|n| str::from_utf8(n).ok()) {
+                Some(name) => name.to_owned(),
+                None => format!("func[{}]", i),
+            };
+
+            let mut f = FunctionReport {
+                calls: counts.calls,
+                ..FunctionReport::default()
+            };
+            let ops = body.block_ops().collect::<Vec<_>>();
+            collect_sites(&ops, &mut f);
+            for (offset, count) in f.ops.iter_mut() {
+                *count = *counts.ops.get(offset).unwrap_or(&0);
+            }
+            for (key, count) in f.branches.iter_mut() {
+                *count = *counts.branches.get(key).unwrap_or(&0);
+            }
+
+            report.add(name, f);
+        }
+
+        report
+    }
+}
+
+fn collect_sites(ops: &[BlockOp], f: &mut FunctionReport) {
+    for op in ops {
+        f.ops.insert(op.offset(), 0);
+        match op {
+            &BlockOp::Block(_, Block::Block(ref ops)) |
+            &BlockOp::Block(_, Block::Loop(ref ops)) => collect_sites(ops, f),
+            &BlockOp::Block(offset, Block::If(ref then, ref otherwise)) => {
+                f.branches.insert((offset, 0), 0);
+                f.branches.insert((offset, 1), 0);
+                collect_sites(then, f);
+                collect_sites(otherwise, f);
+            }
+            &BlockOp::Normal(offset, NormalOp::BrIf{..}) => {
+                f.branches.insert((offset, 0), 0);
+                f.branches.insert((offset, 1), 0);
+            }
+            &BlockOp::Normal(offset, NormalOp::BrTable{target_data, ..}) => {
+                for direction in 0..target_data.len() / 4 + 1 {
+                    f.branches.insert((offset, direction), 0);
+                }
+            }
+            &BlockOp::Normal(..) => {}
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct FunctionReport {
+    pub calls: u64,
+    /// Execution count by op offset.
+    pub ops: BTreeMap<usize, u64>,
+    /// Count by op offset and branch direction.
+    pub branches: BTreeMap<(usize, usize), u64>,
+}
+
+/// Coverage keyed by function name, so reports from different instances,
+/// runs or builds of a module can be merged.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct CoverageReport {
+    pub functions: BTreeMap<String, FunctionReport>,
+}
+
+impl CoverageReport {
+    pub fn new() -> CoverageReport {
+        CoverageReport::default()
+    }
+
+    fn add(&mut self, name: String, other: FunctionReport) {
+        let f = self.functions.entry(name).or_default();
+        f.calls += other.calls;
+        for (k, v) in other.ops {
+            *f.ops.entry(k).or_insert(0) += v;
+        }
+        for (k, v) in other.branches {
+            *f.branches.entry(k).or_insert(0) += v;
+        }
+    }
+
+    pub fn merge(&mut self, other: &CoverageReport) {
+        for (name, f) in &other.functions {
+            self.add(name.clone(), f.clone());
+        }
+    }
+
+    /// Writes the report in lcov's tracefile format, with one record per
+    /// function. The function name stands in for the source file and op
+    /// offsets for line numbers.
+    pub fn write_lcov<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (name, f) in &self.functions {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", name)?;
+            writeln!(out, "FN:{},{}", f.ops.keys().next().cloned().unwrap_or(0), name)?;
+            writeln!(out, "FNDA:{},{}", f.calls, name)?;
+            writeln!(out, "FNF:1")?;
+            writeln!(out, "FNH:{}", if f.calls > 0 { 1 } else { 0 })?;
+            for (&(offset, direction), &count) in &f.branches {
+                if f.ops.get(&offset).cloned().unwrap_or(0) == 0 {
+                    writeln!(out, "BRDA:{},0,{},-", offset, direction)?;
+                } else {
+                    writeln!(out, "BRDA:{},0,{},{}", offset, direction, count)?;
+                }
+            }
+            writeln!(out, "BRF:{}", f.branches.len())?;
+            writeln!(out, "BRH:{}", f.branches.values().filter(|&&c| c > 0).count())?;
+            for (&offset, &count) in &f.ops {
+                writeln!(out, "DA:{},{}", offset, count)?;
+            }
+            writeln!(out, "LF:{}", f.ops.len())?;
+            writeln!(out, "LH:{}", f.ops.values().filter(|&&c| c > 0).count())?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Reads a report written by `write_lcov`, e.g. to merge it with the
+    /// results of a later run.
+    pub fn read_lcov<R: BufRead>(input: R) -> io::Result<CoverageReport> {
+        fn bad(line: &str) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, format!("malformed lcov line: {}", line))
+        }
+        fn count(text: &str, line: &str) -> io::Result<u64> {
+            if text == "-" {
+                Ok(0)
+            } else {
+                text.parse().map_err(|_| bad(line))
+            }
+        }
+
+        let mut report = CoverageReport::new();
+        let mut current: Option<(String, FunctionReport)> = None;
+
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line == "end_of_record" {
+                if let Some((name, f)) = current.take() {
+                    report.add(name, f);
+                }
+                continue;
+            }
+            let (tag, value) = match line.find(':') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => continue,
+            };
+            if tag == "SF" {
+                current = Some((value.to_owned(), FunctionReport::default()));
+                continue;
+            }
+            let f = match current {
+                Some((_, ref mut f)) => f,
+                None => continue,
+            };
+            let fields = value.split(',').collect::<Vec<_>>();
+            match tag {
+                "FNDA" if fields.len() >= 2 => f.calls += count(fields[0], line)?,
+                "DA" if fields.len() >= 2 => {
+                    let offset = fields[0].parse().map_err(|_| bad(line))?;
+                    *f.ops.entry(offset).or_insert(0) += count(fields[1], line)?;
+                }
+                "BRDA" if fields.len() >= 4 => {
+                    let offset = fields[0].parse().map_err(|_| bad(line))?;
+                    let direction = fields[2].parse().map_err(|_| bad(line))?;
+                    *f.branches.entry((offset, direction)).or_insert(0) += count(fields[3], line)?;
+                }
+                "FNDA" | "DA" | "BRDA" => return Err(bad(line)),
+                _ => {}
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[test]
+fn test_lcov_round_trip_and_merge() {
+    let mut f = FunctionReport::default();
+    f.calls = 2;
+    f.ops.insert(0, 2);
+    f.ops.insert(3, 0);
+    f.branches.insert((0, 0), 2);
+    f.branches.insert((0, 1), 0);
+    let mut report = CoverageReport::new();
+    report.add("f".to_owned(), f);
+
+    let mut text = Vec::new();
+    report.write_lcov(&mut text).unwrap();
+    let parsed = CoverageReport::read_lcov(&text[..]).unwrap();
+    assert_eq!(parsed, report);
+
+    let mut merged = parsed.clone();
+    merged.merge(&report);
+    let f = &merged.functions["f"];
+    assert_eq!(f.calls, 4);
+    assert_eq!(f.ops[&0], 4);
+    assert_eq!(f.branches[&(0, 0)], 4);
+    assert_eq!(f.branches[&(0, 1)], 0);
+}
+
+#[test]
+fn test_coverage_records_ops_and_branches() {
+    use std::collections::HashMap;
+    use interp::Instance;
+    use types::Dynamic;
+
+    let m = ::wat::parse_module("(module
+        (func $f \"f\" (export \"f\") (param $x i32) (result i32)
+            (if (get_local $x) (nop) (nop))
+            (block $a (br_if $a (get_local $x)) (nop))
+            (block $b (block $c (br_table $c $b (get_local $x))))
+            (i32.const 7)))").unwrap();
+
+    // Offsets of the branching ops and the nops, in source order.
+    fn sites(ops: &[BlockOp], out: &mut Vec<(&'static str, usize)>) {
+        for op in ops {
+            match op {
+                &BlockOp::Block(offset, Block::If(ref then, ref otherwise)) => {
+                    out.push(("if", offset));
+                    sites(then, out);
+                    sites(otherwise, out);
+                }
+                &BlockOp::Block(_, Block::Block(ref ops)) => sites(ops, out),
+                &BlockOp::Normal(offset, NormalOp::BrIf{..}) => out.push(("br_if", offset)),
+                &BlockOp::Normal(offset, NormalOp::BrTable{..}) => out.push(("br_table", offset)),
+                &BlockOp::Normal(offset, NormalOp::Nop) => out.push(("nop", offset)),
+                _ => {}
+            }
+        }
+    }
+    let mut found = Vec::new();
+    sites(&m.code[0].block_ops().collect::<Vec<_>>(), &mut found);
+    let kinds = found.iter().map(|s| s.0).collect::<Vec<_>>();
+    assert_eq!(kinds, vec!["if", "nop", "nop", "br_if", "nop", "br_table"]);
+    let offset = |i: usize| found[i].1;
+
+    let mut instance = Instance::new(&m, HashMap::new());
+    instance.coverage = Some(Coverage::new());
+    let f = m.find(b"f").unwrap();
+    for &x in &[1, 0, 0] {
+        instance.invoke(f, &[Dynamic::from_u32(x)]);
+    }
+
+    let report = instance.coverage.as_ref().unwrap().report(&m);
+    let f = &report.functions["f"];
+    assert_eq!(f.calls, 3);
+    assert_eq!(f.ops[&offset(0)], 3);
+    // The then arm runs for 1, the else arm for each 0.
+    assert_eq!((f.ops[&offset(1)], f.ops[&offset(2)]), (1, 2));
+    assert_eq!((f.branches[&(offset(0), 0)], f.branches[&(offset(0), 1)]), (1, 2));
+    // br_if is taken for 1 and falls through to the nop for each 0.
+    assert_eq!((f.branches[&(offset(3), 0)], f.branches[&(offset(3), 1)]), (2, 1));
+    assert_eq!(f.ops[&offset(4)], 2);
+    // br_table uses its only target for 0 and the default otherwise.
+    assert_eq!((f.branches[&(offset(5), 0)], f.branches[&(offset(5), 1)]), (2, 1));
+    assert_eq!(f.branches.len(), 6);
+}
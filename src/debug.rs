@@ -0,0 +1,295 @@
+use std::mem;
+
+use module::{AsBytes, Module, FunctionIndex};
+use types::Dynamic;
+use interp::Memory;
+use watch::{Access, Watchpoint, MemoryAccess};
+
+/// Where execution should stop. An `offset` of `None` stops at the first op
+/// of every call to the function.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub function: FunctionIndex,
+    pub offset: Option<usize>,
+}
+
+/// How to continue after a pause.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Step {
+    /// Run until the next breakpoint.
+    Continue,
+    /// Stop at the next op, following calls.
+    Into,
+    /// Stop at the next op in this function or a caller.
+    Over,
+    /// Stop at the next op after this function returns.
+    Out,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PauseReason {
+    Breakpoint,
+    Step,
+    /// The op about to run accesses a watched address; see `Pause::access`.
+    Watchpoint,
+    /// The paused op trapped. Execution unwinds once the handler returns,
+    /// whatever step it asks for.
+    Trap,
+}
+
+/// One active call: the function and the offset of the op it is running.
+#[derive(Copy, Clone)]
+pub struct Frame {
+    pub function: FunctionIndex,
+    pub offset: usize,
+}
+
+/// The state of the innermost frame at a pause.
+pub struct FrameState<'p> {
+    pub function_name: Option<&'p [u8]>,
+    pub op: String,
+    pub locals: &'p [Dynamic],
+    /// From the names chunk; may be shorter than `locals`.
+    pub local_names: Vec<&'p [u8]>,
+    pub stack: &'p [Option<Dynamic>],
+    pub memory: &'p Memory,
+}
+
+pub struct Pause<'p> {
+    pub reason: PauseReason,
+    pub frame: FrameState<'p>,
+    /// Active calls, outermost first. The last entry is the paused op.
+    pub backtrace: &'p [Frame],
+    /// May be changed by the handler to take effect right away.
+    pub breakpoints: &'p mut Vec<Breakpoint>,
+    pub watchpoints: &'p mut Vec<Watchpoint>,
+    /// The access that hit a watchpoint, for `PauseReason::Watchpoint`.
+    pub access: Option<MemoryAccess>,
+}
+
+impl<'p> Pause<'p> {
+    pub fn function(&self) -> FunctionIndex {
+        self.backtrace.last().unwrap().function
+    }
+
+    pub fn offset(&self) -> usize {
+        self.backtrace.last().unwrap().offset
+    }
+
+    pub fn local_name(&self, index: usize) -> Option<&[u8]> {
+        self.frame.local_names.get(index).cloned().filter(|n| !n.is_empty())
+    }
+}
+
+/// Called whenever execution pauses. Returns how to continue.
+pub trait DebugHandler {
+    fn pause(&mut self, pause: &mut Pause) -> Step;
+}
+
+/// Drives breakpoints and stepping while attached to an `Instance` (see
+/// `Instance::debugger`).
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    handler: Box<dyn DebugHandler>,
+    step: Step,
+    step_depth: usize,
+    frames: Vec<Frame>,
+    at_entry: bool,
+    trap_reported: bool,
+}
+
+impl Debugger {
+    pub fn new(handler: Box<dyn DebugHandler>) -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            handler,
+            step: Step::Continue,
+            step_depth: 0,
+            frames: Vec::new(),
+            at_entry: false,
+            trap_reported: false,
+        }
+    }
+
+    pub fn break_at(&mut self, function: FunctionIndex, offset: Option<usize>) {
+        self.breakpoints.push(Breakpoint { function, offset });
+    }
+
+    /// Sets a breakpoint on a function found by export or debug name.
+    /// Returns false if there is no such function.
+    pub fn break_at_name<B: AsBytes>(&mut self, module: &Module<B>, name: &[u8], offset: Option<usize>) -> bool {
+        match module.find(name).or_else(|| module.find_by_debug_name(name)) {
+            Some(function) => {
+                self.break_at(function, offset);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Pauses before any load or store of `kind` touching `len` bytes from
+    /// `start`.
+    pub fn watch(&mut self, start: usize, len: usize, access: Access) {
+        self.watchpoints.push(Watchpoint::new(start, len, access));
+    }
+
+    pub fn watch_hit(&self, address: usize, width: usize, kind: Access) -> bool {
+        self.watchpoints.iter().any(|w| w.hit(address, width, kind))
+    }
+
+    /// Sets how to proceed from the current point; `Step::Into` before an
+    /// invoke stops at its first op.
+    pub fn set_step(&mut self, step: Step) {
+        self.step = step;
+        self.step_depth = self.frames.len();
+    }
+
+    pub fn backtrace(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn enter(&mut self, function: FunctionIndex) {
+        if self.frames.is_empty() {
+            self.trap_reported = false;
+        }
+        self.frames.push(Frame { function, offset: 0 });
+        self.at_entry = true;
+    }
+
+    pub fn exit(&mut self) {
+        self.frames.pop();
+        self.at_entry = false;
+    }
+
+    /// Records that the innermost frame is about to run the op at `offset`,
+    /// and returns why execution should pause there, if it should.
+    pub fn before_op(&mut self, offset: usize) -> Option<PauseReason> {
+        let function = {
+            let frame = self.frames.last_mut().unwrap();
+            frame.offset = offset;
+            frame.function
+        };
+        let at_entry = mem::replace(&mut self.at_entry, false);
+        let depth = self.frames.len();
+
+        let hit = self.breakpoints.iter().any(|b| b.function == function && match b.offset {
+            Some(o) => o == offset,
+            None => at_entry,
+        });
+        let stepped = match self.step {
+            Step::Continue => false,
+            Step::Into => true,
+            Step::Over => depth <= self.step_depth,
+            Step::Out => depth < self.step_depth,
+        };
+
+        if hit {
+            Some(PauseReason::Breakpoint)
+        } else if stepped {
+            Some(PauseReason::Step)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true the first time it's called for a trap, so a trap is
+    /// reported at the op that caused it and not again in each caller.
+    pub fn report_trap(&mut self) -> bool {
+        !mem::replace(&mut self.trap_reported, true)
+    }
+
+    pub fn pause(&mut self, reason: PauseReason, frame: FrameState, access: Option<MemoryAccess>) {
+        let step = {
+            let mut pause = Pause {
+                reason,
+                frame,
+                backtrace: &self.frames,
+                breakpoints: &mut self.breakpoints,
+                watchpoints: &mut self.watchpoints,
+                access,
+            };
+            self.handler.pause(&mut pause)
+        };
+        self.set_step(step);
+    }
+}
+
+#[test]
+fn test_debugger() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use interp::Instance;
+    use types::Dynamic;
+    use module::FunctionIndex;
+    use self::PauseReason::{Breakpoint, Step as Stepped};
+
+    /// Records each pause and answers with the next scripted step.
+    struct Script {
+        steps: Vec<Step>,
+        stops: Rc<RefCell<Vec<(PauseReason, usize, usize, String)>>>,
+        locals: Rc<RefCell<Vec<Vec<Dynamic>>>>,
+    }
+
+    impl DebugHandler for Script {
+        fn pause(&mut self, pause: &mut Pause) -> Step {
+            self.stops.borrow_mut().push((pause.reason, pause.function().0, pause.backtrace.len(),
+                pause.frame.op.clone()));
+            self.locals.borrow_mut().push(pause.frame.locals.to_vec());
+            if self.steps.is_empty() { Step::Continue } else { self.steps.remove(0) }
+        }
+    }
+
+    fn run<F: Fn(&mut Debugger)>(setup: F, steps: Vec<Step>)
+            -> (Vec<(PauseReason, usize, usize, String)>, Vec<Vec<Dynamic>>) {
+        let m = ::wat::parse_module("(module
+            (func $add1 \"add1\" (param $x i32) (result i32) (i32.add (get_local $x) (i32.const 1)))
+            (func $main \"main\" (param $n i32) (result i32)
+                (i32.mul (call $add1 (get_local $n)) (i32.const 2))))").unwrap();
+        let stops = Rc::new(RefCell::new(Vec::new()));
+        let locals = Rc::new(RefCell::new(Vec::new()));
+        let mut debugger = Debugger::new(Box::new(Script { steps, stops: stops.clone(), locals: locals.clone() }));
+        setup(&mut debugger);
+        let mut instance = Instance::new(&m, HashMap::new());
+        instance.debugger = Some(debugger);
+        instance.invoke(FunctionIndex(1), &[Dynamic::from_u32(5)]);
+        let stops = stops.borrow().clone();
+        let locals = locals.borrow().clone();
+        (stops, locals)
+    }
+
+    let stop = |reason, function, depth, op: &str| (reason, function, depth, op.to_owned());
+
+    // A breakpoint on entry, then over the next op, then out to main.
+    let (stops, locals) = run(|d| d.break_at(FunctionIndex(0), None), vec![Step::Over, Step::Out]);
+    assert_eq!(stops, vec![
+        stop(Breakpoint, 0, 2, "get_local 0"),
+        stop(Stepped, 0, 2, "i32.const 1"),
+        stop(Stepped, 1, 1, "i32.const 2"),
+    ]);
+    assert_eq!(locals, vec![vec![Dynamic::from_u32(5)]; 3]);
+
+    // Stepping in follows the call; stepping over from its last op
+    // returns to the caller.
+    let (stops, _) = run(|d| d.set_step(Step::Into),
+        vec![Step::Into, Step::Into, Step::Over, Step::Over, Step::Over]);
+    assert_eq!(stops, vec![
+        stop(Stepped, 1, 1, "get_local 0"),
+        stop(Stepped, 1, 1, "call 1 0"),
+        stop(Stepped, 0, 2, "get_local 0"),
+        stop(Stepped, 0, 2, "i32.const 1"),
+        stop(Stepped, 0, 2, "i32.add"),
+        stop(Stepped, 1, 1, "i32.const 2"),
+    ]);
+
+    // Stepping over the call doesn't stop inside it.
+    let (stops, _) = run(|d| d.set_step(Step::Into), vec![Step::Over, Step::Over]);
+    assert_eq!(stops, vec![
+        stop(Stepped, 1, 1, "get_local 0"),
+        stop(Stepped, 1, 1, "call 1 0"),
+        stop(Stepped, 1, 1, "i32.const 2"),
+    ]);
+}
@@ -0,0 +1,266 @@
+use std::{io, str};
+use std::io::Write;
+use std::collections::HashMap;
+
+use module::{AsBytes, Module, FunctionIndex};
+use types::Dynamic;
+use interp::InterpResult;
+
+/// A call in progress: the function and the offset of the call op it was
+/// running, or 0 for the innermost frame if it hadn't called anything yet.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct CallSite {
+    pub function: FunctionIndex,
+    pub offset: usize,
+}
+
+/// A block returned by the allocator and not yet freed.
+#[derive(Clone)]
+pub struct Allocation {
+    pub address: u32,
+    pub size: u32,
+    /// The calls that led to the allocation, outermost first, ending with the
+    /// frame that called the allocator.
+    pub backtrace: Vec<CallSite>,
+}
+
+/// A call to the free function with a pointer that isn't live.
+#[derive(Clone)]
+pub struct BadFree {
+    pub address: u32,
+    pub backtrace: Vec<CallSite>,
+    /// Where the block was freed before, for a double free. `None` means the
+    /// pointer never came from the allocator while the profiler was attached.
+    pub previous_free: Option<Vec<CallSite>>,
+}
+
+/// The state of the heap at the end of an invocation.
+#[derive(Clone, Default)]
+pub struct HeapReport {
+    /// Blocks still allocated, by address. This includes blocks allocated by
+    /// earlier invocations.
+    pub leaks: Vec<Allocation>,
+    pub bad_frees: Vec<BadFree>,
+    /// The most bytes live at once during the invocation.
+    pub peak_bytes: u64,
+    pub allocations: u64,
+    pub frees: u64,
+}
+
+impl HeapReport {
+    pub fn write<B: AsBytes, W: Write>(&self, module: &Module<B>, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{} allocations, {} frees, peak {} bytes", self.allocations, self.frees, self.peak_bytes)?;
+        for b in &self.bad_frees {
+            match b.previous_free {
+                Some(ref previous) => {
+                    writeln!(out, "double free of {:#x}", b.address)?;
+                    write_backtrace(module, &b.backtrace, out)?;
+                    writeln!(out, "  previously freed at")?;
+                    write_backtrace(module, previous, out)?;
+                }
+                None => {
+                    writeln!(out, "free of unallocated {:#x}", b.address)?;
+                    write_backtrace(module, &b.backtrace, out)?;
+                }
+            }
+        }
+        let leaked = self.leaks.iter().map(|a| a.size as u64).sum::<u64>();
+        writeln!(out, "{} bytes in {} blocks still allocated", leaked, self.leaks.len())?;
+        for a in &self.leaks {
+            writeln!(out, "{} bytes at {:#x} allocated at", a.size, a.address)?;
+            write_backtrace(module, &a.backtrace, out)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_backtrace<B: AsBytes, W: Write>(module: &Module<B>, backtrace: &[CallSite], out: &mut W) -> io::Result<()> {
+    for frame in backtrace.iter().rev() {
+        match module.find_name(frame.function).and_then(|n| str::from_utf8(n).ok()) {
+            Some(name) => writeln!(out, "    {}+{}", name, frame.offset)?,
+            None => writeln!(out, "    func[{}]+{}", frame.function.0, frame.offset)?,
+        }
+    }
+    Ok(())
+}
+
+/// Tracks the blocks handed out by a module's own allocator while attached
+/// to an `Instance` (see `Instance::heap`), by watching calls to its malloc
+/// and free exports. Calls the allocator makes to itself are ignored.
+pub struct HeapProfiler {
+    malloc: FunctionIndex,
+    free: FunctionIndex,
+    frames: Vec<CallSite>,
+    in_allocator: usize,
+    malloc_size: u32,
+    live: HashMap<u32, Allocation>,
+    freed: HashMap<u32, Vec<CallSite>>,
+    live_bytes: u64,
+    current: HeapReport,
+    last_report: Option<HeapReport>,
+}
+
+impl HeapProfiler {
+    /// Returns `None` if the module doesn't export both functions.
+    pub fn new<B: AsBytes>(module: &Module<B>, malloc: &[u8], free: &[u8]) -> Option<HeapProfiler> {
+        match (module.find(malloc), module.find(free)) {
+            (Some(malloc), Some(free)) => Some(HeapProfiler {
+                malloc,
+                free,
+                frames: Vec::new(),
+                in_allocator: 0,
+                malloc_size: 0,
+                live: HashMap::new(),
+                freed: HashMap::new(),
+                live_bytes: 0,
+                current: HeapReport::default(),
+                last_report: None,
+            }),
+            _ => None,
+        }
+    }
+
+    fn is_allocator(&self, func: FunctionIndex) -> bool {
+        func == self.malloc || func == self.free
+    }
+
+    pub fn enter(&mut self, func: FunctionIndex, args: &[Dynamic]) {
+        if self.frames.is_empty() {
+            self.current = HeapReport::default();
+            self.current.peak_bytes = self.live_bytes;
+        }
+        if self.is_allocator(func) {
+            if self.in_allocator == 0 {
+                if func == self.malloc {
+                    self.malloc_size = args[0].to_u32();
+                } else {
+                    self.free(args[0].to_u32());
+                }
+            }
+            self.in_allocator += 1;
+        }
+        self.frames.push(CallSite { function: func, offset: 0 });
+    }
+
+    /// Records the offset of the call op the innermost frame is running.
+    pub fn call_site(&mut self, offset: usize) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.offset = offset;
+        }
+    }
+
+    pub fn exit(&mut self, func: FunctionIndex, result: InterpResult) {
+        self.frames.pop();
+        if self.is_allocator(func) {
+            self.in_allocator -= 1;
+            if self.in_allocator == 0 && func == self.malloc {
+                if let InterpResult::Value(Some(address)) = result {
+                    self.allocate(address.to_u32());
+                }
+            }
+        }
+
+        if self.frames.is_empty() {
+            let mut report = self.current.clone();
+            report.leaks = self.live.values().cloned().collect();
+            report.leaks.sort_by_key(|a| a.address);
+            self.last_report = Some(report);
+        }
+    }
+
+    fn allocate(&mut self, address: u32) {
+        if address == 0 {
+            return;
+        }
+        let size = self.malloc_size;
+        self.freed.remove(&address);
+        self.live.insert(address, Allocation {
+            address,
+            size,
+            backtrace: self.frames.clone(),
+        });
+        self.live_bytes += size as u64;
+        self.current.allocations += 1;
+        if self.live_bytes > self.current.peak_bytes {
+            self.current.peak_bytes = self.live_bytes;
+        }
+    }
+
+    fn free(&mut self, address: u32) {
+        if address == 0 {
+            return;
+        }
+        self.current.frees += 1;
+        match self.live.remove(&address) {
+            Some(a) => {
+                self.live_bytes -= a.size as u64;
+                self.freed.insert(address, self.frames.clone());
+            }
+            None => {
+                self.current.bad_frees.push(BadFree {
+                    address,
+                    backtrace: self.frames.clone(),
+                    previous_free: self.freed.get(&address).cloned(),
+                });
+            }
+        }
+    }
+
+    /// The blocks currently allocated.
+    pub fn live(&self) -> Vec<&Allocation> {
+        let mut live = self.live.values().collect::<Vec<_>>();
+        live.sort_by_key(|a| a.address);
+        live
+    }
+
+    /// The report for the most recent invocation to finish.
+    pub fn last_report(&self) -> Option<&HeapReport> {
+        self.last_report.as_ref()
+    }
+}
+
+#[test]
+fn test_heap_profiler() {
+    use interp::Instance;
+
+    // A bump allocator that keeps its next free address at 0 and never
+    // reuses memory, so every block gets a distinct address.
+    let m = ::wat::parse_module("(module (memory 1)
+        (func $malloc \"malloc\" (export \"malloc\") (param $n i32) (result i32) (local $p i32)
+            (set_local $p (i32.add (i32.load (i32.const 0)) (i32.const 16)))
+            (i32.store (i32.const 0) (i32.add (get_local $p) (get_local $n)))
+            (get_local $p))
+        (func $free \"free\" (export \"free\") (param i32) (nop))
+        (func $main \"main\" (export \"main\") (local $a i32)
+            (set_local $a (call $malloc (i32.const 8)))
+            (drop (call $malloc (i32.const 24)))
+            (call $free (get_local $a))
+            (call $free (get_local $a))
+            (call $free (i32.const 1000))
+            (drop (call $malloc (i32.const 4)))))").unwrap();
+    let mut instance = Instance::new(&m, HashMap::new());
+    instance.heap = HeapProfiler::new(&m, b"malloc", b"free");
+    instance.invoke(m.find(b"main").unwrap(), &[]);
+
+    let heap = instance.heap.as_ref().unwrap();
+    let report = heap.last_report().unwrap();
+    assert_eq!((report.allocations, report.frees, report.peak_bytes), (3, 3, 32));
+
+    let leaks = report.leaks.iter().map(|a| (a.address, a.size)).collect::<Vec<_>>();
+    assert_eq!(leaks, vec![(40, 24), (80, 4)]);
+    assert_eq!(heap.live().len(), 2);
+    let main = 2;
+    assert_eq!(report.leaks[0].backtrace.len(), 1);
+    assert_eq!(report.leaks[0].backtrace[0].function.0, main);
+
+    assert_eq!(report.bad_frees.len(), 2);
+    let double = &report.bad_frees[0];
+    assert_eq!(double.address, 16);
+    assert_eq!(double.backtrace[0].function.0, main);
+    let previous = double.previous_free.as_ref().unwrap();
+    assert_eq!(previous[0].function.0, main);
+    assert!(previous[0].offset < double.backtrace[0].offset);
+    let unknown = &report.bad_frees[1];
+    assert_eq!(unknown.address, 1000);
+    assert!(unknown.previous_free.is_none());
+}
@@ -1,189 +1,192 @@
-use std::str::FromStr;
-use std::cmp::min;
+use literal::{LiteralError, split_digits};
 
-pub enum Expanded {
-    Normal {  // (-1)^negative * 0.left_aligned_mantissa * 2^exponent
-        negative: bool,
-        mantissa: u64,
-        exponent: i32
-    },
-    Zero {
-        negative: bool,
-    },
-    Infinite {
-        negative: bool,
-    },
-    Nan {
-        negative: bool,
-    }
+/// A hex float's value, `(-1)^negative * mantissa * 2^exponent`. Digits
+/// beyond the 64 bits of `mantissa` are dropped, and `inexact` records
+/// whether any of them were nonzero.
+pub struct Expanded {
+    pub negative: bool,
+    pub mantissa: u64,
+    pub exponent: i64,
+    pub inexact: bool,
 }
 
-fn parse_hex_digit(ch: char) -> usize {
-    match ch {
-        '0'...'9' => (ch as usize) - ('0' as usize),
-        'a'...'f' => (ch as usize) - ('a' as usize) + 10,
-        'A'...'F' => (ch as usize) - ('A' as usize) + 10,
-        _ => panic!(),
+impl Expanded {
+    fn push_digit(&mut self, digit: u64, fraction: bool) {
+        if self.mantissa >> 60 == 0 {
+            self.mantissa = self.mantissa << 4 | digit;
+            if fraction {
+                self.exponent -= 4;
+            }
+        } else {
+            self.inexact |= digit != 0;
+            if !fraction {
+                self.exponent += 4;
+            }
+        }
     }
 }
 
-pub fn parse_expanded(text: &str) -> Expanded {
-
+pub fn parse_expanded(text: &str) -> Result<Expanded, LiteralError> {
     let mut text = text;
     let mut neg = false;
 
     if text.starts_with("-") {
         text = &text[1..];
         neg = true;
+    } else if text.starts_with("+") {
+        text = &text[1..];
     }
 
-    assert!(text.starts_with("0x"));
-    let text = &text[2..];
+    if !text.starts_with("0x") {
+        return Err(LiteralError::Malformed);
+    }
 
-    let (d, f, e) = match text.find(|c| c == '.') {
-        None => match text.find(|c| c == 'p') {
-            None => (text, "", ""),
-            Some(pindex) => (&text[..pindex], "", &text[pindex + 1..]),
-        },
-        Some(dindex) => match text[dindex + 1..].find(|c| c == 'p') {
-            None => (&text[..dindex], &text[dindex + 1..], ""),
-            Some(pindex) => (&text[..dindex], &text[dindex + 1..dindex + pindex + 1], &text[dindex + pindex + 2..]),
-        }
+    let mut expanded = Expanded {
+        negative: neg,
+        mantissa: 0,
+        exponent: 0,
+        inexact: false,
     };
 
-    let mut exp = if e.len() > 0 { i32::from_str(e).unwrap() } else { 0 };
-    assert!(exp >= -1022 && exp <= 1023);
-
-    let mut bits = 0u64;
-
-    let mut shift = 64 - 4;
+    let (d, rest) = split_digits(&text[2..], 16)?;
+    if d.is_empty() {
+        return Err(LiteralError::Malformed);
+    }
     for ch in d.chars() {
-        bits |= (parse_hex_digit(ch) as u64) << shift;
-        shift -= 4;
-        exp += 4;
+        expanded.push_digit(ch.to_digit(16).unwrap() as u64, false);
     }
 
-    for ch in f.chars() {
-        bits |= (parse_hex_digit(ch) as u64) << shift;
-        shift -= 4;
+    let mut rest = rest;
+    if rest.starts_with(".") {
+        let (f, after) = split_digits(&rest[1..], 16)?;
+        for ch in f.chars() {
+            expanded.push_digit(ch.to_digit(16).unwrap() as u64, true);
+        }
+        rest = after;
     }
 
-    if bits == 0 {
-        Expanded::Zero{negative: neg}
-    } else {
-        let leading = bits.leading_zeros();
-        bits <<= leading;
-        exp -= leading as i32;
-
-        Expanded::Normal {
-            negative: neg,
-            mantissa: bits,
-            exponent: exp
+    if rest.starts_with("p") || rest.starts_with("P") {
+        let mut e = &rest[1..];
+        let exp_neg = e.starts_with("-");
+        if e.starts_with("-") || e.starts_with("+") {
+            e = &e[1..];
         }
+        let (digits, after) = split_digits(e, 10)?;
+        if digits.is_empty() {
+            return Err(LiteralError::Malformed);
+        }
+        // Anything this large is out of range or zero either way.
+        let mut exp = 0i64;
+        for ch in digits.chars() {
+            exp = (exp * 10 + ch.to_digit(10).unwrap() as i64).min(1 << 40);
+        }
+        expanded.exponent += if exp_neg { -exp } else { exp };
+        rest = after;
+    }
+
+    if !rest.is_empty() {
+        return Err(LiteralError::Malformed);
     }
+    Ok(expanded)
 }
 
-pub fn parse_bits_32(text: &str) -> u32 {
-    let mut bits = 0u32;
-    match parse_expanded(text) {
-        Expanded::Normal {negative, mantissa, exponent} => {
-            if negative {
-                bits |= 0x8000_0000;
-            }
+/// Rounds a hex float to nearest even in a format with the given field
+/// widths, returning its bits. Values too large for the format become
+/// infinity.
+fn parse_bits(text: &str, mantissa_bits: u32, exponent_bits: u32) -> Result<u64, LiteralError> {
+    let expanded = parse_expanded(text)?;
 
-            let exponent = exponent - 1;
+    let sign = if expanded.negative { 1u64 << (mantissa_bits + exponent_bits) } else { 0 };
+    if expanded.mantissa == 0 {
+        return Ok(sign);
+    }
 
-            let mut mant = mantissa;
-            if exponent > 127 {
-                bits |= 0xff << 23;
-            } else if exponent < -126 {
-                let shift = 32 + 9 + min(24, -126 - exponent) - 1;
-                if shift < 64 {
-                    mant >>= shift;
-                } else {
-                    mant = 0;
-                }
-                bits |= mant as u32;
-            } else {
-                mant <<= 1;
-                let leftover = mant << (32 - 9);
-                mant >>= 32 + 9;
-                bits |= mant as u32;
-                if leftover > 0x8000_0000_0000_0000 ||
-                    (leftover == 0x8000_0000_0000_0000 && (bits & 1) == 1) {
-                    // round up
-                    bits += 1;
-                }
-                bits |= ((exponent + 127) as u32) << 23;
-            }
-        }
-        Expanded::Zero {negative} => {
-            if negative {
-                bits |= 0x8000_0000;
-            }
-        }
-        Expanded::Infinite {negative} => {
-            if negative {
-                bits |= 0x8000_0000;
-            }
-            bits |= 0xff << 23;
-        }
-        Expanded::Nan {negative} => {
-            if negative {
-                bits |= 0x8000_0000;
-            }
-            bits |= 0xff << 23;
-            bits |= 1;
-        }
+    // Left-align the mantissa, so the value is 1.f * 2^exponent with the
+    // leading 1 in bit 63.
+    let leading = expanded.mantissa.leading_zeros();
+    let mant = expanded.mantissa << leading;
+    let mut exponent = expanded.exponent - leading as i64 + 63;
+
+    let bias = (1i64 << (exponent_bits - 1)) - 1;
+    let min_exponent = 1 - bias;
+
+    // The number of low bits of `mant` to round away; subnormals lose more.
+    let mut shift = 63 - mantissa_bits as i64;
+    if exponent < min_exponent {
+        shift += min_exponent - exponent;
+    }
+    let (mut bits, half, below_half) = if shift > 64 {
+        (0, false, true)
+    } else if shift == 64 {
+        (0, mant >> 63 == 1, mant << 1 != 0)
+    } else {
+        (mant >> shift, (mant >> (shift - 1)) & 1 == 1, mant & ((1 << (shift - 1)) - 1) != 0)
+    };
+    if half && (below_half || expanded.inexact || bits & 1 == 1) {
+        bits += 1;
     }
 
-    bits
+    if exponent < min_exponent {
+        // A carry out of the top here makes the smallest normal number,
+        // whose biased exponent of 1 lands in the right place by itself.
+        return Ok(sign | bits);
+    }
+    if bits >> (mantissa_bits + 1) != 0 {
+        bits >>= 1;
+        exponent += 1;
+    }
+    if exponent > bias {
+        return Ok(sign | ((1 << exponent_bits) - 1) << mantissa_bits);
+    }
+    Ok(sign | ((exponent + bias) as u64) << mantissa_bits | bits & ((1 << mantissa_bits) - 1))
 }
 
-pub fn parse_bits_64(text: &str) -> u64 {
-    let mut bits = 0u64;
-    match parse_expanded(text) {
-        Expanded::Normal {negative, mantissa, exponent} => {
-            if negative {
-                bits |= 0x8000_0000_0000_0000;
-            }
+pub fn parse_bits_32(text: &str) -> Result<u32, LiteralError> {
+    parse_bits(text, 23, 8).map(|bits| bits as u32)
+}
 
-            let exponent = exponent - 1;
+pub fn parse_bits_64(text: &str) -> Result<u64, LiteralError> {
+    parse_bits(text, 52, 11)
+}
 
-            let mut mant = mantissa;
-            if exponent > 1023 {
-                bits |= 0xff << 23;
-            } else if exponent < -1022 {
-                mant >>= 12 + min(52, -1022 - exponent) - 1;
-                bits |= mant;
-            } else {
-                mant <<= 1;
-                mant >>= 12;
-                bits |= mant;
-                bits |= ((exponent + 1023) as u64) << 52;
-            }
-        }
-        Expanded::Zero {negative} => {
-            if negative {
-                bits |= 0x8000_0000_0000_0000;
-            }
-        }
-        Expanded::Infinite {negative} => {
-            if negative {
-                bits |= 0x8000_0000_0000_0000;
-            }
-            bits |= 0xff << 52;
-        }
-        Expanded::Nan {negative} => {
-            if negative {
-                bits |= 0x8000_0000_0000_0000;
-            }
-            bits |= 0xff << 52;
-            bits |= 1;
+/// Writes a finite float's bits as hex float text, like `-0x1.8p+3`.
+/// Subnormals are normalized, so every nonzero value starts with `0x1`.
+fn format_bits(bits: u64, mantissa_bits: u32, exponent_bits: u32) -> String {
+    let sign = if (bits >> (mantissa_bits + exponent_bits)) & 1 == 1 { "-" } else { "" };
+    let mut mantissa = bits & ((1 << mantissa_bits) - 1);
+    let biased = (bits >> mantissa_bits) & ((1 << exponent_bits) - 1);
+    let bias = (1i64 << (exponent_bits - 1)) - 1;
+    assert!(biased != (1 << exponent_bits) - 1, "not a finite float");
+
+    if biased == 0 && mantissa == 0 {
+        return format!("{}0x0p+0", sign);
+    }
+    let mut exponent = biased as i64 - bias;
+    if biased == 0 {
+        exponent = 1 - bias;
+        while mantissa >> mantissa_bits == 0 {
+            mantissa <<= 1;
+            exponent -= 1;
         }
+        mantissa &= (1 << mantissa_bits) - 1;
+    }
+
+    // Pad the fraction out to whole hex digits, then trim trailing zeros.
+    let digits = mantissa_bits.div_ceil(4);
+    let mut fraction = format!("{:0width$x}", mantissa << (digits * 4 - mantissa_bits), width = digits as usize);
+    while fraction.ends_with('0') {
+        fraction.pop();
     }
+    let dot = if fraction.is_empty() { "" } else { "." };
+    format!("{}0x1{}{}p{:+}", sign, dot, fraction, exponent)
+}
+
+pub fn format_bits_32(bits: u32) -> String {
+    format_bits(bits as u64, 23, 8)
+}
 
-    bits
+pub fn format_bits_64(bits: u64) -> String {
+    format_bits(bits, 52, 11)
 }
 
 macro_rules! assert_bin_eq {
@@ -200,348 +203,363 @@ macro_rules! assert_bin_eq {
 mod tests {
     use hexfloat::parse_bits_32;
     use hexfloat::parse_bits_64;
+    use hexfloat::format_bits_32;
+    use hexfloat::format_bits_64;
 
     #[test]
     fn test_parse() {
-        assert_bin_eq!(parse_bits_32("-0x0p+0"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0p+0"), 0x8000000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.000002p-126"), 0x80800001);
-        assert_bin_eq!(parse_bits_64("-0x1.000002p-126"), 0xb810000020000000);
-        assert_bin_eq!(parse_bits_32("-0x1.3bd3cep+5"), 0xc21de9e7);
-        assert_bin_eq!(parse_bits_64("-0x1.3bd3cep+5"), 0xc043bd3ce0000000);
-        assert_bin_eq!(parse_bits_32("-0x1.45f304p+125"), 0xfe22f982);
-        assert_bin_eq!(parse_bits_64("-0x1.45f304p+125"), 0xc7c45f3040000000);
-        assert_bin_eq!(parse_bits_32("-0x1.45f306p-3"), 0xbe22f983);
-        assert_bin_eq!(parse_bits_64("-0x1.45f306p-3"), 0xbfc45f3060000000);
-        assert_bin_eq!(parse_bits_32("-0x1.45f306p-4"), 0xbda2f983);
-        assert_bin_eq!(parse_bits_64("-0x1.45f306p-4"), 0xbfb45f3060000000);
-        assert_bin_eq!(parse_bits_32("-0x1.45f3p-129"), 0x80145f30);
-        assert_bin_eq!(parse_bits_64("-0x1.45f3p-129"), 0xb7e45f3000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.521fb6p+2"), 0xc0a90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.521fb6p+2"), 0xc01521fb60000000);
-        assert_bin_eq!(parse_bits_32("-0x1.721fb6p+2"), 0xc0b90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.721fb6p+2"), 0xc01721fb60000000);
-        assert_bin_eq!(parse_bits_32("-0x1.8p+0"), 0xbfc00000);
-        assert_bin_eq!(parse_bits_64("-0x1.8p+0"), 0xbff8000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.8p+2"), 0xc0c00000);
-        assert_bin_eq!(parse_bits_64("-0x1.8p+2"), 0xc018000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.8p-147"), 0x80000006);
-        assert_bin_eq!(parse_bits_64("-0x1.8p-147"), 0xb6c8000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb6p+1"), 0xc0490fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb6p+1"), 0xc00921fb60000000);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb6p+2"), 0xc0c90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb6p+2"), 0xc01921fb60000000);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb6p+3"), 0xc1490fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb6p+3"), 0xc02921fb60000000);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb6p-124"), 0x81c90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb6p-124"), 0xb83921fb60000000);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb8p-126"), 0x80c90fdc);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb8p-126"), 0xb81921fb80000000);
-        assert_bin_eq!(parse_bits_32("-0x1.b21fb6p+2"), 0xc0d90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.b21fb6p+2"), 0xc01b21fb60000000);
-        assert_bin_eq!(parse_bits_32("-0x1.cp+2"), 0xc0e00000);
-        assert_bin_eq!(parse_bits_64("-0x1.cp+2"), 0xc01c000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.d21fb6p+2"), 0xc0e90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.d21fb6p+2"), 0xc01d21fb60000000);
-        assert_bin_eq!(parse_bits_32("-0x1.fffffcp-127"), 0x807fffff);
-        assert_bin_eq!(parse_bits_64("-0x1.fffffcp-127"), 0xb80fffffc0000000);
-        assert_bin_eq!(parse_bits_32("-0x1.fffffep+1"), 0xc07fffff);
-        assert_bin_eq!(parse_bits_64("-0x1.fffffep+1"), 0xc00fffffe0000000);
-        assert_bin_eq!(parse_bits_32("-0x1.fffffep+126"), 0xfeffffff);
-        assert_bin_eq!(parse_bits_64("-0x1.fffffep+126"), 0xc7dfffffe0000000);
-        assert_bin_eq!(parse_bits_32("-0x1.fffffep+127"), 0xff7fffff);
-        assert_bin_eq!(parse_bits_64("-0x1.fffffep+127"), 0xc7efffffe0000000);
-        assert_bin_eq!(parse_bits_32("-0x1.fffffep-22"), 0xb4ffffff);
-        assert_bin_eq!(parse_bits_64("-0x1.fffffep-22"), 0xbe9fffffe0000000);
-        assert_bin_eq!(parse_bits_32("-0x1p+0"), 0xbf800000);
-        assert_bin_eq!(parse_bits_64("-0x1p+0"), 0xbff0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p+1"), 0xc0000000);
-        assert_bin_eq!(parse_bits_64("-0x1p+1"), 0xc000000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p+125"), 0xfe000000);
-        assert_bin_eq!(parse_bits_64("-0x1p+125"), 0xc7c0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p+126"), 0xfe800000);
-        assert_bin_eq!(parse_bits_64("-0x1p+126"), 0xc7d0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p+23"), 0xcb000000);
-        assert_bin_eq!(parse_bits_64("-0x1p+23"), 0xc160000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-1"), 0xbf000000);
-        assert_bin_eq!(parse_bits_64("-0x1p-1"), 0xbfe0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-125"), 0x81000000);
-        assert_bin_eq!(parse_bits_64("-0x1p-125"), 0xb820000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-126"), 0x80800000);
-        assert_bin_eq!(parse_bits_64("-0x1p-126"), 0xb810000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-127"), 0x80400000);
-        assert_bin_eq!(parse_bits_64("-0x1p-127"), 0xb800000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-128"), 0x80200000);
-        assert_bin_eq!(parse_bits_64("-0x1p-128"), 0xb7f0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-129"), 0x80100000);
-        assert_bin_eq!(parse_bits_64("-0x1p-129"), 0xb7e0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-148"), 0x80000002);
-        assert_bin_eq!(parse_bits_64("-0x1p-148"), 0xb6b0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-149"), 0x80000001);
-        assert_bin_eq!(parse_bits_64("-0x1p-149"), 0xb6a0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-2"), 0xbe800000);
-        assert_bin_eq!(parse_bits_64("-0x1p-2"), 0xbfd0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-23"), 0xb4000000);
-        assert_bin_eq!(parse_bits_64("-0x1p-23"), 0xbe80000000000000);
-        assert_bin_eq!(parse_bits_32("0x0p+0"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0p+0"), 0x0);
-        assert_bin_eq!(parse_bits_32("0x1.000002p-126"), 0x800001);
-        assert_bin_eq!(parse_bits_64("0x1.000002p-126"), 0x3810000020000000);
-        assert_bin_eq!(parse_bits_32("0x1.3bd3cep+5"), 0x421de9e7);
-        assert_bin_eq!(parse_bits_64("0x1.3bd3cep+5"), 0x4043bd3ce0000000);
-        assert_bin_eq!(parse_bits_32("0x1.40d932p+1"), 0x40206c99);
-        assert_bin_eq!(parse_bits_64("0x1.40d932p+1"), 0x40040d9320000000);
-        assert_bin_eq!(parse_bits_32("0x1.45f304p+125"), 0x7e22f982);
-        assert_bin_eq!(parse_bits_64("0x1.45f304p+125"), 0x47c45f3040000000);
-        assert_bin_eq!(parse_bits_32("0x1.45f306p-3"), 0x3e22f983);
-        assert_bin_eq!(parse_bits_64("0x1.45f306p-3"), 0x3fc45f3060000000);
-        assert_bin_eq!(parse_bits_32("0x1.45f306p-4"), 0x3da2f983);
-        assert_bin_eq!(parse_bits_64("0x1.45f306p-4"), 0x3fb45f3060000000);
-        assert_bin_eq!(parse_bits_32("0x1.45f3p-129"), 0x145f30);
-        assert_bin_eq!(parse_bits_64("0x1.45f3p-129"), 0x37e45f3000000000);
-        assert_bin_eq!(parse_bits_32("0x1.521fb6p+2"), 0x40a90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.521fb6p+2"), 0x401521fb60000000);
-        assert_bin_eq!(parse_bits_32("0x1.6a09e6p-1"), 0x3f3504f3);
-        assert_bin_eq!(parse_bits_64("0x1.6a09e6p-1"), 0x3fe6a09e60000000);
-        assert_bin_eq!(parse_bits_32("0x1.6a09e6p-75"), 0x1a3504f3);
-        assert_bin_eq!(parse_bits_64("0x1.6a09e6p-75"), 0x3b46a09e60000000);
-        assert_bin_eq!(parse_bits_32("0x1.721fb6p+2"), 0x40b90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.721fb6p+2"), 0x401721fb60000000);
-        assert_bin_eq!(parse_bits_32("0x1.8p+0"), 0x3fc00000);
-        assert_bin_eq!(parse_bits_64("0x1.8p+0"), 0x3ff8000000000000);
-        assert_bin_eq!(parse_bits_32("0x1.8p+2"), 0x40c00000);
-        assert_bin_eq!(parse_bits_64("0x1.8p+2"), 0x4018000000000000);
-        assert_bin_eq!(parse_bits_32("0x1.8p-147"), 0x6);
-        assert_bin_eq!(parse_bits_64("0x1.8p-147"), 0x36c8000000000000);
-        assert_bin_eq!(parse_bits_32("0x1.921fb6p+1"), 0x40490fdb);
-        assert_bin_eq!(parse_bits_64("0x1.921fb6p+1"), 0x400921fb60000000);
-        assert_bin_eq!(parse_bits_32("0x1.921fb6p+2"), 0x40c90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.921fb6p+2"), 0x401921fb60000000);
-        assert_bin_eq!(parse_bits_32("0x1.921fb6p+3"), 0x41490fdb);
-        assert_bin_eq!(parse_bits_64("0x1.921fb6p+3"), 0x402921fb60000000);
-        assert_bin_eq!(parse_bits_32("0x1.921fb6p-124"), 0x1c90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.921fb6p-124"), 0x383921fb60000000);
-        assert_bin_eq!(parse_bits_32("0x1.921fb8p-126"), 0xc90fdc);
-        assert_bin_eq!(parse_bits_64("0x1.921fb8p-126"), 0x381921fb80000000);
-        assert_bin_eq!(parse_bits_32("0x1.b21fb6p+2"), 0x40d90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.b21fb6p+2"), 0x401b21fb60000000);
-        assert_bin_eq!(parse_bits_32("0x1.cp+2"), 0x40e00000);
-        assert_bin_eq!(parse_bits_64("0x1.cp+2"), 0x401c000000000000);
-        assert_bin_eq!(parse_bits_32("0x1.d21fb6p+2"), 0x40e90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.d21fb6p+2"), 0x401d21fb60000000);
-        assert_bin_eq!(parse_bits_32("0x1.fffffcp-127"), 0x7fffff);
-        assert_bin_eq!(parse_bits_64("0x1.fffffcp-127"), 0x380fffffc0000000);
-        assert_bin_eq!(parse_bits_32("0x1.fffffep+1"), 0x407fffff);
-        assert_bin_eq!(parse_bits_64("0x1.fffffep+1"), 0x400fffffe0000000);
-        assert_bin_eq!(parse_bits_32("0x1.fffffep+126"), 0x7effffff);
-        assert_bin_eq!(parse_bits_64("0x1.fffffep+126"), 0x47dfffffe0000000);
-        assert_bin_eq!(parse_bits_32("0x1.fffffep+127"), 0x7f7fffff);
-        assert_bin_eq!(parse_bits_64("0x1.fffffep+127"), 0x47efffffe0000000);
-        assert_bin_eq!(parse_bits_32("0x1.fffffep+63"), 0x5f7fffff);
-        assert_bin_eq!(parse_bits_64("0x1.fffffep+63"), 0x43efffffe0000000);
-        assert_bin_eq!(parse_bits_32("0x1.fffffep-22"), 0x34ffffff);
-        assert_bin_eq!(parse_bits_64("0x1.fffffep-22"), 0x3e9fffffe0000000);
-        assert_bin_eq!(parse_bits_32("0x1p+0"), 0x3f800000);
-        assert_bin_eq!(parse_bits_64("0x1p+0"), 0x3ff0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p+1"), 0x40000000);
-        assert_bin_eq!(parse_bits_64("0x1p+1"), 0x4000000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p+125"), 0x7e000000);
-        assert_bin_eq!(parse_bits_64("0x1p+125"), 0x47c0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p+126"), 0x7e800000);
-        assert_bin_eq!(parse_bits_64("0x1p+126"), 0x47d0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p+23"), 0x4b000000);
-        assert_bin_eq!(parse_bits_64("0x1p+23"), 0x4160000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-1"), 0x3f000000);
-        assert_bin_eq!(parse_bits_64("0x1p-1"), 0x3fe0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-125"), 0x1000000);
-        assert_bin_eq!(parse_bits_64("0x1p-125"), 0x3820000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-126"), 0x800000);
-        assert_bin_eq!(parse_bits_64("0x1p-126"), 0x3810000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-127"), 0x400000);
-        assert_bin_eq!(parse_bits_64("0x1p-127"), 0x3800000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-128"), 0x200000);
-        assert_bin_eq!(parse_bits_64("0x1p-128"), 0x37f0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-129"), 0x100000);
-        assert_bin_eq!(parse_bits_64("0x1p-129"), 0x37e0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-148"), 0x2);
-        assert_bin_eq!(parse_bits_64("0x1p-148"), 0x36b0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-149"), 0x1);
-        assert_bin_eq!(parse_bits_64("0x1p-149"), 0x36a0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-2"), 0x3e800000);
-        assert_bin_eq!(parse_bits_64("0x1p-2"), 0x3fd0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-23"), 0x34000000);
-        assert_bin_eq!(parse_bits_64("0x1p-23"), 0x3e80000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-63"), 0x20000000);
-        assert_bin_eq!(parse_bits_64("0x1p-63"), 0x3c00000000000000);
-        assert_bin_eq!(parse_bits_32("-0x0.0000000000001p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0.0000000000001p-1022"), 0x8000000000000001);
-        assert_bin_eq!(parse_bits_32("-0x0.0000000000002p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0.0000000000002p-1022"), 0x8000000000000002);
-        assert_bin_eq!(parse_bits_32("-0x0.0000000000006p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0.0000000000006p-1022"), 0x8000000000000006);
-        assert_bin_eq!(parse_bits_32("-0x0.28be60db9391p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0.28be60db9391p-1022"), 0x80028be60db93910);
-        assert_bin_eq!(parse_bits_32("-0x0.2p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0.2p-1022"), 0x8002000000000000);
-        assert_bin_eq!(parse_bits_32("-0x0.4p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0.4p-1022"), 0x8004000000000000);
-        assert_bin_eq!(parse_bits_32("-0x0.8p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0.8p-1022"), 0x8008000000000000);
-        assert_bin_eq!(parse_bits_32("-0x0.fffffffffffffp-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0.fffffffffffffp-1022"), 0x800fffffffffffff);
-        assert_bin_eq!(parse_bits_32("-0x0p+0"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x0p+0"), 0x8000000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.0000000000001p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x1.0000000000001p-1022"), 0x8010000000000001);
-        assert_bin_eq!(parse_bits_32("-0x1.3bd3cc9be45dep+5"), 0xc21de9e6);
-        assert_bin_eq!(parse_bits_64("-0x1.3bd3cc9be45dep+5"), 0xc043bd3cc9be45de);
-        assert_bin_eq!(parse_bits_32("-0x1.45f306dc9c882p+1021"), 0xff800000);
-        assert_bin_eq!(parse_bits_64("-0x1.45f306dc9c882p+1021"), 0xffc45f306dc9c882);
-        assert_bin_eq!(parse_bits_32("-0x1.45f306dc9c883p-3"), 0xbe22f983);
-        assert_bin_eq!(parse_bits_64("-0x1.45f306dc9c883p-3"), 0xbfc45f306dc9c883);
-        assert_bin_eq!(parse_bits_32("-0x1.45f306dc9c883p-4"), 0xbda2f983);
-        assert_bin_eq!(parse_bits_64("-0x1.45f306dc9c883p-4"), 0xbfb45f306dc9c883);
-        assert_bin_eq!(parse_bits_32("-0x1.521fb54442d18p+2"), 0xc0a90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.521fb54442d18p+2"), 0xc01521fb54442d18);
-        assert_bin_eq!(parse_bits_32("-0x1.721fb54442d18p+2"), 0xc0b90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.721fb54442d18p+2"), 0xc01721fb54442d18);
-        assert_bin_eq!(parse_bits_32("-0x1.8p+0"), 0xbfc00000);
-        assert_bin_eq!(parse_bits_64("-0x1.8p+0"), 0xbff8000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.8p+2"), 0xc0c00000);
-        assert_bin_eq!(parse_bits_64("-0x1.8p+2"), 0xc018000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d18p+1"), 0xc0490fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d18p+1"), 0xc00921fb54442d18);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d18p+2"), 0xc0c90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d18p+2"), 0xc01921fb54442d18);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d18p+3"), 0xc1490fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d18p+3"), 0xc02921fb54442d18);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d18p-1020"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d18p-1020"), 0x803921fb54442d18);
-        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d19p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d19p-1022"), 0x801921fb54442d19);
-        assert_bin_eq!(parse_bits_32("-0x1.b21fb54442d18p+2"), 0xc0d90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.b21fb54442d18p+2"), 0xc01b21fb54442d18);
-        assert_bin_eq!(parse_bits_32("-0x1.cp+2"), 0xc0e00000);
-        assert_bin_eq!(parse_bits_64("-0x1.cp+2"), 0xc01c000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1.d21fb54442d18p+2"), 0xc0e90fdb);
-        assert_bin_eq!(parse_bits_64("-0x1.d21fb54442d18p+2"), 0xc01d21fb54442d18);
-        assert_bin_eq!(parse_bits_32("-0x1.fffffffffffffp+1"), 0xc0800000);
-        assert_bin_eq!(parse_bits_64("-0x1.fffffffffffffp+1"), 0xc00fffffffffffff);
-        assert_bin_eq!(parse_bits_32("-0x1.fffffffffffffp+1022"), 0xff800000);
-        assert_bin_eq!(parse_bits_64("-0x1.fffffffffffffp+1022"), 0xffdfffffffffffff);
-        assert_bin_eq!(parse_bits_32("-0x1.fffffffffffffp+1023"), 0xff800000);
-        assert_bin_eq!(parse_bits_64("-0x1.fffffffffffffp+1023"), 0xffefffffffffffff);
-        assert_bin_eq!(parse_bits_32("-0x1.fffffffffffffp-51"), 0xa6800000);
-        assert_bin_eq!(parse_bits_64("-0x1.fffffffffffffp-51"), 0xbccfffffffffffff);
-        assert_bin_eq!(parse_bits_32("-0x1p+0"), 0xbf800000);
-        assert_bin_eq!(parse_bits_64("-0x1p+0"), 0xbff0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p+1"), 0xc0000000);
-        assert_bin_eq!(parse_bits_64("-0x1p+1"), 0xc000000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p+1021"), 0xff800000);
-        assert_bin_eq!(parse_bits_64("-0x1p+1021"), 0xffc0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p+1022"), 0xff800000);
-        assert_bin_eq!(parse_bits_64("-0x1p+1022"), 0xffd0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p+52"), 0xd9800000);
-        assert_bin_eq!(parse_bits_64("-0x1p+52"), 0xc330000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-1"), 0xbf000000);
-        assert_bin_eq!(parse_bits_64("-0x1p-1"), 0xbfe0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-1021"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x1p-1021"), 0x8020000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-1022"), 0x80000000);
-        assert_bin_eq!(parse_bits_64("-0x1p-1022"), 0x8010000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-2"), 0xbe800000);
-        assert_bin_eq!(parse_bits_64("-0x1p-2"), 0xbfd0000000000000);
-        assert_bin_eq!(parse_bits_32("-0x1p-52"), 0xa5800000);
-        assert_bin_eq!(parse_bits_64("-0x1p-52"), 0xbcb0000000000000);
-        assert_bin_eq!(parse_bits_32("0x0.0000000000001p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0.0000000000001p-1022"), 0x1);
-        assert_bin_eq!(parse_bits_32("0x0.0000000000002p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0.0000000000002p-1022"), 0x2);
-        assert_bin_eq!(parse_bits_32("0x0.0000000000006p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0.0000000000006p-1022"), 0x6);
-        assert_bin_eq!(parse_bits_32("0x0.28be60db9391p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0.28be60db9391p-1022"), 0x28be60db93910);
-        assert_bin_eq!(parse_bits_32("0x0.2p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0.2p-1022"), 0x2000000000000);
-        assert_bin_eq!(parse_bits_32("0x0.4p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0.4p-1022"), 0x4000000000000);
-        assert_bin_eq!(parse_bits_32("0x0.8p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0.8p-1022"), 0x8000000000000);
-        assert_bin_eq!(parse_bits_32("0x0.fffffffffffffp-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0.fffffffffffffp-1022"), 0xfffffffffffff);
-        assert_bin_eq!(parse_bits_32("0x0p+0"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x0p+0"), 0x0);
-        assert_bin_eq!(parse_bits_32("0x1.0000000000001p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x1.0000000000001p-1022"), 0x10000000000001);
-        assert_bin_eq!(parse_bits_32("0x1.3bd3cc9be45dep+5"), 0x421de9e6);
-        assert_bin_eq!(parse_bits_64("0x1.3bd3cc9be45dep+5"), 0x4043bd3cc9be45de);
-        assert_bin_eq!(parse_bits_32("0x1.40d931ff62705p+1"), 0x40206c99);
-        assert_bin_eq!(parse_bits_64("0x1.40d931ff62705p+1"), 0x40040d931ff62705);
-        assert_bin_eq!(parse_bits_32("0x1.45f306dc9c882p+1021"), 0x7f800000);
-        assert_bin_eq!(parse_bits_64("0x1.45f306dc9c882p+1021"), 0x7fc45f306dc9c882);
-        assert_bin_eq!(parse_bits_32("0x1.45f306dc9c883p-3"), 0x3e22f983);
-        assert_bin_eq!(parse_bits_64("0x1.45f306dc9c883p-3"), 0x3fc45f306dc9c883);
-        assert_bin_eq!(parse_bits_32("0x1.45f306dc9c883p-4"), 0x3da2f983);
-        assert_bin_eq!(parse_bits_64("0x1.45f306dc9c883p-4"), 0x3fb45f306dc9c883);
-        assert_bin_eq!(parse_bits_32("0x1.521fb54442d18p+2"), 0x40a90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.521fb54442d18p+2"), 0x401521fb54442d18);
-        assert_bin_eq!(parse_bits_32("0x1.6a09e667f3bcdp-1"), 0x3f3504f3);
-        assert_bin_eq!(parse_bits_64("0x1.6a09e667f3bcdp-1"), 0x3fe6a09e667f3bcd);
-        assert_bin_eq!(parse_bits_32("0x1.721fb54442d18p+2"), 0x40b90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.721fb54442d18p+2"), 0x401721fb54442d18);
-        assert_bin_eq!(parse_bits_32("0x1.8p+0"), 0x3fc00000);
-        assert_bin_eq!(parse_bits_64("0x1.8p+0"), 0x3ff8000000000000);
-        assert_bin_eq!(parse_bits_32("0x1.8p+2"), 0x40c00000);
-        assert_bin_eq!(parse_bits_64("0x1.8p+2"), 0x4018000000000000);
-        assert_bin_eq!(parse_bits_32("0x1.921fb54442d18p+1"), 0x40490fdb);
-        assert_bin_eq!(parse_bits_64("0x1.921fb54442d18p+1"), 0x400921fb54442d18);
-        assert_bin_eq!(parse_bits_32("0x1.921fb54442d18p+2"), 0x40c90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.921fb54442d18p+2"), 0x401921fb54442d18);
-        assert_bin_eq!(parse_bits_32("0x1.921fb54442d18p+3"), 0x41490fdb);
-        assert_bin_eq!(parse_bits_64("0x1.921fb54442d18p+3"), 0x402921fb54442d18);
-        assert_bin_eq!(parse_bits_32("0x1.921fb54442d18p-1020"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x1.921fb54442d18p-1020"), 0x3921fb54442d18);
-        assert_bin_eq!(parse_bits_32("0x1.921fb54442d19p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x1.921fb54442d19p-1022"), 0x1921fb54442d19);
-        assert_bin_eq!(parse_bits_32("0x1.b21fb54442d18p+2"), 0x40d90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.b21fb54442d18p+2"), 0x401b21fb54442d18);
-        assert_bin_eq!(parse_bits_32("0x1.cp+2"), 0x40e00000);
-        assert_bin_eq!(parse_bits_64("0x1.cp+2"), 0x401c000000000000);
-        assert_bin_eq!(parse_bits_32("0x1.d21fb54442d18p+2"), 0x40e90fdb);
-        assert_bin_eq!(parse_bits_64("0x1.d21fb54442d18p+2"), 0x401d21fb54442d18);
-        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp+1"), 0x40800000);
-        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp+1"), 0x400fffffffffffff);
-        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp+1022"), 0x7f800000);
-        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp+1022"), 0x7fdfffffffffffff);
-        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp+1023"), 0x7f800000);
-        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp+1023"), 0x7fefffffffffffff);
-        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp+511"), 0x7f800000);
-        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp+511"), 0x5fefffffffffffff);
-        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp-51"), 0x26800000);
-        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp-51"), 0x3ccfffffffffffff);
-        assert_bin_eq!(parse_bits_32("0x1p+0"), 0x3f800000);
-        assert_bin_eq!(parse_bits_64("0x1p+0"), 0x3ff0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p+1"), 0x40000000);
-        assert_bin_eq!(parse_bits_64("0x1p+1"), 0x4000000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p+1021"), 0x7f800000);
-        assert_bin_eq!(parse_bits_64("0x1p+1021"), 0x7fc0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p+1022"), 0x7f800000);
-        assert_bin_eq!(parse_bits_64("0x1p+1022"), 0x7fd0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p+52"), 0x59800000);
-        assert_bin_eq!(parse_bits_64("0x1p+52"), 0x4330000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-1"), 0x3f000000);
-        assert_bin_eq!(parse_bits_64("0x1p-1"), 0x3fe0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-1021"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x1p-1021"), 0x20000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-1022"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x1p-1022"), 0x10000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-2"), 0x3e800000);
-        assert_bin_eq!(parse_bits_64("0x1p-2"), 0x3fd0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-511"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x1p-511"), 0x2000000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-52"), 0x25800000);
-        assert_bin_eq!(parse_bits_64("0x1p-52"), 0x3cb0000000000000);
-        assert_bin_eq!(parse_bits_32("0x1p-537"), 0x0);
-        assert_bin_eq!(parse_bits_64("0x1p-537"), 0x1e60000000000000);
+        assert_bin_eq!(parse_bits_32("-0x0p+0").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0p+0").unwrap(), 0x8000000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.000002p-126").unwrap(), 0x80800001);
+        assert_bin_eq!(parse_bits_64("-0x1.000002p-126").unwrap(), 0xb810000020000000);
+        assert_bin_eq!(parse_bits_32("-0x1.3bd3cep+5").unwrap(), 0xc21de9e7);
+        assert_bin_eq!(parse_bits_64("-0x1.3bd3cep+5").unwrap(), 0xc043bd3ce0000000);
+        assert_bin_eq!(parse_bits_32("-0x1.45f304p+125").unwrap(), 0xfe22f982);
+        assert_bin_eq!(parse_bits_64("-0x1.45f304p+125").unwrap(), 0xc7c45f3040000000);
+        assert_bin_eq!(parse_bits_32("-0x1.45f306p-3").unwrap(), 0xbe22f983);
+        assert_bin_eq!(parse_bits_64("-0x1.45f306p-3").unwrap(), 0xbfc45f3060000000);
+        assert_bin_eq!(parse_bits_32("-0x1.45f306p-4").unwrap(), 0xbda2f983);
+        assert_bin_eq!(parse_bits_64("-0x1.45f306p-4").unwrap(), 0xbfb45f3060000000);
+        assert_bin_eq!(parse_bits_32("-0x1.45f3p-129").unwrap(), 0x80145f30);
+        assert_bin_eq!(parse_bits_64("-0x1.45f3p-129").unwrap(), 0xb7e45f3000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.521fb6p+2").unwrap(), 0xc0a90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.521fb6p+2").unwrap(), 0xc01521fb60000000);
+        assert_bin_eq!(parse_bits_32("-0x1.721fb6p+2").unwrap(), 0xc0b90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.721fb6p+2").unwrap(), 0xc01721fb60000000);
+        assert_bin_eq!(parse_bits_32("-0x1.8p+0").unwrap(), 0xbfc00000);
+        assert_bin_eq!(parse_bits_64("-0x1.8p+0").unwrap(), 0xbff8000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.8p+2").unwrap(), 0xc0c00000);
+        assert_bin_eq!(parse_bits_64("-0x1.8p+2").unwrap(), 0xc018000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.8p-147").unwrap(), 0x80000006);
+        assert_bin_eq!(parse_bits_64("-0x1.8p-147").unwrap(), 0xb6c8000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb6p+1").unwrap(), 0xc0490fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb6p+1").unwrap(), 0xc00921fb60000000);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb6p+2").unwrap(), 0xc0c90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb6p+2").unwrap(), 0xc01921fb60000000);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb6p+3").unwrap(), 0xc1490fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb6p+3").unwrap(), 0xc02921fb60000000);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb6p-124").unwrap(), 0x81c90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb6p-124").unwrap(), 0xb83921fb60000000);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb8p-126").unwrap(), 0x80c90fdc);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb8p-126").unwrap(), 0xb81921fb80000000);
+        assert_bin_eq!(parse_bits_32("-0x1.b21fb6p+2").unwrap(), 0xc0d90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.b21fb6p+2").unwrap(), 0xc01b21fb60000000);
+        assert_bin_eq!(parse_bits_32("-0x1.cp+2").unwrap(), 0xc0e00000);
+        assert_bin_eq!(parse_bits_64("-0x1.cp+2").unwrap(), 0xc01c000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.d21fb6p+2").unwrap(), 0xc0e90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.d21fb6p+2").unwrap(), 0xc01d21fb60000000);
+        assert_bin_eq!(parse_bits_32("-0x1.fffffcp-127").unwrap(), 0x807fffff);
+        assert_bin_eq!(parse_bits_64("-0x1.fffffcp-127").unwrap(), 0xb80fffffc0000000);
+        assert_bin_eq!(parse_bits_32("-0x1.fffffep+1").unwrap(), 0xc07fffff);
+        assert_bin_eq!(parse_bits_64("-0x1.fffffep+1").unwrap(), 0xc00fffffe0000000);
+        assert_bin_eq!(parse_bits_32("-0x1.fffffep+126").unwrap(), 0xfeffffff);
+        assert_bin_eq!(parse_bits_64("-0x1.fffffep+126").unwrap(), 0xc7dfffffe0000000);
+        assert_bin_eq!(parse_bits_32("-0x1.fffffep+127").unwrap(), 0xff7fffff);
+        assert_bin_eq!(parse_bits_64("-0x1.fffffep+127").unwrap(), 0xc7efffffe0000000);
+        assert_bin_eq!(parse_bits_32("-0x1.fffffep-22").unwrap(), 0xb4ffffff);
+        assert_bin_eq!(parse_bits_64("-0x1.fffffep-22").unwrap(), 0xbe9fffffe0000000);
+        assert_bin_eq!(parse_bits_32("-0x1p+0").unwrap(), 0xbf800000);
+        assert_bin_eq!(parse_bits_64("-0x1p+0").unwrap(), 0xbff0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p+1").unwrap(), 0xc0000000);
+        assert_bin_eq!(parse_bits_64("-0x1p+1").unwrap(), 0xc000000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p+125").unwrap(), 0xfe000000);
+        assert_bin_eq!(parse_bits_64("-0x1p+125").unwrap(), 0xc7c0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p+126").unwrap(), 0xfe800000);
+        assert_bin_eq!(parse_bits_64("-0x1p+126").unwrap(), 0xc7d0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p+23").unwrap(), 0xcb000000);
+        assert_bin_eq!(parse_bits_64("-0x1p+23").unwrap(), 0xc160000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-1").unwrap(), 0xbf000000);
+        assert_bin_eq!(parse_bits_64("-0x1p-1").unwrap(), 0xbfe0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-125").unwrap(), 0x81000000);
+        assert_bin_eq!(parse_bits_64("-0x1p-125").unwrap(), 0xb820000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-126").unwrap(), 0x80800000);
+        assert_bin_eq!(parse_bits_64("-0x1p-126").unwrap(), 0xb810000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-127").unwrap(), 0x80400000);
+        assert_bin_eq!(parse_bits_64("-0x1p-127").unwrap(), 0xb800000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-128").unwrap(), 0x80200000);
+        assert_bin_eq!(parse_bits_64("-0x1p-128").unwrap(), 0xb7f0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-129").unwrap(), 0x80100000);
+        assert_bin_eq!(parse_bits_64("-0x1p-129").unwrap(), 0xb7e0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-148").unwrap(), 0x80000002);
+        assert_bin_eq!(parse_bits_64("-0x1p-148").unwrap(), 0xb6b0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-149").unwrap(), 0x80000001);
+        assert_bin_eq!(parse_bits_64("-0x1p-149").unwrap(), 0xb6a0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-2").unwrap(), 0xbe800000);
+        assert_bin_eq!(parse_bits_64("-0x1p-2").unwrap(), 0xbfd0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-23").unwrap(), 0xb4000000);
+        assert_bin_eq!(parse_bits_64("-0x1p-23").unwrap(), 0xbe80000000000000);
+        assert_bin_eq!(parse_bits_32("0x0p+0").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0p+0").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_32("0x1.000002p-126").unwrap(), 0x800001);
+        assert_bin_eq!(parse_bits_64("0x1.000002p-126").unwrap(), 0x3810000020000000);
+        assert_bin_eq!(parse_bits_32("0x1.3bd3cep+5").unwrap(), 0x421de9e7);
+        assert_bin_eq!(parse_bits_64("0x1.3bd3cep+5").unwrap(), 0x4043bd3ce0000000);
+        assert_bin_eq!(parse_bits_32("0x1.40d932p+1").unwrap(), 0x40206c99);
+        assert_bin_eq!(parse_bits_64("0x1.40d932p+1").unwrap(), 0x40040d9320000000);
+        assert_bin_eq!(parse_bits_32("0x1.45f304p+125").unwrap(), 0x7e22f982);
+        assert_bin_eq!(parse_bits_64("0x1.45f304p+125").unwrap(), 0x47c45f3040000000);
+        assert_bin_eq!(parse_bits_32("0x1.45f306p-3").unwrap(), 0x3e22f983);
+        assert_bin_eq!(parse_bits_64("0x1.45f306p-3").unwrap(), 0x3fc45f3060000000);
+        assert_bin_eq!(parse_bits_32("0x1.45f306p-4").unwrap(), 0x3da2f983);
+        assert_bin_eq!(parse_bits_64("0x1.45f306p-4").unwrap(), 0x3fb45f3060000000);
+        assert_bin_eq!(parse_bits_32("0x1.45f3p-129").unwrap(), 0x145f30);
+        assert_bin_eq!(parse_bits_64("0x1.45f3p-129").unwrap(), 0x37e45f3000000000);
+        assert_bin_eq!(parse_bits_32("0x1.521fb6p+2").unwrap(), 0x40a90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.521fb6p+2").unwrap(), 0x401521fb60000000);
+        assert_bin_eq!(parse_bits_32("0x1.6a09e6p-1").unwrap(), 0x3f3504f3);
+        assert_bin_eq!(parse_bits_64("0x1.6a09e6p-1").unwrap(), 0x3fe6a09e60000000);
+        assert_bin_eq!(parse_bits_32("0x1.6a09e6p-75").unwrap(), 0x1a3504f3);
+        assert_bin_eq!(parse_bits_64("0x1.6a09e6p-75").unwrap(), 0x3b46a09e60000000);
+        assert_bin_eq!(parse_bits_32("0x1.721fb6p+2").unwrap(), 0x40b90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.721fb6p+2").unwrap(), 0x401721fb60000000);
+        assert_bin_eq!(parse_bits_32("0x1.8p+0").unwrap(), 0x3fc00000);
+        assert_bin_eq!(parse_bits_64("0x1.8p+0").unwrap(), 0x3ff8000000000000);
+        assert_bin_eq!(parse_bits_32("0x1.8p+2").unwrap(), 0x40c00000);
+        assert_bin_eq!(parse_bits_64("0x1.8p+2").unwrap(), 0x4018000000000000);
+        assert_bin_eq!(parse_bits_32("0x1.8p-147").unwrap(), 0x6);
+        assert_bin_eq!(parse_bits_64("0x1.8p-147").unwrap(), 0x36c8000000000000);
+        assert_bin_eq!(parse_bits_32("0x1.921fb6p+1").unwrap(), 0x40490fdb);
+        assert_bin_eq!(parse_bits_64("0x1.921fb6p+1").unwrap(), 0x400921fb60000000);
+        assert_bin_eq!(parse_bits_32("0x1.921fb6p+2").unwrap(), 0x40c90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.921fb6p+2").unwrap(), 0x401921fb60000000);
+        assert_bin_eq!(parse_bits_32("0x1.921fb6p+3").unwrap(), 0x41490fdb);
+        assert_bin_eq!(parse_bits_64("0x1.921fb6p+3").unwrap(), 0x402921fb60000000);
+        assert_bin_eq!(parse_bits_32("0x1.921fb6p-124").unwrap(), 0x1c90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.921fb6p-124").unwrap(), 0x383921fb60000000);
+        assert_bin_eq!(parse_bits_32("0x1.921fb8p-126").unwrap(), 0xc90fdc);
+        assert_bin_eq!(parse_bits_64("0x1.921fb8p-126").unwrap(), 0x381921fb80000000);
+        assert_bin_eq!(parse_bits_32("0x1.b21fb6p+2").unwrap(), 0x40d90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.b21fb6p+2").unwrap(), 0x401b21fb60000000);
+        assert_bin_eq!(parse_bits_32("0x1.cp+2").unwrap(), 0x40e00000);
+        assert_bin_eq!(parse_bits_64("0x1.cp+2").unwrap(), 0x401c000000000000);
+        assert_bin_eq!(parse_bits_32("0x1.d21fb6p+2").unwrap(), 0x40e90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.d21fb6p+2").unwrap(), 0x401d21fb60000000);
+        assert_bin_eq!(parse_bits_32("0x1.fffffcp-127").unwrap(), 0x7fffff);
+        assert_bin_eq!(parse_bits_64("0x1.fffffcp-127").unwrap(), 0x380fffffc0000000);
+        assert_bin_eq!(parse_bits_32("0x1.fffffep+1").unwrap(), 0x407fffff);
+        assert_bin_eq!(parse_bits_64("0x1.fffffep+1").unwrap(), 0x400fffffe0000000);
+        assert_bin_eq!(parse_bits_32("0x1.fffffep+126").unwrap(), 0x7effffff);
+        assert_bin_eq!(parse_bits_64("0x1.fffffep+126").unwrap(), 0x47dfffffe0000000);
+        assert_bin_eq!(parse_bits_32("0x1.fffffep+127").unwrap(), 0x7f7fffff);
+        assert_bin_eq!(parse_bits_64("0x1.fffffep+127").unwrap(), 0x47efffffe0000000);
+        assert_bin_eq!(parse_bits_32("0x1.fffffep+63").unwrap(), 0x5f7fffff);
+        assert_bin_eq!(parse_bits_64("0x1.fffffep+63").unwrap(), 0x43efffffe0000000);
+        assert_bin_eq!(parse_bits_32("0x1.fffffep-22").unwrap(), 0x34ffffff);
+        assert_bin_eq!(parse_bits_64("0x1.fffffep-22").unwrap(), 0x3e9fffffe0000000);
+        assert_bin_eq!(parse_bits_32("0x1p+0").unwrap(), 0x3f800000);
+        assert_bin_eq!(parse_bits_64("0x1p+0").unwrap(), 0x3ff0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p+1").unwrap(), 0x40000000);
+        assert_bin_eq!(parse_bits_64("0x1p+1").unwrap(), 0x4000000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p+125").unwrap(), 0x7e000000);
+        assert_bin_eq!(parse_bits_64("0x1p+125").unwrap(), 0x47c0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p+126").unwrap(), 0x7e800000);
+        assert_bin_eq!(parse_bits_64("0x1p+126").unwrap(), 0x47d0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p+23").unwrap(), 0x4b000000);
+        assert_bin_eq!(parse_bits_64("0x1p+23").unwrap(), 0x4160000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-1").unwrap(), 0x3f000000);
+        assert_bin_eq!(parse_bits_64("0x1p-1").unwrap(), 0x3fe0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-125").unwrap(), 0x1000000);
+        assert_bin_eq!(parse_bits_64("0x1p-125").unwrap(), 0x3820000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-126").unwrap(), 0x800000);
+        assert_bin_eq!(parse_bits_64("0x1p-126").unwrap(), 0x3810000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-127").unwrap(), 0x400000);
+        assert_bin_eq!(parse_bits_64("0x1p-127").unwrap(), 0x3800000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-128").unwrap(), 0x200000);
+        assert_bin_eq!(parse_bits_64("0x1p-128").unwrap(), 0x37f0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-129").unwrap(), 0x100000);
+        assert_bin_eq!(parse_bits_64("0x1p-129").unwrap(), 0x37e0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-148").unwrap(), 0x2);
+        assert_bin_eq!(parse_bits_64("0x1p-148").unwrap(), 0x36b0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-149").unwrap(), 0x1);
+        assert_bin_eq!(parse_bits_64("0x1p-149").unwrap(), 0x36a0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-2").unwrap(), 0x3e800000);
+        assert_bin_eq!(parse_bits_64("0x1p-2").unwrap(), 0x3fd0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-23").unwrap(), 0x34000000);
+        assert_bin_eq!(parse_bits_64("0x1p-23").unwrap(), 0x3e80000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-63").unwrap(), 0x20000000);
+        assert_bin_eq!(parse_bits_64("0x1p-63").unwrap(), 0x3c00000000000000);
+        assert_bin_eq!(parse_bits_32("-0x0.0000000000001p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0.0000000000001p-1022").unwrap(), 0x8000000000000001);
+        assert_bin_eq!(parse_bits_32("-0x0.0000000000002p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0.0000000000002p-1022").unwrap(), 0x8000000000000002);
+        assert_bin_eq!(parse_bits_32("-0x0.0000000000006p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0.0000000000006p-1022").unwrap(), 0x8000000000000006);
+        assert_bin_eq!(parse_bits_32("-0x0.28be60db9391p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0.28be60db9391p-1022").unwrap(), 0x80028be60db93910);
+        assert_bin_eq!(parse_bits_32("-0x0.2p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0.2p-1022").unwrap(), 0x8002000000000000);
+        assert_bin_eq!(parse_bits_32("-0x0.4p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0.4p-1022").unwrap(), 0x8004000000000000);
+        assert_bin_eq!(parse_bits_32("-0x0.8p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0.8p-1022").unwrap(), 0x8008000000000000);
+        assert_bin_eq!(parse_bits_32("-0x0.fffffffffffffp-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0.fffffffffffffp-1022").unwrap(), 0x800fffffffffffff);
+        assert_bin_eq!(parse_bits_32("-0x0p+0").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x0p+0").unwrap(), 0x8000000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.0000000000001p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x1.0000000000001p-1022").unwrap(), 0x8010000000000001);
+        assert_bin_eq!(parse_bits_32("-0x1.3bd3cc9be45dep+5").unwrap(), 0xc21de9e6);
+        assert_bin_eq!(parse_bits_64("-0x1.3bd3cc9be45dep+5").unwrap(), 0xc043bd3cc9be45de);
+        assert_bin_eq!(parse_bits_32("-0x1.45f306dc9c882p+1021").unwrap(), 0xff800000);
+        assert_bin_eq!(parse_bits_64("-0x1.45f306dc9c882p+1021").unwrap(), 0xffc45f306dc9c882);
+        assert_bin_eq!(parse_bits_32("-0x1.45f306dc9c883p-3").unwrap(), 0xbe22f983);
+        assert_bin_eq!(parse_bits_64("-0x1.45f306dc9c883p-3").unwrap(), 0xbfc45f306dc9c883);
+        assert_bin_eq!(parse_bits_32("-0x1.45f306dc9c883p-4").unwrap(), 0xbda2f983);
+        assert_bin_eq!(parse_bits_64("-0x1.45f306dc9c883p-4").unwrap(), 0xbfb45f306dc9c883);
+        assert_bin_eq!(parse_bits_32("-0x1.521fb54442d18p+2").unwrap(), 0xc0a90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.521fb54442d18p+2").unwrap(), 0xc01521fb54442d18);
+        assert_bin_eq!(parse_bits_32("-0x1.721fb54442d18p+2").unwrap(), 0xc0b90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.721fb54442d18p+2").unwrap(), 0xc01721fb54442d18);
+        assert_bin_eq!(parse_bits_32("-0x1.8p+0").unwrap(), 0xbfc00000);
+        assert_bin_eq!(parse_bits_64("-0x1.8p+0").unwrap(), 0xbff8000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.8p+2").unwrap(), 0xc0c00000);
+        assert_bin_eq!(parse_bits_64("-0x1.8p+2").unwrap(), 0xc018000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d18p+1").unwrap(), 0xc0490fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d18p+1").unwrap(), 0xc00921fb54442d18);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d18p+2").unwrap(), 0xc0c90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d18p+2").unwrap(), 0xc01921fb54442d18);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d18p+3").unwrap(), 0xc1490fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d18p+3").unwrap(), 0xc02921fb54442d18);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d18p-1020").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d18p-1020").unwrap(), 0x803921fb54442d18);
+        assert_bin_eq!(parse_bits_32("-0x1.921fb54442d19p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x1.921fb54442d19p-1022").unwrap(), 0x801921fb54442d19);
+        assert_bin_eq!(parse_bits_32("-0x1.b21fb54442d18p+2").unwrap(), 0xc0d90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.b21fb54442d18p+2").unwrap(), 0xc01b21fb54442d18);
+        assert_bin_eq!(parse_bits_32("-0x1.cp+2").unwrap(), 0xc0e00000);
+        assert_bin_eq!(parse_bits_64("-0x1.cp+2").unwrap(), 0xc01c000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1.d21fb54442d18p+2").unwrap(), 0xc0e90fdb);
+        assert_bin_eq!(parse_bits_64("-0x1.d21fb54442d18p+2").unwrap(), 0xc01d21fb54442d18);
+        assert_bin_eq!(parse_bits_32("-0x1.fffffffffffffp+1").unwrap(), 0xc0800000);
+        assert_bin_eq!(parse_bits_64("-0x1.fffffffffffffp+1").unwrap(), 0xc00fffffffffffff);
+        assert_bin_eq!(parse_bits_32("-0x1.fffffffffffffp+1022").unwrap(), 0xff800000);
+        assert_bin_eq!(parse_bits_64("-0x1.fffffffffffffp+1022").unwrap(), 0xffdfffffffffffff);
+        assert_bin_eq!(parse_bits_32("-0x1.fffffffffffffp+1023").unwrap(), 0xff800000);
+        assert_bin_eq!(parse_bits_64("-0x1.fffffffffffffp+1023").unwrap(), 0xffefffffffffffff);
+        assert_bin_eq!(parse_bits_32("-0x1.fffffffffffffp-51").unwrap(), 0xa6800000);
+        assert_bin_eq!(parse_bits_64("-0x1.fffffffffffffp-51").unwrap(), 0xbccfffffffffffff);
+        assert_bin_eq!(parse_bits_32("-0x1p+0").unwrap(), 0xbf800000);
+        assert_bin_eq!(parse_bits_64("-0x1p+0").unwrap(), 0xbff0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p+1").unwrap(), 0xc0000000);
+        assert_bin_eq!(parse_bits_64("-0x1p+1").unwrap(), 0xc000000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p+1021").unwrap(), 0xff800000);
+        assert_bin_eq!(parse_bits_64("-0x1p+1021").unwrap(), 0xffc0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p+1022").unwrap(), 0xff800000);
+        assert_bin_eq!(parse_bits_64("-0x1p+1022").unwrap(), 0xffd0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p+52").unwrap(), 0xd9800000);
+        assert_bin_eq!(parse_bits_64("-0x1p+52").unwrap(), 0xc330000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-1").unwrap(), 0xbf000000);
+        assert_bin_eq!(parse_bits_64("-0x1p-1").unwrap(), 0xbfe0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-1021").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x1p-1021").unwrap(), 0x8020000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-1022").unwrap(), 0x80000000);
+        assert_bin_eq!(parse_bits_64("-0x1p-1022").unwrap(), 0x8010000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-2").unwrap(), 0xbe800000);
+        assert_bin_eq!(parse_bits_64("-0x1p-2").unwrap(), 0xbfd0000000000000);
+        assert_bin_eq!(parse_bits_32("-0x1p-52").unwrap(), 0xa5800000);
+        assert_bin_eq!(parse_bits_64("-0x1p-52").unwrap(), 0xbcb0000000000000);
+        assert_bin_eq!(parse_bits_32("0x0.0000000000001p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0.0000000000001p-1022").unwrap(), 0x1);
+        assert_bin_eq!(parse_bits_32("0x0.0000000000002p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0.0000000000002p-1022").unwrap(), 0x2);
+        assert_bin_eq!(parse_bits_32("0x0.0000000000006p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0.0000000000006p-1022").unwrap(), 0x6);
+        assert_bin_eq!(parse_bits_32("0x0.28be60db9391p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0.28be60db9391p-1022").unwrap(), 0x28be60db93910);
+        assert_bin_eq!(parse_bits_32("0x0.2p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0.2p-1022").unwrap(), 0x2000000000000);
+        assert_bin_eq!(parse_bits_32("0x0.4p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0.4p-1022").unwrap(), 0x4000000000000);
+        assert_bin_eq!(parse_bits_32("0x0.8p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0.8p-1022").unwrap(), 0x8000000000000);
+        assert_bin_eq!(parse_bits_32("0x0.fffffffffffffp-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0.fffffffffffffp-1022").unwrap(), 0xfffffffffffff);
+        assert_bin_eq!(parse_bits_32("0x0p+0").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x0p+0").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_32("0x1.0000000000001p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x1.0000000000001p-1022").unwrap(), 0x10000000000001);
+        assert_bin_eq!(parse_bits_32("0x1.3bd3cc9be45dep+5").unwrap(), 0x421de9e6);
+        assert_bin_eq!(parse_bits_64("0x1.3bd3cc9be45dep+5").unwrap(), 0x4043bd3cc9be45de);
+        assert_bin_eq!(parse_bits_32("0x1.40d931ff62705p+1").unwrap(), 0x40206c99);
+        assert_bin_eq!(parse_bits_64("0x1.40d931ff62705p+1").unwrap(), 0x40040d931ff62705);
+        assert_bin_eq!(parse_bits_32("0x1.45f306dc9c882p+1021").unwrap(), 0x7f800000);
+        assert_bin_eq!(parse_bits_64("0x1.45f306dc9c882p+1021").unwrap(), 0x7fc45f306dc9c882);
+        assert_bin_eq!(parse_bits_32("0x1.45f306dc9c883p-3").unwrap(), 0x3e22f983);
+        assert_bin_eq!(parse_bits_64("0x1.45f306dc9c883p-3").unwrap(), 0x3fc45f306dc9c883);
+        assert_bin_eq!(parse_bits_32("0x1.45f306dc9c883p-4").unwrap(), 0x3da2f983);
+        assert_bin_eq!(parse_bits_64("0x1.45f306dc9c883p-4").unwrap(), 0x3fb45f306dc9c883);
+        assert_bin_eq!(parse_bits_32("0x1.521fb54442d18p+2").unwrap(), 0x40a90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.521fb54442d18p+2").unwrap(), 0x401521fb54442d18);
+        assert_bin_eq!(parse_bits_32("0x1.6a09e667f3bcdp-1").unwrap(), 0x3f3504f3);
+        assert_bin_eq!(parse_bits_64("0x1.6a09e667f3bcdp-1").unwrap(), 0x3fe6a09e667f3bcd);
+        assert_bin_eq!(parse_bits_32("0x1.721fb54442d18p+2").unwrap(), 0x40b90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.721fb54442d18p+2").unwrap(), 0x401721fb54442d18);
+        assert_bin_eq!(parse_bits_32("0x1.8p+0").unwrap(), 0x3fc00000);
+        assert_bin_eq!(parse_bits_64("0x1.8p+0").unwrap(), 0x3ff8000000000000);
+        assert_bin_eq!(parse_bits_32("0x1.8p+2").unwrap(), 0x40c00000);
+        assert_bin_eq!(parse_bits_64("0x1.8p+2").unwrap(), 0x4018000000000000);
+        assert_bin_eq!(parse_bits_32("0x1.921fb54442d18p+1").unwrap(), 0x40490fdb);
+        assert_bin_eq!(parse_bits_64("0x1.921fb54442d18p+1").unwrap(), 0x400921fb54442d18);
+        assert_bin_eq!(parse_bits_32("0x1.921fb54442d18p+2").unwrap(), 0x40c90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.921fb54442d18p+2").unwrap(), 0x401921fb54442d18);
+        assert_bin_eq!(parse_bits_32("0x1.921fb54442d18p+3").unwrap(), 0x41490fdb);
+        assert_bin_eq!(parse_bits_64("0x1.921fb54442d18p+3").unwrap(), 0x402921fb54442d18);
+        assert_bin_eq!(parse_bits_32("0x1.921fb54442d18p-1020").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x1.921fb54442d18p-1020").unwrap(), 0x3921fb54442d18);
+        assert_bin_eq!(parse_bits_32("0x1.921fb54442d19p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x1.921fb54442d19p-1022").unwrap(), 0x1921fb54442d19);
+        assert_bin_eq!(parse_bits_32("0x1.b21fb54442d18p+2").unwrap(), 0x40d90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.b21fb54442d18p+2").unwrap(), 0x401b21fb54442d18);
+        assert_bin_eq!(parse_bits_32("0x1.cp+2").unwrap(), 0x40e00000);
+        assert_bin_eq!(parse_bits_64("0x1.cp+2").unwrap(), 0x401c000000000000);
+        assert_bin_eq!(parse_bits_32("0x1.d21fb54442d18p+2").unwrap(), 0x40e90fdb);
+        assert_bin_eq!(parse_bits_64("0x1.d21fb54442d18p+2").unwrap(), 0x401d21fb54442d18);
+        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp+1").unwrap(), 0x40800000);
+        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp+1").unwrap(), 0x400fffffffffffff);
+        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp+1022").unwrap(), 0x7f800000);
+        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp+1022").unwrap(), 0x7fdfffffffffffff);
+        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp+1023").unwrap(), 0x7f800000);
+        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp+1023").unwrap(), 0x7fefffffffffffff);
+        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp+511").unwrap(), 0x7f800000);
+        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp+511").unwrap(), 0x5fefffffffffffff);
+        assert_bin_eq!(parse_bits_32("0x1.fffffffffffffp-51").unwrap(), 0x26800000);
+        assert_bin_eq!(parse_bits_64("0x1.fffffffffffffp-51").unwrap(), 0x3ccfffffffffffff);
+        assert_bin_eq!(parse_bits_32("0x1p+0").unwrap(), 0x3f800000);
+        assert_bin_eq!(parse_bits_64("0x1p+0").unwrap(), 0x3ff0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p+1").unwrap(), 0x40000000);
+        assert_bin_eq!(parse_bits_64("0x1p+1").unwrap(), 0x4000000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p+1021").unwrap(), 0x7f800000);
+        assert_bin_eq!(parse_bits_64("0x1p+1021").unwrap(), 0x7fc0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p+1022").unwrap(), 0x7f800000);
+        assert_bin_eq!(parse_bits_64("0x1p+1022").unwrap(), 0x7fd0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p+52").unwrap(), 0x59800000);
+        assert_bin_eq!(parse_bits_64("0x1p+52").unwrap(), 0x4330000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-1").unwrap(), 0x3f000000);
+        assert_bin_eq!(parse_bits_64("0x1p-1").unwrap(), 0x3fe0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-1021").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x1p-1021").unwrap(), 0x20000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-1022").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x1p-1022").unwrap(), 0x10000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-2").unwrap(), 0x3e800000);
+        assert_bin_eq!(parse_bits_64("0x1p-2").unwrap(), 0x3fd0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-511").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x1p-511").unwrap(), 0x2000000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-52").unwrap(), 0x25800000);
+        assert_bin_eq!(parse_bits_64("0x1p-52").unwrap(), 0x3cb0000000000000);
+        assert_bin_eq!(parse_bits_32("0x1p-537").unwrap(), 0x0);
+        assert_bin_eq!(parse_bits_64("0x1p-537").unwrap(), 0x1e60000000000000);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_bits_32(0x41400000), "0x1.8p+3");
+        assert_eq!(format_bits_32(0x80000000), "-0x0p+0");
+        assert_eq!(format_bits_32(0x3f800001), "0x1.000002p+0");
+        assert_eq!(format_bits_32(0x00000001), "0x1p-149");
+        assert_eq!(format_bits_32(0x00600000), "0x1.8p-127");
+        assert_eq!(format_bits_32(0x7f7fffff), "0x1.fffffep+127");
+        assert_eq!(format_bits_64(0x3fb999999999999a), "0x1.999999999999ap-4");
+        assert_eq!(format_bits_64(0x0000000000000001), "0x1p-1074");
+        assert_eq!(format_bits_64(0xc000000000000000), "-0x1p+1");
     }
 }
@@ -1,108 +1,221 @@
-use std::{mem, str, iter};
+use std::{mem, str};
+use std::rc::Rc;
+use std::cell::RefCell;
 use std::num::Wrapping;
 use std::collections::HashMap;
 
 use module::{Module, FunctionIndex, ImportIndex, ExportIndex, AsBytes, FunctionType};
+use profile::Profiler;
+use coverage::Coverage;
+use debug::{Debugger, FrameState, PauseReason};
+use watch::{Access, Watcher, MemoryAccess};
+use trace::Tracer;
+use replay::{ImportCall, Recorder, Replayer};
+use align::{AlignmentChecker, AlignmentFault};
+use sanitize::{Sanitizer, UninitializedRead};
+use heap::HeapProfiler;
 use types::{Type, Dynamic, Sign, Size, IntType, FloatType};
 use ops::{
     BlockOp, Block, NormalOp, MemImm,
     IntBinOp, IntCmpOp, IntUnOp, FloatBinOp, FloatUnOp, FloatCmpOp};
 
-pub struct Memory(Vec<u8>);
+/// Size of the unit in which linear memory is shared between forked instances.
+const PAGE_SIZE: usize = 4096;
+
+/// Number of pages in each block of the page table.
+const BLOCK_PAGES: usize = 1024;
+
+type Page = [u8; PAGE_SIZE];
+
+type PageBlock = Vec<Rc<Page>>;
+
+/// Linear memory, kept as a two-level table of 4 KiB pages.
+///
+/// The table, its blocks and the pages are all reference counted, so
+/// cloning a `Memory` only bumps the count on the top of the table. A write
+/// copies just what it goes through that is still shared: the top level (one
+/// entry per 4 MiB), the block of 1024 page pointers, and the page itself.
+/// All-zero pages start out shared with each other as well.
+#[derive(Clone)]
+pub struct Memory {
+    blocks: Rc<Vec<Rc<PageBlock>>>,
+    len: usize,
+}
 
 impl Memory {
+    pub fn new(len: usize) -> Memory {
+        let mut m = Memory {
+            blocks: Rc::new(Vec::new()),
+            len: 0,
+        };
+        m.resize(len);
+        m
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Grows the memory to `len` bytes. New bytes read as zero.
+    pub fn resize(&mut self, len: usize) {
+        assert!(len >= self.len);
+        let page_count = len.div_ceil(PAGE_SIZE);
+        let mut have = self.blocks.iter().map(|b| b.len()).sum::<usize>();
+        if page_count > have {
+            let zero = Rc::new([0; PAGE_SIZE]);
+            let blocks = Rc::make_mut(&mut self.blocks);
+            while have < page_count {
+                if have % BLOCK_PAGES == 0 {
+                    blocks.push(Rc::new(Vec::new()));
+                }
+                let block = Rc::make_mut(blocks.last_mut().unwrap());
+                let n = ::std::cmp::min(BLOCK_PAGES - block.len(), page_count - have);
+                block.resize(block.len() + n, zero.clone());
+                have += n;
+            }
+        }
+        self.len = len;
+    }
+
+    /// Number of pages whose storage is shared with another `Memory`.
+    pub fn shared_page_count(&self) -> usize {
+        let all_shared = Rc::strong_count(&self.blocks) > 1;
+        self.blocks.iter().map(|b| {
+            if all_shared || Rc::strong_count(b) > 1 {
+                b.len()
+            } else {
+                b.iter().filter(|p| Rc::strong_count(p) > 1).count()
+            }
+        }).sum()
+    }
+
+    fn page(&self, addr: usize) -> &Page {
+        let page = addr / PAGE_SIZE;
+        &self.blocks[page / BLOCK_PAGES][page % BLOCK_PAGES]
+    }
+
+    /// Returns the page holding `addr`, first copying whatever part of the
+    /// table on the way to it is still shared.
+    fn page_mut(&mut self, addr: usize) -> &mut Page {
+        let page = addr / PAGE_SIZE;
+        let block = Rc::make_mut(&mut Rc::make_mut(&mut self.blocks)[page / BLOCK_PAGES]);
+        Rc::make_mut(&mut block[page % BLOCK_PAGES])
+    }
+
+    /// Copies `buf.len()` bytes starting at `addr` into `buf`. Panics if the
+    /// range isn't inside memory.
+    pub fn read(&self, addr: usize, buf: &mut [u8]) {
+        assert!(addr + buf.len() <= self.len);
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = self.load_u8(addr + i);
+        }
+    }
+
+    /// Copies `data` into memory starting at `addr`. Panics if the range
+    /// isn't inside memory.
+    pub fn write(&mut self, addr: usize, data: &[u8]) {
+        assert!(addr + data.len() <= self.len);
+        let mut addr = addr;
+        let mut data = data;
+        while !data.is_empty() {
+            let offset = addr % PAGE_SIZE;
+            let n = ::std::cmp::min(PAGE_SIZE - offset, data.len());
+            self.page_mut(addr)[offset..offset + n].copy_from_slice(&data[..n]);
+            addr += n;
+            data = &data[n..];
+        }
+    }
+
+    // The byte accessors below don't bounds-check: every caller has already
+    // checked the whole access against `len` once.
     fn store_u8(&mut self, addr: usize, val: u8) {
-        self.0[addr] = val;
+        self.page_mut(addr)[addr % PAGE_SIZE] = val;
     }
 
     fn store_u16(&mut self, addr: usize, val: u16) {
-        self.0[addr + 0] = (val >> 0*8) as u8;
-        self.0[addr + 1] = (val >> 1*8) as u8;
+        self.store_u8(addr + 0, (val >> 0*8) as u8);
+        self.store_u8(addr + 1, (val >> 1*8) as u8);
     }
 
     fn store_u32(&mut self, addr: usize, val: u32) {
-        self.0[addr + 0] = (val >> 0*8) as u8;
-        self.0[addr + 1] = (val >> 1*8) as u8;
-        self.0[addr + 2] = (val >> 2*8) as u8;
-        self.0[addr + 3] = (val >> 3*8) as u8;
+        self.store_u8(addr + 0, (val >> 0*8) as u8);
+        self.store_u8(addr + 1, (val >> 1*8) as u8);
+        self.store_u8(addr + 2, (val >> 2*8) as u8);
+        self.store_u8(addr + 3, (val >> 3*8) as u8);
     }
 
     fn store_u64(&mut self, addr: usize, val: u64) {
-        self.0[addr + 0] = (val >> 0*8) as u8;
-        self.0[addr + 1] = (val >> 1*8) as u8;
-        self.0[addr + 2] = (val >> 2*8) as u8;
-        self.0[addr + 3] = (val >> 3*8) as u8;
-        self.0[addr + 4] = (val >> 4*8) as u8;
-        self.0[addr + 5] = (val >> 5*8) as u8;
-        self.0[addr + 6] = (val >> 6*8) as u8;
-        self.0[addr + 7] = (val >> 7*8) as u8;
+        self.store_u8(addr + 0, (val >> 0*8) as u8);
+        self.store_u8(addr + 1, (val >> 1*8) as u8);
+        self.store_u8(addr + 2, (val >> 2*8) as u8);
+        self.store_u8(addr + 3, (val >> 3*8) as u8);
+        self.store_u8(addr + 4, (val >> 4*8) as u8);
+        self.store_u8(addr + 5, (val >> 5*8) as u8);
+        self.store_u8(addr + 6, (val >> 6*8) as u8);
+        self.store_u8(addr + 7, (val >> 7*8) as u8);
     }
 
     fn load_u8(&self, addr: usize) -> u8 {
-        self.0[addr]
+        self.page(addr)[addr % PAGE_SIZE]
     }
 
     fn load_u16(&self, addr: usize) -> u16 {
-        ((self.0[addr + 0] as u16) << 0*8) |
-        ((self.0[addr + 1] as u16) << 1*8)
+        ((self.load_u8(addr + 0) as u16) << 0*8) |
+        ((self.load_u8(addr + 1) as u16) << 1*8)
     }
 
     fn load_u32(&self, addr: usize) -> u32 {
-        ((self.0[addr + 0] as u32) << 0*8) |
-        ((self.0[addr + 1] as u32) << 1*8) |
-        ((self.0[addr + 2] as u32) << 2*8) |
-        ((self.0[addr + 3] as u32) << 3*8)
+        ((self.load_u8(addr + 0) as u32) << 0*8) |
+        ((self.load_u8(addr + 1) as u32) << 1*8) |
+        ((self.load_u8(addr + 2) as u32) << 2*8) |
+        ((self.load_u8(addr + 3) as u32) << 3*8)
     }
 
     fn load_u64(&self, addr: usize) -> u64 {
-        ((self.0[addr + 0] as u64) << 0*8) |
-        ((self.0[addr + 1] as u64) << 1*8) |
-        ((self.0[addr + 2] as u64) << 2*8) |
-        ((self.0[addr + 3] as u64) << 3*8) |
-        ((self.0[addr + 4] as u64) << 4*8) |
-        ((self.0[addr + 5] as u64) << 5*8) |
-        ((self.0[addr + 6] as u64) << 6*8) |
-        ((self.0[addr + 7] as u64) << 7*8)
+        ((self.load_u8(addr + 0) as u64) << 0*8) |
+        ((self.load_u8(addr + 1) as u64) << 1*8) |
+        ((self.load_u8(addr + 2) as u64) << 2*8) |
+        ((self.load_u8(addr + 3) as u64) << 3*8) |
+        ((self.load_u8(addr + 4) as u64) << 4*8) |
+        ((self.load_u8(addr + 5) as u64) << 5*8) |
+        ((self.load_u8(addr + 6) as u64) << 6*8) |
+        ((self.load_u8(addr + 7) as u64) << 7*8)
     }
 
-    fn load_int(&self, addr: u32, inttype: IntType, sign: Sign, size: Size, memimm: MemImm) -> Dynamic {
+    fn load_int(&self, addr: usize, inttype: IntType, sign: Sign, size: Size) -> Dynamic {
         match size {
-            Size::I8 => extend_u8(self.load_u8((addr + memimm.offset) as usize), inttype, sign),
-            Size::I16 => extend_u16(self.load_u16((addr + memimm.offset) as usize), inttype, sign),
-            Size::I32 => extend_u32(self.load_u32((addr + memimm.offset) as usize), inttype, sign),
-            Size::I64 => extend_u64(self.load_u64((addr + memimm.offset) as usize), inttype, sign),
+            Size::I8 => extend_u8(self.load_u8(addr), inttype, sign),
+            Size::I16 => extend_u16(self.load_u16(addr), inttype, sign),
+            Size::I32 => extend_u32(self.load_u32(addr), inttype, sign),
+            Size::I64 => extend_u64(self.load_u64(addr), inttype, sign),
         }
     }
 
-    fn load_float(&self, addr: u32, floattype: FloatType, memimm: MemImm) -> Dynamic {
+    fn load_float(&self, addr: usize, floattype: FloatType) -> Dynamic {
         match floattype {
-            FloatType::Float32 => Dynamic::Float32(unsafe {
-                mem::transmute(self.load_u32((addr + memimm.offset) as usize))
-            }),
-            FloatType::Float64 => Dynamic::Float64(unsafe {
-                mem::transmute(self.load_u64((addr + memimm.offset) as usize))
-            })
+            FloatType::Float32 => Dynamic::Float32(f32::from_bits(self.load_u32(addr))),
+            FloatType::Float64 => Dynamic::Float64(f64::from_bits(self.load_u64(addr))),
         }
     }
 
-    fn store_int(&mut self, addr: u32, value: Dynamic, size: Size, memimm: MemImm) {
+    fn store_int(&mut self, addr: usize, value: Dynamic, size: Size) {
         match size {
-            Size::I8 => self.store_u8((addr + memimm.offset) as usize, (value.to_int().0 & ((1 << 8) - 1)) as u8),
-            Size::I16 => self.store_u16((addr + memimm.offset) as usize, (value.to_int().0 & ((1 << 16) - 1)) as u16),
-            Size::I32 => self.store_u32((addr + memimm.offset) as usize, (value.to_int().0 & ((1 << 32) - 1)) as u32),
-            Size::I64 => self.store_u64((addr + memimm.offset) as usize, value.to_int().0),
+            Size::I8 => self.store_u8(addr, (value.to_int().0 & ((1 << 8) - 1)) as u8),
+            Size::I16 => self.store_u16(addr, (value.to_int().0 & ((1 << 16) - 1)) as u16),
+            Size::I32 => self.store_u32(addr, (value.to_int().0 & ((1 << 32) - 1)) as u32),
+            Size::I64 => self.store_u64(addr, value.to_int().0),
         }
     }
 
-    fn store_float(&mut self, addr: u32, value: Dynamic, floattype: FloatType, memimm: MemImm) {
+    fn store_float(&mut self, addr: usize, value: Dynamic, floattype: FloatType) {
         assert!(value.get_type() == floattype.to_type());
         match floattype {
-            FloatType::Float32 => self.store_u32((addr + memimm.offset) as usize, unsafe {
-                mem::transmute(value.to_f32())
-            }),
-            FloatType::Float64 => self.store_u64((addr + memimm.offset) as usize, unsafe {
-                mem::transmute(value.to_f64())
-            })
+            FloatType::Float32 => self.store_u32(addr, value.to_f32().to_bits()),
+            FloatType::Float64 => self.store_u64(addr, value.to_f64().to_bits()),
         }
     }
 
@@ -110,7 +223,7 @@ impl Memory {
 
 #[test]
 fn test_store_load() {
-    let mut m = Memory(iter::repeat(0).take(1024).collect::<Vec<_>>());
+    let mut m = Memory::new(1024);
 
     for i in 0..10 {
         m.store_u32(i*4, i as u32);
@@ -122,9 +235,95 @@ fn test_store_load() {
     }
 }
 
+#[test]
+fn test_fork_copy_on_write() {
+    let module = Module::<Vec<u8>>::new();
+    let mut parent = Instance::new(&module, HashMap::new());
+    parent.memory.store_u32(0, 1);
+
+    let mut child = parent.fork();
+    assert!(Rc::ptr_eq(&child.memory.blocks, &parent.memory.blocks));
+    assert_eq!(child.memory.len(), parent.memory.len());
+    assert_eq!(child.memory.load_u32(0), 1);
+    assert_eq!(child.memory.shared_page_count(), 16);
+
+    child.memory.store_u32(0, 2);
+    child.memory.store_u32(PAGE_SIZE - 2, 3);
+    assert_eq!(parent.memory.load_u32(0), 1);
+    assert_eq!(parent.memory.load_u32(PAGE_SIZE - 2), 0);
+    assert_eq!(child.memory.load_u32(0), 2);
+    assert_eq!(child.memory.load_u32(PAGE_SIZE - 2), 3);
+    assert_eq!(child.memory.shared_page_count(), 14);
+}
+
+#[test]
+fn test_canonicalize_nan() {
+    let nan32 = f32::from_bits(0xffc0_1234);
+    let nan64 = f64::from_bits(0xfff0_0000_0000_0001);
+    let bits32 = |v: Dynamic| v.to_f32().to_bits();
+    let bits64 = |v: Dynamic| v.to_f64().to_bits();
+    assert_eq!(bits32(Dynamic::Float32(nan32).canonicalize_nan()), 0x7fc0_0000);
+    assert_eq!(bits64(Dynamic::Float64(nan64).canonicalize_nan()), 0x7ff8_0000_0000_0000);
+    assert_eq!(bits32(Dynamic::Float32(-1.5).canonicalize_nan()), 0xbfc0_0000);
+}
+
+#[test]
+fn test_deterministic_nans() {
+    let m = ::wat::parse_module("(module
+        (func (param f32 f32) (result f32) (f32.add (get_local 0) (get_local 1)))
+        (func (param f32) (result f32) (f32.sqrt (get_local 0)))
+        (func (param f32) (result f64) (f64.promote/f32 (get_local 0)))
+        (func (param f32) (result f32) (f32.neg (get_local 0)))
+        (func (param f32) (result i32)
+            (drop (f32.store (i32.const 0) (get_local 0)))
+            (i32.reinterpret/f32 (f32.load (i32.const 0))))
+        (memory 1))").unwrap();
+    let nan = Dynamic::Float32(f32::from_bits(0xffa0_0001));
+    let run = |deterministic: bool, func: usize, args: &[Dynamic]| {
+        let mut instance = Instance::new(&m, HashMap::new());
+        instance.deterministic = deterministic;
+        match instance.invoke(FunctionIndex(func), args) {
+            InterpResult::Value(Some(Dynamic::Float32(v))) => v.to_bits() as u64,
+            InterpResult::Value(Some(Dynamic::Float64(v))) => v.to_bits(),
+            InterpResult::Value(Some(v)) => v.to_u32() as u64,
+            r => panic!("unexpected result {:?}", r),
+        }
+    };
+
+    assert_eq!(run(false, 0, &[nan, Dynamic::Float32(1.0)]), 0xffe0_0001);
+    assert_eq!(run(true, 0, &[nan, Dynamic::Float32(1.0)]), 0x7fc0_0000);
+    assert_eq!(run(false, 1, &[nan]), 0xffe0_0001);
+    assert_eq!(run(true, 1, &[nan]), 0x7fc0_0000);
+    assert_eq!(run(false, 2, &[nan]), 0xfffc_0000_2000_0000);
+    assert_eq!(run(true, 2, &[nan]), 0x7ff8_0000_0000_0000);
+    assert_eq!(run(false, 3, &[nan]), 0x7fa0_0001);
+    assert_eq!(run(true, 3, &[nan]), 0x7fc0_0000);
+    // Loads, stores and reinterpretations move the bits through either way.
+    assert_eq!(run(true, 4, &[nan]), 0xffa0_0001);
+}
+
+#[test]
+fn test_offset_out_of_bounds() {
+    let trap = |body: &str| {
+        let text = format!("(module (memory 1) (func (result i32) {}))", body);
+        let m = ::wat::parse_module(&text).unwrap();
+        let mut instance = Instance::new(&m, HashMap::new());
+        let result = instance.invoke(FunctionIndex(0), &[]);
+        (result, instance.trap_message)
+    };
+    let oob = (InterpResult::Trap, Some("out of bounds memory access".to_owned()));
+    assert_eq!(trap("(i32.load offset=8 (i32.const 65530))"), oob);
+    assert_eq!(trap("(i32.load offset=4294967295 (i32.const 1))"), oob);
+    assert_eq!(trap("(i32.load offset=4 (i32.const 65528))").0, InterpResult::Value(Some(Dynamic::from_u32(0))));
+}
+
 pub trait BoundInstance {
     fn invoke_export(&mut self, func: ExportIndex, args: &[Dynamic]) -> InterpResult;
     fn export_by_name_and_type(&self, name: &[u8], ty: FunctionType<&[u8]>) -> ExportIndex;
+    /// Why the last call to `invoke_export` trapped, if known.
+    fn trap_message(&self) -> Option<String> {
+        None
+    }
 }
 
 pub struct Instance<'a, B: AsBytes + 'a> {
@@ -132,7 +331,41 @@ pub struct Instance<'a, B: AsBytes + 'a> {
     pub module: &'a Module<B>,
     pub call_stack_depth: usize,
     pub bound_imports: Vec<(usize, ExportIndex)>,
-    pub bound_instances: Vec<Box<BoundInstance>>,
+    /// Shared with any forks of this instance, so the host sees one set of
+    /// import state no matter which fork calls it.
+    pub bound_instances: Vec<Rc<RefCell<Box<BoundInstance + 'a>>>>,
+    /// When set, every NaN produced by a float operation is replaced by the
+    /// canonical NaN, so results don't depend on the host FPU's NaN payloads.
+    /// Loads, stores and reinterpretations still move bits through unchanged.
+    pub deterministic: bool,
+    /// Set to `Some` to collect call counts, op counts and timings for every
+    /// function this instance runs, including calls out to imports.
+    pub profiler: Option<Profiler>,
+    /// Set to `Some` to record which ops and branch directions run.
+    pub coverage: Option<Coverage>,
+    /// Set to `Some` to stop at breakpoints and steps; see `Debugger`.
+    pub debugger: Option<Debugger>,
+    /// Set to `Some` to be called back on loads and stores of watched
+    /// addresses.
+    pub watcher: Option<Watcher>,
+    /// Set to `Some` to write a JSON Lines trace of calls, ops and traps.
+    pub tracer: Option<Tracer>,
+    /// Set to `Some` to log every import call and its result.
+    pub recorder: Option<Recorder>,
+    /// Set to `Some` to answer import calls from a recording instead of
+    /// calling the bound imports.
+    pub replayer: Option<Replayer>,
+    /// Set to `Some` to check loads and stores against their alignment
+    /// hints.
+    pub alignment: Option<AlignmentChecker>,
+    /// Set to `Some` to report loads of memory that was never written.
+    pub sanitizer: Option<Sanitizer>,
+    /// Set to `Some` to track the blocks the module's allocator hands out.
+    pub heap: Option<HeapProfiler>,
+    /// Why the last call trapped, in the wording of the spec tests, such as
+    /// "integer divide by zero". `None` if a host import trapped without
+    /// saying why.
+    pub trap_message: Option<String>,
 }
 
 fn read_u32(data: &[u8]) -> u32 {
@@ -152,21 +385,23 @@ impl<'a, B: AsBytes> BoundInstance for Instance<'a, B> {
     fn invoke_export(&mut self, func: ExportIndex, args: &[Dynamic]) -> InterpResult {
         self.invoke(self.module.exports[func.0].function_index, args)
     }
+    fn trap_message(&self) -> Option<String> {
+        self.trap_message.clone()
+    }
     fn export_by_name_and_type(&self, name: &[u8], ty: FunctionType<&[u8]>) -> ExportIndex {
         self.module.find_export(name, ty).unwrap()
     }
 }
 
 impl<'a, B: AsBytes> Instance<'a, B> {
-    pub fn new(module: &'a Module<B>, imports: HashMap<&[u8], Box<BoundInstance>>) -> Instance<'a, B> {
-        let mut memory = Vec::with_capacity(module.memory_info.initial_64k_pages * 64 * 1024);
-        memory.resize(module.memory_info.initial_64k_pages * 64 * 1024, 0);
+    pub fn new(module: &'a Module<B>, imports: HashMap<&[u8], Box<BoundInstance + 'a>>) -> Instance<'a, B> {
+        let mut memory = Memory::new(module.memory_info.initial_64k_pages * 64 * 1024);
 
         for m in &module.memory_chunks {
             let data = m.data.as_bytes();
             let newlen = ::std::cmp::max(m.offset + data.len(), memory.len());
-            memory.resize(newlen, 0);
-            memory[m.offset..m.offset + data.len()].copy_from_slice(data);
+            memory.resize(newlen);
+            memory.write(m.offset, data);
         }
 
         let mut bound_instances = Vec::new();
@@ -175,39 +410,147 @@ impl<'a, B: AsBytes> Instance<'a, B> {
 
         for (k, v) in imports {
             instance_indices.insert(k, bound_instances.len());
-            bound_instances.push(v);
+            bound_instances.push(Rc::new(RefCell::new(v)));
         }
 
         let bound_imports = module.imports.iter().map(|i| {
             let instance_index = *instance_indices.get(i.module_name.as_bytes())
                 .unwrap_or_else(|| panic!("expected module {}", str::from_utf8(i.module_name.as_bytes())
                     .unwrap_or("<bad_utf8>")));
-            let export_index = bound_instances[instance_index]
+            let export_index = bound_instances[instance_index].borrow()
                 .export_by_name_and_type(i.function_name.as_bytes(), module.types[i.function_type.0].as_ref());
             (instance_index, export_index)
         }).collect::<Vec<_>>();
 
         Instance {
-            memory: Memory(memory),
+            memory: memory,
             module: module,
             call_stack_depth: 0,
             bound_imports: bound_imports,
             bound_instances: bound_instances,
+            deterministic: false,
+            profiler: None,
+            coverage: None,
+            debugger: None,
+            watcher: None,
+            tracer: None,
+            recorder: None,
+            replayer: None,
+            alignment: None,
+            sanitizer: None,
+            heap: None,
+            trap_message: None,
         }
     }
 
+    /// Creates an independent instance that starts from this one's current
+    /// state, without re-running data initialization or the start function.
+    ///
+    /// Linear memory is shared copy-on-write: forking takes one reference to
+    /// the page table whatever the memory size, and a write on either side
+    /// later copies only the table entries and the 4 KiB page on its path.
+    /// The memory bytes themselves are never copied up front.
+    ///
+    /// Bound imports are not forked. Both instances call into the very same
+    /// host objects, so any state those hold (counters, buffers, their own
+    /// memories) is shared, and changes made through one instance are seen
+    /// by the other. The fork starts without any of the profiling,
+    /// debugging, tracing or record/replay attachments.
+    pub fn fork(&self) -> Instance<'a, B> {
+        assert_eq!(self.call_stack_depth, 0);
+        Instance {
+            memory: self.memory.clone(),
+            module: self.module,
+            call_stack_depth: 0,
+            bound_imports: self.bound_imports.clone(),
+            bound_instances: self.bound_instances.clone(),
+            deterministic: self.deterministic,
+            profiler: None,
+            coverage: None,
+            debugger: None,
+            watcher: None,
+            tracer: None,
+            recorder: None,
+            replayer: None,
+            alignment: None,
+            sanitizer: None,
+            heap: None,
+            trap_message: None,
+        }
+    }
+
+    fn call_import(&mut self, index: ImportIndex, args: &[Dynamic]) -> InterpResult {
+        let import = &self.module.imports[index.0];
+        let module_name = String::from_utf8_lossy(import.module_name.as_bytes());
+        let function_name = String::from_utf8_lossy(import.function_name.as_bytes());
+
+        if let Some(ref mut replayer) = self.replayer {
+            return match replayer.replay(&module_name, &function_name, args) {
+                Some(call) => {
+                    if call.result == InterpResult::Trap {
+                        self.trap_message = call.trap_message.clone();
+                    }
+                    call.result
+                }
+                None => {
+                    self.trap_message = Some(format!("no recorded call to {}.{}", module_name, function_name));
+                    InterpResult::Trap
+                }
+            };
+        }
+
+        let (module, export) = self.bound_imports[index.0];
+        println!("module {} index {}", module, export.0);
+        let res = self.bound_instances[module].borrow_mut().invoke_export(export, args);
+        if res == InterpResult::Trap {
+            self.trap_message = self.bound_instances[module].borrow().trap_message();
+        }
+
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.record(ImportCall {
+                module: module_name.into_owned(),
+                name: function_name.into_owned(),
+                args: args.to_vec(),
+                result: res,
+                trap_message: if res == InterpResult::Trap { self.trap_message.clone() } else { None },
+            });
+        }
+
+        res
+    }
+
     pub fn invoke(&mut self, func: FunctionIndex, args: &[Dynamic]) -> InterpResult {
         println!("running {}",
             self.module.find_name(func)
                 .and_then(|n| str::from_utf8(n).ok())
                 .unwrap_or("<unknown>"));
 
+        if self.call_stack_depth == 0 {
+            self.trap_message = None;
+        }
         if self.call_stack_depth > 200 {
+            self.trap_message = Some("call stack exhausted".to_owned());
             return InterpResult::Trap;
         }
 
         self.call_stack_depth += 1;
 
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.enter_function(func);
+        }
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.record_call(func);
+        }
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.enter(func);
+        }
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.call(func, args);
+        }
+        if let Some(ref mut heap) = self.heap {
+            heap.enter(func, args);
+        }
+
         let ty = &self.module.types[self.module.functions[func.0].0];
         if args.len() != ty.param_types.as_bytes().len() {
             panic!("expected {} args, but got {}", ty.param_types.as_bytes().len(), args.len());
@@ -229,10 +572,23 @@ impl<'a, B: AsBytes> Instance<'a, B> {
 
         struct Context<'b, 'a: 'b, B: AsBytes + 'a> {
             instance: &'b mut Instance<'a, B>,
+            func: FunctionIndex,
             locals: Vec<Dynamic>,
             stack: Vec<Option<Dynamic>>,
         }
 
+        impl<'b, 'a: 'b, B: AsBytes + 'a> Context<'b, 'a, B> {
+            fn float_result(&self, v: Dynamic) -> Dynamic {
+                if self.instance.deterministic { v.canonicalize_nan() } else { v }
+            }
+
+            fn record_branch(&mut self, offset: usize, direction: usize) {
+                if let Some(ref mut coverage) = self.instance.coverage {
+                    coverage.record_branch(self.func, offset, direction);
+                }
+            }
+        }
+
         #[derive(Debug)]
         enum Res {
             Value(Option<Dynamic>),
@@ -257,8 +613,183 @@ impl<'a, B: AsBytes> Instance<'a, B> {
 
         fn run_instr<'a, B: AsBytes>(context: &'a mut Context<B>, op: &BlockOp) -> Res {
             println!("run {}", op);
-            let res = match op {
-                &BlockOp::Block(Block::Block(ref ops)) => {
+            if let Some(ref mut profiler) = context.instance.profiler {
+                profiler.count_op();
+            }
+            if let Some(ref mut coverage) = context.instance.coverage {
+                coverage.record_op(context.func, op.offset());
+            }
+            let pause = context.instance.debugger.as_mut().and_then(|d| d.before_op(op.offset()));
+            if let Some(reason) = pause {
+                debug_pause(context, op, reason, None);
+            }
+
+            let consumed = match (op, context.instance.tracer.as_ref()) {
+                (&BlockOp::Normal(_, ref normal), Some(tracer)) if tracer.traces(context.func) => {
+                    let len = context.stack.len();
+                    Some(context.stack[len - normal.arity()..].to_vec())
+                }
+                _ => None
+            };
+            if let (&BlockOp::Block(offset, ref block), Some(tracer)) = (op, context.instance.tracer.as_mut()) {
+                let (kind, arity) = match block {
+                    &Block::Block(_) => ("block", 0),
+                    &Block::Loop(_) => ("loop", 0),
+                    &Block::If(..) => ("if", 1),
+                };
+                let len = context.stack.len();
+                tracer.enter_block(context.func, offset, kind, &context.stack[len - arity..]);
+            }
+
+            let res = exec_instr(context, op);
+
+            if let Res::Trap = res {
+                if context.instance.debugger.as_mut().map_or(false, |d| d.report_trap()) {
+                    debug_pause(context, op, PauseReason::Trap, None);
+                }
+            }
+            if let (Some(consumed), &BlockOp::Normal(offset, ref normal)) = (consumed, op) {
+                let tracer = context.instance.tracer.as_mut().unwrap();
+                match res {
+                    Res::Trap => tracer.trap(context.func, offset, normal),
+                    Res::Value(v) => tracer.op(context.func, offset, normal, &consumed, v),
+                    Res::Branch(..) | Res::Return(_) => tracer.op(context.func, offset, normal, &consumed, None),
+                }
+            }
+            if let (&BlockOp::Block(offset, _), Some(tracer)) = (op, context.instance.tracer.as_mut()) {
+                match res {
+                    Res::Trap => {}
+                    Res::Value(v) => tracer.end_block(context.func, offset, v),
+                    Res::Branch(..) | Res::Return(_) => tracer.end_block(context.func, offset, None),
+                }
+            }
+            println!("res {} -> {:?}", op, res);
+            res
+        }
+
+        fn debug_pause<B: AsBytes>(context: &mut Context<B>, op: &BlockOp, reason: PauseReason,
+                access: Option<MemoryAccess>) {
+            let instance = &mut *context.instance;
+            let module = instance.module;
+            let func = context.func;
+            let op_text = format!("{}", op);
+            let frame = FrameState {
+                function_name: module.find_name(func),
+                // Blocks display with their contents; only show the header.
+                op: op_text.lines().next().unwrap_or("").to_owned(),
+                locals: &context.locals,
+                local_names: module.names.get(func.0)
+                    .map(|n| n.local_names.iter().map(|l| l.as_bytes()).collect())
+                    .unwrap_or_default(),
+                stack: &context.stack,
+                memory: &instance.memory,
+            };
+            instance.debugger.as_mut().unwrap().pause(reason, frame, access);
+        }
+
+        /// Reports a load or store of `width` bytes at `address` to the
+        /// watcher and debugger if either watches it, and to the tracer.
+        /// Called before the access happens.
+        fn watch_access<B: AsBytes>(context: &mut Context<B>, instr: &BlockOp, address: usize, width: usize,
+                kind: Access, new: Option<u64>) {
+            let to_watcher = context.instance.watcher.as_ref().map_or(false, |w| w.hit(address, width, kind));
+            let to_debugger = context.instance.debugger.as_ref().map_or(false, |d| d.watch_hit(address, width, kind));
+            let to_tracer = context.instance.tracer.as_ref().map_or(false, |t| t.traces(context.func));
+            if !to_watcher && !to_debugger && !to_tracer {
+                return;
+            }
+
+            let mut buf = [0u8; 8];
+            context.instance.memory.read(address, &mut buf[..width]);
+            let access = MemoryAccess {
+                function: context.func,
+                offset: instr.offset(),
+                address: address,
+                width: width,
+                kind: kind,
+                old: buf.iter().rev().fold(0, |v, &b| v << 8 | b as u64),
+                new: new,
+            };
+
+            if to_watcher {
+                context.instance.watcher.as_mut().unwrap().report(&access);
+            }
+            if to_tracer {
+                context.instance.tracer.as_mut().unwrap().memory_access(access);
+            }
+            if to_debugger {
+                debug_pause(context, instr, PauseReason::Watchpoint, Some(access));
+            }
+        }
+
+        /// Traps, recording why.
+        fn trap<B: AsBytes>(context: &mut Context<B>, message: String) -> Res {
+            context.instance.trap_message = Some(message);
+            Res::Trap
+        }
+
+        /// Computes the address a load or store of `width` bytes touches,
+        /// or None if any of it lies outside linear memory.
+        fn effective_address<B: AsBytes>(context: &Context<B>, addr: u32, memimm: MemImm, width: usize)
+                -> Option<usize> {
+            let ea = addr as u64 + memimm.offset as u64;
+            if ea + width as u64 <= context.instance.memory.len() as u64 {
+                Some(ea as usize)
+            } else {
+                None
+            }
+        }
+
+        /// Checks a load or store against its alignment hint if alignment
+        /// checking is on. Returns true if the access should trap.
+        fn check_alignment<B: AsBytes>(context: &mut Context<B>, instr: &BlockOp, address: usize, width: usize,
+                memimm: MemImm, kind: Access) -> bool {
+            let error = match context.instance.alignment {
+                Some(_) => AlignmentChecker::check(address, width, memimm.log_of_alignment),
+                None => None,
+            };
+            match error {
+                Some(error) => context.instance.alignment.as_mut().unwrap().report(&AlignmentFault {
+                    function: context.func,
+                    offset: instr.offset(),
+                    address: address,
+                    width: width,
+                    log_of_alignment: memimm.log_of_alignment,
+                    kind: kind,
+                    error: error,
+                }),
+                None => false,
+            }
+        }
+
+        /// Reports a load of `width` bytes at `address` to the sanitizer if
+        /// any of them are uninitialized.
+        fn sanitize_load<B: AsBytes>(context: &mut Context<B>, instr: &BlockOp, address: usize, width: usize) {
+            let func = context.func;
+            if let Some(ref mut sanitizer) = context.instance.sanitizer {
+                let uninitialized = sanitizer.check(address, width);
+                if uninitialized != 0 {
+                    sanitizer.report(&UninitializedRead {
+                        function: func,
+                        offset: instr.offset(),
+                        address: address,
+                        width: width,
+                        uninitialized: uninitialized,
+                    });
+                }
+            }
+        }
+
+        /// Marks the `width` bytes a store writes at `address` as initialized.
+        fn sanitize_store<B: AsBytes>(context: &mut Context<B>, address: usize, width: usize) {
+            if let Some(ref mut sanitizer) = context.instance.sanitizer {
+                sanitizer.initialize(address, width);
+            }
+        }
+
+        fn exec_instr<'a, B: AsBytes>(context: &'a mut Context<B>, instr: &BlockOp) -> Res {
+            match instr {
+                &BlockOp::Block(_, Block::Block(ref ops)) => {
                     let stack_depth = context.stack.len();
                     let res = match run_block(context, ops) {
                         Res::Branch(0, val) => Res::Value(val),
@@ -268,7 +799,7 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                     context.stack.resize(stack_depth, None);
                     res
                 }
-                &BlockOp::Block(Block::Loop(ref ops)) => {
+                &BlockOp::Block(_, Block::Loop(ref ops)) => {
                     loop {
                         let stack_depth = context.stack.len();
                         match run_block(context, ops) {
@@ -297,8 +828,9 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                         context.stack.resize(stack_depth, None);
                     }
                 }
-                &BlockOp::Block(Block::If(ref then, ref otherwise)) => {
+                &BlockOp::Block(offset, Block::If(ref then, ref otherwise)) => {
                     let cond = context.stack.pop().unwrap().unwrap();
+                    context.record_branch(offset, if cond.to_u32() != 0 { 0 } else { 1 });
                     let stack_depth = context.stack.len();
                     let res = match run_block(context, if cond.to_u32() != 0 { then } else { otherwise }) {
                         Res::Branch(0, val) => Res::Value(val),
@@ -308,7 +840,7 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                     context.stack.resize(stack_depth, None);
                     res
                 }
-                &BlockOp::Normal(ref op) => match op {
+                &BlockOp::Normal(offset, ref op) => match op {
                     &NormalOp::Nop => Res::Value(None),
                     &NormalOp::Select => {
                         let cond = context.stack.pop().unwrap().unwrap();
@@ -332,8 +864,10 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                             None
                         };
                         if cond.to_u32() != 0 {
+                            context.record_branch(offset, 1);
                             Res::Branch(relative_depth, val)
                         } else {
+                            context.record_branch(offset, 0);
                             Res::Value(None)
                         }
                     }
@@ -344,7 +878,9 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                         } else {
                             None
                         };
-                        let relative_depth = if value >= target_data.len() / 4 {
+                        let target_count = target_data.len() / 4;
+                        context.record_branch(offset, ::std::cmp::min(value, target_count));
+                        let relative_depth = if value >= target_count {
                             default
                         } else {
                             read_u32(&target_data[value * 4.. value * 4 + 4])
@@ -359,7 +895,7 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                         }
                     }
                     &NormalOp::Unreachable => {
-                        Res::Trap
+                        trap(context, "unreachable executed".to_owned())
                     }
                     &NormalOp::Drop => {
                         context.stack.pop().unwrap();
@@ -385,6 +921,9 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                     }
                     &NormalOp::Call{argument_count, index} => {
                         let stack_len = context.stack.len();
+                        if let Some(ref mut heap) = context.instance.heap {
+                            heap.call_site(instr.offset());
+                        }
                         let res = {
                             let args = context.stack[stack_len - argument_count as usize..]
                                 .iter().map(|e| e.unwrap()).collect::<Vec<_>>();
@@ -401,7 +940,7 @@ impl<'a, B: AsBytes> Instance<'a, B> {
 
                         let ti = table_index as usize;
                         if ti >= context.instance.module.table.len() {
-                            Res::Trap
+                            trap(context, format!("undefined table index {}", table_index as i32))
                         } else {
                             let index = context.instance.module.table[ti];
 
@@ -409,6 +948,9 @@ impl<'a, B: AsBytes> Instance<'a, B> {
 
                             if context.instance.module.functions[index.0] == type_index {
                                 let stack_len = context.stack.len();
+                                if let Some(ref mut heap) = context.instance.heap {
+                                    heap.call_site(instr.offset());
+                                }
                                 let res = {
                                     let args = context.stack[stack_len - argument_count as usize..]
                                         .iter().map(|e| e.unwrap()).collect::<Vec<_>>();
@@ -420,7 +962,7 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                                 context.stack.drain(stack_len - argument_count as usize - 1..);
                                 res
                             } else {
-                                Res::Trap
+                                trap(context, "indirect call signature mismatch".to_owned())
                             }
                         }
                     }
@@ -432,9 +974,14 @@ impl<'a, B: AsBytes> Instance<'a, B> {
 
                             println!("import {} of {}", index.0, context.instance.bound_imports.len());
 
-                            let (module, index) = context.instance.bound_imports[index.0];
-                            println!("module {} index {}", module, index.0);
-                            match context.instance.bound_instances[module].invoke_export(index, args.as_slice()) {
+                            if let Some(ref mut profiler) = context.instance.profiler {
+                                profiler.enter_import(index);
+                            }
+                            let res = context.instance.call_import(index, args.as_slice());
+                            if let Some(ref mut profiler) = context.instance.profiler {
+                                profiler.exit();
+                            }
+                            match res {
                                 InterpResult::Value(v) => Res::Value(v),
                                 InterpResult::Trap => return Res::Trap,
                             }
@@ -444,54 +991,87 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                     }
                     &NormalOp::IntLoad(ty, sign, size, memimm) => {
                         let addr = context.stack.pop().unwrap().unwrap().to_u32();
-                        if addr as usize + size.to_int()/8 <= context.instance.memory.0.len() {
-                            Res::Value(Some(context.instance.memory.load_int(addr, ty, sign, size, memimm)))
-                        } else {
-                            Res::Trap
+                        let width = size.to_int()/8;
+                        let ea = match effective_address(context, addr, memimm, width) {
+                            Some(ea) => ea,
+                            None => return trap(context, "out of bounds memory access".to_owned()),
+                        };
+                        if check_alignment(context, instr, ea, width, memimm, Access::Read) {
+                            return trap(context, "unaligned memory access".to_owned());
                         }
+                        watch_access(context, instr, ea, width, Access::Read, None);
+                        sanitize_load(context, instr, ea, width);
+                        Res::Value(Some(context.instance.memory.load_int(ea, ty, sign, size)))
                     }
                     &NormalOp::FloatLoad(ty, memimm) => {
                         let addr = context.stack.pop().unwrap().unwrap().to_u32();
-                        if addr as usize + ty.to_type().size().to_int()/8 <= context.instance.memory.0.len() {
-                            Res::Value(Some(context.instance.memory.load_float(addr, ty, memimm)))
-                        } else {
-                            Res::Trap
+                        let width = ty.to_type().size().to_int()/8;
+                        let ea = match effective_address(context, addr, memimm, width) {
+                            Some(ea) => ea,
+                            None => return trap(context, "out of bounds memory access".to_owned()),
+                        };
+                        if check_alignment(context, instr, ea, width, memimm, Access::Read) {
+                            return trap(context, "unaligned memory access".to_owned());
                         }
+                        watch_access(context, instr, ea, width, Access::Read, None);
+                        sanitize_load(context, instr, ea, width);
+                        Res::Value(Some(context.instance.memory.load_float(ea, ty)))
                     }
                     &NormalOp::IntStore(ty, size, memimm) => {
                         let value = context.stack.pop().unwrap().unwrap();
                         let addr = context.stack.pop().unwrap().unwrap().to_u32();
-                        if addr as usize + size.to_int()/8 <= context.instance.memory.0.len() {
-                            assert!(value.get_type() == ty.to_type());
-                            context.instance.memory.store_int(addr, value, size, memimm);
-                            Res::Value(Some(value))
-                        } else {
-                            Res::Trap
+                        assert!(value.get_type() == ty.to_type());
+                        let width = size.to_int()/8;
+                        let ea = match effective_address(context, addr, memimm, width) {
+                            Some(ea) => ea,
+                            None => return trap(context, "out of bounds memory access".to_owned()),
+                        };
+                        if check_alignment(context, instr, ea, width, memimm, Access::Write) {
+                            return trap(context, "unaligned memory access".to_owned());
                         }
+                        let bits = if width == 8 { value.to_int().0 } else { value.to_int().0 & ((1 << (width * 8)) - 1) };
+                        watch_access(context, instr, ea, width, Access::Write, Some(bits));
+                        context.instance.memory.store_int(ea, value, size);
+                        sanitize_store(context, ea, width);
+                        Res::Value(Some(value))
                     }
                     &NormalOp::FloatStore(ty, memimm) => {
                         let value = context.stack.pop().unwrap().unwrap();
                         let addr = context.stack.pop().unwrap().unwrap().to_u32();
-                        if addr as usize + ty.to_type().size().to_int()/8 <= context.instance.memory.0.len() {
-                            context.instance.memory.store_float(addr, value, ty, memimm);
-                            Res::Value(Some(value))
-                        } else {
-                            Res::Trap
+                        let width = ty.to_type().size().to_int()/8;
+                        let ea = match effective_address(context, addr, memimm, width) {
+                            Some(ea) => ea,
+                            None => return trap(context, "out of bounds memory access".to_owned()),
+                        };
+                        if check_alignment(context, instr, ea, width, memimm, Access::Write) {
+                            return trap(context, "unaligned memory access".to_owned());
                         }
+                        let bits = match value {
+                            Dynamic::Float32(v) => v.to_bits() as u64,
+                            Dynamic::Float64(v) => v.to_bits(),
+                            _ => panic!()
+                        };
+                        watch_access(context, instr, ea, width, Access::Write, Some(bits));
+                        context.instance.memory.store_float(ea, value, ty);
+                        sanitize_store(context, ea, width);
+                        Res::Value(Some(value))
                     }
 
                     &NormalOp::CurrentMemory => {
-                        Res::Value(Some(Dynamic::from_u32(context.instance.memory.0.len() as u32 / 0x10000)))
+                        Res::Value(Some(Dynamic::from_u32(context.instance.memory.len() as u32 / 0x10000)))
                     }
                     &NormalOp::GrowMemory => {
-                        let len = context.instance.memory.0.len();
+                        let len = context.instance.memory.len();
                         let extra_pages = context.stack.pop().unwrap().unwrap().to_u32() as usize;
                         let new_len = len + extra_pages * 0x10000;
                         if new_len < 0x8000_0000 {
-                            context.instance.memory.0.resize(new_len, 0);
+                            context.instance.memory.resize(new_len);
+                            if let Some(ref mut sanitizer) = context.instance.sanitizer {
+                                sanitizer.resize(new_len);
+                            }
                             Res::Value(Some(Dynamic::from_u32(len as u32 / 0x10000)))
                         } else {
-                            Res::Trap
+                            trap(context, "memory size exceeds implementation limit".to_owned())
                         }
                     }
 
@@ -500,7 +1080,8 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                         let a = context.stack.pop().unwrap().unwrap();
                         match interp_int_bin(inttype, intbinop, a, b) {
                             InterpResult::Value(v) => Res::Value(v),
-                            InterpResult::Trap => Res::Trap,
+                            InterpResult::Trap if b.to_int().0 == 0 => trap(context, "integer divide by zero".to_owned()),
+                            InterpResult::Trap => trap(context, "integer overflow".to_owned()),
                         }
                     }
                     &NormalOp::IntCmp(inttype, intcmpop) => {
@@ -519,11 +1100,11 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                     &NormalOp::FloatBin(floattype, floatbinop) => {
                         let b = context.stack.pop().unwrap().unwrap();
                         let a = context.stack.pop().unwrap().unwrap();
-                        Res::Value(Some(interp_float_bin(floattype, floatbinop, a, b)))
+                        Res::Value(Some(context.float_result(interp_float_bin(floattype, floatbinop, a, b))))
                     }
                     &NormalOp::FloatUn(floattype, floatunop) => {
                         let a = context.stack.pop().unwrap().unwrap();
-                        Res::Value(Some(interp_float_un(floattype, floatunop, a)))
+                        Res::Value(Some(context.float_result(interp_float_un(floattype, floatunop, a))))
                     }
                     &NormalOp::FloatCmp(floattype, floatcmpop) => {
                         let b = context.stack.pop().unwrap().unwrap();
@@ -533,7 +1114,8 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                     &NormalOp::FloatToInt(floattype, inttype, sign) => {
                         let a = context.stack.pop().unwrap().unwrap();
                         match interp_float_to_int(floattype, inttype, sign, a) {
-                            None => Res::Trap,
+                            None if a.to_float().is_nan() => trap(context, "invalid conversion to integer".to_owned()),
+                            None => trap(context, "integer overflow".to_owned()),
                             Some(v) => Res::Value(Some(v))
                         }
                     }
@@ -551,17 +1133,15 @@ impl<'a, B: AsBytes> Instance<'a, B> {
                     }
                     &NormalOp::FloatConvert(floattype) => {
                         let a = context.stack.pop().unwrap().unwrap();
-                        Res::Value(Some(interp_float_convert(floattype, a)))
+                        Res::Value(Some(context.float_result(interp_float_convert(floattype, a))))
                     }
                     &NormalOp::Reinterpret(type_from, type_to) => {
                         let a = context.stack.pop().unwrap().unwrap();
                         Res::Value(Some(interp_reinterpret(type_from, type_to, a)))
                     }
                 }
-            };
-            println!("res {} -> {:?}", op, res);
-            res
-        };
+            }
+        }
 
         fn verify_return_type(ty: Option<Type>, v: Option<Dynamic>) -> InterpResult {
             match ty {
@@ -580,6 +1160,7 @@ impl<'a, B: AsBytes> Instance<'a, B> {
         let res = {
             let mut context = Context {
                 instance: self,
+                func: func,
                 locals: locals,
                 stack: Vec::new(),
             };
@@ -593,6 +1174,19 @@ impl<'a, B: AsBytes> Instance<'a, B> {
 
         self.call_stack_depth -= 1;
 
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.exit();
+        }
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.exit();
+        }
+        if let (Some(ref mut tracer), InterpResult::Value(v)) = (self.tracer.as_mut(), res) {
+            tracer.ret(func, v);
+        }
+        if let Some(ref mut heap) = self.heap {
+            heap.exit(func, res);
+        }
+
         res
     }
 }
@@ -616,19 +1210,126 @@ fn u64_from_i64(val: Wrapping<i64>) -> Wrapping<u64> {
 }
 
 
-fn copysign_f32(a: f32, b: f32) -> f32 {
-    if (unsafe { mem::transmute::<f32, u32>(b) } & 0x8000_0000u32) == 0 {
-        a.abs()
-    } else {
-        (-a.abs())
+/// Defines the bit-exact float operations for one float width. Every op
+/// works in the operand's own width, and NaN results are chosen explicitly
+/// rather than left to the host FPU: a NaN operand is propagated with its
+/// quiet bit set, and a NaN produced from non-NaN operands is the canonical
+/// positive NaN.
+macro_rules! float_ops {
+    ($t:ident, $sign:expr, $quiet:expr, $canonical:expr, $exact:expr,
+     $bin:ident, $un:ident, $nearest:ident) => {
+        fn $nearest(a: $t) -> $t {
+            // Values this large are already integers, and NaN/infinity round
+            // to themselves.
+            if !(a.abs() < $exact) {
+                return a;
+            }
+            let r = if (a - a.trunc()).abs() == 0.5 {
+                2.0 * (a / 2.0).round()
+            } else {
+                a.round()
+            };
+            // Keep the sign for results that round to zero, e.g. -0.4 => -0.
+            $t::from_bits(r.to_bits() & !$sign | a.to_bits() & $sign)
+        }
+
+        fn $bin(op: FloatBinOp, a: $t, b: $t) -> $t {
+            let (ab, bb) = (a.to_bits(), b.to_bits());
+            match op {
+                FloatBinOp::Copysign => return $t::from_bits(ab & !$sign | bb & $sign),
+                _ => {}
+            }
+            if a.is_nan() {
+                return $t::from_bits(ab | $quiet);
+            }
+            if b.is_nan() {
+                return $t::from_bits(bb | $quiet);
+            }
+            let res = match op {
+                FloatBinOp::Add => a + b,
+                FloatBinOp::Sub => a - b,
+                FloatBinOp::Mul => a * b,
+                FloatBinOp::Div => a / b,
+                FloatBinOp::Min => if a == b {
+                    // Only differs for -0 vs +0, where min picks -0.
+                    $t::from_bits(ab | bb)
+                } else if a < b { a } else { b },
+                FloatBinOp::Max => if a == b {
+                    $t::from_bits(ab & bb)
+                } else if a > b { a } else { b },
+                FloatBinOp::Copysign => unreachable!(),
+            };
+            if res.is_nan() { $t::from_bits($canonical) } else { res }
+        }
+
+        fn $un(op: FloatUnOp, a: $t) -> $t {
+            let ab = a.to_bits();
+            match op {
+                FloatUnOp::Abs => return $t::from_bits(ab & !$sign),
+                FloatUnOp::Neg => return $t::from_bits(ab ^ $sign),
+                _ => {}
+            }
+            if a.is_nan() {
+                return $t::from_bits(ab | $quiet);
+            }
+            let res = match op {
+                FloatUnOp::Ceil => a.ceil(),
+                FloatUnOp::Floor => a.floor(),
+                FloatUnOp::Trunc => a.trunc(),
+                FloatUnOp::Nearest => $nearest(a),
+                FloatUnOp::Sqrt => a.sqrt(),
+                FloatUnOp::Abs | FloatUnOp::Neg => unreachable!(),
+            };
+            if res.is_nan() { $t::from_bits($canonical) } else { res }
+        }
     }
 }
-fn copysign_f64(a: f64, b: f64) -> f64 {
-    if (unsafe { mem::transmute::<f64, u64>(b) } & 0x8000_0000_0000_0000u64) == 0 {
-        a.abs()
-    } else {
-        -a.abs()
-    }
+
+float_ops!(f32, 0x8000_0000u32, 0x0040_0000u32, 0x7fc0_0000u32, 8388608f32,
+    float_bin_f32, float_un_f32, nearest_f32);
+float_ops!(f64, 0x8000_0000_0000_0000u64, 0x0008_0000_0000_0000u64, 0x7ff8_0000_0000_0000u64,
+    4503599627370496f64, float_bin_f64, float_un_f64, nearest_f64);
+
+#[test]
+fn test_float_ops_bits() {
+    let f = f32::from_bits;
+    let d = f64::from_bits;
+    assert_eq!(float_bin_f32(FloatBinOp::Min, -0.0, 0.0).to_bits(), 0x8000_0000);
+    assert_eq!(float_bin_f32(FloatBinOp::Min, 0.0, -0.0).to_bits(), 0x8000_0000);
+    assert_eq!(float_bin_f32(FloatBinOp::Max, -0.0, 0.0).to_bits(), 0x0000_0000);
+    assert_eq!(float_bin_f64(FloatBinOp::Min, -0.0, 0.0).to_bits(), 0x8000_0000_0000_0000);
+    // A NaN operand comes back quieted with its payload; an invalid op
+    // gives the canonical NaN.
+    assert_eq!(float_bin_f32(FloatBinOp::Add, f(0xff80_0001), 1.0).to_bits(), 0xffc0_0001);
+    assert_eq!(float_bin_f32(FloatBinOp::Sub, f32::INFINITY, f32::INFINITY).to_bits(), 0x7fc0_0000);
+
+    assert_eq!(nearest_f32(2.5).to_bits(), 0x4000_0000);
+    assert_eq!(nearest_f32(3.5).to_bits(), 0x4080_0000);
+    assert_eq!(nearest_f32(-0.4).to_bits(), 0x8000_0000);
+    assert_eq!(nearest_f64(2.5).to_bits(), 0x4000_0000_0000_0000);
+    assert_eq!(nearest_f64(-2.5).to_bits(), 0xc000_0000_0000_0000);
+
+    // Abs, neg and copysign only touch the sign bit, even of a signaling NaN.
+    assert_eq!(float_un_f32(FloatUnOp::Abs, f(0xff80_0001)).to_bits(), 0x7f80_0001);
+    assert_eq!(float_un_f32(FloatUnOp::Neg, f(0x7f80_0001)).to_bits(), 0xff80_0001);
+    assert_eq!(float_un_f32(FloatUnOp::Neg, 0.0).to_bits(), 0x8000_0000);
+    assert_eq!(float_un_f64(FloatUnOp::Abs, d(0xfff0_0000_0000_0001)).to_bits(), 0x7ff0_0000_0000_0001);
+    assert_eq!(float_bin_f32(FloatBinOp::Copysign, f(0x7fa0_0000), -1.0).to_bits(), 0xffa0_0000);
+    assert_eq!(float_bin_f64(FloatBinOp::Copysign, 1.0, d(0x8000_0000_0000_0000)).to_bits(),
+        0xbff0_0000_0000_0000);
+
+    let to_f32 = |v: u64| interp_int_to_float(IntType::Int64, Sign::Signed, FloatType::Float32,
+        Dynamic::from_u64(v)).to_f32().to_bits();
+    assert_eq!(to_f32((1 << 53) + 1), 0x5a00_0000);
+    // Rounding through f64 first would give 0x5a00_0000 here.
+    assert_eq!(to_f32((1 << 53) + (1 << 29) + 1), 0x5a00_0001);
+
+    let promote = |v: u32| interp_float_convert(FloatType::Float64, Dynamic::Float32(f(v))).to_f64().to_bits();
+    assert_eq!(promote(0x7fa0_0001), 0x7ffc_0000_2000_0000);
+    assert_eq!(promote(0xffc0_0000), 0xfff8_0000_0000_0000);
+    let demote = |v: u64| interp_float_convert(FloatType::Float32, Dynamic::Float64(d(v))).to_f32().to_bits();
+    assert_eq!(demote(0x7ff4_0000_2000_0000), 0x7fe0_0001);
+    assert_eq!(demote(0xfff0_0000_0000_0001), 0xffc0_0000);
 }
 
 fn interp_int_bin(ty: IntType, op: IntBinOp, a: Dynamic, b: Dynamic) -> InterpResult {
@@ -799,57 +1500,21 @@ fn interp_float_bin(ty: FloatType, op: FloatBinOp, a: Dynamic, b: Dynamic) -> Dy
     assert_eq!(a.get_type(), ty.to_type());
     assert_eq!(b.get_type(), ty.to_type());
 
-    let ao = a.to_float();
-    let bo = b.to_float();
-
-    let res = match op {
-        FloatBinOp::Add => ao + bo,
-        FloatBinOp::Sub => ao - bo,
-        FloatBinOp::Mul => ao * bo,
-        FloatBinOp::Div => ao / bo,
-        FloatBinOp::Min => if ao.is_nan() { ao } else if bo.is_nan() { bo } else { ao.min(bo) },
-        FloatBinOp::Max => if ao.is_nan() { ao } else if bo.is_nan() { bo } else { ao.max(bo) },
-        FloatBinOp::Copysign => {
-            match (a, b) {
-                (Dynamic::Float32(a), Dynamic::Float32(b)) => copysign_f32(a, b) as f64,
-                (Dynamic::Float64(a), Dynamic::Float64(b)) => copysign_f64(a, b),
-                _ => panic!()
-            }
-        }
-    };
-
-    Dynamic::from_float(ty, res)
-}
-
-fn round_to_even(a: f64) -> f64 {
-    let b = a.floor();
-    if (a - b).abs() == 0.5f64 {
-        if (b as u64) & 1 == 0 {
-            b
-        } else {
-            b + 1f64
-        }
-    } else {
-        a.round()
+    match (a, b) {
+        (Dynamic::Float32(a), Dynamic::Float32(b)) => Dynamic::Float32(float_bin_f32(op, a, b)),
+        (Dynamic::Float64(a), Dynamic::Float64(b)) => Dynamic::Float64(float_bin_f64(op, a, b)),
+        _ => panic!()
     }
 }
 
 fn interp_float_un(ty: FloatType, op: FloatUnOp, a: Dynamic) -> Dynamic {
     assert_eq!(a.get_type(), ty.to_type());
 
-    let a = a.to_float();
-
-    let res = match op {
-        FloatUnOp::Abs => a.abs(),
-        FloatUnOp::Neg => -a,
-        FloatUnOp::Ceil => a.ceil(),
-        FloatUnOp::Floor => a.floor(),
-        FloatUnOp::Trunc => a.trunc(),
-        FloatUnOp::Nearest => round_to_even(a),
-        FloatUnOp::Sqrt => a.sqrt(),
-    };
-
-    Dynamic::from_float(ty, res)
+    match a {
+        Dynamic::Float32(a) => Dynamic::Float32(float_un_f32(op, a)),
+        Dynamic::Float64(a) => Dynamic::Float64(float_un_f64(op, a)),
+        _ => panic!()
+    }
 }
 
 fn interp_float_cmp(ty: FloatType, op: FloatCmpOp, a: Dynamic, b: Dynamic) -> Dynamic {
@@ -968,16 +1633,42 @@ fn interp_int_to_float(inttype: IntType, sign: Sign, floattype: FloatType, a: Dy
 
     let a = a.to_int();
 
-    Dynamic::from_float(floattype, match (sign, inttype) {
-        (Sign::Signed, IntType::Int32) => i64_from_i32(a).0 as f64,
-        (Sign::Unsigned, IntType::Int32) => a.0 as f64,
-        (Sign::Signed, IntType::Int64) => i64_from_u64(a).0 as f64,
-        (Sign::Unsigned, IntType::Int64) => a.0 as f64,
-    })
+    // Convert straight to the target width; going through f64 first would
+    // round twice for 64-bit integers converted to f32.
+    match floattype {
+        FloatType::Float32 => Dynamic::Float32(match (sign, inttype) {
+            (Sign::Signed, IntType::Int32) => i64_from_i32(a).0 as f32,
+            (Sign::Unsigned, IntType::Int32) => a.0 as f32,
+            (Sign::Signed, IntType::Int64) => i64_from_u64(a).0 as f32,
+            (Sign::Unsigned, IntType::Int64) => a.0 as f32,
+        }),
+        FloatType::Float64 => Dynamic::Float64(match (sign, inttype) {
+            (Sign::Signed, IntType::Int32) => i64_from_i32(a).0 as f64,
+            (Sign::Unsigned, IntType::Int32) => a.0 as f64,
+            (Sign::Signed, IntType::Int64) => i64_from_u64(a).0 as f64,
+            (Sign::Unsigned, IntType::Int64) => a.0 as f64,
+        }),
+    }
 }
 
 fn interp_float_convert(ty: FloatType, a: Dynamic) -> Dynamic {
-    Dynamic::from_float(ty, a.to_float())
+    // NaNs keep their sign and the high bits of their payload, and come out
+    // quiet, as they do on hardware.
+    match (ty, a) {
+        (FloatType::Float64, Dynamic::Float32(v)) => Dynamic::Float64(if v.is_nan() {
+            let bits = v.to_bits() as u64;
+            f64::from_bits((bits & 0x8000_0000) << 32 | 0x7ff8_0000_0000_0000 | (bits & 0x007f_ffff) << 29)
+        } else {
+            v as f64
+        }),
+        (FloatType::Float32, Dynamic::Float64(v)) => Dynamic::Float32(if v.is_nan() {
+            let bits = v.to_bits();
+            f32::from_bits(((bits >> 32) & 0x8000_0000) as u32 | 0x7fc0_0000 | ((bits >> 29) & 0x007f_ffff) as u32)
+        } else {
+            v as f32
+        }),
+        _ => panic!()
+    }
 }
 
 fn interp_reinterpret(type_from: Type, type_to: Type, a: Dynamic) -> Dynamic {
@@ -0,0 +1,297 @@
+use std::fmt::Write;
+
+use types::Dynamic;
+
+/// Returns `text` as a JSON string literal.
+pub fn quote(text: &str) -> String {
+    let mut res = String::with_capacity(text.len() + 2);
+    res.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(res, "\\u{:04x}", c as u32).unwrap(),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[test]
+fn test_quote() {
+    assert_eq!(quote("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+}
+
+/// A parsed JSON value. Numbers keep their source text so that integers
+/// wider than an f64 mantissa survive.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|f| f.0 == key).map(|f| &f.1),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(n) => n.parse().ok(),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            &Json::Bool(b) => Some(b),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut p = Parser { text: text.as_bytes(), pos: 0 };
+        let res = p.parse_value()?;
+        p.skip_ws();
+        if p.pos != p.text.len() {
+            return Err(p.error("trailing characters"));
+        }
+        Ok(res)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &str) -> String {
+        format!("{} at byte {}", msg, self.pos)
+    }
+
+    fn skip_ws(&mut self) {
+        while self.pos < self.text.len() && (self.text[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, ch: u8) -> bool {
+        self.skip_ws();
+        if self.pos < self.text.len() && self.text[self.pos] == ch {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_ws();
+        if self.pos >= self.text.len() {
+            return Err(self.error("unexpected end of input"));
+        }
+        match self.text[self.pos] {
+            b'{' => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if self.eat(b'}') {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_ws();
+                    let key = self.parse_string()?;
+                    if !self.eat(b':') {
+                        return Err(self.error("expected ':'"));
+                    }
+                    fields.push((key, self.parse_value()?));
+                    if self.eat(b'}') {
+                        return Ok(Json::Object(fields));
+                    }
+                    if !self.eat(b',') {
+                        return Err(self.error("expected ',' or '}'"));
+                    }
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.eat(b']') {
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.parse_value()?);
+                    if self.eat(b']') {
+                        return Ok(Json::Array(items));
+                    }
+                    if !self.eat(b',') {
+                        return Err(self.error("expected ',' or ']'"));
+                    }
+                }
+            }
+            b'"' => self.parse_string().map(Json::String),
+            b't' | b'f' | b'n' => {
+                for &(word, ref value) in &[("true", Json::Bool(true)), ("false", Json::Bool(false)), ("null", Json::Null)] {
+                    if self.text[self.pos..].starts_with(word.as_bytes()) {
+                        self.pos += word.len();
+                        return Ok(value.clone());
+                    }
+                }
+                Err(self.error("unexpected word"))
+            }
+            b'-' | b'0'..=b'9' => {
+                let start = self.pos;
+                while self.pos < self.text.len() &&
+                    matches!(self.text[self.pos], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+                    self.pos += 1;
+                }
+                Ok(Json::Number(String::from_utf8(self.text[start..self.pos].to_vec()).unwrap()))
+            }
+            _ => Err(self.error("unexpected character"))
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        if self.pos >= self.text.len() || self.text[self.pos] != b'"' {
+            return Err(self.error("expected string"));
+        }
+        self.pos += 1;
+        let mut res = Vec::new();
+        loop {
+            if self.pos >= self.text.len() {
+                return Err(self.error("unterminated string"));
+            }
+            let ch = self.text[self.pos];
+            self.pos += 1;
+            match ch {
+                b'"' => break,
+                b'\\' => {
+                    if self.pos >= self.text.len() {
+                        return Err(self.error("unterminated string"));
+                    }
+                    let esc = self.text[self.pos];
+                    self.pos += 1;
+                    match esc {
+                        b'"' | b'\\' | b'/' => res.push(esc),
+                        b'n' => res.push(b'\n'),
+                        b'r' => res.push(b'\r'),
+                        b't' => res.push(b'\t'),
+                        b'b' => res.push(8),
+                        b'f' => res.push(12),
+                        b'u' => {
+                            let hex = self.text.get(self.pos..self.pos + 4)
+                                .and_then(|h| ::std::str::from_utf8(h).ok())
+                                .and_then(|h| u32::from_str_radix(h, 16).ok());
+                            let ch = match hex.and_then(::std::char::from_u32) {
+                                Some(ch) => ch,
+                                None => return Err(self.error("bad \\u escape")),
+                            };
+                            self.pos += 4;
+                            let mut buf = [0; 4];
+                            res.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+                        }
+                        _ => return Err(self.error("bad escape")),
+                    }
+                }
+                _ => res.push(ch),
+            }
+        }
+        String::from_utf8(res).map_err(|_| self.error("invalid utf-8 in string"))
+    }
+}
+
+/// Writes a value as `{"type":"i32","bits":"0x0000002a"}`, or `null`.
+pub fn value(v: Option<Dynamic>) -> String {
+    match v {
+        Some(Dynamic::Int32(v)) => format!("{{\"type\":\"i32\",\"bits\":\"{:#010x}\"}}", v.0),
+        Some(Dynamic::Int64(v)) => format!("{{\"type\":\"i64\",\"bits\":\"{:#018x}\"}}", v.0),
+        Some(Dynamic::Float32(v)) => format!("{{\"type\":\"f32\",\"bits\":\"{:#010x}\"}}", v.to_bits()),
+        Some(Dynamic::Float64(v)) => format!("{{\"type\":\"f64\",\"bits\":\"{:#018x}\"}}", v.to_bits()),
+        None => "null".to_owned(),
+    }
+}
+
+/// Reads a value written by `value`.
+pub fn parse_value(json: &Json) -> Result<Option<Dynamic>, String> {
+    if *json == Json::Null {
+        return Ok(None);
+    }
+    let bits = json.get("bits").and_then(|b| b.as_str())
+        .and_then(|b| b.strip_prefix("0x")).and_then(|b| u64::from_str_radix(b, 16).ok());
+    match (json.get("type").and_then(|t| t.as_str()), bits) {
+        (Some("i32"), Some(bits)) => Ok(Some(Dynamic::from_u32(bits as u32))),
+        (Some("i64"), Some(bits)) => Ok(Some(Dynamic::from_u64(bits))),
+        (Some("f32"), Some(bits)) => Ok(Some(Dynamic::Float32(f32::from_bits(bits as u32)))),
+        (Some("f64"), Some(bits)) => Ok(Some(Dynamic::Float64(f64::from_bits(bits)))),
+        _ => Err("malformed value".to_owned())
+    }
+}
+
+/// Writes a value the way wast2json does, as `{"type":"i32","value":"42"}`
+/// with the bits in unsigned decimal.
+pub fn spec_value(v: Dynamic) -> String {
+    let (ty, bits) = match v {
+        Dynamic::Int32(v) => ("i32", v.0 as u64),
+        Dynamic::Int64(v) => ("i64", v.0),
+        Dynamic::Float32(v) => ("f32", v.to_bits() as u64),
+        Dynamic::Float64(v) => ("f64", v.to_bits()),
+    };
+    format!("{{\"type\":\"{}\",\"value\":\"{}\"}}", ty, bits)
+}
+
+/// Reads a value written by `spec_value`.
+pub fn parse_spec_value(json: &Json) -> Result<Dynamic, String> {
+    let bits = json.get("value").and_then(|b| b.as_str()).and_then(|b| b.parse::<u64>().ok());
+    match (json.get("type").and_then(|t| t.as_str()), bits) {
+        (Some("i32"), Some(bits)) if bits <= u32::MAX as u64 => Ok(Dynamic::from_u32(bits as u32)),
+        (Some("i64"), Some(bits)) => Ok(Dynamic::from_u64(bits)),
+        (Some("f32"), Some(bits)) if bits <= u32::MAX as u64 => Ok(Dynamic::Float32(f32::from_bits(bits as u32))),
+        (Some("f64"), Some(bits)) => Ok(Dynamic::Float64(f64::from_bits(bits))),
+        _ => Err("malformed value".to_owned())
+    }
+}
+
+#[test]
+fn test_parse() {
+    let json = Json::parse(r#" {"a": [1, -2.5e3, "x\"A"], "b": {"c": null, "d": true}} "#).unwrap();
+    assert_eq!(json.get("a"), Some(&Json::Array(vec![
+        Json::Number("1".to_owned()),
+        Json::Number("-2.5e3".to_owned()),
+        Json::String("x\"A".to_owned())])));
+    assert_eq!(json.get("b").and_then(|b| b.get("d")), Some(&Json::Bool(true)));
+    assert!(Json::parse("[1,").is_err());
+
+    let v = Dynamic::Float32(f32::from_bits(0x7fa0_0001));
+    match parse_value(&Json::parse(&value(Some(v))).unwrap()) {
+        Ok(Some(Dynamic::Float32(f))) => assert_eq!(f.to_bits(), 0x7fa0_0001),
+        _ => panic!()
+    }
+    let v = Dynamic::from_u32(0xffff_ffff);
+    assert_eq!(spec_value(v), r#"{"type":"i32","value":"4294967295"}"#);
+    assert_eq!(spec_value(parse_spec_value(&Json::parse(&spec_value(v)).unwrap()).unwrap()), spec_value(v));
+}
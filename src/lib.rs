@@ -1,18 +1,40 @@
 
 mod reader;
 
+#[macro_use]
+mod sexpr;
 mod types;
 mod module;
 pub mod ops;
 mod interp;
+pub mod wat;
 mod testcase;
-mod sexpr;
 mod hexfloat;
+mod literal;
+mod profile;
+mod coverage;
+mod debug;
+mod watch;
+mod json;
+mod trace;
+mod replay;
+mod align;
+mod sanitize;
+mod heap;
 
 pub use types::Dynamic;
-pub use module::{Module, FunctionIndex, ExportIndex, FunctionType};
-pub use interp::{Instance, BoundInstance, InterpResult};
-pub use testcase::TestCase;
+pub use module::{Module, FunctionIndex, ImportIndex, ExportIndex, FunctionType};
+pub use interp::{Instance, BoundInstance, InterpResult, Memory};
+pub use profile::{Profiler, FunctionStats};
+pub use coverage::{Coverage, CoverageReport, FunctionReport};
+pub use trace::Tracer;
+pub use replay::{ImportCall, Recorder, Replayer, Divergence, save_calls, load_calls, replay_imports};
+pub use align::{AlignmentChecker, AlignmentHandler, AlignmentFault, AlignmentError};
+pub use sanitize::{Sanitizer, SanitizerHandler, UninitializedRead};
+pub use heap::{HeapProfiler, HeapReport, Allocation, BadFree, CallSite};
+pub use watch::{Access, Watchpoint, MemoryAccess, WatchHandler, Watcher};
+pub use debug::{Debugger, DebugHandler, Breakpoint, Step, Pause, PauseReason, Frame, FrameState};
+pub use testcase::{TestCase, Report, Outcome, Status};
 
 #[cfg(test)]
 mod test {
@@ -37,4 +59,13 @@ mod test {
             println!("\n\n\n\n");
         }
     }
+
+    #[test]
+    fn run_nan_propagation_wast() {
+        let mut contents = Vec::new();
+        File::open("test/nan-propagation.wast").expect("readable file").read_to_end(&mut contents).expect("read succeeds");
+        let report = ::testcase::TestCase::parse(&contents).run();
+        assert!(report.passed() > 0);
+        assert_eq!(report.failed(), 0);
+    }
 }
@@ -0,0 +1,328 @@
+use std::fmt;
+use std::str::FromStr;
+
+use types::{Dynamic, IntType, FloatType};
+use hexfloat;
+
+/// Why the text of a numeric literal was rejected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LiteralError {
+    /// The text isn't a literal of the expected kind.
+    Malformed,
+    /// The literal is well formed but its value doesn't fit the type.
+    OutOfRange,
+}
+
+impl fmt::Display for LiteralError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            LiteralError::Malformed => write!(f, "malformed number"),
+            LiteralError::OutOfRange => write!(f, "constant out of range"),
+        }
+    }
+}
+
+/// Splits a leading `+` or `-` off `text`, returning `Some(true)` for `-`.
+fn split_sign(text: &str) -> (Option<bool>, &str) {
+    if let Some(rest) = text.strip_prefix('+') {
+        (Some(false), rest)
+    } else if let Some(rest) = text.strip_prefix('-') {
+        (Some(true), rest)
+    } else {
+        (None, text)
+    }
+}
+
+/// Splits a run of digits in `radix` off the front of `text`. Single `_`
+/// separators may appear between digits. Returns the digits without the
+/// separators and the rest of the text.
+pub fn split_digits(text: &str, radix: u32) -> Result<(String, &str), LiteralError> {
+    let mut digits = String::new();
+    let mut end = 0;
+    let mut after_separator = false;
+    for (i, c) in text.char_indices() {
+        if c == '_' {
+            if digits.is_empty() || after_separator {
+                return Err(LiteralError::Malformed);
+            }
+            after_separator = true;
+        } else if c.is_digit(radix) {
+            digits.push(c);
+            after_separator = false;
+        } else {
+            break;
+        }
+        end = i + c.len_utf8();
+    }
+    if after_separator {
+        return Err(LiteralError::Malformed);
+    }
+    Ok((digits, &text[end..]))
+}
+
+/// Parses a whole run of digits, with separators, as an unsigned number.
+fn parse_digits(text: &str, radix: u32) -> Result<u64, LiteralError> {
+    let (digits, rest) = split_digits(text, radix)?;
+    if digits.is_empty() || !rest.is_empty() {
+        return Err(LiteralError::Malformed);
+    }
+    u64::from_str_radix(&digits, radix).map_err(|_| LiteralError::OutOfRange)
+}
+
+/// Parses an integer literal. Unsigned values may use the whole width,
+/// `+` values must be below the signed maximum, and `-` values may go down
+/// to the signed minimum.
+pub fn parse_int(text: &str, ty: IntType) -> Result<Dynamic, LiteralError> {
+    let (sign, body) = split_sign(text);
+    let magnitude = if let Some(hex) = body.strip_prefix("0x") {
+        parse_digits(hex, 16)?
+    } else {
+        parse_digits(body, 10)?
+    };
+    let max = ty.max_unsigned_value();
+    let half = (max >> 1) + 1;
+    let value = match sign {
+        None if magnitude <= max => magnitude,
+        Some(false) if magnitude < half => magnitude,
+        Some(true) if magnitude <= half => magnitude.wrapping_neg() & max,
+        _ => return Err(LiteralError::OutOfRange),
+    };
+    Ok(Dynamic::from_int(ty, value))
+}
+
+/// Parses an unsigned, sign-free literal such as a memory offset.
+pub fn parse_u32(text: &str) -> Result<u32, LiteralError> {
+    if text.starts_with("+") || text.starts_with("-") {
+        return Err(LiteralError::Malformed);
+    }
+    parse_int(text, IntType::Int32).map(|v| v.to_u32())
+}
+
+/// Parses a float literal directly to `ty`, so that every form rounds
+/// once, to nearest even. Literals that round to infinity are out of range.
+pub fn parse_float(text: &str, ty: FloatType) -> Result<Dynamic, LiteralError> {
+    let (mantissa_bits, exponent_bits) = match ty {
+        FloatType::Float32 => (23, 8),
+        FloatType::Float64 => (52, 11),
+    };
+    let exponent_mask = ((1u64 << exponent_bits) - 1) << mantissa_bits;
+
+    let (sign, body) = split_sign(text);
+    let bits = if body.starts_with("0x") {
+        let bits = match ty {
+            FloatType::Float32 => hexfloat::parse_bits_32(text)? as u64,
+            FloatType::Float64 => hexfloat::parse_bits_64(text)?,
+        };
+        if bits & exponent_mask == exponent_mask {
+            return Err(LiteralError::OutOfRange);
+        }
+        bits
+    } else {
+        let magnitude = if body == "inf" || body == "infinity" {
+            exponent_mask
+        } else if body == "nan" {
+            exponent_mask | 1 << (mantissa_bits - 1)
+        } else if let Some(hex) = body.strip_prefix("nan:0x") {
+            let payload = parse_digits(hex, 16)?;
+            if payload == 0 || payload >> mantissa_bits != 0 {
+                return Err(LiteralError::OutOfRange);
+            }
+            exponent_mask | payload
+        } else {
+            parse_decimal(body, ty)?
+        };
+        if sign == Some(true) {
+            magnitude | 1 << (mantissa_bits + exponent_bits)
+        } else {
+            magnitude
+        }
+    };
+
+    Ok(match ty {
+        FloatType::Float32 => Dynamic::Float32(f32::from_bits(bits as u32)),
+        FloatType::Float64 => Dynamic::Float64(f64::from_bits(bits)),
+    })
+}
+
+/// The bits of an unsigned decimal float. The standard library's parsers
+/// round correctly, so this only checks the syntax and drops separators.
+fn parse_decimal(text: &str, ty: FloatType) -> Result<u64, LiteralError> {
+    let (mut clean, mut rest) = split_digits(text, 10)?;
+    if clean.is_empty() {
+        return Err(LiteralError::Malformed);
+    }
+    if rest.starts_with(".") {
+        let (fraction, after) = split_digits(&rest[1..], 10)?;
+        if !fraction.is_empty() {
+            clean.push('.');
+            clean.push_str(&fraction);
+        }
+        rest = after;
+    }
+    if rest.starts_with("e") || rest.starts_with("E") {
+        let (sign, exponent) = split_sign(&rest[1..]);
+        clean.push('e');
+        if sign == Some(true) {
+            clean.push('-');
+        }
+        let (digits, after) = split_digits(exponent, 10)?;
+        if digits.is_empty() {
+            return Err(LiteralError::Malformed);
+        }
+        clean.push_str(&digits);
+        rest = after;
+    }
+    if !rest.is_empty() {
+        return Err(LiteralError::Malformed);
+    }
+
+    let (bits, infinite) = match ty {
+        FloatType::Float32 => {
+            let value = f32::from_str(&clean).map_err(|_| LiteralError::Malformed)?;
+            (value.to_bits() as u64, value.is_infinite())
+        }
+        FloatType::Float64 => {
+            let value = f64::from_str(&clean).map_err(|_| LiteralError::Malformed)?;
+            (value.to_bits(), value.is_infinite())
+        }
+    };
+    if infinite {
+        return Err(LiteralError::OutOfRange);
+    }
+    Ok(bits)
+}
+
+/// How `format_float` writes finite values.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// Hex float text, like `0x1.8p+3`.
+    Hex,
+    /// The shortest decimal that parses back to the same value.
+    Decimal,
+}
+
+/// Writes a float so that `parse_float` gives back exactly the same bits,
+/// including the sign of zero and any NaN payload.
+pub fn format_float(value: Dynamic, format: FloatFormat) -> String {
+    match value {
+        Dynamic::Float32(v) => {
+            let bits = v.to_bits();
+            format_special(bits as u64, 23, 8).unwrap_or_else(|| match format {
+                FloatFormat::Hex => hexfloat::format_bits_32(bits),
+                FloatFormat::Decimal => format!("{:?}", v),
+            })
+        }
+        Dynamic::Float64(v) => {
+            let bits = v.to_bits();
+            format_special(bits, 52, 11).unwrap_or_else(|| match format {
+                FloatFormat::Hex => hexfloat::format_bits_64(bits),
+                FloatFormat::Decimal => format!("{:?}", v),
+            })
+        }
+        _ => panic!("not a float: {}", value),
+    }
+}
+
+/// The text of an infinity or NaN. The canonical NaN is plain `nan`.
+fn format_special(bits: u64, mantissa_bits: u32, exponent_bits: u32) -> Option<String> {
+    let exponent_mask = ((1u64 << exponent_bits) - 1) << mantissa_bits;
+    if bits & exponent_mask != exponent_mask {
+        return None;
+    }
+    let sign = if (bits >> (mantissa_bits + exponent_bits)) & 1 == 1 { "-" } else { "" };
+    let payload = bits & ((1 << mantissa_bits) - 1);
+    Some(if payload == 0 {
+        format!("{}inf", sign)
+    } else if payload == 1 << (mantissa_bits - 1) {
+        format!("{}nan", sign)
+    } else {
+        format!("{}nan:0x{:x}", sign, payload)
+    })
+}
+
+#[test]
+fn test_parse_literals() {
+    let int32 = |text: &str| parse_int(text, IntType::Int32).map(|v| v.to_u32());
+    assert_eq!(int32("4_294_967_295"), Ok(0xffff_ffff));
+    assert_eq!(int32("-0x8000_0000"), Ok(0x8000_0000));
+    assert_eq!(int32("+0x7fffffff"), Ok(0x7fff_ffff));
+    assert_eq!(int32("-1"), Ok(0xffff_ffff));
+    assert_eq!(int32("4294967296"), Err(LiteralError::OutOfRange));
+    assert_eq!(int32("+0x80000000"), Err(LiteralError::OutOfRange));
+    assert_eq!(int32("-2147483649"), Err(LiteralError::OutOfRange));
+    assert_eq!(int32("1__0"), Err(LiteralError::Malformed));
+    assert_eq!(int32("_1"), Err(LiteralError::Malformed));
+    assert_eq!(int32("1_"), Err(LiteralError::Malformed));
+    assert_eq!(int32("0x"), Err(LiteralError::Malformed));
+    assert_eq!(parse_int("18446744073709551616", IntType::Int64), Err(LiteralError::OutOfRange));
+
+    let f32_bits = |text: &str| parse_float(text, FloatType::Float32).map(|v| v.to_f32().to_bits());
+    let f64_bits = |text: &str| parse_float(text, FloatType::Float64).map(|v| v.to_f64().to_bits());
+    // Rounding through f64 first would give 0x3f800000 here.
+    assert_eq!(f32_bits("1.000000059604644775390625000000001"), Ok(0x3f80_0001));
+    assert_eq!(f32_bits("1.00000005960464477539062499"), Ok(0x3f80_0000));
+    assert_eq!(f32_bits("1_000.5e-3"), Ok(1.0005f32.to_bits()));
+    assert_eq!(f32_bits("-0"), Ok(0x8000_0000));
+    assert_eq!(f32_bits("3.4028235e38"), Ok(0x7f7f_ffff));
+    assert_eq!(f32_bits("3.5e38"), Err(LiteralError::OutOfRange));
+    assert_eq!(f32_bits("1e-50"), Ok(0));
+    assert_eq!(f32_bits("1."), Ok(0x3f80_0000));
+    assert_eq!(f32_bits(".5"), Err(LiteralError::Malformed));
+    assert_eq!(f32_bits("1e"), Err(LiteralError::Malformed));
+    assert_eq!(f32_bits("-inf"), Ok(0xff80_0000));
+    assert_eq!(f32_bits("nan"), Ok(0x7fc0_0000));
+    assert_eq!(f32_bits("-nan:0x7f_ffff"), Ok(0xffff_ffff));
+    assert_eq!(f32_bits("nan:0x80_0000"), Err(LiteralError::OutOfRange));
+    assert_eq!(f32_bits("nan:0x0"), Err(LiteralError::OutOfRange));
+    assert_eq!(f64_bits("nan:0xf_ffff_ffff_ffff"), Ok(0x7fff_ffff_ffff_ffff));
+
+    assert_eq!(f32_bits("0x1.000001p0"), Ok(0x3f80_0000));
+    assert_eq!(f32_bits("0x1.000001000000000000001p0"), Ok(0x3f80_0001));
+    assert_eq!(f32_bits("0x1.000003p0"), Ok(0x3f80_0002));
+    assert_eq!(f32_bits("0x1p-149"), Ok(1));
+    assert_eq!(f32_bits("0x1p-150"), Ok(0));
+    assert_eq!(f32_bits("0x1.0000000000001p-150"), Ok(1));
+    assert_eq!(f32_bits("0x1.fffffep-127"), Ok(0x0080_0000));
+    assert_eq!(f32_bits("0x1p-99999999999999"), Ok(0));
+    assert_eq!(f32_bits("0x1.fffffefp127"), Ok(0x7f7f_ffff));
+    assert_eq!(f32_bits("0x1.ffffffp127"), Err(LiteralError::OutOfRange));
+    assert_eq!(f64_bits("0x1p-1074"), Ok(1));
+    assert_eq!(f64_bits("0x1p1024"), Err(LiteralError::OutOfRange));
+    assert_eq!(f64_bits("-0x_1p0"), Err(LiteralError::Malformed));
+    assert_eq!(f64_bits("0x1p"), Err(LiteralError::Malformed));
+}
+
+#[test]
+fn test_format_round_trip() {
+    // Edge cases first, then a fixed xorshift sequence of bit patterns.
+    let mut patterns = vec![0, 1, 0x7ff, 0x8000_0000, 0x007f_ffff, 0x0080_0000, 0x7f7f_ffff, 0x7f80_0000,
+        0xff80_0001, 0x7fc0_0000, 0x8000_0000_0000_0000, 0x000f_ffff_ffff_ffff, 0x7fef_ffff_ffff_ffff,
+        0x7ff0_0000_0000_0000, 0xfff8_0000_0000_0001, 0x7ff8_0000_0000_0000, u64::max_value()];
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for _ in 0..20000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        patterns.push(state);
+    }
+
+    for &bits in &patterns {
+        for &format in &[FloatFormat::Hex, FloatFormat::Decimal] {
+            let value = Dynamic::Float32(f32::from_bits(bits as u32));
+            let text = format_float(value, format);
+            assert_eq!(parse_float(&text, FloatType::Float32).map(|v| v.to_f32().to_bits()),
+                Ok(bits as u32), "{}", text);
+
+            let value = Dynamic::Float64(f64::from_bits(bits));
+            let text = format_float(value, format);
+            assert_eq!(parse_float(&text, FloatType::Float64).map(|v| v.to_f64().to_bits()),
+                Ok(bits), "{}", text);
+        }
+    }
+
+    assert_eq!(format_float(Dynamic::Float32(-0.0), FloatFormat::Decimal), "-0.0");
+    assert_eq!(format_float(Dynamic::Float32(0.1), FloatFormat::Decimal), "0.1");
+    assert_eq!(format_float(Dynamic::Float32(f32::from_bits(0xffa0_0000)), FloatFormat::Hex), "-nan:0x200000");
+    assert_eq!(format_float(Dynamic::Float64(f64::from_bits(0x7ff8_0000_0000_0000)), FloatFormat::Hex), "nan");
+    assert_eq!(format_float(Dynamic::Float64(-1.0 / 0.0), FloatFormat::Decimal), "-inf");
+}
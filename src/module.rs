@@ -1,4 +1,4 @@
-use std::{str, mem, fmt};
+use std::{mem, fmt};
 
 use types::{Type, Pr, IntType, FloatType, Sign, Dynamic, Size};
 use reader::Reader;
@@ -195,7 +195,7 @@ fn test_write_var_u64() {
     }
 }
 
-fn write_u32(ast: &mut Vec<u8>, v: u32) {
+pub(crate) fn write_u32(ast: &mut Vec<u8>, v: u32) {
     ast.push(((v >> 0*8) & 0xff) as u8);
     ast.push(((v >> 1*8) & 0xff) as u8);
     ast.push(((v >> 2*8) & 0xff) as u8);
@@ -213,6 +213,18 @@ fn write_u64(ast: &mut Vec<u8>, v: u64) {
     ast.push(((v >> (4+3)*8) & 0xff) as u8);
 }
 
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_var_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+/// Writes a chunk, leaving `data` empty for the next one.
+fn write_chunk(out: &mut Vec<u8>, name: &[u8], data: &mut Vec<u8>) {
+    write_bytes(out, name);
+    write_bytes(out, data);
+    data.clear();
+}
+
 fn write_mem_imm(ast: &mut Vec<u8>, imm: MemImm) {
     write_var_u32(ast, imm.log_of_alignment);
     write_var_u32(ast, imm.offset);
@@ -276,6 +288,7 @@ impl FunctionBuilder {
                     ast.push(if has_arg { 1 } else { 0 });
                 }
                 NormalOp::Unreachable => ast.push(0x0a),
+                NormalOp::Drop => ast.push(0x0b),
                 NormalOp::GetLocal(index) => {
                     ast.push(0x14);
                     ast.push(index as u8);
@@ -603,9 +616,7 @@ impl<B: AsBytes> Module<B> {
     }
 
     pub fn find_export(&self, name: &[u8], ty: FunctionType<&[u8]>) -> Option<ExportIndex> {
-        println!("looking for {}", str::from_utf8(name).unwrap());
         for (i, e) in self.exports.iter().enumerate() {
-            // println!("checking {}", str::from_utf8(e.function_name.as_bytes()).unwrap());
             if e.function_name.as_bytes() == name && self.types[self.functions[e.function_index.0].0].as_ref() == ty {
                 return Some(ExportIndex(i));
             }
@@ -614,9 +625,7 @@ impl<B: AsBytes> Module<B> {
     }
 
     pub fn find(&self, name: &[u8]) -> Option<FunctionIndex> {
-        println!("looking for {}", str::from_utf8(name).unwrap());
         for e in &self.exports {
-            // println!("checking {}", str::from_utf8(e.function_name.as_bytes()).unwrap());
             if e.function_name.as_bytes() == name {
                 return Some(e.function_index);
             }
@@ -625,9 +634,7 @@ impl<B: AsBytes> Module<B> {
     }
 
     pub fn find_by_debug_name(&self, name: &[u8]) -> Option<FunctionIndex> {
-        println!("looking for debug {}", str::from_utf8(name).unwrap());
         for (i, e) in self.names.iter().enumerate() {
-            // println!("checking debug {}", str::from_utf8(e.function_name.as_bytes()).unwrap());
             if e.function_name.as_bytes() == name {
                 return Some(FunctionIndex(i));
             }
@@ -646,6 +653,138 @@ impl<B: AsBytes> Module<B> {
         }
         None
     }
+
+    /// Copies the module out of whatever buffer it borrows from.
+    pub fn to_vec(&self) -> Module<Vec<u8>> {
+        let bytes = |b: &B| b.as_bytes().to_vec();
+        Module {
+            types: self.types.iter().map(|t| FunctionType {
+                param_types: bytes(&t.param_types),
+                return_type: t.return_type,
+            }).collect(),
+            imports: self.imports.iter().map(|i| Import {
+                function_type: i.function_type,
+                module_name: bytes(&i.module_name),
+                function_name: bytes(&i.function_name),
+            }).collect(),
+            functions: self.functions.clone(),
+            table: self.table.clone(),
+            memory_info: MemoryInfo {
+                initial_64k_pages: self.memory_info.initial_64k_pages,
+                maximum_64k_pages: self.memory_info.maximum_64k_pages,
+                is_exported: self.memory_info.is_exported,
+            },
+            start_function_index: self.start_function_index,
+            exports: self.exports.iter().map(|e| Export {
+                function_index: e.function_index,
+                function_name: bytes(&e.function_name),
+            }).collect(),
+            code: self.code.iter().map(|c| FunctionBody {
+                locals: c.locals.clone(),
+                ast: bytes(&c.ast),
+            }).collect(),
+            memory_chunks: self.memory_chunks.iter().map(|c| MemoryChunk {
+                offset: c.offset,
+                data: bytes(&c.data),
+            }).collect(),
+            names: self.names.iter().map(|n| Names {
+                function_name: bytes(&n.function_name),
+                local_names: n.local_names.iter().map(&bytes).collect(),
+            }).collect(),
+        }
+    }
+
+    /// Encodes the module in the format `parse` reads.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_u32(&mut out, 0x6d736100);
+        write_u32(&mut out, 11);
+
+        let mut c = Vec::new();
+        write_var_u32(&mut c, self.types.len() as u32);
+        for t in &self.types {
+            write_var_u32(&mut c, 0x40);
+            write_bytes(&mut c, t.param_types.as_bytes());
+            match t.return_type {
+                Some(ty) => write_bytes(&mut c, &[ty as u8]),
+                None => write_bytes(&mut c, &[]),
+            }
+        }
+        write_chunk(&mut out, b"type", &mut c);
+
+        write_var_u32(&mut c, self.imports.len() as u32);
+        for i in &self.imports {
+            write_var_u32(&mut c, i.function_type.0 as u32);
+            write_bytes(&mut c, i.module_name.as_bytes());
+            write_bytes(&mut c, i.function_name.as_bytes());
+        }
+        write_chunk(&mut out, b"import", &mut c);
+
+        write_var_u32(&mut c, self.functions.len() as u32);
+        for f in &self.functions {
+            write_var_u32(&mut c, f.0 as u32);
+        }
+        write_chunk(&mut out, b"function", &mut c);
+
+        write_var_u32(&mut c, self.table.len() as u32);
+        for f in &self.table {
+            write_var_u32(&mut c, f.0 as u32);
+        }
+        write_chunk(&mut out, b"table", &mut c);
+
+        write_var_u32(&mut c, self.memory_info.initial_64k_pages as u32);
+        write_var_u32(&mut c, self.memory_info.maximum_64k_pages as u32);
+        c.push(if self.memory_info.is_exported { 1 } else { 0 });
+        write_chunk(&mut out, b"memory", &mut c);
+
+        write_var_u32(&mut c, self.exports.len() as u32);
+        for e in &self.exports {
+            write_var_u32(&mut c, e.function_index.0 as u32);
+            write_bytes(&mut c, e.function_name.as_bytes());
+        }
+        write_chunk(&mut out, b"export", &mut c);
+
+        if let Some(index) = self.start_function_index {
+            write_var_u32(&mut c, index.0 as u32);
+            write_chunk(&mut out, b"start", &mut c);
+        }
+
+        write_var_u32(&mut c, self.code.len() as u32);
+        for f in &self.code {
+            let mut body = Vec::new();
+            write_var_u32(&mut body, f.locals.len() as u32);
+            for &(ty, count) in &f.locals {
+                write_var_u32(&mut body, count as u32);
+                body.push(ty as u8);
+            }
+            body.extend_from_slice(f.ast.as_bytes());
+            write_bytes(&mut c, &body);
+        }
+        write_chunk(&mut out, b"code", &mut c);
+
+        if !self.memory_chunks.is_empty() {
+            write_var_u32(&mut c, self.memory_chunks.len() as u32);
+            for m in &self.memory_chunks {
+                write_var_u32(&mut c, m.offset as u32);
+                write_bytes(&mut c, m.data.as_bytes());
+            }
+            write_chunk(&mut out, b"data", &mut c);
+        }
+
+        if !self.names.is_empty() {
+            write_var_u32(&mut c, self.names.len() as u32);
+            for n in &self.names {
+                write_bytes(&mut c, n.function_name.as_bytes());
+                write_var_i32(&mut c, n.local_names.len() as i32);
+                for l in &n.local_names {
+                    write_bytes(&mut c, l.as_bytes());
+                }
+            }
+            write_chunk(&mut out, b"name", &mut c);
+        }
+
+        out
+    }
 }
 
 impl<'a> Module<&'a [u8]> {
@@ -315,9 +315,11 @@ pub enum LinearOp<'a> {
     Normal(NormalOp<'a>),
 }
 
+/// An op in structured form. The `usize` is the byte offset of the op's
+/// opcode within the function body.
 pub enum BlockOp<'a> {
-    Block(Block<'a>),
-    Normal(NormalOp<'a>),
+    Block(usize, Block<'a>),
+    Normal(usize, NormalOp<'a>),
 }
 
 pub enum Block<'a> {
@@ -466,6 +468,49 @@ impl<'a> fmt::Display for NormalOp<'a> {
     }
 }
 
+impl<'a> NormalOp<'a> {
+    /// The number of operands the op takes from the stack.
+    pub fn arity(&self) -> usize {
+        let arg = |has_arg| if has_arg { 1 } else { 0 };
+        match self {
+            &NormalOp::Nop |
+            &NormalOp::Unreachable |
+            &NormalOp::Const(_) |
+            &NormalOp::GetLocal(_) |
+            &NormalOp::CurrentMemory => 0,
+            &NormalOp::Br{has_arg, ..} |
+            &NormalOp::Return{has_arg} => arg(has_arg),
+            &NormalOp::BrIf{has_arg, ..} |
+            &NormalOp::BrTable{has_arg, ..} => arg(has_arg) + 1,
+            &NormalOp::Select => 3,
+            &NormalOp::Call{argument_count, ..} |
+            &NormalOp::CallImport{argument_count, ..} => argument_count as usize,
+            &NormalOp::CallIndirect{argument_count, ..} => argument_count as usize + 1,
+            &NormalOp::IntStore(..) |
+            &NormalOp::FloatStore(..) |
+            &NormalOp::IntBin(..) |
+            &NormalOp::IntCmp(..) |
+            &NormalOp::FloatBin(..) |
+            &NormalOp::FloatCmp(..) => 2,
+            &NormalOp::Drop |
+            &NormalOp::SetLocal(_) |
+            &NormalOp::TeeLocal(_) |
+            &NormalOp::IntLoad(..) |
+            &NormalOp::FloatLoad(..) |
+            &NormalOp::GrowMemory |
+            &NormalOp::IntUn(..) |
+            &NormalOp::IntEqz(_) |
+            &NormalOp::FloatUn(..) |
+            &NormalOp::FloatToInt(..) |
+            &NormalOp::IntExtend(_) |
+            &NormalOp::IntTruncate |
+            &NormalOp::IntToFloat(..) |
+            &NormalOp::FloatConvert(_) |
+            &NormalOp::Reinterpret(..) => 1,
+        }
+    }
+}
+
 pub struct Indented<T: fmt::Display>(pub usize, pub T);
 
 impl<T: fmt::Display> fmt::Display for Indented<T> {
@@ -519,8 +564,8 @@ impl<'a> fmt::Display for Block<'a> {
 impl<'a> fmt::Display for BlockOp<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            &BlockOp::Block(ref b) => write!(f, "{}", b),
-            &BlockOp::Normal(ref n) => write!(f, "{}", n),
+            &BlockOp::Block(_, ref b) => write!(f, "{}", b),
+            &BlockOp::Normal(_, ref n) => write!(f, "{}", n),
         }
     }
 }
@@ -778,18 +823,18 @@ fn read_linear_op<'a>(r: &mut Reader<'a>) -> Option<LinearOp<'a>> {
 }
 
 enum BlockStackEl<'a> {
-    Block(Vec<BlockOp<'a>>),
-    Loop(Vec<BlockOp<'a>>),
-    If(bool, Vec<BlockOp<'a>>, Vec<BlockOp<'a>>),
+    Block(usize, Vec<BlockOp<'a>>),
+    Loop(usize, Vec<BlockOp<'a>>),
+    If(usize, bool, Vec<BlockOp<'a>>, Vec<BlockOp<'a>>),
 }
 
 fn push_block<'a>(op: BlockOp<'a>, blocks: &mut Vec<BlockStackEl<'a>>) -> Option<BlockOp<'a>> {
     match blocks.last_mut() {
         Some(b) => {
             match b {
-                &mut BlockStackEl::Block(ref mut ops) => ops.push(op),
-                &mut BlockStackEl::Loop(ref mut ops) => ops.push(op),
-                &mut BlockStackEl::If(in_cond, ref mut then, ref mut otherwise) =>
+                &mut BlockStackEl::Block(_, ref mut ops) => ops.push(op),
+                &mut BlockStackEl::Loop(_, ref mut ops) => ops.push(op),
+                &mut BlockStackEl::If(_, in_cond, ref mut then, ref mut otherwise) =>
                     if in_cond { then } else { otherwise }.push(op),
             }
             None
@@ -799,17 +844,28 @@ fn push_block<'a>(op: BlockOp<'a>, blocks: &mut Vec<BlockStackEl<'a>>) -> Option
 }
 
 impl<'a> BlockOp<'a> {
+    pub fn offset(&self) -> usize {
+        match self {
+            &BlockOp::Block(offset, _) | &BlockOp::Normal(offset, _) => offset,
+        }
+    }
+
     pub fn parse(r: &mut Reader<'a>) -> BlockOp<'a> {
         let mut blocks = Vec::new();
 
-        while let Some(l) = read_linear_op(r) {
+        loop {
+            let offset = r.position();
+            let l = match read_linear_op(r) {
+                Some(l) => l,
+                None => break,
+            };
             match l {
-                LinearOp::Block => blocks.push(BlockStackEl::Block(Vec::new())),
-                LinearOp::Loop => blocks.push(BlockStackEl::Loop(Vec::new())),
-                LinearOp::If => blocks.push(BlockStackEl::If(true, Vec::new(), Vec::new())),
+                LinearOp::Block => blocks.push(BlockStackEl::Block(offset, Vec::new())),
+                LinearOp::Loop => blocks.push(BlockStackEl::Loop(offset, Vec::new())),
+                LinearOp::If => blocks.push(BlockStackEl::If(offset, true, Vec::new(), Vec::new())),
                 LinearOp::Else => {
                     match blocks.last_mut().unwrap() {
-                        &mut BlockStackEl::If(ref mut in_cond, _, _) => {
+                        &mut BlockStackEl::If(_, ref mut in_cond, _, _) => {
                             assert!(*in_cond);
                             *in_cond = false;
                         }
@@ -818,16 +874,16 @@ impl<'a> BlockOp<'a> {
                 }
                 LinearOp::End => {
                     let b = match blocks.pop().unwrap() {
-                        BlockStackEl::Block(ops) => BlockOp::Block(Block::Block(ops)),
-                        BlockStackEl::Loop(ops) => BlockOp::Block(Block::Loop(ops)),
-                        BlockStackEl::If(_, then, otherwise) => BlockOp::Block(Block::If(then, otherwise)),
+                        BlockStackEl::Block(offset, ops) => BlockOp::Block(offset, Block::Block(ops)),
+                        BlockStackEl::Loop(offset, ops) => BlockOp::Block(offset, Block::Loop(ops)),
+                        BlockStackEl::If(offset, _, then, otherwise) => BlockOp::Block(offset, Block::If(then, otherwise)),
                     };
                     match push_block(b, &mut blocks) {
                         None => {}
                         Some(val) => return val,
                     }
                 }
-                LinearOp::Normal(x) => match push_block(BlockOp::Normal(x), &mut blocks) {
+                LinearOp::Normal(x) => match push_block(BlockOp::Normal(offset, x), &mut blocks) {
                     None => {}
                     Some(val) => return val,
                 },
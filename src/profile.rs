@@ -0,0 +1,204 @@
+use std::{io, str};
+use std::io::Write;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use module::{AsBytes, Module, FunctionIndex, ImportIndex};
+
+/// A function that can appear on the profiled call stack.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum Frame {
+    Function(usize),
+    Import(usize),
+}
+
+struct ActiveFrame {
+    frame: Frame,
+    start: Instant,
+    child_time: Duration,
+    /// The profiler's op count when this call started.
+    start_ops: u64,
+}
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FunctionStats {
+    pub calls: u64,
+    /// Ops executed by the function itself, excluding its callees.
+    pub ops: u64,
+    /// Ops executed by the function and its callees, counted like
+    /// `total_time`.
+    pub total_ops: u64,
+    /// Time spent in the function itself, excluding its callees.
+    pub self_time: Duration,
+    /// Time spent in the function and its callees. Recursive calls are only
+    /// counted once, at the outermost activation.
+    pub total_time: Duration,
+}
+
+/// Collects per-function call counts, executed op counts and timings while
+/// attached to an `Instance` (see `Instance::profiler`).
+#[derive(Default)]
+pub struct Profiler {
+    stack: Vec<ActiveFrame>,
+    stats: HashMap<Frame, FunctionStats>,
+    stacks: HashMap<Vec<Frame>, Duration>,
+    ops: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn enter_function(&mut self, func: FunctionIndex) {
+        self.enter(Frame::Function(func.0));
+    }
+
+    pub fn enter_import(&mut self, import: ImportIndex) {
+        self.enter(Frame::Import(import.0));
+    }
+
+    fn enter(&mut self, frame: Frame) {
+        self.stats.entry(frame).or_default().calls += 1;
+        self.stack.push(ActiveFrame {
+            frame,
+            start: Instant::now(),
+            child_time: Duration::new(0, 0),
+            start_ops: self.ops,
+        });
+    }
+
+    /// Ends the innermost call started by `enter_function` or `enter_import`.
+    pub fn exit(&mut self) {
+        let active = self.stack.pop().expect("profiler exit without matching enter");
+        let total = active.start.elapsed();
+        let self_time = total - active.child_time;
+
+        let key = self.stack.iter().map(|f| f.frame)
+            .chain(Some(active.frame))
+            .collect::<Vec<_>>();
+        *self.stacks.entry(key).or_insert(Duration::new(0, 0)) += self_time;
+
+        let recursive = self.stack.iter().any(|f| f.frame == active.frame);
+        let stats = self.stats.get_mut(&active.frame).unwrap();
+        stats.self_time += self_time;
+        if !recursive {
+            stats.total_time += total;
+            stats.total_ops += self.ops - active.start_ops;
+        }
+
+        if let Some(parent) = self.stack.last_mut() {
+            parent.child_time += total;
+        }
+    }
+
+    /// Counts one executed op against the innermost active function.
+    pub fn count_op(&mut self) {
+        self.ops += 1;
+        if let Some(active) = self.stack.last() {
+            self.stats.get_mut(&active.frame).unwrap().ops += 1;
+        }
+    }
+
+    pub fn function_stats(&self, func: FunctionIndex) -> Option<FunctionStats> {
+        self.stats.get(&Frame::Function(func.0)).cloned()
+    }
+
+    pub fn import_stats(&self, import: ImportIndex) -> Option<FunctionStats> {
+        self.stats.get(&Frame::Import(import.0)).cloned()
+    }
+
+    fn frame_name<B: AsBytes>(&self, module: &Module<B>, frame: Frame) -> String {
+        match frame {
+            Frame::Function(i) => match module.find_name(FunctionIndex(i)).and_then(|n| str::from_utf8(n).ok()) {
+                Some(name) => name.to_owned(),
+                None => format!("func[{}]", i),
+            },
+            Frame::Import(i) => {
+                let import = &module.imports[i];
+                format!("{}.{}",
+                    str::from_utf8(import.module_name.as_bytes()).unwrap_or("<bad_utf8>"),
+                    str::from_utf8(import.function_name.as_bytes()).unwrap_or("<bad_utf8>"))
+            }
+        }
+    }
+
+    /// Writes a table of all called functions, most expensive self time first.
+    pub fn write_table<B: AsBytes, W: Write>(&self, module: &Module<B>, out: &mut W) -> io::Result<()> {
+        let mut rows = self.stats.iter()
+            .map(|(&frame, stats)| (self.frame_name(module, frame), *stats))
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| b.1.self_time.cmp(&a.1.self_time).then_with(|| a.0.cmp(&b.0)));
+
+        writeln!(out, "{:>10} {:>12} {:>12} {:>12} {:>12}  function",
+            "calls", "ops", "total ops", "self (us)", "total (us)")?;
+        for (name, stats) in rows {
+            writeln!(out, "{:>10} {:>12} {:>12} {:>12} {:>12}  {}",
+                stats.calls, stats.ops, stats.total_ops, micros(stats.self_time), micros(stats.total_time), name)?;
+        }
+        Ok(())
+    }
+
+    /// Writes one line per distinct call stack, in the folded format read by
+    /// flamegraph tools: frames outermost first, separated by `;`, followed
+    /// by the self time of the innermost frame in microseconds.
+    pub fn write_folded<B: AsBytes, W: Write>(&self, module: &Module<B>, out: &mut W) -> io::Result<()> {
+        let mut lines = self.stacks.iter()
+            .map(|(stack, &time)| {
+                let names = stack.iter().map(|&f| self.frame_name(module, f)).collect::<Vec<_>>();
+                (names.join(";"), time)
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+
+        for (stack, time) in lines {
+            writeln!(out, "{} {}", stack, micros(time))?;
+        }
+        Ok(())
+    }
+}
+
+fn micros(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000 + d.subsec_micros() as u64
+}
+
+#[test]
+fn test_profiler() {
+    use interp::Instance;
+
+    let m = ::wat::parse_module("(module
+        (func $leaf \"leaf\" (result i32) (i32.const 1))
+        (func $mid \"mid\" (result i32) (i32.add (call $leaf) (call $leaf)))
+        (func $main \"main\" (export \"main\") (result i32) (i32.add (call $mid) (call $leaf))))").unwrap();
+    let mut instance = Instance::new(&m, HashMap::new());
+    instance.profiler = Some(Profiler::new());
+    instance.invoke(FunctionIndex(2), &[]);
+    let profiler = instance.profiler.take().unwrap();
+
+    let counts = |i| {
+        let stats = profiler.function_stats(FunctionIndex(i)).unwrap();
+        (stats.calls, stats.ops, stats.total_ops)
+    };
+    assert_eq!(counts(0), (3, 3, 3));
+    assert_eq!(counts(1), (1, 3, 5));
+    assert_eq!(counts(2), (1, 3, 9));
+
+    let mut folded = Vec::new();
+    profiler.write_folded(&m, &mut folded).unwrap();
+    let stacks = str::from_utf8(&folded).unwrap().lines()
+        .map(|l| l.rsplitn(2, ' ').nth(1).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(stacks, vec!["main", "main;leaf", "main;mid", "main;mid;leaf"]);
+
+    let mut table = Vec::new();
+    profiler.write_table(&m, &mut table).unwrap();
+    let table = String::from_utf8(table).unwrap();
+    let rows = table.lines().skip(1)
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let mut names = rows.iter().map(|r| r[5]).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["leaf", "main", "mid"]);
+    let self_times = rows.iter().map(|r| r[3].parse::<u64>().unwrap()).collect::<Vec<_>>();
+    assert!(self_times.windows(2).all(|w| w[0] >= w[1]));
+}
@@ -0,0 +1,332 @@
+use std::{fmt, io, str};
+use std::io::{BufRead, Write};
+use std::collections::HashMap;
+
+use module::{AsBytes, Module, ExportIndex, FunctionType};
+use types::Dynamic;
+use interp::{BoundInstance, InterpResult};
+use json::{self, Json};
+
+/// One call from the module to a host import, as recorded. Imports can't
+/// reach the calling instance's memory, so a call's only effect on the
+/// module is its result.
+#[derive(Clone, Debug)]
+pub struct ImportCall {
+    pub module: String,
+    pub name: String,
+    pub args: Vec<Dynamic>,
+    pub result: InterpResult,
+    /// Why the host trapped, if the call trapped and the host said.
+    pub trap_message: Option<String>,
+}
+
+fn same_value(a: &Dynamic, b: &Dynamic) -> bool {
+    json::value(Some(*a)) == json::value(Some(*b))
+}
+
+fn show_call(module: &str, name: &str, args: &[Dynamic]) -> String {
+    let args = args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    format!("{}.{}({})", module, name, args.join(", "))
+}
+
+impl ImportCall {
+    /// Whether this recorded call is the same call as the one described.
+    pub fn matches(&self, module: &str, name: &str, args: &[Dynamic]) -> bool {
+        self.module == module && self.name == name &&
+            self.args.len() == args.len() &&
+            self.args.iter().zip(args).all(|(a, b)| same_value(a, b))
+    }
+
+    pub fn to_json(&self) -> String {
+        let args = self.args.iter().map(|&a| json::value(Some(a))).collect::<Vec<_>>();
+        let (trap, result) = match self.result {
+            InterpResult::Value(v) => (false, json::value(v)),
+            InterpResult::Trap => (true, json::value(None)),
+        };
+        let trap_message = self.trap_message.as_ref().map_or("null".to_owned(), |m| json::quote(m));
+        format!("{{\"module\":{},\"name\":{},\"args\":[{}],\"trap\":{},\"result\":{},\"trap_message\":{}}}",
+            json::quote(&self.module), json::quote(&self.name), args.join(","), trap, result, trap_message)
+    }
+
+    pub fn from_json(json: &Json) -> Result<ImportCall, String> {
+        let string = |key| json.get(key).and_then(|v| v.as_str()).map(|s| s.to_owned())
+            .ok_or_else(|| format!("missing {}", key));
+        let array = |key| json.get(key).and_then(|v| v.as_array()).ok_or_else(|| format!("missing {}", key));
+
+        let mut args = Vec::new();
+        for a in array("args")? {
+            args.push(json::parse_value(a)?.ok_or("null argument")?);
+        }
+
+        let result = if json.get("trap").and_then(|t| t.as_bool()).ok_or("missing trap")? {
+            InterpResult::Trap
+        } else {
+            InterpResult::Value(json::parse_value(json.get("result").ok_or("missing result")?)?)
+        };
+
+        Ok(ImportCall {
+            module: string("module")?,
+            name: string("name")?,
+            args,
+            result,
+            trap_message: json.get("trap_message").and_then(|m| m.as_str()).map(|m| m.to_owned()),
+        })
+    }
+}
+
+impl fmt::Display for ImportCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", show_call(&self.module, &self.name, &self.args))?;
+        match self.result {
+            InterpResult::Value(Some(v)) => write!(f, " -> {}", v),
+            InterpResult::Value(None) => Ok(()),
+            InterpResult::Trap => match self.trap_message {
+                Some(ref message) => write!(f, " -> trap: {}", message),
+                None => write!(f, " -> trap"),
+            },
+        }
+    }
+}
+
+/// Writes calls as JSON Lines, one call per line.
+pub fn save_calls<W: Write>(calls: &[ImportCall], out: &mut W) -> io::Result<()> {
+    for c in calls {
+        writeln!(out, "{}", c.to_json())?;
+    }
+    Ok(())
+}
+
+/// Reads calls written by `save_calls`.
+pub fn load_calls<R: BufRead>(input: R) -> io::Result<Vec<ImportCall>> {
+    let mut calls = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let call = Json::parse(&line).and_then(|j| ImportCall::from_json(&j))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", i + 1, e)));
+        calls.push(call?);
+    }
+    Ok(calls)
+}
+
+/// Logs every import call while attached to an `Instance` (see
+/// `Instance::recorder`).
+#[derive(Default)]
+pub struct Recorder {
+    pub calls: Vec<ImportCall>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    pub fn record(&mut self, call: ImportCall) {
+        self.calls.push(call);
+    }
+
+    pub fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        save_calls(&self.calls, out)
+    }
+}
+
+/// The first call where a replayed run stopped matching the recording.
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    /// Position of the call in the recording.
+    pub index: usize,
+    /// The recorded call, or `None` if the recording had ended.
+    pub expected: Option<ImportCall>,
+    pub module: String,
+    pub name: String,
+    pub args: Vec<Dynamic>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let actual = show_call(&self.module, &self.name, &self.args);
+        match self.expected {
+            Some(ref expected) =>
+                write!(f, "call #{}: recorded {}, but the module called {}", self.index, expected, actual),
+            None =>
+                write!(f, "call #{}: the recording ended, but the module called {}", self.index, actual),
+        }
+    }
+}
+
+/// Answers import calls from a recording instead of the host, while
+/// attached to an `Instance` (see `Instance::replayer`). A call that doesn't
+/// match the next recorded one traps, and is kept as the divergence.
+pub struct Replayer {
+    calls: Vec<ImportCall>,
+    next: usize,
+    divergence: Option<Divergence>,
+}
+
+impl Replayer {
+    pub fn new(calls: Vec<ImportCall>) -> Replayer {
+        Replayer {
+            calls,
+            next: 0,
+            divergence: None,
+        }
+    }
+
+    pub fn load<R: BufRead>(input: R) -> io::Result<Replayer> {
+        load_calls(input).map(Replayer::new)
+    }
+
+    /// Returns the recorded answer to this call, or `None` if the run has
+    /// diverged from the recording.
+    pub fn replay(&mut self, module: &str, name: &str, args: &[Dynamic]) -> Option<&ImportCall> {
+        if self.divergence.is_some() {
+            return None;
+        }
+        let index = self.next;
+        let matched = self.calls.get(index).map_or(false, |c| c.matches(module, name, args));
+        if matched {
+            self.next += 1;
+            Some(&self.calls[index])
+        } else {
+            self.divergence = Some(Divergence {
+                index,
+                expected: self.calls.get(index).cloned(),
+                module: module.to_owned(),
+                name: name.to_owned(),
+                args: args.to_vec(),
+            });
+            None
+        }
+    }
+
+    pub fn divergence(&self) -> Option<&Divergence> {
+        self.divergence.as_ref()
+    }
+
+    /// Recorded calls the run hasn't made (yet).
+    pub fn remaining(&self) -> &[ImportCall] {
+        &self.calls[self.next..]
+    }
+}
+
+struct ReplayStub;
+
+impl BoundInstance for ReplayStub {
+    fn invoke_export(&mut self, _func: ExportIndex, _args: &[Dynamic]) -> InterpResult {
+        panic!("import called without a replayer attached");
+    }
+    fn export_by_name_and_type(&self, _name: &[u8], _ty: FunctionType<&[u8]>) -> ExportIndex {
+        ExportIndex(0)
+    }
+}
+
+/// Placeholder imports for every module `module` imports from, to
+/// instantiate it for replay without the real host.
+pub fn replay_imports<B: AsBytes>(module: &Module<B>) -> HashMap<&[u8], Box<dyn BoundInstance>> {
+    let mut imports = HashMap::new();
+    for i in &module.imports {
+        imports.insert(i.module_name.as_bytes(), Box::new(ReplayStub) as Box<dyn BoundInstance>);
+    }
+    imports
+}
+
+#[test]
+fn test_replay_divergence() {
+    let call = ImportCall {
+        module: "env".to_owned(),
+        name: "f".to_owned(),
+        args: vec![Dynamic::from_u32(1), Dynamic::Float64(f64::from_bits(0x7ff8_0000_0000_0001))],
+        result: InterpResult::Value(Some(Dynamic::from_u32(7))),
+        trap_message: None,
+    };
+    let mut text = Vec::new();
+    save_calls(&[call.clone(), call.clone()], &mut text).unwrap();
+
+    let mut replayer = Replayer::load(&text[..]).unwrap();
+    {
+        let replayed = replayer.replay("env", "f", &call.args).unwrap();
+        assert_eq!(replayed.result, call.result);
+    }
+    assert!(replayer.replay("env", "g", &call.args).is_none());
+    assert!(replayer.replay("env", "f", &call.args).is_none());
+
+    let divergence = replayer.divergence().unwrap();
+    assert_eq!(divergence.index, 1);
+    assert_eq!(divergence.name, "g");
+    assert_eq!(replayer.remaining().len(), 1);
+}
+
+#[test]
+fn test_record_and_replay_instance() {
+    use interp::Instance;
+    use module::FunctionIndex;
+
+    struct Host {
+        next: u32,
+    }
+    impl BoundInstance for Host {
+        fn invoke_export(&mut self, func: ExportIndex, args: &[Dynamic]) -> InterpResult {
+            if func.0 == 1 {
+                return InterpResult::Trap;
+            }
+            self.next += 1;
+            match args[0] {
+                Dynamic::Int32(v) => InterpResult::Value(Some(Dynamic::from_u32(v.0 + self.next))),
+                _ => InterpResult::Trap,
+            }
+        }
+        fn export_by_name_and_type(&self, name: &[u8], _ty: FunctionType<&[u8]>) -> ExportIndex {
+            ExportIndex(if name == b"fail" { 1 } else { 0 })
+        }
+        fn trap_message(&self) -> Option<String> {
+            Some("host refused".to_owned())
+        }
+    }
+
+    let m = ::wat::parse_module("(module
+        (type $t (func (param i32) (result i32)))
+        (import $f \"env\" \"f\" (type $t))
+        (import $fail \"env\" \"fail\" (type $t))
+        (func (param i32) (result i32) (i32.add (call_import $f (get_local 0)) (call_import $f (get_local 0))))
+        (func (param i32) (result i32) (call_import $fail (get_local 0))))").unwrap();
+    let arg = |a: u32| [Dynamic::from_u32(a)];
+    let sum = InterpResult::Value(Some(Dynamic::from_u32(5)));
+
+    let mut imports = HashMap::new();
+    imports.insert(&b"env"[..], Box::new(Host { next: 0 }) as Box<dyn BoundInstance>);
+    let mut instance = Instance::new(&m, imports);
+    instance.recorder = Some(Recorder::new());
+    assert_eq!(instance.invoke(FunctionIndex(0), &arg(1)), sum);
+    assert_eq!(instance.invoke(FunctionIndex(1), &arg(1)), InterpResult::Trap);
+    let mut text = Vec::new();
+    instance.recorder.unwrap().save(&mut text).unwrap();
+    let calls = load_calls(&text[..]).unwrap();
+    assert_eq!(calls.len(), 3);
+
+    // The host isn't there on replay; its answers and trap messages come
+    // from the recording.
+    let mut instance = Instance::new(&m, replay_imports(&m));
+    instance.replayer = Some(Replayer::new(calls.clone()));
+    assert_eq!(instance.invoke(FunctionIndex(0), &arg(1)), sum);
+    assert_eq!(instance.invoke(FunctionIndex(1), &arg(1)), InterpResult::Trap);
+    assert_eq!(instance.trap_message.as_ref().map(|m| &m[..]), Some("host refused"));
+    {
+        let replayer = instance.replayer.as_ref().unwrap();
+        assert!(replayer.divergence().is_none());
+        assert!(replayer.remaining().is_empty());
+    }
+
+    let mut instance = Instance::new(&m, replay_imports(&m));
+    instance.replayer = Some(Replayer::new(calls));
+    assert_eq!(instance.invoke(FunctionIndex(0), &arg(2)), InterpResult::Trap);
+    assert_eq!(instance.trap_message.as_ref().map(|m| &m[..]), Some("no recorded call to env.f"));
+    let replayer = instance.replayer.as_ref().unwrap();
+    let divergence = replayer.divergence().unwrap();
+    assert_eq!(divergence.index, 0);
+    assert!(divergence.expected.as_ref().unwrap().matches("env", "f", &arg(1)));
+    assert_eq!(divergence.args.len(), 1);
+    assert!(same_value(&divergence.args[0], &Dynamic::from_u32(2)));
+    assert_eq!(replayer.remaining().len(), 3);
+}
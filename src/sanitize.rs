@@ -0,0 +1,143 @@
+use module::{AsBytes, Module, FunctionIndex};
+
+/// A load that touched bytes nothing had written.
+#[derive(Copy, Clone)]
+pub struct UninitializedRead {
+    pub function: FunctionIndex,
+    pub offset: usize,
+    pub address: usize,
+    pub width: usize,
+    /// Which bytes of the access were uninitialized; bit `i` is set for the
+    /// byte at `address + i`.
+    pub uninitialized: u8,
+}
+
+pub trait SanitizerHandler {
+    fn uninitialized_read(&mut self, read: &UninitializedRead);
+}
+
+/// Tracks which bytes of linear memory have been written, and reports loads
+/// of any that haven't, while attached to an `Instance` (see
+/// `Instance::sanitizer`).
+///
+/// Bytes start out initialized only if a data segment covers them; stores
+/// initialize the bytes they write, and pages added by `grow_memory` start
+/// uninitialized. Create the sanitizer from the module and attach it before
+/// running anything, so no writes are missed.
+pub struct Sanitizer {
+    shadow: Vec<u8>,
+    len: usize,
+    handler: Box<dyn SanitizerHandler>,
+}
+
+impl Sanitizer {
+    pub fn new<B: AsBytes>(module: &Module<B>, handler: Box<dyn SanitizerHandler>) -> Sanitizer {
+        let mut sanitizer = Sanitizer {
+            shadow: Vec::new(),
+            len: 0,
+            handler,
+        };
+        sanitizer.resize(module.memory_info.initial_64k_pages * 64 * 1024);
+        for m in &module.memory_chunks {
+            sanitizer.initialize(m.offset, m.data.as_bytes().len());
+        }
+        sanitizer
+    }
+
+    /// Follows a change in the memory's size. New bytes are uninitialized.
+    pub fn resize(&mut self, len: usize) {
+        if len > self.len {
+            self.shadow.resize(len.div_ceil(8), 0);
+        }
+        self.len = len;
+    }
+
+    pub fn is_initialized(&self, address: usize) -> bool {
+        address < self.len && self.shadow[address / 8] & (1 << (address % 8)) != 0
+    }
+
+    pub fn initialize(&mut self, address: usize, len: usize) {
+        if address + len > self.len {
+            self.resize(address + len);
+        }
+        for a in address..address + len {
+            self.shadow[a / 8] |= 1 << (a % 8);
+        }
+    }
+
+    /// Returns the mask of uninitialized bytes among the `width` (at most 8)
+    /// bytes at `address`.
+    pub fn check(&self, address: usize, width: usize) -> u8 {
+        (0..width).filter(|&i| !self.is_initialized(address + i)).fold(0, |mask, i| mask | 1 << i)
+    }
+
+    pub fn report(&mut self, read: &UninitializedRead) {
+        self.handler.uninitialized_read(read);
+    }
+}
+
+#[test]
+fn test_shadow() {
+    struct Ignore;
+    impl SanitizerHandler for Ignore {
+        fn uninitialized_read(&mut self, _read: &UninitializedRead) {}
+    }
+
+    let mut s = Sanitizer {
+        shadow: Vec::new(),
+        len: 0,
+        handler: Box::new(Ignore),
+    };
+    s.resize(16);
+    s.initialize(3, 6);
+    assert_eq!(s.check(0, 4), 0b0111);
+    assert_eq!(s.check(4, 4), 0);
+    assert_eq!(s.check(6, 4), 0b1000);
+    s.resize(32);
+    assert_eq!(s.check(14, 4), 0b1111);
+    assert_eq!(s.check(30, 4), 0b1111);
+}
+
+#[test]
+fn test_sanitizer_in_instance() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use interp::Instance;
+    use ops::{BlockOp, NormalOp};
+
+    struct Log(Rc<RefCell<Vec<(usize, usize, usize, usize, u8)>>>);
+    impl SanitizerHandler for Log {
+        fn uninitialized_read(&mut self, read: &UninitializedRead) {
+            self.0.borrow_mut().push((read.function.0, read.offset, read.address, read.width, read.uninitialized));
+        }
+    }
+
+    let m = ::wat::parse_module("(module (memory 1 2 (segment 16 \"ab\"))
+        (func (drop (i32.load8_u (i32.const 17))))
+        (func
+            (i32.store8 (i32.const 100) (i32.const 1))
+            (drop (i32.load8_u (i32.const 100))))
+        (func (drop (i32.load8_u (i32.const 200))))
+        (func
+            (drop (grow_memory (i32.const 1)))
+            (drop (i32.load8_u (i32.const 65540))))
+        (func (drop (i32.load (i32.const 16)))))").unwrap();
+    let load_offset = |func: usize| m.code[func].block_ops().filter_map(|op| match op {
+        BlockOp::Normal(offset, NormalOp::IntLoad(..)) => Some(offset),
+        _ => None,
+    }).next().unwrap();
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut instance = Instance::new(&m, HashMap::new());
+    instance.sanitizer = Some(Sanitizer::new(&m, Box::new(Log(log.clone()))));
+    for func in 0..5 {
+        instance.invoke(FunctionIndex(func), &[]);
+    }
+
+    assert_eq!(*log.borrow(), vec![
+        (2, load_offset(2), 200, 1, 0b1),
+        (3, load_offset(3), 65540, 1, 0b1),
+        (4, load_offset(4), 16, 4, 0b1100),
+    ]);
+}
@@ -1,213 +1,368 @@
 use std::{fmt, str};
 
+macro_rules! vec_form {
+    ($val:expr => () => $code:expr) => {{
+        if $val.len() == 0 {
+            Some($code)
+        } else {
+            None
+        }
+    }};
+    ($val:expr => (*$rest:ident) => $code:expr) => {{
+        let $rest = &$val[..];
+        Some($code)
+    }};
+    ($val:expr => (ident:&$ident:ident $($parts:tt)*) => $code:expr) => {{
+        if $val.len() > 0 {
+            if let &Sexpr::Identifier(ref $ident, _) = &$val[0] {
+                vec_form!($val[1..] => ($($parts)*) => $code)
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }};
+    ($val:expr => (str:&$ident:ident $($parts:tt)*) => $code:expr) => {{
+        if $val.len() > 0 {
+            if let &Sexpr::String(ref $ident, _) = &$val[0] {
+                vec_form!($val[1..] => ($($parts)*) => $code)
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }};
+    ($val:expr => (&$ident:ident $($parts:tt)*) => $code:expr) => {{
+        if $val.len() > 0 {
+            let $ident = &$val[0];
+
+            vec_form!($val[1..] => ($($parts)*) => $code)
+        } else {
+            None
+        }
+    }};
+    ($val:expr => ($ident:ident $($parts:tt)*) => $code:expr) => {{
+        if $val.len() > 0 {
+            if let &Sexpr::Identifier(ref name, _) = &$val[0] {
+                if name.as_slice() == stringify!($ident).as_bytes() {
+                    vec_form!($val[1..] => ($($parts)*) => $code)
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }};
+}
+
+macro_rules! sexpr_match {
+    ($val:expr;) => {{ None }};
+    ($val:expr; _ => $code:expr) => {{ Some($code) }};
+    ($val:expr; $sexpr:tt => $code:expr; $($sexpr_rest:tt => $code_rest:expr);*) => {{
+        let val = $val;
+        let res = if let &Sexpr::List(ref items, _) = val {
+            vec_form!(items => $sexpr => $code)
+        } else {
+            None
+        };
+        if let None = res {
+            sexpr_match!(val; $($sexpr_rest => $code_rest);*)
+        } else {
+            res
+        }
+    }};
+}
+
+/// A position in the source text. `line` and `column` count from 1, and
+/// columns count characters rather than bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pos {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The text a node was parsed from, from the start of its first character
+/// to the end of its last.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
 #[derive(Debug)]
 pub enum Sexpr {
-    String(Vec<u8>),
-    Identifier(Vec<u8>),
-    Variable(Vec<u8>),
-    List(Vec<Sexpr>)
+    String(Vec<u8>, Span),
+    Identifier(Vec<u8>, Span),
+    Variable(Vec<u8>, Span),
+    List(Vec<Sexpr>, Span)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub pos: Pos,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}: {}", self.pos, self.message)
+    }
 }
 
 struct Parser<'a> {
     text: &'a [u8],
-    pos: usize
+    pos: usize,
+    // The last position worked out, which `position` counts on from.
+    last: Pos,
 }
 
 fn is_ws_char(ch: u8) -> bool {
-    ch == b' ' || ch == b'\n' || ch == b'\t'
+    ch == b' ' || ch == b'\n' || ch == b'\t' || ch == b'\r'
 }
 
 fn is_sep_char(ch: u8) -> bool {
     ch == b'(' || ch == b')' || is_ws_char(ch)
 }
 
-fn parse_hex_digit(ch: u8) -> u8 {
+fn hex_digit(ch: u8) -> Option<u32> {
     match ch {
-        b'0'...b'9' => ch - b'0',
-        b'a'...b'f' => ch - b'a' + 10,
-        b'A'...b'F' => ch - b'A' + 10,
-        _ => panic!(),
+        b'0'..=b'9' => Some((ch - b'0') as u32),
+        b'a'..=b'f' => Some((ch - b'a' + 10) as u32),
+        b'A'..=b'F' => Some((ch - b'A' + 10) as u32),
+        _ => None,
     }
 }
 
-fn unescape(text: &[u8]) -> Vec<u8> {
-    let mut res = Vec::new();
-
-    enum State {
-        Start,
-        Esc,
-        One(u8),
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Parser {
+        Parser {
+            text: text.as_bytes(),
+            pos: 0,
+            last: Pos { offset: 0, line: 1, column: 1 },
+        }
     }
 
-    let mut state = State::Start;
-
-    for ch in text {
-        match state {
-            State::Start => match *ch {
-                b'\\' => state = State::Esc,
-                _ => res.push(*ch),
-            },
-            State::Esc => match *ch {
-                b'0'...b'9' | b'a'...b'f' | b'A'...b'F' =>
-                    state = State::One(parse_hex_digit(*ch)),
-                b'n' => {
-                    res.push(b'\n');
-                    state = State::Start;
-                }
-                b'\\' => {
-                    res.push(b'\\');
-                    state = State::Start;
-                }
-                b'\"' => {
-                    res.push(b'\"');
-                    state = State::Start;
-                }
-                _ => panic!("unexpected escape {}", *ch),
-            },
-            State::One(val) => match *ch {
-                b'0'...b'9' | b'a'...b'f' | b'A'...b'F' => {
-                    let d = parse_hex_digit(*ch);
-                    res.push(d + (val << 4));
-                    state = State::Start;
-                }
-                _ => panic!("unexpected second escape {}", *ch),
-            },
+    /// The line and column of `offset`. Positions are usually asked for in
+    /// order, so this counts on from the last one.
+    fn position(&mut self, offset: usize) -> Pos {
+        let mut p = self.last;
+        if offset < p.offset {
+            p = Pos { offset: 0, line: 1, column: 1 };
         }
+        for &ch in &self.text[p.offset..offset] {
+            if ch == b'\n' {
+                p.line += 1;
+                p.column = 1;
+            } else if ch & 0xc0 != 0x80 {
+                p.column += 1;
+            }
+        }
+        p.offset = offset;
+        self.last = p;
+        p
     }
 
-    res
-}
+    fn error<T>(&mut self, offset: usize, message: String) -> Result<T, ParseError> {
+        Err(ParseError {
+            message,
+            pos: self.position(offset),
+        })
+    }
 
-impl<'a> Parser<'a> {
-    fn new(text: &'a str) -> Parser {
-        Parser {
-            text: text.as_bytes(),
-            pos: 0
-        }
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.text.get(self.pos + offset).cloned()
     }
 
-    fn skip_ws(&mut self) {
+    fn skip_ws(&mut self) -> Result<(), ParseError> {
         loop {
             while self.pos < self.text.len() && is_ws_char(self.text[self.pos]) {
                 self.pos += 1;
             }
 
-            if self.pos + 1 < self.text.len() &&
-                self.text[self.pos] == ';' as u8 &&
-                self.text[self.pos + 1] == ';' as u8 {
+            if self.peek(0) == Some(b';') && self.peek(1) == Some(b';') {
                 while self.pos < self.text.len() && self.text[self.pos] != b'\n' {
                     self.pos += 1;
                 }
+            } else if self.peek(0) == Some(b'(') && self.peek(1) == Some(b';') {
+                self.skip_block_comment()?;
             } else {
-                break;
+                return Ok(());
             }
         }
     }
 
-    fn parse_item(&mut self) -> Sexpr {
-        self.skip_ws();
-
-        if self.pos >= self.text.len() {
-            panic!();
+    /// Skips a `(; ... ;)` comment, which may contain others.
+    fn skip_block_comment(&mut self) -> Result<(), ParseError> {
+        let start = self.pos;
+        let mut depth = 0;
+        while self.pos < self.text.len() {
+            if self.peek(0) == Some(b'(') && self.peek(1) == Some(b';') {
+                depth += 1;
+                self.pos += 2;
+            } else if self.peek(0) == Some(b';') && self.peek(1) == Some(b')') {
+                depth -= 1;
+                self.pos += 2;
+                if depth == 0 {
+                    return Ok(());
+                }
+            } else {
+                self.pos += 1;
+            }
         }
+        self.error(start, "unterminated block comment".to_owned())
+    }
+
+    fn parse_item(&mut self) -> Result<Sexpr, ParseError> {
+        self.skip_ws()?;
 
-        let res = match self.text[self.pos] {
+        let start = self.pos;
+        let start_pos = self.position(start);
+        let first = match self.peek(0) {
+            Some(ch) => ch,
+            None => return self.error(start, "unexpected end of input".to_owned()),
+        };
+
+        let res = match first {
             b'(' => {
                 let mut res = Vec::new();
 
                 self.pos += 1;
 
-                self.skip_ws();
+                self.skip_ws()?;
 
-                while self.text[self.pos] != b')' {
-                    res.push(self.parse_item());
+                loop {
+                    match self.peek(0) {
+                        Some(b')') => break,
+                        Some(_) => res.push(self.parse_item()?),
+                        None => return self.error(start, "unclosed list".to_owned()),
+                    }
                 }
 
                 self.pos += 1;
 
-                Sexpr::List(res)
+                let span = self.span(start_pos);
+                Sexpr::List(res, span)
             }
+            b')' => return self.error(start, "unexpected )".to_owned()),
             b'"' => {
-                let mut len = 1;
-
-                loop {
-                    if self.pos + len >= self.text.len() {
-                        panic!();
+                let text = self.parse_string()?;
+                let span = self.span(start_pos);
+                Sexpr::String(text, span)
+            }
+            _ => {
+                while self.pos < self.text.len() && !is_sep_char(self.text[self.pos]) {
+                    if self.text[self.pos] == b'"' {
+                        let pos = self.pos;
+                        return self.error(pos, "unexpected \" in a name".to_owned());
                     }
+                    self.pos += 1;
+                }
+                let span = self.span(start_pos);
+                if first == b'$' {
+                    Sexpr::Variable(Vec::from(&self.text[start + 1..self.pos]), span)
+                } else {
+                    Sexpr::Identifier(Vec::from(&self.text[start..self.pos]), span)
+                }
+            }
+        };
 
-                    if self.text[self.pos + len] == b'\\' {
-                        len += 1;
-                        if len == self.text.len() {
-                            panic!();
-                        } else {
-                            len += 1;
-                        }
-                    }
+        self.skip_ws()?;
 
-                    if self.text[self.pos + len] == b'"' {
-                        len += 1;
-                        break;
-                    }
-                    len += 1;
-                }
-                let offset = self.pos + 1;
-                self.pos += len;
+        Ok(res)
+    }
+
+    fn span(&mut self, start: Pos) -> Span {
+        let end = self.pos;
+        Span {
+            start,
+            end: self.position(end),
+        }
+    }
 
-                Sexpr::String(unescape(&self.text[offset..self.pos - 1]))
+    /// Reads a string starting at the opening quote, and unescapes it.
+    fn parse_string(&mut self) -> Result<Vec<u8>, ParseError> {
+        let start = self.pos;
+        let mut res = Vec::new();
+        self.pos += 1;
+        loop {
+            let ch = match self.peek(0) {
+                Some(ch) => ch,
+                None => return self.error(start, "unterminated string".to_owned()),
+            };
+            self.pos += 1;
+            match ch {
+                b'"' => return Ok(res),
+                b'\\' => self.parse_escape(&mut res)?,
+                _ => res.push(ch),
             }
-            // x @ b'0'...b'9' => {
-            //     let mut len = 1;
-            //     loop {
-            //         if self.pos + len >= self.text.len() || is_sep_char(self.text[self.pos + len]) {
-            //             break;
-            //         } else {
-            //             match self.text[self.pos + len] {
-            //                 b'0'...b'9' => {},
-            //                 _ => break;
-            //             }
-            //             panic!();
-            //         }
-            //         len += 1;
-            //     }
-            //     let offset = self.pos;
-            //     self.pos += len;
-
-            //     Sexpr::Number(::std::str::from_utf8(&self.text[offset..self.pos]).unwrap().to_owned())
-            // }
-            b'$' => {
-                let mut len = 1;
-                loop {
-                    if self.pos + len >= self.text.len() || is_sep_char(self.text[self.pos + len]) {
-                        break;
-                    } else if self.text[self.pos + len] == b'"' {
-                        panic!();
+        }
+    }
+
+    fn parse_escape(&mut self, res: &mut Vec<u8>) -> Result<(), ParseError> {
+        let start = self.pos - 1;
+        let ch = match self.peek(0) {
+            Some(ch) => ch,
+            None => return self.error(start, "unterminated string".to_owned()),
+        };
+        self.pos += 1;
+        match ch {
+            b'n' => res.push(b'\n'),
+            b't' => res.push(b'\t'),
+            b'r' => res.push(b'\r'),
+            b'\\' => res.push(b'\\'),
+            b'"' => res.push(b'"'),
+            b'\'' => res.push(b'\''),
+            b'u' => {
+                if self.peek(0) != Some(b'{') {
+                    return self.error(start, "expected { after \\u".to_owned());
+                }
+                self.pos += 1;
+                let mut value: u32 = 0;
+                let mut digits = 0;
+                while let Some(d) = self.peek(0).and_then(hex_digit) {
+                    value = value.saturating_mul(16).saturating_add(d);
+                    digits += 1;
+                    self.pos += 1;
+                }
+                if digits == 0 || self.peek(0) != Some(b'}') {
+                    return self.error(start, "bad \\u escape".to_owned());
+                }
+                self.pos += 1;
+                match ::std::char::from_u32(value) {
+                    Some(c) => {
+                        let mut buf = [0; 4];
+                        res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
                     }
-                    len += 1;
+                    None => return self.error(start, format!("bad code point {:#x}", value)),
                 }
-                let offset = self.pos + 1;
-                self.pos += len;
-
-                Sexpr::Variable(Vec::from(&self.text[offset..self.pos]))
             }
-            x if !is_sep_char(x) => {
-                let mut len = 1;
-                loop {
-                    if self.pos + len >= self.text.len() || is_sep_char(self.text[self.pos + len]) {
-                        break;
+            _ => {
+                match (hex_digit(ch), self.peek(0).and_then(hex_digit)) {
+                    (Some(hi), Some(lo)) => {
+                        self.pos += 1;
+                        res.push((hi * 16 + lo) as u8);
+                    }
+                    _ => {
+                        let end = self.pos;
+                        let escape = String::from_utf8_lossy(&self.text[start..end]).into_owned();
+                        return self.error(start, format!("unknown escape {}", escape));
                     }
-                    len += 1;
                 }
-                let offset = self.pos;
-                self.pos += len;
-
-                Sexpr::Identifier(Vec::from(&self.text[offset..self.pos]))
             }
-            _ => panic!()
-        };
-
-        self.skip_ws();
-
-        res
+        }
+        Ok(())
     }
 
     fn at_end(&self) -> bool {
@@ -216,13 +371,23 @@ impl<'a> Parser<'a> {
 }
 
 impl Sexpr {
-    pub fn parse(text: &str) -> Vec<Sexpr> {
+    pub fn parse(text: &str) -> Result<Vec<Sexpr>, ParseError> {
         let mut res = Vec::new();
         let mut p = Parser::new(text);
+        p.skip_ws()?;
         while !p.at_end() {
-            res.push(p.parse_item());
+            res.push(p.parse_item()?);
+        }
+        Ok(res)
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            &Sexpr::String(_, span) |
+            &Sexpr::Identifier(_, span) |
+            &Sexpr::Variable(_, span) |
+            &Sexpr::List(_, span) => span,
         }
-        res
     }
 }
 
@@ -230,10 +395,10 @@ impl Sexpr {
 impl fmt::Display for Sexpr {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            &Sexpr::String(ref text) |
-            &Sexpr::Identifier(ref text) |
-            &Sexpr::Variable(ref text) => write!(f, "{}", str::from_utf8(text).unwrap_or("<bad utf8>")),
-            &Sexpr::List(ref items) => {
+            &Sexpr::String(ref text, _) |
+            &Sexpr::Identifier(ref text, _) |
+            &Sexpr::Variable(ref text, _) => write!(f, "{}", str::from_utf8(text).unwrap_or("<bad utf8>")),
+            &Sexpr::List(ref items, _) => {
                 try!(write!(f, "("));
                 for (i, s) in items.iter().enumerate() {
                     if i != 0 {
@@ -246,3 +411,23 @@ impl fmt::Display for Sexpr {
         }
     }
 }
+
+#[test]
+fn test_parse() {
+    let exprs = Sexpr::parse("(; a (; nested ;) comment ;)\n(foo \"\\t\\u{e9}\\'\\41\")\n  ;; end\n  $bar").unwrap();
+    assert_eq!(exprs.len(), 2);
+    sexpr_match!(&exprs[0];
+        (foo str:&s) => assert_eq!(s.as_slice(), "\t\u{e9}'A".as_bytes());
+        _ => panic!("expected (foo \"...\")")
+    );
+    let span = exprs[0].span();
+    assert_eq!((span.start.line, span.start.column, span.end.column), (2, 1, 22));
+    let span = exprs[1].span();
+    assert_eq!((span.start.line, span.start.column, span.end.column), (4, 3, 7));
+
+    let error = |text| Sexpr::parse(text).unwrap_err().to_string();
+    assert_eq!(error("(a\n  (b \"x\\q\"))"), "2:8: unknown escape \\q");
+    assert_eq!(error("(a\n  (b"), "2:3: unclosed list");
+    assert_eq!(error("(a (; b"), "1:4: unterminated block comment");
+    assert_eq!(error("a)"), "1:2: unexpected )");
+}
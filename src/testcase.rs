@@ -1,103 +1,33 @@
-use std::str::{self, FromStr};
-use std::{mem, f32, f64, fmt};
+use std::{str, fmt, panic, io, mem};
+use std::any::Any;
+use std::io::{Read, Write};
+use std::fs::File;
+use std::path::Path;
+use std::panic::AssertUnwindSafe;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::collections::HashMap;
-use std::num::Wrapping;
 
 use sexpr::Sexpr;
-use module::{AsBytes, Module, MemoryInfo, FunctionBuilder,
-    Export, FunctionIndex, ImportIndex, Names, MemoryChunk,
-    Import, FunctionType, ExportIndex, TableIndex, TypeIndex};
-use types::{Type, Dynamic, IntType, FloatType, Sign, Size};
-use ops::{LinearOp, NormalOp, IntBinOp, IntUnOp, IntCmpOp,
-    FloatBinOp, FloatUnOp, FloatCmpOp, MemImm};
+use module::{AsBytes, Module, FunctionType, ExportIndex};
+use types::{Dynamic, Type};
+use literal::{format_float, FloatFormat};
 use interp::{Instance, InterpResult, BoundInstance};
-use hexfloat;
-
-macro_rules! vec_form {
-    ($val:expr => () => $code:expr) => {{
-        if $val.len() == 0 {
-            Some($code)
-        } else {
-            None
-        }
-    }};
-    ($val:expr => (*$rest:ident) => $code:expr) => {{
-        let $rest = &$val[..];
-        Some($code)
-    }};
-    ($val:expr => (ident:&$ident:ident $($parts:tt)*) => $code:expr) => {{
-        if $val.len() > 0 {
-            if let &Sexpr::Identifier(ref $ident) = &$val[0] {
-                vec_form!($val[1..] => ($($parts)*) => $code)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    }};
-    ($val:expr => (str:&$ident:ident $($parts:tt)*) => $code:expr) => {{
-        if $val.len() > 0 {
-            if let &Sexpr::String(ref $ident) = &$val[0] {
-                vec_form!($val[1..] => ($($parts)*) => $code)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    }};
-    ($val:expr => (&$ident:ident $($parts:tt)*) => $code:expr) => {{
-        if $val.len() > 0 {
-            let $ident = &$val[0];
-
-            vec_form!($val[1..] => ($($parts)*) => $code)
-        } else {
-            None
-        }
-    }};
-    ($val:expr => ($ident:ident $($parts:tt)*) => $code:expr) => {{
-        if $val.len() > 0 {
-            if let &Sexpr::Identifier(ref name) = &$val[0] {
-                if name.as_slice() == stringify!($ident).as_bytes() {
-                    vec_form!($val[1..] => ($($parts)*) => $code)
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    }};
-}
-
-macro_rules! sexpr_match {
-    ($val:expr;) => {{ None }};
-    ($val:expr; _ => $code:expr) => {{ Some($code) }};
-    ($val:expr; $sexpr:tt => $code:expr; $($sexpr_rest:tt => $code_rest:expr);*) => {{
-        let val = $val;
-        let res = if let &Sexpr::List(ref items) = val {
-            vec_form!(items => $sexpr => $code)
-        } else {
-            None
-        };
-        if let None = res {
-            sexpr_match!(val; $($sexpr_rest => $code_rest);*)
-        } else {
-            res
-        }
-    }};
-}
+use wat::{compile_module, parse_const};
+use json::{self, Json};
 
 pub struct Invoke {
+    /// The `$name` of the module to call into, or `None` for the latest.
+    module: Option<Vec<u8>>,
     function_name: Vec<u8>,
     arguments: Vec<Dynamic>,
 }
 
 impl fmt::Display for Invoke {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if let Some(ref module) = self.module {
+            write!(f, "${}.", str::from_utf8(module).unwrap_or("<bad_utf8>"))?;
+        }
         try!(write!(f, "{}(", str::from_utf8(&self.function_name).unwrap_or("<bad_utf8>")));
         for (i, a) in self.arguments.iter().enumerate() {
             try!(write!(f, "{}{}", if i == 0 { "" } else { ", " }, a));
@@ -107,1526 +37,1067 @@ impl fmt::Display for Invoke {
 }
 
 impl Invoke {
-    fn run<'a, B: AsBytes>(&self, instance: &mut Instance<'a, B>) -> InterpResult {
-        let func =
+    fn run<'a, B: AsBytes>(&self, instance: &mut Instance<'a, B>) -> Result<InterpResult, String> {
+        let func = 
             instance.module.find(self.function_name.as_bytes())
             .or_else(|| instance.module.find_by_debug_name(self.function_name.as_bytes()))
-            .unwrap();
+            .ok_or_else(|| format!("no function {}", String::from_utf8_lossy(&self.function_name)))?;
         let res = instance.invoke(func, &self.arguments);
         assert_eq!(instance.call_stack_depth, 0);
-        res
+        Ok(res)
+    }
+}
+
+pub enum Action {
+    Invoke(Invoke),
+    /// Reads an exported global, from the named module or the latest.
+    Get(Option<Vec<u8>>, Vec<u8>),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &Action::Invoke(ref invoke) => write!(f, "{}", invoke),
+            &Action::Get(ref module, ref name) => {
+                if let &Some(ref module) = module {
+                    write!(f, "${}.", str::from_utf8(module).unwrap_or("<bad_utf8>"))?;
+                }
+                write!(f, "{}", str::from_utf8(name).unwrap_or("<bad_utf8>"))
+            }
+        }
+    }
+}
+
+impl Action {
+    /// Runs the action, or says why it couldn't be run and whether that
+    /// counts as a failure.
+    fn run<'a>(&self, script: &Script<'a>) -> Result<(InterpResult, Option<String>), (Status, String)> {
+        match self {
+            &Action::Invoke(ref invoke) => {
+                let instance = script.instance(&invoke.module)?;
+                let mut instance = instance.borrow_mut();
+                let res = invoke.run(&mut *instance).map_err(|e| (Status::Fail, e))?;
+                Ok((res, instance.trap_message.clone()))
+            }
+            &Action::Get(..) => Err((Status::Skip, "modules in this format have no globals".to_owned())),
+        }
+    }
+}
+
+/// Describes a result the way the spec tests write them.
+fn describe(result: &InterpResult, trap_message: &Option<String>) -> String {
+    match (result, trap_message) {
+        (&InterpResult::Value(Some(v)), _) => v.to_string(),
+        (&InterpResult::Value(None), _) => "nothing".to_owned(),
+        (&InterpResult::Trap, &Some(ref message)) => format!("trap: {}", message),
+        (&InterpResult::Trap, &None) => "trap".to_owned(),
+    }
+}
+
+fn same_value(a: Option<Dynamic>, b: Option<Dynamic>) -> bool {
+    match (a, b) {
+        (Some(Dynamic::Int32(a)), Some(Dynamic::Int32(b))) => a == b,
+        (Some(Dynamic::Int64(a)), Some(Dynamic::Int64(b))) => a == b,
+        (Some(Dynamic::Float32(a)), Some(Dynamic::Float32(b))) => a.to_bits() == b.to_bits(),
+        (Some(Dynamic::Float64(a)), Some(Dynamic::Float64(b))) => a.to_bits() == b.to_bits(),
+        (None, None) => true,
+        _ => false,
     }
 }
 
 pub enum Assert {
-    Return(Invoke, Option<Dynamic>),
-    ReturnNan(Invoke),
-    Trap(Invoke),
-    NoTrap(Invoke),
+    Return(Action, Option<Dynamic>),
+    ReturnNan(Action),
+    /// An action that should trap with a message starting with the text.
+    Trap(Action, String),
+    NoTrap(Action),
+    /// The interpreter reports running out of call stack as an ordinary
+    /// trap, so the message is compared like any other.
+    Exhaustion(Action, String),
+    /// The result of parsing or decoding a module that shouldn't parse,
+    /// with the module's source and the expected message.
+    Malformed(Source, Result<(), String>, String),
+    /// A module that shouldn't validate. There is no validator yet, so
+    /// these are skipped.
+    Invalid(Source, String),
+    /// A module whose imports shouldn't resolve.
+    Unlinkable(Result<Module<Vec<u8>>, String>, String),
+    /// A module whose start function should trap with the text.
+    Uninstantiable(Result<Module<Vec<u8>>, String>, String),
 }
 
-impl Assert {
-    fn run<'a, B: AsBytes>(&self, instance: &mut Instance<'a, B>) {
+impl fmt::Display for Assert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            &Assert::Return(ref invoke, result) => {
-                println!("testing {} => {:?}", invoke, result);
-                let a = invoke.run(instance);
-                match (a, result) {
-                    (InterpResult::Value(Some(Dynamic::Int32(a))), Some(Dynamic::Int32(b))) => assert_eq!(a, b),
-                    (InterpResult::Value(Some(Dynamic::Int64(a))), Some(Dynamic::Int64(b))) => assert_eq!(a, b),
-                    (InterpResult::Value(Some(Dynamic::Float32(a))), Some(Dynamic::Float32(b))) => {
-                        println!("{} {}", a, b);
-                        assert!(a == b ||
-                            (a.is_nan() && b.is_nan() && a.is_sign_negative() == b.is_sign_negative()));
-                    }
-                    (InterpResult::Value(Some(Dynamic::Float64(a))), Some(Dynamic::Float64(b))) => {
-                        println!("{} {}", a, b);
-                        assert!(a == b ||
-                            (a.is_nan() && b.is_nan() && a.is_sign_negative() == b.is_sign_negative()));
-                    }
-                    (InterpResult::Value(None), None) => {}
-                    _ => panic!("no match: {:?} vs {:?}", a, result)
+            &Assert::Return(ref action, _) => write!(f, "assert_return {}", action),
+            &Assert::ReturnNan(ref action) => write!(f, "assert_return_nan {}", action),
+            &Assert::Trap(ref action, _) => write!(f, "assert_trap {}", action),
+            &Assert::NoTrap(ref action) => write!(f, "{}", action),
+            &Assert::Exhaustion(ref action, _) => write!(f, "assert_exhaustion {}", action),
+            &Assert::Malformed(..) => write!(f, "assert_malformed"),
+            &Assert::Invalid(..) => write!(f, "assert_invalid"),
+            &Assert::Unlinkable(..) => write!(f, "assert_unlinkable"),
+            &Assert::Uninstantiable(..) => write!(f, "assert_trap (module)"),
+        }
+    }
+}
+
+impl Assert {
+    /// Returns the expected and actual results and whether they agree.
+    fn run<'a>(&'a self, script: &Script<'a>) -> (String, String, Status) {
+        let trap = |expected: &str, result: InterpResult, message: Option<String>| {
+            let actual = describe(&result, &message);
+            let status = match (result, message) {
+                (InterpResult::Trap, Some(ref message)) if message.starts_with(expected) => Status::Pass,
+                _ => Status::Fail,
+            };
+            (format!("trap: {}", expected), actual, status)
+        };
+        macro_rules! run {
+            ($action:expr, $expected:expr) => {
+                match $action.run(script) {
+                    Ok(res) => res,
+                    Err((status, actual)) => return ($expected, actual, status),
                 }
             }
-            &Assert::ReturnNan(ref invoke) => {
-                println!("testing {} returns nan", invoke);
-                match invoke.run(instance) {
-                    InterpResult::Value(Some(Dynamic::Float32(v))) => assert!(v.is_nan()),
-                    InterpResult::Value(Some(Dynamic::Float64(v))) => assert!(v.is_nan()),
-                    _ => panic!()
+        }
+        match self {
+            &Assert::Return(ref action, result) => {
+                let expected = describe(&InterpResult::Value(result), &None);
+                let (a, message) = run!(action, expected);
+                let status = match a {
+                    InterpResult::Value(a) if same_value(a, result) => Status::Pass,
+                    _ => Status::Fail,
+                };
+                (expected, describe(&a, &message), status)
+            }
+            &Assert::ReturnNan(ref action) => {
+                let (a, message) = run!(action, "nan".to_owned());
+                let status = match a {
+                    InterpResult::Value(Some(Dynamic::Float32(v))) if v.is_nan() => Status::Pass,
+                    InterpResult::Value(Some(Dynamic::Float64(v))) if v.is_nan() => Status::Pass,
+                    _ => Status::Fail,
+                };
+                ("nan".to_owned(), describe(&a, &message), status)
+            }
+            &Assert::Trap(ref action, ref text) |
+            &Assert::Exhaustion(ref action, ref text) => {
+                let (a, message) = run!(action, format!("trap: {}", text));
+                trap(text, a, message)
+            }
+            &Assert::NoTrap(ref action) => {
+                let (a, message) = run!(action, "no trap".to_owned());
+                let status = if a == InterpResult::Trap { Status::Fail } else { Status::Pass };
+                ("no trap".to_owned(), describe(&a, &message), status)
+            }
+            &Assert::Malformed(_, ref result, _) => {
+                match result {
+                    &Ok(()) => ("malformed".to_owned(), "accepted".to_owned(), Status::Fail),
+                    &Err(ref e) => ("malformed".to_owned(), e.clone(), Status::Pass),
                 }
             }
-            &Assert::Trap(ref invoke) => {
-                println!("testing {} traps", invoke);
-                assert_eq!(invoke.run(instance), InterpResult::Trap);
+            &Assert::Invalid(..) => ("invalid".to_owned(), "not validated".to_owned(), Status::Skip),
+            &Assert::Unlinkable(ref module, _) => {
+                let module = match module {
+                    &Ok(ref module) => module,
+                    &Err(ref e) => return ("unlinkable".to_owned(), e.clone(), Status::Skip),
+                };
+                match script.link(module) {
+                    Ok(_) => ("unlinkable".to_owned(), "linked".to_owned(), Status::Fail),
+                    Err(e) => ("unlinkable".to_owned(), e, Status::Pass),
+                }
             }
-            &Assert::NoTrap(ref invoke) => {
-                println!("testing {} doesn't trap", invoke);
-                assert!(invoke.run(instance) != InterpResult::Trap);
+            &Assert::Uninstantiable(ref module, ref text) => {
+                let module = match module {
+                    &Ok(ref module) => module,
+                    &Err(ref e) => return (format!("trap: {}", text), e.clone(), Status::Skip),
+                };
+                let imports = match script.link(module) {
+                    Ok(imports) => imports,
+                    Err(e) => return (format!("trap: {}", text), e, Status::Fail),
+                };
+                let mut instance = Instance::new(module, imports);
+                let res = start(&mut instance);
+                trap(text, res, instance.trap_message.clone())
             }
         }
     }
 }
 
+enum Command {
+    /// Instantiates a module, optionally giving it a `$name`.
+    Module(Option<Vec<u8>>, Result<Module<Vec<u8>>, String>),
+    /// Makes a module's exports importable under a name.
+    Register(Vec<u8>, Option<Vec<u8>>),
+    Assert(Assert),
+    /// A command that couldn't be read, with the reason.
+    Error(String),
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &Command::Module(Some(ref name), _) => write!(f, "module ${}", String::from_utf8_lossy(name)),
+            &Command::Module(None, _) => write!(f, "module"),
+            &Command::Register(ref as_name, _) => write!(f, "register {}", String::from_utf8_lossy(as_name)),
+            &Command::Assert(ref assert) => write!(f, "{}", assert),
+            &Command::Error(_) => write!(f, "unreadable command"),
+        }
+    }
+}
+
 pub struct TestCase {
-    modules: Vec<(Module<Vec<u8>>, Vec<Assert>)>
+    /// Each command with the line it starts on.
+    commands: Vec<(usize, Command)>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    /// The command needs something the interpreter doesn't support, or
+    /// depends on a module that failed to load.
+    Skip,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Fail => "fail",
+            Status::Skip => "skip",
+        }
+    }
+}
+
+/// The result of one command of a script.
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub line: usize,
+    pub command: String,
+    pub expected: String,
+    pub actual: String,
+    pub status: Status,
+    /// The lines `spectest` printed while the command ran.
+    pub output: Vec<String>,
 }
 
-fn parse_type(text: &[u8]) -> Type {
-    match text {
-        b"i32" => Type::Int32,
-        b"i64" => Type::Int64,
-        b"f32" => Type::Float32,
-        b"f64" => Type::Float64,
-        _ => panic!()
+/// The outcome of every command of a script, in order.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    fn count(&self, status: Status) -> usize {
+        self.outcomes.iter().filter(|o| o.status == status).count()
+    }
+
+    pub fn passed(&self) -> usize {
+        self.count(Status::Pass)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(Status::Fail)
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(Status::Skip)
+    }
+
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{{\"passed\":{},\"failed\":{},\"skipped\":{},\"outcomes\":[",
+            self.passed(), self.failed(), self.skipped())?;
+        for (i, o) in self.outcomes.iter().enumerate() {
+            let output = o.output.iter().map(|line| json::quote(line)).collect::<Vec<_>>();
+            write!(out, "{}{{\"line\":{},\"command\":{},\"expected\":{},\"actual\":{},\"status\":\"{}\",\"output\":[{}]}}",
+                if i == 0 { "" } else { "," }, o.line, json::quote(&o.command), json::quote(&o.expected),
+                json::quote(&o.actual), o.status.name(), output.join(","))?;
+        }
+        writeln!(out, "]}}")
+    }
+
+    /// Writes the report as a JUnit test suite called `name`, with one test
+    /// case per command.
+    pub fn write_junit<W: Write>(&self, name: &str, out: &mut W) -> io::Result<()> {
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(out, "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">",
+            xml_escape(name), self.outcomes.len(), self.failed(), self.skipped())?;
+        for o in &self.outcomes {
+            let case = format!("line {}: {}", o.line, o.command);
+            write!(out, "  <testcase classname=\"{}\" name=\"{}\"", xml_escape(name), xml_escape(&case))?;
+            match o.status {
+                Status::Pass => writeln!(out, "/>")?,
+                Status::Fail => writeln!(out, "><failure message=\"expected {}, got {}\"/></testcase>",
+                    xml_escape(&o.expected), xml_escape(&o.actual))?,
+                Status::Skip => writeln!(out, "><skipped message=\"{}\"/></testcase>", xml_escape(&o.actual))?,
+            }
+        }
+        writeln!(out, "</testsuite>")
     }
 }
 
-fn parse_type_expr(s: &Sexpr) -> Type {
-    match s {
-        &Sexpr::Identifier(ref text) => parse_type(text.as_slice()),
-        _ => panic!(),
+fn xml_escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '&' => res.push_str("&amp;"),
+            '"' => res.push_str("&quot;"),
+            c if (c as u32) < 0x20 && c != '\n' && c != '\t' => res.push('?'),
+            c => res.push(c),
+        }
     }
+    res
 }
 
-fn parse_invoke(s: &Sexpr) -> Invoke {
+fn parse_action(s: &Sexpr) -> Result<Action, String> {
     sexpr_match!(s;
-        (invoke str:&name *args) => {
-            let args = args.iter().map(parse_const).collect::<Vec<_>>();
-            return Invoke {
-                function_name: name.clone(),
-                arguments: args
+        (invoke *args) => {
+            let (module, args) = split_module_name(args);
+            let name = match args.first() {
+                Some(&Sexpr::String(ref name, _)) => name.clone(),
+                _ => return Err(format!("expected a function name: {}", s)),
             };
+            let mut arguments = Vec::new();
+            for a in &args[1..] {
+                arguments.push(parse_const(a).map_err(|e| e.to_string())?);
+            }
+            return Ok(Action::Invoke(Invoke {
+                module: module,
+                function_name: name,
+                arguments: arguments
+            }));
         };
-        _ => panic!()
+        (get *args) => {
+            let (module, args) = split_module_name(args);
+            if let (1, Some(&Sexpr::String(ref name, _))) = (args.len(), args.first()) {
+                return Ok(Action::Get(module, name.clone()));
+            }
+        };
+        _ => {}
     );
-    panic!();
+    Err(format!("expected an action: {}", s))
 }
 
-fn parse_const(s: &Sexpr) -> Dynamic {
+/// Splits the optional `$name` off the front of a command.
+fn split_module_name(items: &[Sexpr]) -> (Option<Vec<u8>>, &[Sexpr]) {
+    match items.first() {
+        Some(&Sexpr::Variable(ref name, _)) => (Some(name.clone()), &items[1..]),
+        _ => (None, items),
+    }
+}
+
+/// Reads a `(module ...)` with an optional `$name`. Its body is either
+/// module fields, `binary` followed by strings of the encoding, or `quote`
+/// followed by strings of module fields. Returns `None` if `s` isn't a
+/// module, and the parse error as text if it doesn't parse.
+fn parse_module(s: &Sexpr) -> Option<(Option<Vec<u8>>, Result<Module<Vec<u8>>, String>)> {
     sexpr_match!(s;
-        (ident:&ty &value) => {
-            return match ty.as_slice() {
-                b"i32.const" => parse_int(value, IntType::Int32),
-                b"i64.const" => parse_int(value, IntType::Int64),
-                b"f32.const" => parse_float(value, FloatType::Float32),
-                b"f64.const" => parse_float(value, FloatType::Float64),
-                _ => panic!()
+        (module *items) => {
+            let (name, items) = split_module_name(items);
+            let module = match items.first() {
+                Some(&Sexpr::Identifier(ref kind, _)) if kind.as_slice() == b"binary" => {
+                    strings(&items[1..]).and_then(|bytes| decode_binary(&bytes))
+                }
+                Some(&Sexpr::Identifier(ref kind, _)) if kind.as_slice() == b"quote" => {
+                    strings(&items[1..])
+                        .and_then(|bytes| String::from_utf8(bytes).map_err(|_| "quoted text isn't utf8".to_owned()))
+                        .and_then(|text| Sexpr::parse(&text).map_err(|e| e.to_string()))
+                        .and_then(|fields| compile_module(&fields).map_err(|e| e.to_string()))
+                }
+                _ => compile_module(items).map_err(|e| e.to_string()),
             };
+            return Some((name, module));
         };
-        _ => panic!()
+        _ => {}
     );
-    panic!();
+    None
 }
 
-impl TestCase {
-    pub fn parse(bytes: &[u8]) -> TestCase {
-        let text = str::from_utf8(bytes).unwrap();
-        let exprs = Sexpr::parse(text);
-
-        let mut modules = Vec::new();
-
-        for s in &exprs {
-            sexpr_match!(s;
-                (module *it) => {
-                    let mut m = Module::<Vec<u8>>::new();
-
-                    let mut function_names = HashMap::new();
-                    let mut function_index = 0;
-                    let mut import_names = HashMap::new();
-
-                    let mut type_names = HashMap::new();
-
-                    for s in it {
-                        sexpr_match!(s;
-                            (func *it) => {
-                                let mut it = it.iter();
-
-                                let mut name = None;
-
-                                let mut text = None;
-
-                                while let Some(s) = it.next() {
-                                    match s {
-                                        &Sexpr::Variable(ref v) => {
-                                            name = Some(v);
-                                            continue;
-                                        }
-                                        &Sexpr::String(ref v) => {
-                                            text = Some(v);
-                                            continue;
-                                        }
-                                        _ => break
-                                    }
-                                }
-
-                                if let Some(text) = text {
-                                    m.names.push(Names {
-                                        function_name: Vec::from(text.as_bytes()),
-                                        local_names: Vec::new(),
-                                    });
-                                } else {
-                                    m.names.push(Names {
-                                        function_name: Vec::new(),
-                                        local_names: Vec::new(),
-                                    });
-                                }
-
-                                if let Some(name) = name {
-                                    function_names.insert(name.as_slice(), function_index);
-                                }
-                                function_index += 1;
-                            };
-                            (import &module &name &ty) => {
-                                m.imports.push(Import {
-                                    function_type: parse_function_ty(&type_names, &mut m.types, ty),
-                                    module_name: parse_name(module),
-                                    function_name: parse_name(name),
-                                });
-                            };
-                            (import &id &module &name &ty) => {
-                                import_names.insert(parse_var_id(id), m.imports.len());
-                                m.imports.push(Import {
-                                    function_type: parse_function_ty(&type_names, &mut m.types, ty),
-                                    module_name: parse_name(module),
-                                    function_name: parse_name(name),
-                                });
-                            };
-                            (type &id &ty) => {
-                                type_names.insert(parse_var_id(id), m.types.len());
-                                m.types.push(parse_type_signature(ty));
-                            };
-                            (type &ty) => {
-                                m.types.push(parse_type_signature(ty));
-                            };
-                            _ => {}
-                        );
-                    }
+fn strings(items: &[Sexpr]) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for item in items {
+        match item {
+            &Sexpr::String(ref text, _) => bytes.extend_from_slice(text),
+            _ => return Err(format!("expected a string: {}", item)),
+        }
+    }
+    Ok(bytes)
+}
 
-                    for s in it {
-                        sexpr_match!(s;
-                            (func *it) => {
-                                let mut it = it.iter();
-
-                                let mut ctx = FunctionContext {
-                                    func: FunctionBuilder::new(),
-                                    local_names: HashMap::new(),
-                                    function_names: &function_names,
-                                    import_names: &import_names,
-                                    type_names: &type_names,
-                                    label_names: Vec::new()
-                                };
-
-                                let mut param_total_count = 0;
-
-                                let mut saw_type = false;
-                                let mut named_param_index = 0;
-
-                                let mut param_types = Vec::new();
-                                let mut return_type = None;
-
-                                while let Some(s) = it.next() {
-                                    match s {
-                                        &Sexpr::Variable(_) => continue,
-                                        &Sexpr::String(_) => continue,
-                                        _ => {}
-                                    }
-                                    sexpr_match!(s;
-                                        (param *args) => {
-                                            let mut last_var = false;
-                                            for a in args {
-                                                match a {
-                                                    &Sexpr::Identifier(ref v) => {
-                                                        if let Some(_) = ctx.func.ty_index {
-                                                            named_param_index += 1;
-                                                        } else {
-                                                            param_types.push(parse_type(v.as_slice()).to_u8());
-                                                        }
-                                                        last_var = false;
-                                                    }
-                                                    &Sexpr::Variable(ref v) => {
-                                                        if let Some(_) = ctx.func.ty_index {
-                                                            ctx.local_names.insert(v.as_slice(), named_param_index);
-                                                        } else {
-                                                            param_total_count += 1;
-                                                            ctx.local_names.insert(v.as_slice(), param_types.len());
-                                                        }
-                                                        last_var = true;
-                                                    }
-                                                    _ => panic!("4")
-                                                }
-                                            }
-                                            assert!(!last_var);
-                                        };
-                                        (result &ty) => {
-                                            if let &Sexpr::Identifier(ref v) = ty {
-                                                return_type = Some(parse_type(v.as_slice()));
-                                            } else {
-                                                panic!("3");
-                                            }
-                                        };
-                                        (type *args) => {
-                                            assert!(!saw_type);
-                                            let ty_index = parse_function_ty(&type_names, &mut m.types, s);
-                                            ctx.func.ty_index = Some(ty_index);
-                                            param_total_count = m.types[ty_index.0].param_types.len();
-                                        };
-                                        (local *args) => {
-                                            let mut last_var = false;
-                                            for a in args {
-                                                match a {
-                                                    &Sexpr::Variable(ref v) =>{
-                                                        ctx.local_names.insert(v.as_slice(), param_total_count + ctx.func.local_types.len());
-                                                        last_var = true;
-                                                    }
-                                                    &Sexpr::Identifier(ref v) => {
-                                                        ctx.func.local_types.push(parse_type(v.as_slice()));
-                                                        last_var = false;
-                                                    }
-                                                    _ => panic!("5")
-                                                }
-                                            }
-                                            assert!(!last_var);
-                                        };
-                                        (local *args) => {
-                                            for ty in args {
-                                                if let &Sexpr::Identifier(ref v) = ty {
-                                                    ctx.func.local_types.push(parse_type(v.as_slice()));
-                                                } else {
-                                                    panic!("6");
-                                                }
-                                            }
-                                        };
-                                        _ => {
-                                            ctx.parse_op(s);
-                                        }
-                                    );
-                                }
-
-                                if let Some(ty_index) = ctx.func.ty_index {
-                                    m.functions.push(ty_index);
-                                } else {
-                                    let mut found = false;
-                                    let myty = FunctionType {
-                                        param_types: param_types,
-                                        return_type: return_type,
-                                    };
-                                    for (i, ty) in m.types.iter().enumerate() {
-                                        if ty.param_types == myty.param_types && ty.return_type == myty.return_type {
-                                            m.functions.push(TypeIndex(i));
-                                            found = true;
-                                            break;
-                                        }
-                                    }
-                                    if !found {
-                                        m.functions.push(TypeIndex(m.types.len()));
-                                        m.types.push(myty);
-                                    }
-                                }
-                                m.code.push(ctx.func.build());
-                            };
-                            (export &name memory) => {
-                                // TODO: ... do something with it ...
-                            };
-                            (export &name &id) => {
-                                match name {
-                                    &Sexpr::String(ref name) => {
-                                        let index = match id {
-                                            &Sexpr::Variable(ref id) => {
-                                                FunctionIndex(*function_names.get(id.as_slice()).unwrap())
-                                            }
-                                            &Sexpr::Identifier(ref id) => {
-                                                FunctionIndex(usize::from_str(str::from_utf8(id.as_slice()).unwrap()).unwrap())
-                                            }
-                                            _ => panic!("6")
-                                        };
-                                        m.exports.push(Export {
-                                            function_index: index,
-                                            function_name: Vec::from(name.as_bytes())
-                                        });
-                                    }
-                                    _ => panic!("7")
-                                }
-                            };
-                            (import *args) => {
-                                // already handled
-                            };
-                            (type *args) => {
-                                // already handled
-                            };
-                            (memory *args) => {
-                                let i = 0;
-                                let i = if i < args.len() {
-                                    match &args[i] {
-                                        &Sexpr::Identifier(ref val) => {
-                                            m.memory_info.initial_64k_pages = usize::from_str(str::from_utf8(val.as_slice()).unwrap()).unwrap();
-                                            i + 1
-                                        }
-                                        _ => {
-                                            m.memory_info.initial_64k_pages = 1;
-                                            i
-                                        }
-                                    }
-                                } else {
-                                    m.memory_info.initial_64k_pages = 1;
-                                    i
-                                };
-                                let i = if i < args.len() {
-                                    match &args[i] {
-                                        &Sexpr::Identifier(ref val) => {
-                                            m.memory_info.maximum_64k_pages = usize::from_str(str::from_utf8(val.as_slice()).unwrap()).unwrap();
-                                            i + 1
-                                        }
-                                        _ => {
-                                            m.memory_info.maximum_64k_pages = 65536;
-                                            i
-                                        }
-                                    }
-                                } else {
-                                    m.memory_info.maximum_64k_pages = 65536;
-                                    i
-                                };
-
-                                assert!(m.memory_info.maximum_64k_pages >= m.memory_info.initial_64k_pages);
-
-                                for s in &args[i..] {
-                                    sexpr_match!(s;
-                                        (segment &offset &data) => {
-                                            m.memory_chunks.push(MemoryChunk {
-                                                offset:parse_int(offset, IntType::Int32).to_u32() as usize,
-                                                data: parse_bin_string(data),
-                                            })
-                                        };
-                                        _ => panic!("a")
-                                    );
-                                }
-                            };
-                            (table *items) => {
-                                for it in items {
-                                    m.table.push(FunctionIndex(read_function_name(&function_names, it)));
-                                }
-                            };
-                            (start &id) => {
-                                m.start_function_index = Some(FunctionIndex(read_function_name(&function_names, id)));
-                            };
-                            _ => {
-                                panic!("unhandled inner: {}", s);
-                            }
-                        );
-                    }
-                    modules.push((m, Vec::new()));
-                };
-                (assert_invalid &module &text) => {
-                    // TODO
-                    // panic!("8");
-                };
-                (assert_return &invoke) => {
-                    modules.last_mut().unwrap().1.push(Assert::Return(parse_invoke(invoke), None));
-                };
-                (assert_return &invoke &result) => {
-                    modules.last_mut().unwrap().1.push(Assert::Return(parse_invoke(invoke), Some(parse_const(result))));
-                };
-                (assert_return_nan &invoke) => {
-                    modules.last_mut().unwrap().1.push(Assert::ReturnNan(parse_invoke(invoke)));
-                };
-                (assert_trap &invoke &text) => {
-                    modules.last_mut().unwrap().1.push(Assert::Trap(parse_invoke(invoke)));
-                };
-                (invoke *args) => {
-                    modules.last_mut().unwrap().1.push(Assert::NoTrap(parse_invoke(s)));
-                };
+/// How a module was written in a script, so that it can be written out on
+/// its own.
+pub enum Source {
+    Binary(Vec<u8>),
+    /// A whole `(module ...)`.
+    Text(String),
+}
+
+/// Recovers the source of the `(module ...)` at `s`, which was parsed from
+/// `text`.
+fn module_source(s: &Sexpr, text: &str) -> Result<Source, String> {
+    sexpr_match!(s;
+        (module *items) => {
+            let (_, items) = split_module_name(items);
+            return match items.first() {
+                Some(&Sexpr::Identifier(ref kind, _)) if kind.as_slice() == b"binary" => {
+                    strings(&items[1..]).map(Source::Binary)
+                }
+                Some(&Sexpr::Identifier(ref kind, _)) if kind.as_slice() == b"quote" => {
+                    strings(&items[1..]).map(|bytes| Source::Text(format!("(module {})", String::from_utf8_lossy(&bytes))))
+                }
                 _ => {
-                    panic!("unhandled: {}", s);
+                    let span = s.span();
+                    Ok(Source::Text(text[span.start.offset..span.end.offset].to_owned()))
                 }
-            );
-        }
-
-        TestCase {
-            modules: modules
-        }
-    }
+            };
+        };
+        _ => {}
+    );
+    Err(format!("expected a module: {}", s))
+}
 
-    pub fn run_all(&self) {
-        for m in &self.modules {
-            let mut import_table = HashMap::new();
-            import_table.insert(&b"spectest"[..], Box::new(SpecTestModule) as Box<BoundInstance>);
-            let mut instance = Instance::new(&m.0, import_table);
-            if let Some(id) = m.0.start_function_index {
-                println!("running start function");
-                assert!(instance.invoke(id, &[]) != InterpResult::Trap);
-            }
-            for assert in &m.1 {
-                assert.run(&mut instance);
+/// Reads a module written out on its own.
+fn parse_source(source: &Source) -> Result<Module<Vec<u8>>, String> {
+    match source {
+        &Source::Binary(ref bytes) => decode_binary(bytes),
+        &Source::Text(ref text) => {
+            let exprs = Sexpr::parse(text).map_err(|e| e.to_string())?;
+            match (exprs.len(), exprs.first().and_then(parse_module)) {
+                (1, Some((_, module))) => module,
+                _ => Err("expected a single module".to_owned()),
             }
         }
     }
 }
 
-struct SpecTestModule;
+/// Decodes a binary module. The decoder panics on bad input, so the panic
+/// is caught and its message returned instead.
+fn decode_binary(bytes: &[u8]) -> Result<Module<Vec<u8>>, String> {
+    panic::catch_unwind(|| Module::parse(bytes).to_vec()).map_err(|e| panic_message(&*e, "undecodable module"))
+}
 
-impl BoundInstance for SpecTestModule {
-    fn invoke_export(&mut self, func: ExportIndex, args: &[Dynamic]) -> InterpResult {
-        for a in args {
-            match a {
-                &Dynamic::Int32(v) => println!("print: {}", v),
-                &Dynamic::Int64(v) => println!("print: {}", v),
-                &Dynamic::Float32(v) => println!("print: {}", v),
-                &Dynamic::Float64(v) => println!("print: {}", v),
-            }
-        }
-        InterpResult::Value(None)
+fn panic_message(e: &(Any + Send), default: &str) -> String {
+    match (e.downcast_ref::<String>(), e.downcast_ref::<&str>()) {
+        (Some(message), _) => message.clone(),
+        (_, Some(message)) => message.to_string(),
+        _ => default.to_owned(),
     }
-    fn export_by_name_and_type(&self, name: &[u8], ty: FunctionType<&[u8]>) -> ExportIndex {
-        assert!(name == b"print");
-        assert!(ty.return_type == None);
-        ExportIndex(0)
+}
+
+/// Runs a module's start function, if it has one.
+fn start<'a>(instance: &mut Instance<'a, Vec<u8>>) -> InterpResult {
+    match instance.module.start_function_index {
+        Some(id) => {
+            println!("running start function");
+            instance.invoke(id, &[])
+        }
+        None => InterpResult::Value(None),
     }
 }
 
-struct FunctionContext<'a> {
-    local_names: HashMap<&'a [u8], usize>,
-    func: FunctionBuilder,
-    function_names: &'a HashMap<&'a [u8], usize>,
-    import_names: &'a HashMap<&'a [u8], usize>,
-    type_names: &'a HashMap<&'a [u8], usize>,
-    label_names: Vec<Option<&'a [u8]>>
+/// The instances a script has made so far. Modules that failed to load
+/// leave a `None`, so that commands naming them can be skipped.
+struct Script<'a> {
+    instances: Vec<Option<Rc<RefCell<Instance<'a, Vec<u8>>>>>>,
+    names: HashMap<&'a [u8], usize>,
+    registered: HashMap<&'a [u8], usize>,
+    /// What `spectest` has printed during the current command.
+    output: Rc<RefCell<Vec<String>>>,
 }
 
-const EMPTY_DATA: &'static [u8] = &[];
+impl<'a> Script<'a> {
+    fn index(&self, name: &Option<Vec<u8>>) -> Result<usize, String> {
+        match name {
+            &Some(ref name) => self.names.get(name.as_slice()).cloned()
+                .ok_or_else(|| format!("no module named ${}", String::from_utf8_lossy(name))),
+            &None if self.instances.is_empty() => Err("no module to run".to_owned()),
+            &None => Ok(self.instances.len() - 1),
+        }
+    }
 
-fn log2(data: u32) -> u32 {
-    assert!(data.count_ones() == 1);
-    data.trailing_zeros()
-}
+    fn instance(&self, name: &Option<Vec<u8>>) -> Result<Rc<RefCell<Instance<'a, Vec<u8>>>>, (Status, String)> {
+        let index = self.index(name).map_err(|e| (Status::Fail, e))?;
+        self.instances[index].clone().ok_or_else(|| (Status::Skip, "the module failed to load".to_owned()))
+    }
 
-fn read_function_name(function_names: &HashMap<&[u8], usize>, expr: &Sexpr) -> usize {
-    match expr {
-        &Sexpr::Variable(ref name) => *function_names.get(name.as_bytes())
-            .unwrap_or_else(||panic!("no function named {}", expr)),
-        &Sexpr::Identifier(ref num) => usize::from_str(str::from_utf8(num).unwrap()).unwrap(),
-        _ => panic!("no function named {}", expr)
+    /// Resolves a module's imports against `spectest` and the registered
+    /// instances.
+    fn link(&self, module: &'a Module<Vec<u8>>) -> Result<HashMap<&'a [u8], Box<BoundInstance + 'a>>, String> {
+        let mut imports = HashMap::new();
+        for i in &module.imports {
+            let module_name = i.module_name.as_bytes();
+            let ty = module.types[i.function_type.0].as_ref();
+            let (found, bound) = if module_name == b"spectest" {
+                (SpecTestModule::find_export(i.function_name.as_bytes(), ty).is_some(),
+                    Box::new(SpecTestModule { output: self.output.clone() }) as Box<BoundInstance + 'a>)
+            } else {
+                let instance = match self.registered.get(module_name).map(|&index| &self.instances[index]) {
+                    Some(&Some(ref instance)) => instance.clone(),
+                    Some(&None) => return Err(format!("module {} failed to load", String::from_utf8_lossy(module_name))),
+                    None => return Err(format!("unknown module {}", String::from_utf8_lossy(module_name))),
+                };
+                let found = instance.borrow().module.find_export(i.function_name.as_bytes(), ty).is_some();
+                (found, Box::new(Shared(instance)) as Box<BoundInstance + 'a>)
+            };
+            if !found {
+                return Err(format!("unknown import {}.{} of type {}", String::from_utf8_lossy(module_name),
+                    String::from_utf8_lossy(i.function_name.as_bytes()), ty));
+            }
+            imports.entry(module_name).or_insert(bound);
+        }
+        Ok(imports)
     }
-}
 
-impl<'a> FunctionContext<'a> {
-    fn read_local(&self, expr: &Sexpr) -> usize {
-        match expr {
-            &Sexpr::Variable(ref name) => *self.local_names.get(name.as_bytes()).unwrap(),
-            &Sexpr::Identifier(ref num) => usize::from_str(str::from_utf8(num).unwrap()).unwrap(),
-            _ => panic!("no local named {}", expr)
+    /// Runs one command, adding any instance it makes.
+    fn run(&mut self, command: &'a Command) -> (String, String, Status) {
+        match command {
+            &Command::Module(ref name, ref module) => {
+                if let &Some(ref name) = name {
+                    self.names.insert(name, self.instances.len());
+                }
+                let module = match module {
+                    &Ok(ref module) => module,
+                    &Err(ref e) => {
+                        self.instances.push(None);
+                        return ("module".to_owned(), e.clone(), Status::Fail);
+                    }
+                };
+                let imports = match self.link(module) {
+                    Ok(imports) => imports,
+                    Err(e) => {
+                        self.instances.push(None);
+                        return ("module".to_owned(), e, Status::Fail);
+                    }
+                };
+                let mut instance = Instance::new(module, imports);
+                let res = start(&mut instance);
+                let actual = describe(&res, &instance.trap_message);
+                if res == InterpResult::Trap {
+                    self.instances.push(None);
+                    ("module".to_owned(), actual, Status::Fail)
+                } else {
+                    self.instances.push(Some(Rc::new(RefCell::new(instance))));
+                    ("module".to_owned(), "module".to_owned(), Status::Pass)
+                }
+            }
+            &Command::Register(ref as_name, ref name) => {
+                match self.index(name) {
+                    Ok(index) => {
+                        self.registered.insert(as_name, index);
+                        ("registered".to_owned(), "registered".to_owned(), Status::Pass)
+                    }
+                    Err(e) => ("registered".to_owned(), e, Status::Fail),
+                }
+            }
+            &Command::Assert(ref assert) => assert.run(self),
+            &Command::Error(ref e) => ("a command".to_owned(), e.clone(), Status::Fail),
         }
     }
+}
+
+/// A registered instance, shared by every module that imports from it.
+struct Shared<'a>(Rc<RefCell<Instance<'a, Vec<u8>>>>);
 
-    fn read_function(&self, expr: &Sexpr) -> usize {
-        read_function_name(self.function_names, expr)
+impl<'a> BoundInstance for Shared<'a> {
+    fn invoke_export(&mut self, func: ExportIndex, args: &[Dynamic]) -> InterpResult {
+        self.0.borrow_mut().invoke_export(func, args)
+    }
+    fn export_by_name_and_type(&self, name: &[u8], ty: FunctionType<&[u8]>) -> ExportIndex {
+        self.0.borrow().export_by_name_and_type(name, ty)
     }
+}
 
-    fn read_type(&self, expr: &Sexpr) -> usize {
-        match expr {
-            &Sexpr::Variable(ref name) => *self.type_names.get(name.as_bytes())
-                .unwrap_or_else(||panic!("no type named {}", expr)),
-            &Sexpr::Identifier(ref num) => usize::from_str(str::from_utf8(num).unwrap()).unwrap(),
-            _ => panic!("no type named {}", expr)
+impl TestCase {
+    /// Reads a script. Commands that can't be read are kept, and fail when
+    /// the script is run.
+    pub fn parse(bytes: &[u8]) -> TestCase {
+        let exprs = match str::from_utf8(bytes).map_err(|e| e.to_string())
+                .and_then(|text| Sexpr::parse(text).map_err(|e| e.to_string())) {
+            Ok(exprs) => exprs,
+            Err(e) => return TestCase { commands: vec![(0, Command::Error(e))] },
+        };
+        let text = str::from_utf8(bytes).unwrap();
+        let commands = exprs.iter()
+            .map(|s| (s.span().start.line, TestCase::parse_command(s, text).unwrap_or_else(Command::Error)))
+            .collect();
+
+        TestCase {
+            commands: commands
         }
     }
 
-    fn read_import(&self, expr: &Sexpr) -> usize {
-        match expr {
-            &Sexpr::Variable(ref name) => *self.import_names.get(name.as_bytes()).unwrap(),
-            &Sexpr::Identifier(ref num) => usize::from_str(str::from_utf8(num).unwrap()).unwrap(),
-            _ => panic!("no import named {}", expr)
+    fn parse_command(s: &Sexpr, source: &str) -> Result<Command, String> {
+        if let Some((name, module)) = parse_module(s) {
+            return Ok(Command::Module(name, module));
         }
+        let module = |s: &Sexpr| match parse_module(s) {
+            Some((_, module)) => Ok(module),
+            None => Err(format!("expected a module: {}", s)),
+        };
+        let text = |s: &Sexpr| match s {
+            &Sexpr::String(ref text, _) => Ok(String::from_utf8_lossy(text).into_owned()),
+            _ => Err(format!("expected a message: {}", s)),
+        };
+        let assert = |a| Ok(Command::Assert(a));
+        sexpr_match!(s;
+            (register str:&name) => {
+                return Ok(Command::Register(name.clone(), None));
+            };
+            (register str:&name &module) => {
+                return match module {
+                    &Sexpr::Variable(ref module, _) => Ok(Command::Register(name.clone(), Some(module.clone()))),
+                    _ => Err(format!("expected a module name: {}", module)),
+                };
+            };
+            (assert_invalid &m &t) => {
+                return assert(Assert::Invalid(module_source(m, source)?, text(t)?));
+            };
+            (assert_malformed &m &t) => {
+                let result = module(m)?.map(|_| ());
+                return assert(Assert::Malformed(module_source(m, source)?, result, text(t)?));
+            };
+            (assert_unlinkable &m &t) => {
+                return assert(Assert::Unlinkable(module(m)?, text(t)?));
+            };
+            (assert_return &action) => {
+                return assert(Assert::Return(parse_action(action)?, None));
+            };
+            (assert_return &action &result) => {
+                let result = parse_const(result).map_err(|e| e.to_string())?;
+                return assert(Assert::Return(parse_action(action)?, Some(result)));
+            };
+            (assert_return_nan &action) => {
+                return assert(Assert::ReturnNan(parse_action(action)?));
+            };
+            (assert_trap &action &t) => {
+                return match parse_module(action) {
+                    Some((_, m)) => assert(Assert::Uninstantiable(m, text(t)?)),
+                    None => assert(Assert::Trap(parse_action(action)?, text(t)?)),
+                };
+            };
+            (assert_exhaustion &action &t) => {
+                return assert(Assert::Exhaustion(parse_action(action)?, text(t)?));
+            };
+            (invoke *args) => {
+                return assert(Assert::NoTrap(parse_action(s)?));
+            };
+            (get *args) => {
+                return assert(Assert::NoTrap(parse_action(s)?));
+            };
+            _ => {}
+        );
+        Err(format!("unhandled: {}", s))
     }
 
-    fn read_label(&self, expr: &'a Sexpr) -> usize {
-        println!("labels {}", self.label_names.len());
-        match expr {
-            &Sexpr::Variable(ref name) => {
-                for i in (0..self.label_names.len()).rev() {
-                    // println!("check label {} {}", i, str::from_utf8(self.label_names[i]).unwrap());
-                    if self.label_names[i] == Some(name.as_slice()) {
-                        return self.label_names.len() - 1 - i;
-                    }
+    /// Runs every command, carrying on past failures. A command that panics
+    /// fails with the panic's message.
+    pub fn run(&self) -> Report {
+        let mut script = Script {
+            instances: Vec::new(),
+            names: HashMap::new(),
+            registered: HashMap::new(),
+            output: Rc::new(RefCell::new(Vec::new())),
+        };
+        let mut report = Report::default();
+        for &(line, ref command) in &self.commands {
+            let (expected, actual, status) = match panic::catch_unwind(AssertUnwindSafe(|| script.run(command))) {
+                Ok(res) => res,
+                Err(e) => {
+                    for instance in script.instances.iter().filter_map(|i| i.as_ref()) {
+                        instance.borrow_mut().call_stack_depth = 0;
+                    }
+                    ("no panic".to_owned(), format!("panic: {}", panic_message(&*e, "unknown")), Status::Fail)
                 }
-                panic!("no label named {}", expr)
-            }
-            &Sexpr::Identifier(ref num) => usize::from_str(str::from_utf8(num).unwrap()).unwrap(),
-            _ => panic!("no label named {}", expr)
+            };
+            report.outcomes.push(Outcome {
+                line,
+                command: command.to_string(),
+                expected,
+                actual,
+                status,
+                output: mem::replace(&mut *script.output.borrow_mut(), Vec::new()),
+            });
         }
+        report
     }
 
-    fn parse_ops(&mut self, exprs: &'a [Sexpr]) -> usize {
-        let mut num = 0;
-        for s in exprs {
-            self.parse_op(s);
-            num += 1;
+    /// Runs every command, panicking after printing the failures if any
+    /// command failed.
+    pub fn run_all(&self) {
+        let report = self.run();
+        for o in report.outcomes.iter().filter(|o| o.status == Status::Fail) {
+            println!("line {}: {}: expected {}, got {}", o.line, o.command, o.expected, o.actual);
         }
-        num
+        assert!(report.failed() == 0, "{} of {} commands failed", report.failed(), report.outcomes.len());
     }
+}
+
+fn quote_name(name: &[u8]) -> String {
+    json::quote(&String::from_utf8_lossy(name))
+}
 
-    fn push<'b>(&mut self, op: NormalOp<'b>) {
-        self.func.write(LinearOp::Normal(op));
+/// The `,"module":"$name"` field naming a module, if it has a name.
+fn module_field(key: &str, name: &Option<Vec<u8>>) -> String {
+    match name {
+        &Some(ref name) => format!(",\"{}\":{}", key, json::quote(&format!("${}", String::from_utf8_lossy(name)))),
+        &None => String::new(),
     }
+}
 
-    fn parse_mem_imm(&mut self, exprs: &'a [Sexpr], count: usize) -> MemImm {
-        let (i, log_of_alignment) = match &exprs[0] {
-            &Sexpr::Identifier(ref text) if text.starts_with(b"align=") =>
-                (1, log2(u32::from_str(str::from_utf8(&text[b"align=".len()..]).unwrap()).unwrap())),
-            _ => (0, 1),
-        };
-        assert_eq!(self.parse_ops(&exprs[i..]), count);
-        MemImm {
-            log_of_alignment: log_of_alignment,
-            offset: 0
+fn action_json(action: &Action) -> String {
+    match action {
+        &Action::Invoke(ref invoke) => {
+            let args = invoke.arguments.iter().map(|&a| json::spec_value(a)).collect::<Vec<_>>();
+            format!("{{\"type\":\"invoke\"{},\"field\":{},\"args\":[{}]}}",
+                module_field("module", &invoke.module), quote_name(&invoke.function_name), args.join(","))
+        }
+        &Action::Get(ref module, ref name) => {
+            format!("{{\"type\":\"get\"{},\"field\":{}}}", module_field("module", module), quote_name(name))
         }
     }
+}
 
-    fn parse_op(&mut self, s: &'a Sexpr) {
-        sexpr_match!(s;
-            (ident:&op *args) => {
-                match op.as_slice() {
-                    b"nop" => {self.push(NormalOp::Nop);},
-                    b"block" | b"then" | b"else" => {
-                        let (index, label_name) = if args.len() > 0 {
-                            match &args[0] {
-                                &Sexpr::Variable(ref v) => (1, Some(v.as_slice())),
-                                _ => (0, None)
-                            }
-                        } else {
-                            (0, None)
-                        };
-
-                        self.label_names.push(label_name);
-
-                        self.func.write(LinearOp::Block);
-                        self.parse_ops(&args[index..]);
-                        self.func.write(LinearOp::End);
-
-                        self.label_names.pop().unwrap();
-                    }
-                    b"loop" => {
-                        let (index, label_name_begin) = if args.len() > 0 {
-                            match &args[0] {
-                                &Sexpr::Variable(ref v) => (1, Some(v.as_slice())),
-                                _ => (0, None)
-                            }
-                        } else {
-                            (0, None)
-                        };
-
-                        let (index, label_name_end) = if index + 1 < args.len() {
-                            match &args[index] {
-                                &Sexpr::Variable(ref v) => (index + 1, Some(v.as_slice())),
-                                _ => (index, None)
-                            }
-                        } else {
-                            (index, None)
-                        };
-
-                        self.label_names.push(label_name_begin);
-                        self.label_names.push(label_name_end);
-
-                        self.func.write(LinearOp::Loop);
-                        self.parse_ops(&args[index..]);
-                        self.func.write(LinearOp::End);
-
-                        self.label_names.pop().unwrap();
-                        self.label_names.pop().unwrap();
-                    }
-                    b"if" => {
-                        assert!(args.len() == 2 || args.len() == 3);
-                        self.parse_op(&args[0]);
-                        self.func.write(LinearOp::If);
+/// Writes a module next to the JSON file as `{stem}.{n}.wasm`, or `.wat`
+/// if it only exists as text, and returns the `filename` and `module_type`
+/// fields that refer to it.
+fn write_source(dir: &Path, stem: &str, files: &mut usize, source: &Source) -> io::Result<String> {
+    let (extension, module_type, bytes) = match source {
+        &Source::Binary(ref bytes) => ("wasm", "binary", bytes.as_slice()),
+        &Source::Text(ref text) => ("wat", "text", text.as_bytes()),
+    };
+    let filename = format!("{}.{}.{}", stem, files, extension);
+    *files += 1;
+    File::create(dir.join(&filename))?.write_all(bytes)?;
+    Ok(format!(",\"filename\":{},\"module_type\":\"{}\"", json::quote(&filename), module_type))
+}
+
+/// Reads a module name, dropping the `$`.
+fn json_name(json: &Json, key: &str) -> Option<Vec<u8>> {
+    json.get(key).and_then(|n| n.as_str()).map(|n| n.strip_prefix('$').unwrap_or(n).as_bytes().to_vec())
+}
 
-                        self.label_names.push(None);
+fn parse_json_action(json: Option<&Json>) -> Result<Action, String> {
+    let json = json.ok_or("missing action")?;
+    let field = json.get("field").and_then(|f| f.as_str()).ok_or("missing field")?.as_bytes().to_vec();
+    match json.get("type").and_then(|t| t.as_str()) {
+        Some("invoke") => {
+            let mut arguments = Vec::new();
+            for a in json.get("args").and_then(|a| a.as_array()).ok_or("missing args")? {
+                arguments.push(json::parse_spec_value(a)?);
+            }
+            Ok(Action::Invoke(Invoke {
+                module: json_name(json, "module"),
+                function_name: field,
+                arguments,
+            }))
+        }
+        Some("get") => Ok(Action::Get(json_name(json, "module"), field)),
+        _ => Err("unknown action type".to_owned()),
+    }
+}
 
-                        self.parse_op(&args[1]);
-                        if args.len() == 3 {
-                            self.func.write(LinearOp::Else);
-                            self.parse_op(&args[2]);
-                        }
-                        self.func.write(LinearOp::End);
+/// Reads the module file a command refers to.
+fn read_source(json: &Json, dir: &Path) -> Result<Source, String> {
+    let filename = json.get("filename").and_then(|f| f.as_str()).ok_or("missing filename")?;
+    let mut bytes = Vec::new();
+    File::open(dir.join(filename)).and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|e| format!("can't read {}: {}", filename, e))?;
+    let text = json.get("module_type").and_then(|t| t.as_str()) == Some("text") || filename.ends_with(".wat");
+    if text {
+        String::from_utf8(bytes).map(Source::Text).map_err(|_| format!("{} isn't utf8", filename))
+    } else {
+        Ok(Source::Binary(bytes))
+    }
+}
 
-                        self.label_names.pop().unwrap();
-                    }
-                    b"select" => {
-                        assert!(self.parse_ops(args) == 3);
-                        self.push(NormalOp::Select);
-                    }
-                    b"br" => {
-                        let relative_depth = self.read_label(&args[0]);
-
-                        if args.len() > 1 {
-                            self.parse_op(&args[1]);
-                            self.push(NormalOp::Br{has_arg: true, relative_depth: relative_depth as u32});
-                        } else {
-                            self.push(NormalOp::Br{has_arg: false, relative_depth: relative_depth as u32});
-                        }
-                    }
-                    b"br_if" => {
-                        let relative_depth = self.read_label(&args[0]);
-                        self.parse_op(&args[1]);
-
-                        if args.len() > 2 {
-                            self.parse_op(&args[2]);
-                            self.push(NormalOp::BrIf{has_arg: true, relative_depth: relative_depth as u32});
-                        } else {
-                            self.push(NormalOp::BrIf{has_arg: false, relative_depth: relative_depth as u32});
-                        }
-                    }
-                    b"br_table" => {
-                        let mut target_data = Vec::new();
-
-                        fn write_u32(ast: &mut Vec<u8>, v: u32) {
-                            ast.push(((v >> 0*8) & 0xff) as u8);
-                            ast.push(((v >> 1*8) & 0xff) as u8);
-                            ast.push(((v >> 2*8) & 0xff) as u8);
-                            ast.push(((v >> 3*8) & 0xff) as u8);
-                        }
-
-                        let mut i = 0;
-                        let mut last = None;
-
-                        loop {
-                            match &args[i] {
-                                &Sexpr::Variable(_) | &Sexpr::Identifier(_) => {
-                                    let l = self.read_label(&args[i]) as u32;
-                                    last = Some(l);
-                                    write_u32(&mut target_data, l);
-                                }
-                                _ => break,
-                            }
-                            i += 1;
-                        }
-
-                        self.parse_op(&args[i]);
-                        if i + 1 < args.len() {
-                            self.parse_op(&args[i + 1]);
-                            self.push(NormalOp::BrTable{has_arg: true, target_data: &target_data[..target_data.len() - 4], default: last.unwrap()});
-                        } else {
-                            self.push(NormalOp::BrTable{has_arg: false, target_data: &target_data[..target_data.len() - 4], default: last.unwrap()});
-                        }
-                    }
-                    b"return" => {
-                        let num = self.parse_ops(args);
-                        assert!(num == 0 || num == 1);
-                        self.push(NormalOp::Return{has_arg: num == 1});
-                    }
-                    b"unreachable" => {
-                        self.push(NormalOp::Unreachable);
-                    }
-                    // "drop" => { self.push(NormalOp::Nop); }
-                    // "end" => { self.push(NormalOp::Nop); }
-                    b"i32.const" |
-                    b"i64.const" |
-                    b"f64.const" |
-                    b"f32.const" => {
-                        self.push(NormalOp::Const(parse_const(s)));
-                    }
-                    b"get_local" => {
-                        assert_eq!(args.len(), 1);
-                        let local = self.read_local(&args[0]);
-                        self.push(NormalOp::GetLocal(local));
-                    }
-                    b"set_local" => {
-                        assert_eq!(self.parse_ops(&args[1..]), 1);
-                        assert_eq!(args.len(), 2);
-                        let local = self.read_local(&args[0]);
-                        self.push(NormalOp::SetLocal(local));
-                    }
-                    b"tee_local" => {
-                        assert_eq!(self.parse_ops(&args[1..]), 1);
-                        assert_eq!(args.len(), 2);
-                        let local = self.read_local(&args[0]);
-                        self.push(NormalOp::TeeLocal(local));
-                    }
-                    b"call" => {
-                        let index = self.read_function(&args[0]);
-                        let num = self.parse_ops(&args[1..]);
-                        self.push(NormalOp::Call{argument_count: num as u32, index: FunctionIndex(index)});
-                    }
-                    b"call_indirect" => {
-                        let index = self.read_type(&args[0]);
-                        let num = self.parse_ops(&args[1..]);
-                        self.push(NormalOp::CallIndirect{argument_count: num as u32 - 1, index: TypeIndex(index)});
-                    }
-                    b"call_import" => {
-                        let index = self.read_import(&args[0]);
-                        let num = self.parse_ops(&args[1..]);
-                        self.push(NormalOp::CallImport{argument_count: num as u32, index: ImportIndex(index)});
-                    }
-                    b"i32.load8_s" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int32, Sign::Signed, Size::I8, memimm));
-                    }
-                    b"i32.load8_u" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int32, Sign::Unsigned, Size::I8, memimm));
-                    }
-                    b"i32.load16_s" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int32, Sign::Signed, Size::I16, memimm));
-                    }
-                    b"i32.load16_u" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int32, Sign::Unsigned, Size::I16, memimm));
-                    }
-                    b"i64.load8_s" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int64, Sign::Signed, Size::I8, memimm));
-                    }
-                    b"i64.load8_u" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int64, Sign::Unsigned, Size::I8, memimm));
-                    }
-                    b"i64.load16_s" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int64, Sign::Signed, Size::I16, memimm));
-                    }
-                    b"i64.load16_u" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int64, Sign::Unsigned, Size::I16, memimm));
-                    }
-                    b"i64.load32_s" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int64, Sign::Signed, Size::I32, memimm));
-                    }
-                    b"i64.load32_u" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int64, Sign::Unsigned, Size::I32, memimm));
-                    }
-                    b"i32.load" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int32, Sign::Unsigned, Size::I32, memimm));
-                    }
-                    b"i64.load" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::IntLoad(IntType::Int64, Sign::Unsigned, Size::I64, memimm));
-                    }
-                    b"f32.load" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::FloatLoad(FloatType::Float32, memimm));
-                    }
-                    b"f64.load" => {
-                        let memimm = self.parse_mem_imm(args, 1);
-                        self.push(NormalOp::FloatLoad(FloatType::Float64, memimm));
-                    }
-                    b"i32.store8" => {
-                        let memimm = self.parse_mem_imm(args, 2);
-                        self.push(NormalOp::IntStore(IntType::Int32, Size::I8, memimm));
-                    }
-                    b"i32.store16" => {
-                        let memimm = self.parse_mem_imm(args, 2);
-                        self.push(NormalOp::IntStore(IntType::Int32, Size::I16, memimm));
-                    }
-                    b"i64.store8" => {
-                        let memimm = self.parse_mem_imm(args, 2);
-                        self.push(NormalOp::IntStore(IntType::Int64, Size::I8, memimm));
-                    }
-                    b"i64.store16" => {
-                        let memimm = self.parse_mem_imm(args, 2);
-                        self.push(NormalOp::IntStore(IntType::Int64, Size::I16, memimm));
-                    }
-                    b"i64.store32" => {
-                        let memimm = self.parse_mem_imm(args, 2);
-                        self.push(NormalOp::IntStore(IntType::Int64, Size::I32, memimm));
-                    }
-                    b"i32.store" => {
-                        let memimm = self.parse_mem_imm(args, 2);
-                        self.push(NormalOp::IntStore(IntType::Int32, Size::I32, memimm));
-                    }
-                    b"i64.store" => {
-                        let memimm = self.parse_mem_imm(args, 2);
-                        self.push(NormalOp::IntStore(IntType::Int64, Size::I64, memimm));
-                    }
-                    b"f32.store" => {
-                        let memimm = self.parse_mem_imm(args, 2);
-                        self.push(NormalOp::FloatStore(FloatType::Float32, memimm));
-                    }
-                    b"f64.store" => {
-                        let memimm = self.parse_mem_imm(args, 2);
-                        self.push(NormalOp::FloatStore(FloatType::Float64, memimm));
-                    }
-                    b"current_memory" => {
-                        assert_eq!(args.len(), 0);
-                        self.push(NormalOp::CurrentMemory);
-                    }
-                    b"grow_memory" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::GrowMemory);
-                    }
-                    b"i32.add" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::Add));
-                    }
-                    b"i32.sub" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::Sub));
-                    }
-                    b"i32.mul" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::Mul));
-                    }
-                    b"i32.div_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::DivS));
-                    }
-                    b"i32.div_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::DivU));
-                    }
-                    b"i32.rem_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::RemS));
-                    }
-                    b"i32.rem_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::RemU));
-                    }
-                    b"i32.and" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::And));
-                    }
-                    b"i32.or" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::Or));
-                    }
-                    b"i32.xor" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::Xor));
-                    }
-                    b"i32.shl" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::Shl));
-                    }
-                    b"i32.shr_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::ShrU));
-                    }
-                    b"i32.shr_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::ShrS));
-                    }
-                    b"i32.rotr" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::Rotr));
-                    }
-                    b"i32.rotl" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int32, IntBinOp::Rotl));
-                    }
-                    b"i32.eq" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::Eq));
-                    }
-                    b"i32.ne" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::Ne));
-                    }
-                    b"i32.lt_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::LtS));
-                    }
-                    b"i32.le_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::LeS));
-                    }
-                    b"i32.lt_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::LtU));
-                    }
-                    b"i32.le_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::LeU));
-                    }
-                    b"i32.gt_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::GtS));
-                    }
-                    b"i32.ge_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::GeS));
-                    }
-                    b"i32.gt_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::GtU));
-                    }
-                    b"i32.ge_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int32, IntCmpOp::GeU));
-                    }
-                    b"i32.clz" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntUn(IntType::Int32, IntUnOp::Clz));
-                    }
-                    b"i32.ctz" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntUn(IntType::Int32, IntUnOp::Ctz));
-                    }
-                    b"i32.popcnt" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntUn(IntType::Int32, IntUnOp::Popcnt));
-                    }
-                    b"i32.eqz" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntEqz(IntType::Int32));
-                    }
-                    b"i64.add" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::Add));
-                    }
-                    b"i64.sub" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::Sub));
-                    }
-                    b"i64.mul" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::Mul));
-                    }
-                    b"i64.div_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::DivS));
-                    }
-                    b"i64.div_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::DivU));
-                    }
-                    b"i64.rem_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::RemS));
-                    }
-                    b"i64.rem_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::RemU));
-                    }
-                    b"i64.and" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::And));
-                    }
-                    b"i64.or" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::Or));
-                    }
-                    b"i64.xor" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::Xor));
-                    }
-                    b"i64.shl" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::Shl));
-                    }
-                    b"i64.shr_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::ShrU));
-                    }
-                    b"i64.shr_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::ShrS));
-                    }
-                    b"i64.rotr" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::Rotr));
-                    }
-                    b"i64.rotl" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntBin(IntType::Int64, IntBinOp::Rotl));
-                    }
-                    b"i64.eq" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::Eq));
-                    }
-                    b"i64.ne" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::Ne));
-                    }
-                    b"i64.lt_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::LtS));
-                    }
-                    b"i64.le_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::LeS));
-                    }
-                    b"i64.lt_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::LtU));
-                    }
-                    b"i64.le_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::LeU));
-                    }
-                    b"i64.gt_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::GtS));
-                    }
-                    b"i64.ge_s" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::GeS));
-                    }
-                    b"i64.gt_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::GtU));
-                    }
-                    b"i64.ge_u" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::IntCmp(IntType::Int64, IntCmpOp::GeU));
-                    }
-                    b"i64.clz" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntUn(IntType::Int64, IntUnOp::Clz));
-                    }
-                    b"i64.ctz" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntUn(IntType::Int64, IntUnOp::Ctz));
-                    }
-                    b"i64.popcnt" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntUn(IntType::Int64, IntUnOp::Popcnt));
-                    }
-                    b"i64.eqz" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntEqz(IntType::Int64));
-                    }
-                    b"f32.add" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float32, FloatBinOp::Add));
-                    }
-                    b"f32.sub" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float32, FloatBinOp::Sub));
-                    }
-                    b"f32.mul" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float32, FloatBinOp::Mul));
-                    }
-                    b"f32.div" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float32, FloatBinOp::Div));
-                    }
-                    b"f32.min" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float32, FloatBinOp::Min));
-                    }
-                    b"f32.max" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float32, FloatBinOp::Max));
-                    }
-                    b"f32.copysign" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float32, FloatBinOp::Copysign));
-                    }
-                    b"f32.abs" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float32, FloatUnOp::Abs));
-                    }
-                    b"f32.neg" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float32, FloatUnOp::Neg));
-                    }
-                    b"f32.ceil" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float32, FloatUnOp::Ceil));
-                    }
-                    b"f32.floor" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float32, FloatUnOp::Floor));
-                    }
-                    b"f32.trunc" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float32, FloatUnOp::Trunc));
-                    }
-                    b"f32.nearest" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float32, FloatUnOp::Nearest));
-                    }
-                    b"f32.sqrt" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float32, FloatUnOp::Sqrt));
-                    }
-                    b"f32.eq" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float32, FloatCmpOp::Eq));
-                    }
-                    b"f32.ne" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float32, FloatCmpOp::Ne));
-                    }
-                    b"f32.lt" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float32, FloatCmpOp::Lt));
-                    }
-                    b"f32.le" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float32, FloatCmpOp::Le));
-                    }
-                    b"f32.gt" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float32, FloatCmpOp::Gt));
-                    }
-                    b"f32.ge" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float32, FloatCmpOp::Ge));
-                    }
-                    b"f64.add" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float64, FloatBinOp::Add));
-                    }
-                    b"f64.sub" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float64, FloatBinOp::Sub));
-                    }
-                    b"f64.mul" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float64, FloatBinOp::Mul));
-                    }
-                    b"f64.div" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float64, FloatBinOp::Div));
-                    }
-                    b"f64.min" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float64, FloatBinOp::Min));
-                    }
-                    b"f64.max" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float64, FloatBinOp::Max));
-                    }
-                    b"f64.copysign" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatBin(FloatType::Float64, FloatBinOp::Copysign));
-                    }
-                    b"f64.abs" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float64, FloatUnOp::Abs));
-                    }
-                    b"f64.neg" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float64, FloatUnOp::Neg));
-                    }
-                    b"f64.ceil" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float64, FloatUnOp::Ceil));
-                    }
-                    b"f64.floor" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float64, FloatUnOp::Floor));
-                    }
-                    b"f64.trunc" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float64, FloatUnOp::Trunc));
-                    }
-                    b"f64.nearest" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float64, FloatUnOp::Nearest));
-                    }
-                    b"f64.sqrt" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatUn(FloatType::Float64, FloatUnOp::Sqrt));
-                    }
-                    b"f64.eq" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float64, FloatCmpOp::Eq));
-                    }
-                    b"f64.ne" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float64, FloatCmpOp::Ne));
-                    }
-                    b"f64.lt" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float64, FloatCmpOp::Lt));
-                    }
-                    b"f64.le" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float64, FloatCmpOp::Le));
-                    }
-                    b"f64.gt" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float64, FloatCmpOp::Gt));
-                    }
-                    b"f64.ge" => {
-                        assert_eq!(self.parse_ops(args), 2);
-                        self.push(NormalOp::FloatCmp(FloatType::Float64, FloatCmpOp::Ge));
-                    }
-                    b"i32.trunc_s/f32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatToInt(FloatType::Float32, IntType::Int32, Sign::Signed));
-                    }
-                    b"i32.trunc_s/f64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatToInt(FloatType::Float64, IntType::Int32, Sign::Signed));
-                    }
-                    b"i32.trunc_u/f32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatToInt(FloatType::Float32, IntType::Int32, Sign::Unsigned));
-                    }
-                    b"i32.trunc_u/f64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatToInt(FloatType::Float64, IntType::Int32, Sign::Unsigned));
-                    }
-                    b"i32.wrap/i64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntTruncate);
-                    }
-                    b"i64.trunc_s/f32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatToInt(FloatType::Float32, IntType::Int64, Sign::Signed));
-                    }
-                    b"i64.trunc_s/f64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatToInt(FloatType::Float64, IntType::Int64, Sign::Signed));
-                    }
-                    b"i64.trunc_u/f32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatToInt(FloatType::Float32, IntType::Int64, Sign::Unsigned));
-                    }
-                    b"i64.trunc_u/f64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatToInt(FloatType::Float64, IntType::Int64, Sign::Unsigned));
-                    }
-                    b"i64.extend_s/i32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntExtend(Sign::Signed));
-                    }
-                    b"i64.extend_u/i32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntExtend(Sign::Unsigned));
-                    }
-                    b"f32.convert_s/i32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntToFloat(IntType::Int32, Sign::Signed, FloatType::Float32));
-                    }
-                    b"f32.convert_u/i32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntToFloat(IntType::Int32, Sign::Unsigned, FloatType::Float32));
-                    }
-                    b"f32.convert_s/i64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntToFloat(IntType::Int64, Sign::Signed, FloatType::Float32));
-                    }
-                    b"f32.convert_u/i64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntToFloat(IntType::Int64, Sign::Unsigned, FloatType::Float32));
-                    }
-                    b"f32.demote/f64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatConvert(FloatType::Float32));
-                    }
-                    b"f32.reinterpret/i32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::Reinterpret(Type::Int32, Type::Float32));
+fn parse_json_command(json: &Json, dir: &Path) -> Result<Command, String> {
+    let text = || json.get("text").and_then(|t| t.as_str()).map(|t| t.to_owned()).ok_or("missing text");
+    let action = || parse_json_action(json.get("action"));
+    let assert = |a| Ok(Command::Assert(a));
+    match json.get("type").and_then(|t| t.as_str()) {
+        Some("module") => Ok(Command::Module(json_name(json, "name"), parse_source(&read_source(json, dir)?))),
+        Some("register") => {
+            let as_name = json.get("as").and_then(|a| a.as_str()).ok_or("missing as")?.as_bytes().to_vec();
+            Ok(Command::Register(as_name, json_name(json, "name")))
+        }
+        Some("action") => assert(Assert::NoTrap(action()?)),
+        Some("assert_return") => {
+            let expected = json.get("expected").and_then(|e| e.as_array()).ok_or("missing expected")?;
+            let result = match expected.first() {
+                Some(v) => Some(json::parse_spec_value(v)?),
+                None => None,
+            };
+            assert(Assert::Return(action()?, result))
+        }
+        Some("assert_return_nan") |
+        Some("assert_return_canonical_nan") |
+        Some("assert_return_arithmetic_nan") => assert(Assert::ReturnNan(action()?)),
+        Some("assert_trap") => assert(Assert::Trap(action()?, text()?)),
+        Some("assert_exhaustion") => assert(Assert::Exhaustion(action()?, text()?)),
+        Some("assert_malformed") => {
+            let source = read_source(json, dir)?;
+            let result = parse_source(&source).map(|_| ());
+            assert(Assert::Malformed(source, result, text()?))
+        }
+        Some("assert_invalid") => assert(Assert::Invalid(read_source(json, dir)?, text()?)),
+        Some("assert_unlinkable") => {
+            assert(Assert::Unlinkable(parse_source(&read_source(json, dir)?), text()?))
+        }
+        Some("assert_uninstantiable") => {
+            assert(Assert::Uninstantiable(parse_source(&read_source(json, dir)?), text()?))
+        }
+        Some(other) => Err(format!("unhandled command type {}", other)),
+        None => Err("missing command type".to_owned()),
+    }
+}
+
+impl TestCase {
+    /// Writes the script in wast2json's layout: `{stem}.json` listing the
+    /// commands, with each module in a file of its own. Modules are
+    /// re-encoded from the compiled text, so that running the result tests
+    /// the binary decoder. Fails if a module that should load doesn't.
+    pub fn write_json_commands(&self, dir: &Path, stem: &str) -> io::Result<()> {
+        let invalid = |line, e: &str| Err(io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, e)));
+        let mut files = 0;
+        let mut commands = Vec::new();
+        for &(line, ref command) in &self.commands {
+            let fields = match command {
+                &Command::Module(ref name, Ok(ref module)) => {
+                    let file = write_source(dir, stem, &mut files, &Source::Binary(module.encode()))?;
+                    format!("\"type\":\"module\"{}{}", module_field("name", name), file)
+                }
+                &Command::Module(_, Err(ref e)) |
+                &Command::Assert(Assert::Unlinkable(Err(ref e), _)) |
+                &Command::Assert(Assert::Uninstantiable(Err(ref e), _)) |
+                &Command::Error(ref e) => return invalid(line, e),
+                &Command::Register(ref as_name, ref name) => {
+                    format!("\"type\":\"register\"{},\"as\":{}", module_field("name", name), quote_name(as_name))
+                }
+                &Command::Assert(ref assert) => match assert {
+                    &Assert::Return(ref action, result) => {
+                        format!("\"type\":\"assert_return\",\"action\":{},\"expected\":[{}]", action_json(action),
+                            result.map(json::spec_value).unwrap_or_default())
                     }
-                    b"f64.convert_s/i32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntToFloat(IntType::Int32, Sign::Signed, FloatType::Float64));
+                    &Assert::ReturnNan(ref action) => {
+                        format!("\"type\":\"assert_return_nan\",\"action\":{},\"expected\":[]", action_json(action))
                     }
-                    b"f64.convert_u/i32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntToFloat(IntType::Int32, Sign::Unsigned, FloatType::Float64));
+                    &Assert::Trap(ref action, ref text) => {
+                        format!("\"type\":\"assert_trap\",\"action\":{},\"text\":{},\"expected\":[]",
+                            action_json(action), json::quote(text))
                     }
-                    b"f64.convert_s/i64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntToFloat(IntType::Int64, Sign::Signed, FloatType::Float64));
+                    &Assert::NoTrap(ref action) => {
+                        format!("\"type\":\"action\",\"action\":{},\"expected\":[]", action_json(action))
                     }
-                    b"f64.convert_u/i64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::IntToFloat(IntType::Int64, Sign::Unsigned, FloatType::Float64));
+                    &Assert::Exhaustion(ref action, ref text) => {
+                        format!("\"type\":\"assert_exhaustion\",\"action\":{},\"text\":{},\"expected\":[]",
+                            action_json(action), json::quote(text))
                     }
-                    b"f64.promote/f32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::FloatConvert(FloatType::Float64));
+                    &Assert::Malformed(ref source, _, ref text) => {
+                        let file = write_source(dir, stem, &mut files, source)?;
+                        format!("\"type\":\"assert_malformed\"{},\"text\":{}", file, json::quote(text))
                     }
-                    b"f64.reinterpret/i64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::Reinterpret(Type::Int64, Type::Float64));
+                    &Assert::Invalid(ref source, ref text) => {
+                        let file = write_source(dir, stem, &mut files, source)?;
+                        format!("\"type\":\"assert_invalid\"{},\"text\":{}", file, json::quote(text))
                     }
-                    b"i32.reinterpret/f32" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::Reinterpret(Type::Float32, Type::Int32));
+                    &Assert::Unlinkable(Ok(ref module), ref text) => {
+                        let file = write_source(dir, stem, &mut files, &Source::Binary(module.encode()))?;
+                        format!("\"type\":\"assert_unlinkable\"{},\"text\":{}", file, json::quote(text))
                     }
-                    b"i64.reinterpret/f64" => {
-                        assert_eq!(self.parse_ops(args), 1);
-                        self.push(NormalOp::Reinterpret(Type::Float64, Type::Int64));
+                    &Assert::Uninstantiable(Ok(ref module), ref text) => {
+                        let file = write_source(dir, stem, &mut files, &Source::Binary(module.encode()))?;
+                        format!("\"type\":\"assert_uninstantiable\"{},\"text\":{}", file, json::quote(text))
                     }
-                    _ => panic!("unexpected instr: {}", s)
-                };
+                    &Assert::Unlinkable(Err(_), _) | &Assert::Uninstantiable(Err(_), _) => unreachable!(),
+                },
             };
-            _ => panic!("unexpected instr: {}", s)
-        );
+            commands.push(format!("  {{\"line\":{},{}}}", line, fields));
+        }
+        let mut out = File::create(dir.join(format!("{}.json", stem)))?;
+        writeln!(out, "{{\"source_filename\":{},\"commands\":[\n{}\n]}}",
+            json::quote(&format!("{}.wast", stem)), commands.join(",\n"))
     }
-}
 
-fn parse_int(node: &Sexpr, ty: IntType) -> Dynamic {
-    match node {
-        &Sexpr::Identifier(ref text) => {
-            // println!("parsing int {}", text);
-            match ty {
-                IntType::Int32 => {
-                    if text.starts_with(b"-0x") {
-                        Dynamic::Int32(!Wrapping(u32::from_str_radix(str::from_utf8(&text[3..]).unwrap(), 16).unwrap()) + Wrapping(1))
-                    } else if text.starts_with(b"-") {
-                        Dynamic::from_i32(i32::from_str_radix(str::from_utf8(text).unwrap(), 10).unwrap())
-                    } else if text.starts_with(b"0x") {
-                        Dynamic::from_u32(u32::from_str_radix(str::from_utf8(&text[2..]).unwrap(), 16).unwrap())
-                    } else {
-                        Dynamic::from_u32(u32::from_str_radix(str::from_utf8(text).unwrap(), 10).unwrap())
-                    }
-                }
-                IntType::Int64 => {
-                    if text.starts_with(b"-0x") {
-                        Dynamic::Int64(!Wrapping(u64::from_str_radix(str::from_utf8(&text[3..]).unwrap(), 16).unwrap()) + Wrapping(1))
-                    } else if text.starts_with(b"-") {
-                        Dynamic::from_i64(i64::from_str_radix(str::from_utf8(text).unwrap(), 10).unwrap())
-                    } else if text.starts_with(b"0x") {
-                        Dynamic::from_u64(u64::from_str_radix(str::from_utf8(&text[2..]).unwrap(), 16).unwrap())
-                    } else {
-                        Dynamic::from_u64(u64::from_str_radix(str::from_utf8(text).unwrap(), 10).unwrap())
-                    }
-                }
-            }
-        }
-        _ => panic!("expected number id: {}", node)
+    /// Reads a script written in wast2json's layout, finding the module
+    /// files next to the JSON file. Commands that can't be read are kept,
+    /// and fail when the script is run.
+    pub fn parse_json_commands(path: &Path) -> Result<TestCase, String> {
+        let mut text = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut text)).map_err(|e| e.to_string())?;
+        let json = Json::parse(&text)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let commands = json.get("commands").and_then(|c| c.as_array()).ok_or("missing commands")?;
+        Ok(TestCase {
+            commands: commands.iter().map(|c| {
+                let line = c.get("line").and_then(|l| l.as_u64()).unwrap_or(0) as usize;
+                (line, parse_json_command(c, dir).unwrap_or_else(Command::Error))
+            }).collect()
+        })
     }
 }
 
-fn parse_float(node: &Sexpr, ty: FloatType) -> Dynamic {
-    match node {
-        &Sexpr::Identifier(ref text) => {
-            println!("parsing {}", str::from_utf8(text).unwrap());
-
-            let mut text = text.as_slice();
-            if text.starts_with(b"+") {
-                text = &text[1..];
-            }
-            let neg = if text.starts_with(b"-") {
-                text = &text[1..];
-                true
-            } else {
-                false
-            };
+/// The functions of the reference interpreter's `spectest` module, by name
+/// and parameter types. None of them return anything. The reference module
+/// also exports `global_i32`, `table` and `memory`, but modules in this
+/// format can only import functions.
+const SPECTEST_FUNCTIONS: &'static [(&'static [u8], &'static [Type])] = &[
+    (b"print", &[]),
+    (b"print_i32", &[Type::Int32]),
+    (b"print_i64", &[Type::Int64]),
+    (b"print_f32", &[Type::Float32]),
+    (b"print_f64", &[Type::Float64]),
+    (b"print_i32_f32", &[Type::Int32, Type::Float32]),
+    (b"print_f64_f64", &[Type::Float64, Type::Float64]),
+];
+
+/// The `spectest` host module. Its prints are captured, one line per
+/// argument in the reference interpreter's `42 : i32` form, rather than
+/// written to stdout.
+struct SpecTestModule {
+    output: Rc<RefCell<Vec<String>>>,
+}
 
-            let mut res = if text.starts_with(b"0x") {
-                unsafe { mem::transmute(hexfloat::parse_bits_64(str::from_utf8(text).unwrap())) }
-            } else if text == b"infinity" {
-                f64::INFINITY
-            } else if text == b"nan" {
-                f64::NAN
-            } else if text.starts_with(b"nan:0x") {
-                let value = u64::from_str_radix(str::from_utf8(&text[b"nan:0x".len()..]).unwrap(), 16).unwrap();
-                assert!(value != 0);
-                return match ty {
-                    FloatType::Float32 => {
-                        let mut bits: u32 = ((1 << 8) - 1) << 23;
-                        bits |= value as u32;
-                        if neg {
-                            bits |= 0x8000_0000;
-                        }
-                        Dynamic::Float32(unsafe { mem::transmute(bits) })
-                    },
-                    FloatType::Float64 => {
-                        let mut bits: u64 = ((1 << 11) - 1) << 52;
-                        bits |= value;
-                        if neg {
-                            bits |= 0x8000_0000_0000_0000;
-                        }
-                        Dynamic::Float64(unsafe { mem::transmute(bits) })
-                    },
-                };
-            } else {
-                f64::from_str(str::from_utf8(text).unwrap()).unwrap()
+impl BoundInstance for SpecTestModule {
+    fn invoke_export(&mut self, _func: ExportIndex, args: &[Dynamic]) -> InterpResult {
+        let mut output = self.output.borrow_mut();
+        for &a in args {
+            let value = match a {
+                Dynamic::Int32(v) => (v.0 as i32).to_string(),
+                Dynamic::Int64(v) => (v.0 as i64).to_string(),
+                Dynamic::Float32(_) | Dynamic::Float64(_) => format_float(a, FloatFormat::Decimal),
             };
-
-            if neg {
-                res = -res;
-            }
-
-            match ty {
-                FloatType::Float32 => Dynamic::Float32(res as f32),
-                FloatType::Float64 => Dynamic::Float64(res),
-            }
+            output.push(format!("{} : {}", value, a.get_type()));
         }
-        _ => panic!("expected number id: {}", node)
+        InterpResult::Value(None)
     }
-}
-
-fn parse_bin_string(node: &Sexpr) -> Vec<u8> {
-    match node {
-        &Sexpr::String(ref text) => Vec::from(text.as_bytes()),
-        _ => panic!()
+    fn export_by_name_and_type(&self, name: &[u8], ty: FunctionType<&[u8]>) -> ExportIndex {
+        SpecTestModule::find_export(name, ty).unwrap()
     }
 }
 
-fn parse_name(node: &Sexpr) -> Vec<u8> {
-    match node {
-        &Sexpr::String(ref text) => text.clone(),
-        _ => panic!("bad name: {:?}", node),
+impl SpecTestModule {
+    /// `print` predates the typed variants and accepts any parameters.
+    fn find_export(name: &[u8], ty: FunctionType<&[u8]>) -> Option<ExportIndex> {
+        if ty.return_type.is_some() {
+            return None;
+        }
+        SPECTEST_FUNCTIONS.iter().position(|&(export, params)| {
+            export == name && (export == b"print" ||
+                params.iter().map(|&t| t as u8).eq(ty.param_types.iter().cloned()))
+        }).map(ExportIndex)
     }
 }
 
-fn parse_var_id(node: &Sexpr) -> &[u8] {
-    match node {
-        &Sexpr::Variable(ref text) => text.as_slice(),
-        _ => panic!("bad var: {:?}", node),
-    }
-}
 
-fn parse_function_ty(
-    type_names: &HashMap<&[u8], usize>,
-    types: &mut Vec<FunctionType<Vec<u8>>>,
-    node: &Sexpr) -> TypeIndex {
+#[test]
+fn test_script_commands() {
+    let script = br#"
+        (module $A
+            (func $add (export "add") (param i32 i32) (result i32) (i32.add (get_local 0) (get_local 1)))
+            (func (export "loop") (call 1)))
+        (register "a" $A)
+        (module $B
+            (func $add (import "a" "add") (param i32 i32) (result i32))
+            (func (export "add3") (param i32) (result i32) (call_import $add (get_local 0) (i32.const 3))))
+        (assert_return (invoke "add3" (i32.const 4)) (i32.const 7))
+        (assert_return (invoke $A "add" (i32.const 1) (i32.const 2)) (i32.const 3))
+        (assert_exhaustion (invoke $A "loop") "call stack exhausted")
+        (module quote "(func (export \"one\") (result i32) (i32.const 1))")
+        (assert_return (invoke "one") (i32.const 1))
+        (assert_malformed (module quote "(func (i32.const 0x))") "malformed number")
+        (assert_malformed (module binary "\00asm\01\00\00\00") "unknown binary version")
+        (assert_unlinkable (module (func (import "a" "sub") (param i32 i32) (result i32))) "unknown import")
+        (assert_unlinkable (module (func (import "b" "add") (param i32 i32) (result i32))) "unknown module")
+        (assert_trap (module (func $f unreachable) (start $f)) "unreachable")
+    "#;
+    let case = TestCase::parse(script);
+    assert_eq!(case.commands.len(), 13);
+    case.run_all();
+}
 
-    sexpr_match!(node;
-        (param *params) => {
-            let mut ty = FunctionType {
-                param_types: Vec::new(),
-                return_type: None
-            };
-            for p in params {
-                ty.param_types.push(parse_type_expr(p).to_u8());
-            }
-            let index = types.len();
-            types.push(ty);
-            return TypeIndex(index);
-        };
-        (type &id) => {
-            let index = match id {
-                &Sexpr::Identifier(ref id) => {
-                    usize::from_str(str::from_utf8(id.as_slice()).unwrap()).unwrap()
-                }
-                &Sexpr::Variable(ref id) => {
-                    *type_names.get(id.as_slice()).unwrap()
-                }
-                _ => panic!()
-            };
-            return TypeIndex(index);
-        };
-        _ => panic!("unknown ty spec: {}", node)
-    );
-    panic!();
+#[test]
+fn test_report() {
+    let script = br#"
+        (module (func (export "div") (param i32) (result i32) (i32.div_s (i32.const 1) (get_local 0))))
+        (assert_return (invoke "div" (i32.const 1)) (i32.const 2))
+        (assert_trap (invoke "div" (i32.const 0)) "integer divide by zero")
+        (assert_trap (invoke "div" (i32.const 0)) "integer overflow")
+        (assert_return (invoke "div" (i32.const 1)) (i32.const 1))
+        (assert_return (invoke "missing"))
+        (assert_invalid (module (func (result i32))) "type mismatch")
+        (assert_bogus)
+        (module (func (i32.const 0x)))
+        (assert_return (invoke "div" (i32.const 1)) (i32.const 1))
+    "#;
+    let report = TestCase::parse(script).run();
+    let statuses = report.outcomes.iter().map(|o| (o.line, o.status)).collect::<Vec<_>>();
+    assert_eq!(statuses, vec![(2, Status::Pass), (3, Status::Fail), (4, Status::Pass), (5, Status::Fail),
+        (6, Status::Pass), (7, Status::Fail), (8, Status::Skip), (9, Status::Fail), (10, Status::Fail),
+        (11, Status::Skip)]);
+    assert_eq!((report.outcomes[1].expected.as_str(), report.outcomes[1].actual.as_str()), ("i32:2", "i32:1"));
+    assert_eq!(report.outcomes[3].actual, "trap: integer divide by zero");
+    assert_eq!((report.passed(), report.failed(), report.skipped()), (3, 5, 2));
+
+    let mut json = Vec::new();
+    report.write_json(&mut json).unwrap();
+    assert!(json::Json::parse(str::from_utf8(&json).unwrap()).is_ok());
+    let mut junit = Vec::new();
+    report.write_junit("div.wast", &mut junit).unwrap();
+    let junit = String::from_utf8(junit).unwrap();
+    assert!(junit.contains("<testsuite name=\"div.wast\" tests=\"10\" failures=\"5\" skipped=\"2\">"));
+    assert!(junit.contains("<failure message=\"expected i32:2, got i32:1\"/>"));
 }
 
-fn parse_type_signature(node: &Sexpr) -> FunctionType<Vec<u8>> {
-    let mut ty = FunctionType {
-        param_types: Vec::new(),
-        return_type: None
-    };
+#[test]
+fn test_spectest_prints() {
+    let script = br#"
+        (module
+            (import $i32 "spectest" "print_i32" (param i32))
+            (import $mixed "spectest" "print_i32_f32" (param i32 f32))
+            (func (export "run")
+                (call_import $i32 (i32.const 42))
+                (call_import $mixed (i32.const -1) (f32.const 1.5))))
+        (invoke "run")
+        (assert_unlinkable (module (import "spectest" "print_i32" (param i64))) "unknown import")
+        (assert_unlinkable (module (import "spectest" "print_f64" (param f64) (result f64))) "unknown import")
+    "#;
+    let report = TestCase::parse(script).run();
+    assert_eq!(report.failed(), 0);
+    assert_eq!(report.outcomes[1].output, vec!["42 : i32", "-1 : i32", "1.5 : f32"]);
+    assert!(report.outcomes[2].output.is_empty());
+}
 
-    sexpr_match!(node;
-        (func *args) => {
-            for s in args {
-                sexpr_match!(s;
-                    (param *params) => {
-                        for p in params {
-                            ty.param_types.push(parse_type_expr(p).to_u8());
-                        }
-                    };
-                    (result &a) => {
-                        ty.return_type = Some(parse_type_expr(a));
-                    };
-                    _ => panic!("unexpected sig part: {}", node)
-                );
-            }
-        };
-        _ => panic!("unknown ty sig: {}", node)
-    );
-    ty
+#[test]
+fn test_json_commands() {
+    use std::{env, fs};
+    let script = br#"
+        (module $A
+            (func (export "add") (param i32 i32) (result i32) (i32.add (get_local 0) (get_local 1)))
+            (func (export "$div") (param i64) (result i64) (i64.div_u (i64.const 1) (get_local 0))))
+        (register "a" $A)
+        (module (func $add (import "a" "add") (param i32 i32) (result i32))
+            (func (export "neg") (param f32) (result f32) (f32.neg (get_local 0))))
+        (assert_return (invoke $A "add" (i32.const -1) (i32.const 2)) (i32.const 1))
+        (assert_return (invoke "neg" (f32.const 1.5)) (f32.const -1.5))
+        (assert_trap (invoke $A "$div" (i64.const 0)) "integer divide by zero")
+        (invoke $A "$div" (i64.const 1))
+        (assert_malformed (module quote "(func (i32.const 0x))") "malformed number")
+        (assert_invalid (module (func (result i32))) "type mismatch")
+        (assert_unlinkable (module (func (import "a" "sub"))) "unknown import")
+    "#;
+    let dir = env::temp_dir().join(format!("wasm-json-commands-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    TestCase::parse(script).write_json_commands(&dir, "script").unwrap();
+    let case = TestCase::parse_json_commands(&dir.join("script.json")).unwrap();
+    let report = case.run();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let statuses = report.outcomes.iter().map(|o| (o.line, o.status)).collect::<Vec<_>>();
+    assert_eq!(statuses, vec![(2, Status::Pass), (5, Status::Pass), (6, Status::Pass), (8, Status::Pass),
+        (9, Status::Pass), (10, Status::Pass), (11, Status::Pass), (12, Status::Pass), (13, Status::Skip),
+        (14, Status::Pass)]);
+    assert_eq!(report.outcomes[5].actual, "trap: integer divide by zero");
 }
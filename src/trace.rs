@@ -0,0 +1,222 @@
+use std::io::{self, Write};
+
+use module::FunctionIndex;
+use types::Dynamic;
+use ops::NormalOp;
+use watch::{Access, MemoryAccess};
+use json;
+
+/// Writes an execution trace as JSON Lines while attached to an `Instance`
+/// (see `Instance::tracer`), one object per event:
+///
+/// ```text
+/// {"event":"call","function":1,"args":[...]}
+/// {"event":"if","function":1,"offset":2,"consumed":[...]}
+/// {"event":"else","function":1,"offset":2}
+/// {"event":"op","function":1,"offset":4,"op":"i32.add","consumed":[...],"produced":...,"memory":[...]}
+/// {"event":"end","function":1,"offset":2,"produced":...}
+/// {"event":"return","function":1,"result":...}
+/// {"event":"trap","function":1,"offset":9,"op":"unreachable"}
+/// ```
+///
+/// Values are written as `{"type":"i32","bits":"0x0000002a"}`, or `null`
+/// for ops that leave nothing on the stack, so floats compare exactly. An
+/// op's event is written after it completes, so the events of any calls it
+/// makes come first. A `block`, `loop` or `if` event is written on entry,
+/// followed by `else` if the else arm runs, and its `end` event (carrying
+/// the offset of the block's header) once control leaves it other than by
+/// trapping. A trap is reported at the trapping op and again at each call
+/// op it unwinds through.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    /// Only trace these functions, if set.
+    pub functions: Option<Vec<FunctionIndex>>,
+    /// Stop before the output grows past this many bytes, if set. A final
+    /// `{"event":"truncated"}` line, not counted against the cap, marks the
+    /// cut.
+    pub max_bytes: Option<u64>,
+    bytes: u64,
+    truncated: bool,
+    memory: Vec<MemoryAccess>,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>) -> Tracer {
+        Tracer {
+            out,
+            functions: None,
+            max_bytes: None,
+            bytes: 0,
+            truncated: false,
+            memory: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Whether events for `func` are still being written.
+    pub fn traces(&self, func: FunctionIndex) -> bool {
+        self.error.is_none() && !self.truncated &&
+            self.functions.as_ref().map_or(true, |f| f.contains(&func))
+    }
+
+    /// Returns the first write error, after which tracing stopped.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    fn write(&mut self, line: String) {
+        let len = line.len() as u64 + 1;
+        let res = if self.max_bytes.map_or(false, |max| self.bytes + len > max) {
+            self.truncated = true;
+            writeln!(self.out, "{{\"event\":\"truncated\"}}")
+        } else {
+            self.bytes += len;
+            writeln!(self.out, "{}", line)
+        };
+        if let Err(e) = res {
+            self.error = Some(e);
+        }
+    }
+
+    pub fn call(&mut self, func: FunctionIndex, args: &[Dynamic]) {
+        if !self.traces(func) {
+            return;
+        }
+        let args = args.iter().map(|&v| json::value(Some(v))).collect::<Vec<_>>();
+        self.write(format!("{{\"event\":\"call\",\"function\":{},\"args\":[{}]}}", func.0, args.join(",")));
+    }
+
+    pub fn ret(&mut self, func: FunctionIndex, result: Option<Dynamic>) {
+        if !self.traces(func) {
+            return;
+        }
+        self.write(format!("{{\"event\":\"return\",\"function\":{},\"result\":{}}}", func.0, json::value(result)));
+    }
+
+    /// Records a load or store made by the op currently running; it is
+    /// written out with that op's event.
+    pub fn memory_access(&mut self, access: MemoryAccess) {
+        self.memory.push(access);
+    }
+
+    pub fn op(&mut self, func: FunctionIndex, offset: usize, op: &NormalOp,
+            consumed: &[Option<Dynamic>], produced: Option<Dynamic>) {
+        let memory = self.memory.drain(..).map(|a| {
+            format!("{{\"kind\":{},\"address\":{},\"width\":{},\"old\":\"{:#x}\"{}}}",
+                if a.kind == Access::Read { "\"read\"" } else { "\"write\"" },
+                a.address, a.width, a.old,
+                a.new.map_or(String::new(), |new| format!(",\"new\":\"{:#x}\"", new)))
+        }).collect::<Vec<_>>();
+        if !self.traces(func) {
+            return;
+        }
+        let consumed = consumed.iter().map(|&v| json::value(v)).collect::<Vec<_>>();
+        self.write(format!("{{\"event\":\"op\",\"function\":{},\"offset\":{},\"op\":{},\"consumed\":[{}],\"produced\":{},\"memory\":[{}]}}",
+            func.0, offset, json::quote(&op.to_string()), consumed.join(","), json::value(produced), memory.join(",")));
+    }
+
+    /// Records entering a `block`, `loop` or `if`, and for an `if`, its
+    /// condition and which arm runs.
+    pub fn enter_block(&mut self, func: FunctionIndex, offset: usize, kind: &str, consumed: &[Option<Dynamic>]) {
+        if !self.traces(func) {
+            return;
+        }
+        let values = consumed.iter().map(|&v| json::value(v)).collect::<Vec<_>>();
+        self.write(format!("{{\"event\":\"{}\",\"function\":{},\"offset\":{},\"consumed\":[{}]}}",
+            kind, func.0, offset, values.join(",")));
+        if kind == "if" && consumed[0].map_or(false, |c| c.to_u32() == 0) {
+            self.write(format!("{{\"event\":\"else\",\"function\":{},\"offset\":{}}}", func.0, offset));
+        }
+    }
+
+    /// Records leaving the block entered at `offset`.
+    pub fn end_block(&mut self, func: FunctionIndex, offset: usize, produced: Option<Dynamic>) {
+        if !self.traces(func) {
+            return;
+        }
+        self.write(format!("{{\"event\":\"end\",\"function\":{},\"offset\":{},\"produced\":{}}}",
+            func.0, offset, json::value(produced)));
+    }
+
+    pub fn trap(&mut self, func: FunctionIndex, offset: usize, op: &NormalOp) {
+        self.memory.clear();
+        if !self.traces(func) {
+            return;
+        }
+        self.write(format!("{{\"event\":\"trap\",\"function\":{},\"offset\":{},\"op\":{}}}",
+            func.0, offset, json::quote(&op.to_string())));
+    }
+}
+
+#[test]
+fn test_trace_lines_are_json() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use interp::Instance;
+
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let m = ::wat::parse_module("(module (memory 1)
+        (func (param $x i32)
+            get_local $x
+            if i32
+                i32.const 10
+            else
+                (i32.store (i32.const 0) (i32.const 20))
+            end
+            drop
+            block
+                loop $exit $cont
+                    (br_if $exit (i32.const 1))
+                end
+            end))").unwrap();
+    let trace = |arg: u32, max_bytes: Option<u64>| {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut tracer = Tracer::new(Box::new(Shared(out.clone())));
+        tracer.max_bytes = max_bytes;
+        let mut instance = Instance::new(&m, HashMap::new());
+        instance.tracer = Some(tracer);
+        instance.invoke(FunctionIndex(0), &[Dynamic::from_u32(arg)]);
+        let text = out.borrow();
+        String::from_utf8(text.clone()).unwrap()
+    };
+
+    let parse = |text: &str| text.lines()
+        .map(|l| json::Json::parse(l).unwrap_or_else(|e| panic!("{}: {}", l, e)))
+        .collect::<Vec<_>>();
+    let events = parse(&trace(0, None));
+    let field = |e: &json::Json, key| e.get(key).and_then(|v| v.as_str().map(str::to_owned)
+        .or_else(|| v.as_u64().map(|n| n.to_string())));
+    let names = events.iter().map(|e| field(e, "event").unwrap()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["call", "op", "if", "else", "op", "op", "op", "end", "op",
+        "block", "loop", "op", "op", "end", "end", "return"]);
+    let offsets = events.iter()
+        .filter(|e| field(e, "event").map_or(false, |n| n != "op" && n != "call" && n != "return"))
+        .map(|e| field(e, "offset").unwrap().parse::<usize>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(offsets[0], offsets[1]);
+    assert_eq!(offsets[0], offsets[2]);
+    assert_eq!(offsets[3..], [offsets[3], offsets[4], offsets[4], offsets[3]]);
+    let store = &events[6];
+    assert_eq!(store.get("memory").unwrap().as_array().unwrap().len(), 1);
+
+    let full = trace(1, None);
+    assert!(!parse(&full).iter().any(|e| field(e, "event").unwrap() == "else"));
+    let max = full.len() as u64 / 2;
+    let cut = trace(1, Some(max));
+    let events = parse(&cut);
+    assert_eq!(field(events.last().unwrap(), "event").unwrap(), "truncated");
+    let kept = cut.len() - cut.lines().last().unwrap().len() - 1;
+    assert!(kept as u64 <= max);
+    assert!(full.starts_with(&cut[..kept]));
+}
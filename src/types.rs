@@ -1,6 +1,8 @@
 use std::{str, mem, fmt};
 use std::num::Wrapping;
 
+use literal::{format_float, FloatFormat};
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
 pub enum Type {
@@ -165,8 +167,8 @@ impl fmt::Display for Dynamic {
         match self {
             &Dynamic::Int32(val) => write!(f, "i32:{}", val),
             &Dynamic::Int64(val) => write!(f, "i64:{}", val),
-            &Dynamic::Float32(val) => write!(f, "f32:{}", val),
-            &Dynamic::Float64(val) => write!(f, "f64:{}", val),
+            &Dynamic::Float32(_) => write!(f, "f32:{}", format_float(*self, FloatFormat::Decimal)),
+            &Dynamic::Float64(_) => write!(f, "f64:{}", format_float(*self, FloatFormat::Decimal)),
         }
     }
 }
@@ -182,10 +184,7 @@ pub struct Pr<T>(pub T);
 impl fmt::Display for Pr<Option<Dynamic>> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
-            &Pr(Some(Dynamic::Int32(val))) => write!(f, "i32:{}", val),
-            &Pr(Some(Dynamic::Int64(val))) => write!(f, "i64:{}", val),
-            &Pr(Some(Dynamic::Float32(val))) => write!(f, "f32:{}", val),
-            &Pr(Some(Dynamic::Float64(val))) => write!(f, "f64:{}", val),
+            &Pr(Some(val)) => write!(f, "{}", val),
             &Pr(None) => write!(f, "void")
         }
     }
@@ -210,8 +209,8 @@ impl fmt::Display for NoType {
         match self.0 {
             Dynamic::Int32(val) => write!(f, "{}", val),
             Dynamic::Int64(val) => write!(f, "{}", val),
-            Dynamic::Float32(val) => write!(f, "{}", val),
-            Dynamic::Float64(val) => write!(f, "{}", val),
+            Dynamic::Float32(_) |
+            Dynamic::Float64(_) => write!(f, "{}", format_float(self.0, FloatFormat::Decimal)),
         }
     }
 }
@@ -304,6 +303,18 @@ impl Dynamic {
         }
     }
 
+    /// Replaces any NaN with the canonical quiet NaN of the same width,
+    /// leaving all other values untouched.
+    pub fn canonicalize_nan(self) -> Dynamic {
+        match self {
+            Dynamic::Float32(v) if v.is_nan() =>
+                Dynamic::Float32(f32::from_bits(0x7fc0_0000)),
+            Dynamic::Float64(v) if v.is_nan() =>
+                Dynamic::Float64(f64::from_bits(0x7ff8_0000_0000_0000)),
+            x => x
+        }
+    }
+
     pub fn get_type(&self) -> Type {
         match self {
             &Dynamic::Int32(_) => Type::Int32,
@@ -0,0 +1,2274 @@
+use std::{str, fmt, cmp};
+use std::collections::HashMap;
+
+use sexpr::Sexpr;
+pub use sexpr::{Pos, Span};
+use module::{AsBytes, Module, FunctionBuilder, Export, FunctionIndex, ImportIndex,
+    Names, MemoryChunk, Import, FunctionType, FunctionBody, TypeIndex, write_u32};
+use types::{Type, Dynamic, IntType, FloatType};
+use ops::{LinearOp, NormalOp, BlockOp, Block, IntBinOp, IntUnOp, IntCmpOp,
+    FloatBinOp, FloatUnOp, FloatCmpOp, MemImm};
+use literal::{self, LiteralError, FloatFormat};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The text isn't shaped like what was expected there.
+    Syntax,
+    /// A `$name`, index, type or instruction that doesn't exist.
+    UnknownName,
+    /// The wrong number of operands.
+    Arity,
+    /// A number, memory immediate or branch target that doesn't parse.
+    Immediate,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &ErrorKind::Syntax => write!(f, "syntax"),
+            &ErrorKind::UnknownName => write!(f, "unknown name"),
+            &ErrorKind::Arity => write!(f, "arity"),
+            &ErrorKind::Immediate => write!(f, "immediate"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatError {
+    pub message: String,
+    pub kind: ErrorKind,
+    /// Where in the text the error is, if it can be pinned down.
+    pub span: Option<Span>,
+}
+
+impl WatError {
+    fn new(message: String) -> WatError {
+        WatError { message, kind: ErrorKind::Syntax, span: None }
+    }
+
+    fn at(expr: &Sexpr, message: String) -> WatError {
+        WatError { message, kind: ErrorKind::Syntax, span: Some(expr.span()) }
+    }
+
+    fn of_kind(self, kind: ErrorKind) -> WatError {
+        WatError { kind, ..self }
+    }
+}
+
+impl fmt::Display for WatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.span {
+            Some(span) => write!(f, "{}: {}", span.start, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Every error found in a module, in the order they were found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostics {
+    pub errors: Vec<WatError>,
+}
+
+impl From<WatError> for Diagnostics {
+    fn from(error: WatError) -> Diagnostics {
+        Diagnostics { errors: vec![error] }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        for (i, e) in self.errors.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { "" } else { "\n" }, e)?;
+        }
+        Ok(())
+    }
+}
+
+impl Diagnostics {
+    /// Renders each error with the line of `text` it's on, marking the
+    /// erroneous part with carets:
+    ///
+    /// ```text
+    /// error[unknown name]: no local named nope
+    ///  --> 1:26
+    ///   |
+    /// 1 | (module (func (get_local $nope)))
+    ///   |                          ^^^^^
+    /// ```
+    pub fn render(&self, text: &str) -> String {
+        let mut out = String::new();
+        for e in &self.errors {
+            out.push_str(&format!("error[{}]: {}\n", e.kind, e.message));
+            let span = match e.span {
+                Some(span) => span,
+                None => continue,
+            };
+            let line = text.lines().nth(span.start.line - 1).unwrap_or("");
+            let number = span.start.line.to_string();
+            let gutter = " ".repeat(number.len());
+            // Tabs are kept so that the carets line up however wide they are.
+            let indent = line.chars().take(span.start.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' }).collect::<String>();
+            let width = if span.end.line == span.start.line {
+                span.end.column.saturating_sub(span.start.column)
+            } else {
+                line.chars().count().saturating_sub(span.start.column - 1)
+            };
+            out.push_str(&format!("{}--> {}\n", gutter, span.start));
+            out.push_str(&format!("{} |\n", gutter));
+            out.push_str(&format!("{} | {}\n", number, line));
+            out.push_str(&format!("{} | {}{}\n", gutter, indent, "^".repeat(cmp::max(width, 1))));
+        }
+        out
+    }
+}
+
+/// Compiles the text of a single `(module ...)`.
+pub fn parse_module(text: &str) -> Result<Module<Vec<u8>>, Diagnostics> {
+    let exprs = Sexpr::parse(text).map_err(|e| WatError {
+        message: e.message,
+        kind: ErrorKind::Syntax,
+        span: Some(Span { start: e.pos, end: e.pos }),
+    })?;
+    if exprs.len() != 1 {
+        return Err(WatError::new(format!("expected one module, found {} expressions", exprs.len())).into());
+    }
+    sexpr_match!(&exprs[0];
+        (module *fields) => {
+            return compile_module(fields);
+        };
+        _ => {}
+    );
+    Err(WatError::at(&exprs[0], format!("expected a module, found {}", exprs[0])).into())
+}
+
+/// Compiles the fields of a `(module ...)`. Errors in one field don't stop
+/// the others from being checked, but declarations are checked before any
+/// function body so that a bad one doesn't cause a cascade.
+pub(crate) fn compile_module<'a>(it: &'a [Sexpr]) -> Result<Module<Vec<u8>>, Diagnostics> {
+    let mut m = Module::<Vec<u8>>::new();
+    // The binary format always has a memory; a module that declares none
+    // gets an empty one, which `print` leaves out.
+    m.memory_info.initial_64k_pages = 0;
+    m.memory_info.maximum_64k_pages = 0;
+    m.memory_info.is_exported = false;
+
+    let mut function_names = HashMap::new();
+    let mut function_index = 0;
+    let mut import_names = HashMap::new();
+    // Imports declared inline with an export, each of which gets a
+    // function that forwards to it, since only functions can be exported.
+    let mut exported_imports = Vec::new();
+
+    let mut type_names = HashMap::new();
+
+    let mut errors = Vec::new();
+    {
+        let mut declare = |s: &'a Sexpr| -> Result<(), WatError> {
+            sexpr_match!(s;
+                (func *fields) => {
+                    let mut name = None;
+
+                    let mut text = None;
+
+                    for s in fields {
+                        match s {
+                            &Sexpr::Variable(ref v, _) => {
+                                name = Some((v, s));
+                                continue;
+                            }
+                            &Sexpr::String(ref v, _) => {
+                                text = Some(v);
+                                continue;
+                            }
+                            _ => break
+                        }
+                    }
+
+                    if let Some((module, import_name)) = inline_import(fields) {
+                        if let Some((name, id)) = name {
+                            bind_name(&mut import_names, "import", id, name, m.imports.len())?;
+                        }
+                        m.imports.push(Import {
+                            function_type: parse_inline_signature(&type_names, &mut m.types, fields)?,
+                            module_name: parse_name(module)?,
+                            function_name: parse_name(import_name)?,
+                        });
+                        if has_inline_export(fields) {
+                            exported_imports.push(m.imports.len() - 1);
+                            m.names.push(Names {
+                                function_name: text.map_or(Vec::new(), |t| Vec::from(t.as_bytes())),
+                                local_names: Vec::new(),
+                            });
+                            function_index += 1;
+                        }
+                        return Ok(());
+                    }
+
+                    if let Some(text) = text {
+                        m.names.push(Names {
+                            function_name: Vec::from(text.as_bytes()),
+                            local_names: Vec::new(),
+                        });
+                    } else {
+                        m.names.push(Names {
+                            function_name: Vec::new(),
+                            local_names: Vec::new(),
+                        });
+                    }
+
+                    if let Some((name, id)) = name {
+                        bind_name(&mut function_names, "func", id, name, function_index)?;
+                    }
+                    function_index += 1;
+                };
+                (import &module &name &ty) => {
+                    m.imports.push(Import {
+                        function_type: parse_function_ty(&type_names, &mut m.types, ty)?,
+                        module_name: parse_name(module)?,
+                        function_name: parse_name(name)?,
+                    });
+                };
+                (import &id &module &name &ty) => {
+                    bind_name(&mut import_names, "import", id, parse_var_id(id)?, m.imports.len())?;
+                    m.imports.push(Import {
+                        function_type: parse_function_ty(&type_names, &mut m.types, ty)?,
+                        module_name: parse_name(module)?,
+                        function_name: parse_name(name)?,
+                    });
+                };
+                (type &id &ty) => {
+                    bind_name(&mut type_names, "type", id, parse_var_id(id)?, m.types.len())?;
+                    m.types.push(parse_type_signature(ty)?);
+                };
+                (type &ty) => {
+                    m.types.push(parse_type_signature(ty)?);
+                };
+                _ => {}
+            );
+            Ok(())
+        };
+        for s in it {
+            if let Err(e) = declare(s) {
+                errors.push(e);
+            }
+        }
+    }
+
+    let type_arities = m.types.iter().map(|t| t.param_types.len()).collect::<Vec<_>>();
+    let mut import_arities = Vec::new();
+    for i in &m.imports {
+        import_arities.push(FunctionContext::arity(&type_arities, "type", i.function_type.0)? as usize);
+    }
+    let mut function_arities = Vec::new();
+    for s in it {
+        sexpr_match!(s;
+            (func *fields) => {
+                if inline_import(fields).is_none() || has_inline_export(fields) {
+                    match count_params(fields, &type_names, &type_arities) {
+                        Ok(count) => function_arities.push(count),
+                        Err(e) => errors.push(e),
+                    }
+                }
+            };
+            _ => {}
+        );
+    }
+    if !errors.is_empty() {
+        return Err(Diagnostics { errors });
+    }
+
+    {
+        let mut exported_imports = exported_imports.into_iter();
+        let mut define = |s: &'a Sexpr| -> Result<(), WatError> {
+            sexpr_match!(s;
+                (func *it) => {
+                    if inline_import(it).is_some() {
+                        if has_inline_export(it) {
+                            let index = exported_imports.next().unwrap();
+                            for s in it {
+                                sexpr_match!(s;
+                                    (export &name) => {
+                                        m.exports.push(Export {
+                                            function_index: FunctionIndex(m.code.len()),
+                                            function_name: parse_name(name)?,
+                                        });
+                                    };
+                                    _ => {}
+                                );
+                            }
+                            let ty_index = m.imports[index].function_type;
+                            m.functions.push(ty_index);
+                            m.code.push(forwarder(ty_index, m.types[ty_index.0].param_types.len(), index));
+                        }
+                        return Ok(());
+                    }
+                    let mut ctx = FunctionContext {
+                        func: FunctionBuilder::new(),
+                        local_names: HashMap::new(),
+                        function_names: &function_names,
+                        import_names: &import_names,
+                        type_names: &type_names,
+                        labels: Vec::new(),
+                        function_arities: &function_arities,
+                        import_arities: &import_arities,
+                        type_arities: &type_arities,
+                        has_result: false,
+                    };
+
+                    let mut param_total_count = 0;
+
+                    let mut named_param_index = 0;
+
+                    let mut param_types = Vec::new();
+                    let mut return_type = None;
+
+                    let mut body = it.len();
+                    for (i, s) in it.iter().enumerate() {
+                        match s {
+                            &Sexpr::Variable(_, _) => continue,
+                            &Sexpr::String(_, _) => continue,
+                            _ => {}
+                        }
+                        sexpr_match!(s;
+                            (param *args) => {
+                                let mut last_var = false;
+                                for a in args {
+                                    match a {
+                                        &Sexpr::Identifier(_, _) => {
+                                            if let Some(_) = ctx.func.ty_index {
+                                                named_param_index += 1;
+                                            } else {
+                                                param_types.push(parse_type_expr(a)?.to_u8());
+                                            }
+                                            last_var = false;
+                                        }
+                                        &Sexpr::Variable(ref v, _) => {
+                                            if let Some(_) = ctx.func.ty_index {
+                                                bind_name(&mut ctx.local_names, "local", a, v, named_param_index)?;
+                                            } else {
+                                                param_total_count += 1;
+                                                bind_name(&mut ctx.local_names, "local", a, v, param_types.len())?;
+                                            }
+                                            last_var = true;
+                                        }
+                                        _ => return Err(WatError::at(a, format!("bad param: {}", a)))
+                                    }
+                                }
+                                if last_var {
+                                    return Err(WatError::at(s, format!("param without a type: {}", s)));
+                                }
+                            };
+                            (result &ty) => {
+                                return_type = Some(parse_type_expr(ty)?);
+                            };
+                            (export &name) => {
+                                m.exports.push(Export {
+                                    function_index: FunctionIndex(m.code.len()),
+                                    function_name: parse_name(name)?,
+                                });
+                            };
+                            (type *args) => {
+                                if ctx.func.ty_index.is_some() {
+                                    return Err(WatError::at(s, format!("duplicate type: {}", s)));
+                                }
+                                let ty_index = parse_function_ty(&type_names, &mut m.types, s)?;
+                                ctx.func.ty_index = Some(ty_index);
+                                param_total_count = match m.types.get(ty_index.0) {
+                                    Some(ty) => ty.param_types.len(),
+                                    None => return Err(WatError::at(s, format!("no type {}", ty_index.0)).of_kind(ErrorKind::UnknownName)),
+                                };
+                            };
+                            (local *args) => {
+                                let mut last_var = false;
+                                for a in args {
+                                    match a {
+                                        &Sexpr::Variable(ref v, _) =>{
+                                            let index = param_total_count + ctx.func.local_types.len();
+                                            bind_name(&mut ctx.local_names, "local", a, v, index)?;
+                                            last_var = true;
+                                        }
+                                        &Sexpr::Identifier(_, _) => {
+                                            ctx.func.local_types.push(parse_type_expr(a)?);
+                                            last_var = false;
+                                        }
+                                        _ => return Err(WatError::at(a, format!("bad local: {}", a)))
+                                    }
+                                }
+                                if last_var {
+                                    return Err(WatError::at(s, format!("local without a type: {}", s)));
+                                }
+                            };
+                            _ => {
+                                body = i;
+                                break;
+                            }
+                        );
+                    }
+
+                    ctx.has_result = match ctx.func.ty_index {
+                        Some(ty_index) => m.types[ty_index.0].return_type.is_some(),
+                        None => return_type.is_some(),
+                    };
+                    ctx.parse_instrs(&it[body..])?;
+
+                    if let Some(ty_index) = ctx.func.ty_index {
+                        m.functions.push(ty_index);
+                    } else {
+                        m.functions.push(intern_type(&mut m.types, FunctionType {
+                            param_types: param_types,
+                            return_type: return_type,
+                        }));
+                    }
+                    m.code.push(ctx.func.build());
+                };
+                (export &name memory) => {
+                    parse_name(name)?;
+                    m.memory_info.is_exported = true;
+                };
+                (export &name &id) => {
+                    m.exports.push(Export {
+                        function_index: FunctionIndex(read_function_name(&function_names, id)?),
+                        function_name: parse_name(name)?,
+                    });
+                };
+                (import *args) => {
+                    // already handled
+                };
+                (type *args) => {
+                    // already handled
+                };
+                (memory *args) => {
+                    let mut i = 0;
+                    while i < args.len() {
+                        sexpr_match!(&args[i];
+                            (export &name) => {
+                                parse_name(name)?;
+                                m.memory_info.is_exported = true;
+                            };
+                            _ => break
+                        );
+                        i += 1;
+                    }
+
+                    let mut inline_data = None;
+                    if i + 1 == args.len() {
+                        sexpr_match!(&args[i];
+                            (data *strings) => {
+                                inline_data = Some(parse_data_strings(strings)?);
+                            };
+                            _ => {}
+                        );
+                    }
+                    if let Some(data) = inline_data {
+                        // The memory is sized to fit its data exactly.
+                        let pages = data.len().div_ceil(0x10000);
+                        m.memory_info.initial_64k_pages = pages;
+                        m.memory_info.maximum_64k_pages = pages;
+                        m.memory_chunks.push(MemoryChunk {
+                            offset: 0,
+                            data,
+                        });
+                        return Ok(());
+                    }
+
+                    let i = if i < args.len() {
+                        match &args[i] {
+                            &Sexpr::Identifier(_, _) => {
+                                m.memory_info.initial_64k_pages = parse_index(&args[i])?;
+                                i + 1
+                            }
+                            _ => {
+                                m.memory_info.initial_64k_pages = 1;
+                                i
+                            }
+                        }
+                    } else {
+                        m.memory_info.initial_64k_pages = 1;
+                        i
+                    };
+                    let i = if i < args.len() {
+                        match &args[i] {
+                            &Sexpr::Identifier(_, _) => {
+                                m.memory_info.maximum_64k_pages = parse_index(&args[i])?;
+                                i + 1
+                            }
+                            _ => {
+                                m.memory_info.maximum_64k_pages = 65536;
+                                i
+                            }
+                        }
+                    } else {
+                        m.memory_info.maximum_64k_pages = 65536;
+                        i
+                    };
+
+                    if m.memory_info.maximum_64k_pages < m.memory_info.initial_64k_pages {
+                        return Err(WatError::at(s, format!("memory maximum is less than its initial size: {}", s)));
+                    }
+
+                    for s in &args[i..] {
+                        sexpr_match!(s;
+                            (segment &offset *strings) => {
+                                m.memory_chunks.push(MemoryChunk {
+                                    offset: parse_int(offset, IntType::Int32)?.to_u32() as usize,
+                                    data: parse_data_strings(strings)?,
+                                })
+                            };
+                            _ => return Err(WatError::at(s, format!("expected a segment: {}", s)))
+                        );
+                    }
+                };
+                (table *items) => {
+                    let mut items = items;
+                    if let Some(&Sexpr::Identifier(ref ty, _)) = items.first() {
+                        if ty.as_slice() == b"anyfunc" {
+                            let mut elems = None;
+                            if items.len() == 2 {
+                                sexpr_match!(&items[1];
+                                    (elem *refs) => {
+                                        elems = Some(refs);
+                                    };
+                                    _ => {}
+                                );
+                            }
+                            items = match elems {
+                                Some(refs) => refs,
+                                None => return Err(WatError::at(s, format!("expected an inline elem: {}", s))),
+                            };
+                        }
+                    }
+                    for it in items {
+                        m.table.push(FunctionIndex(read_function_name(&function_names, it)?));
+                    }
+                };
+                (data &offset *strings) => {
+                    let offset = parse_const(offset)?;
+                    if offset.get_type() != Type::Int32 {
+                        return Err(WatError::at(s, format!("data offset must be an i32.const: {}", s)));
+                    }
+                    m.memory_chunks.push(MemoryChunk {
+                        offset: offset.to_u32() as usize,
+                        data: parse_data_strings(strings)?,
+                    });
+                };
+                (start &id) => {
+                    m.start_function_index = Some(FunctionIndex(read_function_name(&function_names, id)?));
+                };
+                _ => {
+                    return Err(WatError::at(s, format!("unexpected module field: {}", s)));
+                }
+            );
+            Ok(())
+        };
+        for s in it {
+            if let Err(e) = define(s) {
+                errors.push(e);
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(m)
+    } else {
+        Err(Diagnostics { errors })
+    }
+}
+
+/// The number of parameters a `(func ...)` declares, either directly or
+/// through its type.
+fn count_params(fields: &[Sexpr], type_names: &HashMap<&[u8], usize>, type_arities: &[usize]) -> Result<usize, WatError> {
+    let mut count = 0;
+    for s in fields {
+        sexpr_match!(s;
+            (type &id) => {
+                let index = read_name(type_names, "type", id)?;
+                return FunctionContext::arity(type_arities, "type", index).map(|a| a as usize);
+            };
+            (param *args) => {
+                count += args.iter().filter(|a| matches!(*a, &Sexpr::Identifier(_, _))).count();
+            };
+            _ => {}
+        );
+    }
+    Ok(count)
+}
+
+fn parse_type(text: &[u8]) -> Result<Type, WatError> {
+    match text {
+        b"i32" => Ok(Type::Int32),
+        b"i64" => Ok(Type::Int64),
+        b"f32" => Ok(Type::Float32),
+        b"f64" => Ok(Type::Float64),
+        _ => Err(WatError::new(format!("unknown type {}", String::from_utf8_lossy(text))).of_kind(ErrorKind::UnknownName))
+    }
+}
+
+fn parse_type_expr(s: &Sexpr) -> Result<Type, WatError> {
+    match s {
+        &Sexpr::Identifier(ref text, _) => parse_type(text.as_slice()).map_err(|e| WatError { span: Some(s.span()), ..e }),
+        _ => Err(WatError::at(s, format!("expected a type: {}", s))),
+    }
+}
+
+fn parse_index(s: &Sexpr) -> Result<usize, WatError> {
+    match s {
+        &Sexpr::Identifier(ref text, _) => parse_immediate(text).map(|i| i as usize)
+            .ok_or_else(|| WatError::at(s, format!("expected a number: {}", s)).of_kind(ErrorKind::Immediate)),
+        _ => Err(WatError::at(s, format!("expected a number: {}", s)).of_kind(ErrorKind::Immediate)),
+    }
+}
+
+pub(crate) fn parse_const(s: &Sexpr) -> Result<Dynamic, WatError> {
+    sexpr_match!(s;
+        (ident:&ty &value) => {
+            return match ty.as_slice() {
+                b"i32.const" => parse_int(value, IntType::Int32),
+                b"i64.const" => parse_int(value, IntType::Int64),
+                b"f32.const" => parse_float(value, FloatType::Float32),
+                b"f64.const" => parse_float(value, FloatType::Float64),
+                _ => Err(WatError::at(s, format!("expected a constant: {}", s)).of_kind(ErrorKind::Immediate))
+            };
+        };
+        _ => {}
+    );
+    Err(WatError::at(s, format!("expected a constant: {}", s)).of_kind(ErrorKind::Immediate))
+}
+
+struct FunctionContext<'a> {
+    local_names: HashMap<&'a [u8], usize>,
+    func: FunctionBuilder,
+    function_names: &'a HashMap<&'a [u8], usize>,
+    import_names: &'a HashMap<&'a [u8], usize>,
+    type_names: &'a HashMap<&'a [u8], usize>,
+    labels: Vec<Label<'a>>,
+    /// Parameter counts, which flat calls need since they have no operand
+    /// list to count.
+    function_arities: &'a [usize],
+    import_arities: &'a [usize],
+    type_arities: &'a [usize],
+    has_result: bool,
+}
+
+struct Label<'a> {
+    name: Option<&'a [u8]>,
+    /// Whether branches to the label carry a value, which flat branches
+    /// need to know. Only a result type on the block says so.
+    has_arg: bool,
+}
+
+/// The labels and result type after `block`, `loop` or `if`.
+struct BlockHeader<'a> {
+    names: Vec<&'a [u8]>,
+    result: Option<Type>,
+    len: usize,
+}
+
+fn read_block_header<'a>(exprs: &'a [Sexpr], max_names: usize) -> BlockHeader<'a> {
+    let mut header = BlockHeader {
+        names: Vec::new(),
+        result: None,
+        len: 0,
+    };
+    while header.names.len() < max_names {
+        match exprs.get(header.len) {
+            Some(&Sexpr::Variable(ref v, _)) => header.names.push(v.as_slice()),
+            _ => break,
+        }
+        header.len += 1;
+    }
+    if let Some(&Sexpr::Identifier(ref text, _)) = exprs.get(header.len) {
+        if let Ok(ty) = parse_type(text) {
+            header.result = Some(ty);
+            header.len += 1;
+        }
+    }
+    header
+}
+
+/// Splits the `offset=` and `align=` immediates, in either order, off the
+/// front of a memory op's arguments. Returns them and how many expressions
+/// they took. The alignment defaults to `width`, the access's size in bytes.
+fn read_mem_imm(exprs: &[Sexpr], op: &Sexpr, width: u32) -> Result<(MemImm, usize), WatError> {
+    let mut offset = None;
+    let mut align = None;
+    let mut i = 0;
+    while let Some(&Sexpr::Identifier(ref text, _)) = exprs.get(i) {
+        let (value, seen, kind) = if text.starts_with(b"offset=") {
+            (&text[b"offset=".len()..], offset.is_some(), "offset")
+        } else if text.starts_with(b"align=") {
+            (&text[b"align=".len()..], align.is_some(), "alignment")
+        } else {
+            break;
+        };
+        if seen {
+            return Err(WatError::at(&exprs[i], format!("duplicate {}: {}", kind, op)).of_kind(ErrorKind::Immediate));
+        }
+        let value = match parse_immediate(value) {
+            Some(value) => value,
+            None => return Err(WatError::at(&exprs[i], format!("bad {}: {}", kind, op)).of_kind(ErrorKind::Immediate)),
+        };
+        if kind == "offset" {
+            offset = Some(value);
+        } else if value.count_ones() != 1 {
+            return Err(WatError::at(&exprs[i], format!("alignment must be a power of two: {}", op)).of_kind(ErrorKind::Immediate));
+        } else if value > width {
+            return Err(WatError::at(&exprs[i],
+                format!("alignment must not be larger than the {}-byte access: {}", width, op)).of_kind(ErrorKind::Immediate));
+        } else {
+            align = Some(value);
+        }
+        i += 1;
+    }
+    Ok((MemImm {
+        log_of_alignment: align.unwrap_or(width).trailing_zeros(),
+        offset: offset.unwrap_or(0)
+    }, i))
+}
+
+/// A decimal or `0x` hex immediate.
+fn parse_immediate(text: &[u8]) -> Option<u32> {
+    str::from_utf8(text).ok().and_then(|text| literal::parse_u32(text).ok())
+}
+
+/// The number of bytes a load or store accesses.
+fn access_width(op: &NormalOp) -> u32 {
+    match op {
+        &NormalOp::IntLoad(_, _, size, _) |
+        &NormalOp::IntStore(_, size, _) => size.to_int() as u32 / 8,
+        &NormalOp::FloatLoad(FloatType::Float32, _) |
+        &NormalOp::FloatStore(FloatType::Float32, _) => 4,
+        &NormalOp::FloatLoad(FloatType::Float64, _) |
+        &NormalOp::FloatStore(FloatType::Float64, _) => 8,
+        _ => panic!("not a memory op"),
+    }
+}
+
+/// Parses a load or store's immediates from the front of `exprs`. Returns
+/// `None` if `name` isn't a load or store, or else the op and how many
+/// expressions its immediates took.
+fn parse_memory_op(name: &[u8], exprs: &[Sexpr], op: &Sexpr) -> Result<Option<(NormalOp<'static>, usize)>, WatError> {
+    let width = match memory_op(name, MemImm { log_of_alignment: 0, offset: 0 }) {
+        Some(op) => access_width(&op),
+        None => return Ok(None),
+    };
+    let (memimm, len) = read_mem_imm(exprs, op, width)?;
+    Ok(memory_op(name, memimm).map(|op| (op, len)))
+}
+
+fn memory_op(name: &[u8], memimm: MemImm) -> Option<NormalOp<'static>> {
+    use types::IntType::*;
+    use types::FloatType::*;
+    use types::Sign::*;
+    use types::Size::*;
+
+    Some(match name {
+        b"i32.load8_s" => NormalOp::IntLoad(Int32, Signed, I8, memimm),
+        b"i32.load8_u" => NormalOp::IntLoad(Int32, Unsigned, I8, memimm),
+        b"i32.load16_s" => NormalOp::IntLoad(Int32, Signed, I16, memimm),
+        b"i32.load16_u" => NormalOp::IntLoad(Int32, Unsigned, I16, memimm),
+        b"i64.load8_s" => NormalOp::IntLoad(Int64, Signed, I8, memimm),
+        b"i64.load8_u" => NormalOp::IntLoad(Int64, Unsigned, I8, memimm),
+        b"i64.load16_s" => NormalOp::IntLoad(Int64, Signed, I16, memimm),
+        b"i64.load16_u" => NormalOp::IntLoad(Int64, Unsigned, I16, memimm),
+        b"i64.load32_s" => NormalOp::IntLoad(Int64, Signed, I32, memimm),
+        b"i64.load32_u" => NormalOp::IntLoad(Int64, Unsigned, I32, memimm),
+        b"i32.load" => NormalOp::IntLoad(Int32, Unsigned, I32, memimm),
+        b"i64.load" => NormalOp::IntLoad(Int64, Unsigned, I64, memimm),
+        b"f32.load" => NormalOp::FloatLoad(Float32, memimm),
+        b"f64.load" => NormalOp::FloatLoad(Float64, memimm),
+        b"i32.store8" => NormalOp::IntStore(Int32, I8, memimm),
+        b"i32.store16" => NormalOp::IntStore(Int32, I16, memimm),
+        b"i64.store8" => NormalOp::IntStore(Int64, I8, memimm),
+        b"i64.store16" => NormalOp::IntStore(Int64, I16, memimm),
+        b"i64.store32" => NormalOp::IntStore(Int64, I32, memimm),
+        b"i32.store" => NormalOp::IntStore(Int32, I32, memimm),
+        b"i64.store" => NormalOp::IntStore(Int64, I64, memimm),
+        b"f32.store" => NormalOp::FloatStore(Float32, memimm),
+        b"f64.store" => NormalOp::FloatStore(Float64, memimm),
+        _ => return None
+    })
+}
+
+/// The ops that take no immediates, so that all there is to parsing them is
+/// parsing `arity()` operands.
+fn plain_op(name: &[u8]) -> Option<NormalOp<'static>> {
+    use types::IntType::*;
+    use types::FloatType::*;
+    use types::Sign::*;
+
+    Some(match name {
+        b"nop" => NormalOp::Nop,
+        b"drop" => NormalOp::Drop,
+        b"unreachable" => NormalOp::Unreachable,
+        b"select" => NormalOp::Select,
+        b"current_memory" => NormalOp::CurrentMemory,
+        b"grow_memory" => NormalOp::GrowMemory,
+        b"i32.add" => NormalOp::IntBin(Int32, IntBinOp::Add),
+        b"i32.sub" => NormalOp::IntBin(Int32, IntBinOp::Sub),
+        b"i32.mul" => NormalOp::IntBin(Int32, IntBinOp::Mul),
+        b"i32.div_s" => NormalOp::IntBin(Int32, IntBinOp::DivS),
+        b"i32.div_u" => NormalOp::IntBin(Int32, IntBinOp::DivU),
+        b"i32.rem_s" => NormalOp::IntBin(Int32, IntBinOp::RemS),
+        b"i32.rem_u" => NormalOp::IntBin(Int32, IntBinOp::RemU),
+        b"i32.and" => NormalOp::IntBin(Int32, IntBinOp::And),
+        b"i32.or" => NormalOp::IntBin(Int32, IntBinOp::Or),
+        b"i32.xor" => NormalOp::IntBin(Int32, IntBinOp::Xor),
+        b"i32.shl" => NormalOp::IntBin(Int32, IntBinOp::Shl),
+        b"i32.shr_u" => NormalOp::IntBin(Int32, IntBinOp::ShrU),
+        b"i32.shr_s" => NormalOp::IntBin(Int32, IntBinOp::ShrS),
+        b"i32.rotr" => NormalOp::IntBin(Int32, IntBinOp::Rotr),
+        b"i32.rotl" => NormalOp::IntBin(Int32, IntBinOp::Rotl),
+        b"i32.eq" => NormalOp::IntCmp(Int32, IntCmpOp::Eq),
+        b"i32.ne" => NormalOp::IntCmp(Int32, IntCmpOp::Ne),
+        b"i32.lt_s" => NormalOp::IntCmp(Int32, IntCmpOp::LtS),
+        b"i32.le_s" => NormalOp::IntCmp(Int32, IntCmpOp::LeS),
+        b"i32.lt_u" => NormalOp::IntCmp(Int32, IntCmpOp::LtU),
+        b"i32.le_u" => NormalOp::IntCmp(Int32, IntCmpOp::LeU),
+        b"i32.gt_s" => NormalOp::IntCmp(Int32, IntCmpOp::GtS),
+        b"i32.ge_s" => NormalOp::IntCmp(Int32, IntCmpOp::GeS),
+        b"i32.gt_u" => NormalOp::IntCmp(Int32, IntCmpOp::GtU),
+        b"i32.ge_u" => NormalOp::IntCmp(Int32, IntCmpOp::GeU),
+        b"i32.clz" => NormalOp::IntUn(Int32, IntUnOp::Clz),
+        b"i32.ctz" => NormalOp::IntUn(Int32, IntUnOp::Ctz),
+        b"i32.popcnt" => NormalOp::IntUn(Int32, IntUnOp::Popcnt),
+        b"i32.eqz" => NormalOp::IntEqz(Int32),
+        b"i64.add" => NormalOp::IntBin(Int64, IntBinOp::Add),
+        b"i64.sub" => NormalOp::IntBin(Int64, IntBinOp::Sub),
+        b"i64.mul" => NormalOp::IntBin(Int64, IntBinOp::Mul),
+        b"i64.div_s" => NormalOp::IntBin(Int64, IntBinOp::DivS),
+        b"i64.div_u" => NormalOp::IntBin(Int64, IntBinOp::DivU),
+        b"i64.rem_s" => NormalOp::IntBin(Int64, IntBinOp::RemS),
+        b"i64.rem_u" => NormalOp::IntBin(Int64, IntBinOp::RemU),
+        b"i64.and" => NormalOp::IntBin(Int64, IntBinOp::And),
+        b"i64.or" => NormalOp::IntBin(Int64, IntBinOp::Or),
+        b"i64.xor" => NormalOp::IntBin(Int64, IntBinOp::Xor),
+        b"i64.shl" => NormalOp::IntBin(Int64, IntBinOp::Shl),
+        b"i64.shr_u" => NormalOp::IntBin(Int64, IntBinOp::ShrU),
+        b"i64.shr_s" => NormalOp::IntBin(Int64, IntBinOp::ShrS),
+        b"i64.rotr" => NormalOp::IntBin(Int64, IntBinOp::Rotr),
+        b"i64.rotl" => NormalOp::IntBin(Int64, IntBinOp::Rotl),
+        b"i64.eq" => NormalOp::IntCmp(Int64, IntCmpOp::Eq),
+        b"i64.ne" => NormalOp::IntCmp(Int64, IntCmpOp::Ne),
+        b"i64.lt_s" => NormalOp::IntCmp(Int64, IntCmpOp::LtS),
+        b"i64.le_s" => NormalOp::IntCmp(Int64, IntCmpOp::LeS),
+        b"i64.lt_u" => NormalOp::IntCmp(Int64, IntCmpOp::LtU),
+        b"i64.le_u" => NormalOp::IntCmp(Int64, IntCmpOp::LeU),
+        b"i64.gt_s" => NormalOp::IntCmp(Int64, IntCmpOp::GtS),
+        b"i64.ge_s" => NormalOp::IntCmp(Int64, IntCmpOp::GeS),
+        b"i64.gt_u" => NormalOp::IntCmp(Int64, IntCmpOp::GtU),
+        b"i64.ge_u" => NormalOp::IntCmp(Int64, IntCmpOp::GeU),
+        b"i64.clz" => NormalOp::IntUn(Int64, IntUnOp::Clz),
+        b"i64.ctz" => NormalOp::IntUn(Int64, IntUnOp::Ctz),
+        b"i64.popcnt" => NormalOp::IntUn(Int64, IntUnOp::Popcnt),
+        b"i64.eqz" => NormalOp::IntEqz(Int64),
+        b"f32.add" => NormalOp::FloatBin(Float32, FloatBinOp::Add),
+        b"f32.sub" => NormalOp::FloatBin(Float32, FloatBinOp::Sub),
+        b"f32.mul" => NormalOp::FloatBin(Float32, FloatBinOp::Mul),
+        b"f32.div" => NormalOp::FloatBin(Float32, FloatBinOp::Div),
+        b"f32.min" => NormalOp::FloatBin(Float32, FloatBinOp::Min),
+        b"f32.max" => NormalOp::FloatBin(Float32, FloatBinOp::Max),
+        b"f32.copysign" => NormalOp::FloatBin(Float32, FloatBinOp::Copysign),
+        b"f32.abs" => NormalOp::FloatUn(Float32, FloatUnOp::Abs),
+        b"f32.neg" => NormalOp::FloatUn(Float32, FloatUnOp::Neg),
+        b"f32.ceil" => NormalOp::FloatUn(Float32, FloatUnOp::Ceil),
+        b"f32.floor" => NormalOp::FloatUn(Float32, FloatUnOp::Floor),
+        b"f32.trunc" => NormalOp::FloatUn(Float32, FloatUnOp::Trunc),
+        b"f32.nearest" => NormalOp::FloatUn(Float32, FloatUnOp::Nearest),
+        b"f32.sqrt" => NormalOp::FloatUn(Float32, FloatUnOp::Sqrt),
+        b"f32.eq" => NormalOp::FloatCmp(Float32, FloatCmpOp::Eq),
+        b"f32.ne" => NormalOp::FloatCmp(Float32, FloatCmpOp::Ne),
+        b"f32.lt" => NormalOp::FloatCmp(Float32, FloatCmpOp::Lt),
+        b"f32.le" => NormalOp::FloatCmp(Float32, FloatCmpOp::Le),
+        b"f32.gt" => NormalOp::FloatCmp(Float32, FloatCmpOp::Gt),
+        b"f32.ge" => NormalOp::FloatCmp(Float32, FloatCmpOp::Ge),
+        b"f64.add" => NormalOp::FloatBin(Float64, FloatBinOp::Add),
+        b"f64.sub" => NormalOp::FloatBin(Float64, FloatBinOp::Sub),
+        b"f64.mul" => NormalOp::FloatBin(Float64, FloatBinOp::Mul),
+        b"f64.div" => NormalOp::FloatBin(Float64, FloatBinOp::Div),
+        b"f64.min" => NormalOp::FloatBin(Float64, FloatBinOp::Min),
+        b"f64.max" => NormalOp::FloatBin(Float64, FloatBinOp::Max),
+        b"f64.copysign" => NormalOp::FloatBin(Float64, FloatBinOp::Copysign),
+        b"f64.abs" => NormalOp::FloatUn(Float64, FloatUnOp::Abs),
+        b"f64.neg" => NormalOp::FloatUn(Float64, FloatUnOp::Neg),
+        b"f64.ceil" => NormalOp::FloatUn(Float64, FloatUnOp::Ceil),
+        b"f64.floor" => NormalOp::FloatUn(Float64, FloatUnOp::Floor),
+        b"f64.trunc" => NormalOp::FloatUn(Float64, FloatUnOp::Trunc),
+        b"f64.nearest" => NormalOp::FloatUn(Float64, FloatUnOp::Nearest),
+        b"f64.sqrt" => NormalOp::FloatUn(Float64, FloatUnOp::Sqrt),
+        b"f64.eq" => NormalOp::FloatCmp(Float64, FloatCmpOp::Eq),
+        b"f64.ne" => NormalOp::FloatCmp(Float64, FloatCmpOp::Ne),
+        b"f64.lt" => NormalOp::FloatCmp(Float64, FloatCmpOp::Lt),
+        b"f64.le" => NormalOp::FloatCmp(Float64, FloatCmpOp::Le),
+        b"f64.gt" => NormalOp::FloatCmp(Float64, FloatCmpOp::Gt),
+        b"f64.ge" => NormalOp::FloatCmp(Float64, FloatCmpOp::Ge),
+        b"i32.trunc_s/f32" => NormalOp::FloatToInt(Float32, Int32, Signed),
+        b"i32.trunc_s/f64" => NormalOp::FloatToInt(Float64, Int32, Signed),
+        b"i32.trunc_u/f32" => NormalOp::FloatToInt(Float32, Int32, Unsigned),
+        b"i32.trunc_u/f64" => NormalOp::FloatToInt(Float64, Int32, Unsigned),
+        b"i32.wrap/i64" => NormalOp::IntTruncate,
+        b"i64.trunc_s/f32" => NormalOp::FloatToInt(Float32, Int64, Signed),
+        b"i64.trunc_s/f64" => NormalOp::FloatToInt(Float64, Int64, Signed),
+        b"i64.trunc_u/f32" => NormalOp::FloatToInt(Float32, Int64, Unsigned),
+        b"i64.trunc_u/f64" => NormalOp::FloatToInt(Float64, Int64, Unsigned),
+        b"i64.extend_s/i32" => NormalOp::IntExtend(Signed),
+        b"i64.extend_u/i32" => NormalOp::IntExtend(Unsigned),
+        b"f32.convert_s/i32" => NormalOp::IntToFloat(Int32, Signed, Float32),
+        b"f32.convert_u/i32" => NormalOp::IntToFloat(Int32, Unsigned, Float32),
+        b"f32.convert_s/i64" => NormalOp::IntToFloat(Int64, Signed, Float32),
+        b"f32.convert_u/i64" => NormalOp::IntToFloat(Int64, Unsigned, Float32),
+        b"f32.demote/f64" => NormalOp::FloatConvert(Float32),
+        b"f32.reinterpret/i32" => NormalOp::Reinterpret(Type::Int32, Type::Float32),
+        b"f64.convert_s/i32" => NormalOp::IntToFloat(Int32, Signed, Float64),
+        b"f64.convert_u/i32" => NormalOp::IntToFloat(Int32, Unsigned, Float64),
+        b"f64.convert_s/i64" => NormalOp::IntToFloat(Int64, Signed, Float64),
+        b"f64.convert_u/i64" => NormalOp::IntToFloat(Int64, Unsigned, Float64),
+        b"f64.promote/f32" => NormalOp::FloatConvert(Float64),
+        b"f64.reinterpret/i64" => NormalOp::Reinterpret(Type::Int64, Type::Float64),
+        b"i32.reinterpret/f32" => NormalOp::Reinterpret(Type::Float32, Type::Int32),
+        b"i64.reinterpret/f64" => NormalOp::Reinterpret(Type::Float64, Type::Int64),
+        _ => return None
+    })
+}
+
+/// Resolves a `$name` through `names`, or takes a plain index as is.
+fn read_name(names: &HashMap<&[u8], usize>, kind: &str, expr: &Sexpr) -> Result<usize, WatError> {
+    match expr {
+        &Sexpr::Variable(ref name, _) => names.get(name.as_bytes()).cloned()
+            .ok_or_else(|| WatError::at(expr, format!("no {} named {}", kind, expr)).of_kind(ErrorKind::UnknownName)),
+        &Sexpr::Identifier(_, _) => parse_index(expr),
+        _ => Err(WatError::at(expr, format!("expected a {} name or index: {}", kind, expr)).of_kind(ErrorKind::Immediate))
+    }
+}
+
+/// Adds `$name`, written at `id`, to a namespace, unless it's already taken.
+fn bind_name<'a>(names: &mut HashMap<&'a [u8], usize>, kind: &str, id: &Sexpr, name: &'a [u8], index: usize)
+        -> Result<(), WatError> {
+    if names.contains_key(name) {
+        return Err(WatError::at(id, format!("duplicate {} ${}", kind, String::from_utf8_lossy(name))));
+    }
+    names.insert(name, index);
+    Ok(())
+}
+
+fn read_function_name(function_names: &HashMap<&[u8], usize>, expr: &Sexpr) -> Result<usize, WatError> {
+    read_name(function_names, "function", expr)
+}
+
+fn is_label_ref(expr: &Sexpr) -> bool {
+    match expr {
+        &Sexpr::Variable(_, _) => true,
+        &Sexpr::Identifier(ref text, _) => text.len() > 0 && text[0] >= b'0' && text[0] <= b'9',
+        _ => false,
+    }
+}
+
+/// A `block`, `loop` or `if` opened in flat syntax and not yet ended.
+struct OpenBlock<'a> {
+    expr: &'a Sexpr,
+    kind: &'a [u8],
+    names: Vec<&'a [u8]>,
+    seen_else: bool,
+}
+
+impl<'a> FunctionContext<'a> {
+    fn read_local(&self, expr: &Sexpr) -> Result<usize, WatError> {
+        read_name(&self.local_names, "local", expr)
+    }
+
+    fn read_function(&self, expr: &Sexpr) -> Result<usize, WatError> {
+        read_function_name(self.function_names, expr)
+    }
+
+    fn read_type(&self, expr: &Sexpr) -> Result<usize, WatError> {
+        read_name(self.type_names, "type", expr)
+    }
+
+    fn read_import(&self, expr: &Sexpr) -> Result<usize, WatError> {
+        read_name(self.import_names, "import", expr)
+    }
+
+    /// The import a `call` names, when its `$id` belongs to an import
+    /// rather than a function.
+    fn imported_callee(&self, expr: &Sexpr) -> Option<usize> {
+        match expr {
+            &Sexpr::Variable(ref name, _) if !self.function_names.contains_key(name.as_bytes()) =>
+                self.import_names.get(name.as_bytes()).cloned(),
+            _ => None,
+        }
+    }
+
+    fn read_label(&self, expr: &'a Sexpr) -> Result<usize, WatError> {
+        match expr {
+            &Sexpr::Variable(ref name, _) => {
+                for i in (0..self.labels.len()).rev() {
+                    if self.labels[i].name == Some(name.as_slice()) {
+                        return Ok(self.labels.len() - 1 - i);
+                    }
+                }
+                Err(WatError::at(expr, format!("no label named {}", expr)).of_kind(ErrorKind::UnknownName))
+            }
+            &Sexpr::Identifier(_, _) => parse_index(expr),
+            _ => Err(WatError::at(expr, format!("expected a label name or depth: {}", expr)).of_kind(ErrorKind::Immediate))
+        }
+    }
+
+    /// Whether a branch to `depth` carries a value. Branching past every
+    /// label leaves the function, so it follows the result type.
+    fn label_has_arg(&self, depth: usize) -> bool {
+        if depth < self.labels.len() {
+            self.labels[self.labels.len() - 1 - depth].has_arg
+        } else {
+            self.has_result
+        }
+    }
+
+    fn push_labels(&mut self, kind: &[u8], header: &BlockHeader<'a>) {
+        self.labels.push(Label {
+            name: header.names.get(0).cloned(),
+            has_arg: header.result.is_some(),
+        });
+        if kind == b"loop" {
+            self.labels.push(Label {
+                name: header.names.get(1).cloned(),
+                has_arg: false,
+            });
+        }
+    }
+
+    fn pop_labels(&mut self, kind: &[u8]) {
+        self.labels.pop().unwrap();
+        if kind == b"loop" {
+            self.labels.pop().unwrap();
+        }
+    }
+
+    fn arity(arities: &[usize], kind: &str, index: usize) -> Result<u32, WatError> {
+        arities.get(index).map(|&a| a as u32)
+            .ok_or_else(|| WatError::new(format!("no {} {}", kind, index)).of_kind(ErrorKind::UnknownName))
+    }
+
+    fn parse_ops(&mut self, exprs: &'a [Sexpr]) -> Result<usize, WatError> {
+        let mut num = 0;
+        for s in exprs {
+            self.parse_op(s)?;
+            num += 1;
+        }
+        Ok(num)
+    }
+
+    /// Parses the operands of `op`, which must number `count`.
+    fn parse_operands(&mut self, exprs: &'a [Sexpr], count: usize, op: &Sexpr) -> Result<(), WatError> {
+        if exprs.len() != count {
+            return Err(WatError::at(op, format!("expected {} operands, found {}: {}", count, exprs.len(), op))
+                .of_kind(ErrorKind::Arity));
+        }
+        self.parse_ops(exprs)?;
+        Ok(())
+    }
+
+    fn push<'b>(&mut self, op: NormalOp<'b>) {
+        self.func.write(LinearOp::Normal(op));
+    }
+
+    /// Parses a sequence of instructions, each either folded or flat.
+    fn parse_instrs(&mut self, exprs: &'a [Sexpr]) -> Result<(), WatError> {
+        let mut open: Vec<OpenBlock<'a>> = Vec::new();
+        let mut i = 0;
+        while i < exprs.len() {
+            let s = &exprs[i];
+            let name = match s {
+                &Sexpr::Identifier(ref name, _) => name.as_slice(),
+                _ => {
+                    self.parse_op(s)?;
+                    i += 1;
+                    continue;
+                }
+            };
+            let rest = &exprs[i + 1..];
+            i += 1 + match name {
+                b"block" | b"loop" | b"if" => {
+                    let header = read_block_header(rest, if name == b"loop" { 2 } else { 1 });
+                    self.push_labels(name, &header);
+                    self.func.write(match name {
+                        b"block" => LinearOp::Block,
+                        b"loop" => LinearOp::Loop,
+                        _ => LinearOp::If,
+                    });
+                    let len = header.len;
+                    open.push(OpenBlock {
+                        expr: s,
+                        kind: name,
+                        names: header.names,
+                        seen_else: false,
+                    });
+                    len
+                }
+                b"else" | b"end" => {
+                    let len = match open.last_mut() {
+                        Some(block) => {
+                            if name == b"else" && (block.kind != b"if" || block.seen_else) {
+                                return Err(WatError::at(s, format!("else without an if: {}", s)));
+                            }
+                            block.seen_else = true;
+                            match rest.get(0) {
+                                Some(&Sexpr::Variable(ref v, _)) => {
+                                    if !block.names.contains(&v.as_slice()) {
+                                        return Err(WatError::at(s, format!("mismatched label ${} on {}",
+                                            String::from_utf8_lossy(v), s)));
+                                    }
+                                    1
+                                }
+                                _ => 0,
+                            }
+                        }
+                        None => return Err(WatError::at(s, format!("{} without a block", s))),
+                    };
+                    if name == b"else" {
+                        self.func.write(LinearOp::Else);
+                    } else {
+                        let block = open.pop().unwrap();
+                        self.pop_labels(block.kind);
+                        self.func.write(LinearOp::End);
+                    }
+                    len
+                }
+                _ => self.parse_flat_op(s, name, rest)?,
+            };
+        }
+        match open.last() {
+            Some(block) => Err(WatError::at(block.expr, format!("{} without an end", block.expr))),
+            None => Ok(())
+        }
+    }
+
+    /// Parses an instruction in flat form, whose operands are already on the
+    /// stack. Returns how many of the following expressions were its
+    /// immediates.
+    fn parse_flat_op(&mut self, s: &'a Sexpr, name: &[u8], rest: &'a [Sexpr]) -> Result<usize, WatError> {
+        let imm = |i: usize| rest.get(i).ok_or_else(|| WatError::at(s, format!("missing immediate: {}", s)).of_kind(ErrorKind::Immediate));
+        match name {
+            b"br" => {
+                let relative_depth = self.read_label(imm(0)?)?;
+                self.push(NormalOp::Br{has_arg: self.label_has_arg(relative_depth), relative_depth: relative_depth as u32});
+                Ok(1)
+            }
+            b"br_if" => {
+                let relative_depth = self.read_label(imm(0)?)?;
+                self.push(NormalOp::BrIf{has_arg: self.label_has_arg(relative_depth), relative_depth: relative_depth as u32});
+                Ok(1)
+            }
+            b"br_table" => {
+                let mut target_data = Vec::new();
+                let mut count = 0;
+                while count < rest.len() && is_label_ref(&rest[count]) {
+                    write_u32(&mut target_data, self.read_label(&rest[count])? as u32);
+                    count += 1;
+                }
+                if count == 0 {
+                    return Err(WatError::at(s, format!("missing default target: {}", s)).of_kind(ErrorKind::Immediate));
+                }
+                let default = self.read_label(&rest[count - 1])?;
+                self.push(NormalOp::BrTable{
+                    has_arg: self.label_has_arg(default),
+                    target_data: &target_data[..target_data.len() - 4],
+                    default: default as u32
+                });
+                Ok(count)
+            }
+            b"return" => {
+                self.push(NormalOp::Return{has_arg: self.has_result});
+                Ok(0)
+            }
+            b"i32.const" | b"i64.const" | b"f32.const" | b"f64.const" => {
+                let value = imm(0)?;
+                let value = match name {
+                    b"i32.const" => parse_int(value, IntType::Int32)?,
+                    b"i64.const" => parse_int(value, IntType::Int64)?,
+                    b"f32.const" => parse_float(value, FloatType::Float32)?,
+                    _ => parse_float(value, FloatType::Float64)?,
+                };
+                self.push(NormalOp::Const(value));
+                Ok(1)
+            }
+            b"get_local" | b"set_local" | b"tee_local" => {
+                let local = self.read_local(imm(0)?)?;
+                self.push(match name {
+                    b"get_local" => NormalOp::GetLocal(local),
+                    b"set_local" => NormalOp::SetLocal(local),
+                    _ => NormalOp::TeeLocal(local),
+                });
+                Ok(1)
+            }
+            b"call" => {
+                if let Some(index) = self.imported_callee(imm(0)?) {
+                    let argument_count = Self::arity(self.import_arities, "import", index)?;
+                    self.push(NormalOp::CallImport{argument_count, index: ImportIndex(index)});
+                    return Ok(1);
+                }
+                let index = self.read_function(imm(0)?)?;
+                let argument_count = Self::arity(self.function_arities, "function", index)?;
+                self.push(NormalOp::Call{argument_count, index: FunctionIndex(index)});
+                Ok(1)
+            }
+            b"call_indirect" => {
+                let index = self.read_type(imm(0)?)?;
+                let argument_count = Self::arity(self.type_arities, "type", index)?;
+                self.push(NormalOp::CallIndirect{argument_count, index: TypeIndex(index)});
+                Ok(1)
+            }
+            b"call_import" => {
+                let index = self.read_import(imm(0)?)?;
+                let argument_count = Self::arity(self.import_arities, "import", index)?;
+                self.push(NormalOp::CallImport{argument_count, index: ImportIndex(index)});
+                Ok(1)
+            }
+            _ => {
+                let (op, len) = match parse_memory_op(name, rest, s)? {
+                    Some(op) => op,
+                    None => match plain_op(name) {
+                        Some(op) => (op, 0),
+                        None => return Err(WatError::at(s, format!("unexpected instr: {}", s)).of_kind(ErrorKind::UnknownName)),
+                    },
+                };
+                self.push(op);
+                Ok(len)
+            }
+        }
+    }
+
+    fn parse_op(&mut self, s: &'a Sexpr) -> Result<(), WatError> {
+        sexpr_match!(s;
+            (ident:&op *args) => {
+                let arg = |i: usize| args.get(i).ok_or_else(|| WatError::at(s, format!("missing operand: {}", s)).of_kind(ErrorKind::Arity));
+                match op.as_slice() {
+                    b"block" | b"then" | b"else" => {
+                        let header = read_block_header(args, 1);
+                        self.push_labels(b"block", &header);
+
+                        self.func.write(LinearOp::Block);
+                        self.parse_instrs(&args[header.len..])?;
+                        self.func.write(LinearOp::End);
+
+                        self.pop_labels(b"block");
+                    }
+                    b"loop" => {
+                        let header = read_block_header(args, 2);
+                        self.push_labels(b"loop", &header);
+
+                        self.func.write(LinearOp::Loop);
+                        self.parse_instrs(&args[header.len..])?;
+                        self.func.write(LinearOp::End);
+
+                        self.pop_labels(b"loop");
+                    }
+                    b"if" => {
+                        let header = read_block_header(args, 1);
+                        let args = &args[header.len..];
+                        if args.len() != 2 && args.len() != 3 {
+                            return Err(WatError::at(s, format!("expected a condition and one or two arms: {}", s))
+                                .of_kind(ErrorKind::Arity));
+                        }
+                        self.parse_op(&args[0])?;
+                        self.func.write(LinearOp::If);
+
+                        self.push_labels(b"if", &header);
+
+                        self.parse_op(&args[1])?;
+                        if args.len() == 3 {
+                            self.func.write(LinearOp::Else);
+                            self.parse_op(&args[2])?;
+                        }
+                        self.func.write(LinearOp::End);
+
+                        self.pop_labels(b"if");
+                    }
+                    b"br" => {
+                        let relative_depth = self.read_label(arg(0)?)?;
+
+                        if args.len() > 1 {
+                            self.parse_operands(&args[1..], 1, s)?;
+                            self.push(NormalOp::Br{has_arg: true, relative_depth: relative_depth as u32});
+                        } else {
+                            self.push(NormalOp::Br{has_arg: false, relative_depth: relative_depth as u32});
+                        }
+                    }
+                    b"br_if" => {
+                        let relative_depth = self.read_label(arg(0)?)?;
+                        self.parse_op(arg(1)?)?;
+
+                        if args.len() > 2 {
+                            self.parse_operands(&args[2..], 1, s)?;
+                            self.push(NormalOp::BrIf{has_arg: true, relative_depth: relative_depth as u32});
+                        } else {
+                            self.push(NormalOp::BrIf{has_arg: false, relative_depth: relative_depth as u32});
+                        }
+                    }
+                    b"br_table" => {
+                        let mut target_data = Vec::new();
+
+                        let mut i = 0;
+                        let mut last = None;
+
+                        while let &Sexpr::Variable(_, _) | &Sexpr::Identifier(_, _) = arg(i)? {
+                            let l = self.read_label(&args[i])? as u32;
+                            last = Some(l);
+                            write_u32(&mut target_data, l);
+                            i += 1;
+                        }
+
+                        let default = match last {
+                            Some(default) => default,
+                            None => return Err(WatError::at(s, format!("missing default target: {}", s)).of_kind(ErrorKind::Immediate)),
+                        };
+                        self.parse_op(&args[i])?;
+                        if i + 1 < args.len() {
+                            self.parse_operands(&args[i + 1..], 1, s)?;
+                            self.push(NormalOp::BrTable{has_arg: true, target_data: &target_data[..target_data.len() - 4], default});
+                        } else {
+                            self.push(NormalOp::BrTable{has_arg: false, target_data: &target_data[..target_data.len() - 4], default});
+                        }
+                    }
+                    b"return" => {
+                        if args.len() > 1 {
+                            return Err(WatError::at(s, format!("expected at most 1 operand, found {}: {}", args.len(), s))
+                                .of_kind(ErrorKind::Arity));
+                        }
+                        let num = self.parse_ops(args)?;
+                        self.push(NormalOp::Return{has_arg: num == 1});
+                    }
+                    // "end" => { self.push(NormalOp::Nop); }
+                    b"i32.const" |
+                    b"i64.const" |
+                    b"f64.const" |
+                    b"f32.const" => {
+                        self.push(NormalOp::Const(parse_const(s)?));
+                    }
+                    b"get_local" => {
+                        if args.len() != 1 {
+                            return Err(WatError::at(s, format!("expected a local: {}", s)));
+                        }
+                        let local = self.read_local(&args[0])?;
+                        self.push(NormalOp::GetLocal(local));
+                    }
+                    b"set_local" => {
+                        let local = self.read_local(arg(0)?)?;
+                        self.parse_operands(&args[1..], 1, s)?;
+                        self.push(NormalOp::SetLocal(local));
+                    }
+                    b"tee_local" => {
+                        let local = self.read_local(arg(0)?)?;
+                        self.parse_operands(&args[1..], 1, s)?;
+                        self.push(NormalOp::TeeLocal(local));
+                    }
+                    b"call" => {
+                        let callee = arg(0)?;
+                        if let Some(index) = self.imported_callee(callee) {
+                            let num = self.parse_ops(&args[1..])?;
+                            self.push(NormalOp::CallImport{argument_count: num as u32, index: ImportIndex(index)});
+                        } else {
+                            let index = self.read_function(callee)?;
+                            let num = self.parse_ops(&args[1..])?;
+                            self.push(NormalOp::Call{argument_count: num as u32, index: FunctionIndex(index)});
+                        }
+                    }
+                    b"call_indirect" => {
+                        let index = self.read_type(arg(0)?)?;
+                        let num = self.parse_ops(&args[1..])?;
+                        if num == 0 {
+                            return Err(WatError::at(s, format!("missing callee index: {}", s)).of_kind(ErrorKind::Immediate));
+                        }
+                        self.push(NormalOp::CallIndirect{argument_count: num as u32 - 1, index: TypeIndex(index)});
+                    }
+                    b"call_import" => {
+                        let index = self.read_import(arg(0)?)?;
+                        let num = self.parse_ops(&args[1..])?;
+                        self.push(NormalOp::CallImport{argument_count: num as u32, index: ImportIndex(index)});
+                    }
+                    name => {
+                        let (op, len) = match parse_memory_op(name, args, s)? {
+                            Some(op) => op,
+                            None => match plain_op(name) {
+                                Some(op) => (op, 0),
+                                None => return Err(WatError::at(s, format!("unexpected instr: {}", s)).of_kind(ErrorKind::UnknownName)),
+                            },
+                        };
+                        self.parse_operands(&args[len..], op.arity(), s)?;
+                        self.push(op);
+                    }
+                };
+            };
+            _ => return Err(WatError::at(s, format!("unexpected instr: {}", s)).of_kind(ErrorKind::UnknownName))
+        );
+        Ok(())
+    }
+}
+
+/// The text of a numeric literal.
+fn number_text(node: &Sexpr) -> Result<&str, WatError> {
+    match node {
+        &Sexpr::Identifier(ref text, _) => str::from_utf8(text)
+            .map_err(|_| WatError::at(node, format!("{}: {}", LiteralError::Malformed, node))
+                .of_kind(ErrorKind::Immediate)),
+        _ => Err(WatError::at(node, format!("expected a number: {}", node)).of_kind(ErrorKind::Immediate))
+    }
+}
+
+fn parse_int(node: &Sexpr, ty: IntType) -> Result<Dynamic, WatError> {
+    literal::parse_int(number_text(node)?, ty)
+        .map_err(|e| WatError::at(node, format!("{}: {}", e, node)).of_kind(ErrorKind::Immediate))
+}
+
+fn parse_float(node: &Sexpr, ty: FloatType) -> Result<Dynamic, WatError> {
+    literal::parse_float(number_text(node)?, ty)
+        .map_err(|e| WatError::at(node, format!("{}: {}", e, node)).of_kind(ErrorKind::Immediate))
+}
+
+fn parse_bin_string(node: &Sexpr) -> Result<Vec<u8>, WatError> {
+    match node {
+        &Sexpr::String(ref text, _) => Ok(Vec::from(text.as_bytes())),
+        _ => Err(WatError::at(node, format!("expected a string: {}", node)))
+    }
+}
+
+/// Concatenates the strings of a data segment.
+fn parse_data_strings(strings: &[Sexpr]) -> Result<Vec<u8>, WatError> {
+    let mut data = Vec::new();
+    for s in strings {
+        data.extend(parse_bin_string(s)?);
+    }
+    Ok(data)
+}
+
+fn parse_name(node: &Sexpr) -> Result<Vec<u8>, WatError> {
+    match node {
+        &Sexpr::String(ref text, _) => Ok(text.clone()),
+        _ => Err(WatError::at(node, format!("expected a name string: {}", node))),
+    }
+}
+
+fn parse_var_id(node: &Sexpr) -> Result<&[u8], WatError> {
+    match node {
+        &Sexpr::Variable(ref text, _) => Ok(text.as_slice()),
+        _ => Err(WatError::at(node, format!("expected a $name: {}", node))),
+    }
+}
+
+fn parse_function_ty(
+    type_names: &HashMap<&[u8], usize>,
+    types: &mut Vec<FunctionType<Vec<u8>>>,
+    node: &Sexpr) -> Result<TypeIndex, WatError> {
+
+    sexpr_match!(node;
+        (param *params) => {
+            let mut ty = FunctionType {
+                param_types: Vec::new(),
+                return_type: None
+            };
+            for p in params {
+                ty.param_types.push(parse_type_expr(p)?.to_u8());
+            }
+            let index = types.len();
+            types.push(ty);
+            return Ok(TypeIndex(index));
+        };
+        (type &id) => {
+            return read_name(type_names, "type", id).map(TypeIndex);
+        };
+        _ => {}
+    );
+    Err(WatError::at(node, format!("expected a function type: {}", node)))
+}
+
+/// The module and field names of an `(import ...)` inside a `(func ...)`.
+fn inline_import(fields: &[Sexpr]) -> Option<(&Sexpr, &Sexpr)> {
+    for s in fields {
+        sexpr_match!(s;
+            (import &module &name) => {
+                return Some((module, name));
+            };
+            _ => {}
+        );
+    }
+    None
+}
+
+fn has_inline_export(fields: &[Sexpr]) -> bool {
+    fields.iter().any(|s| {
+        sexpr_match!(s;
+            (export &_name) => {
+                return true;
+            };
+            _ => {}
+        );
+        false
+    })
+}
+
+/// A function body that passes its arguments straight on to an import.
+fn forwarder(ty_index: TypeIndex, param_count: usize, import: usize) -> FunctionBody<Vec<u8>> {
+    let mut func = FunctionBuilder::new();
+    func.ty_index = Some(ty_index);
+    for i in 0..param_count {
+        func.write(LinearOp::Normal(NormalOp::GetLocal(i)));
+    }
+    func.write(LinearOp::Normal(NormalOp::CallImport {
+        argument_count: param_count as u32,
+        index: ImportIndex(import),
+    }));
+    func.build()
+}
+
+/// The type of an inline import, given either by `(type ...)` or by its
+/// params and result.
+fn parse_inline_signature(
+    type_names: &HashMap<&[u8], usize>,
+    types: &mut Vec<FunctionType<Vec<u8>>>,
+    fields: &[Sexpr]) -> Result<TypeIndex, WatError> {
+
+    let mut ty = FunctionType {
+        param_types: Vec::new(),
+        return_type: None
+    };
+    for s in fields {
+        sexpr_match!(s;
+            (type &id) => {
+                return read_name(type_names, "type", id).map(TypeIndex);
+            };
+            (param *params) => {
+                for p in params {
+                    match p {
+                        &Sexpr::Variable(_, _) => {}
+                        _ => ty.param_types.push(parse_type_expr(p)?.to_u8()),
+                    }
+                }
+            };
+            (result &a) => {
+                ty.return_type = Some(parse_type_expr(a)?);
+            };
+            _ => {}
+        );
+    }
+    Ok(intern_type(types, ty))
+}
+
+/// The index of a type equal to `ty`, adding it if there is none.
+fn intern_type(types: &mut Vec<FunctionType<Vec<u8>>>, ty: FunctionType<Vec<u8>>) -> TypeIndex {
+    for (i, t) in types.iter().enumerate() {
+        if t.param_types == ty.param_types && t.return_type == ty.return_type {
+            return TypeIndex(i);
+        }
+    }
+    types.push(ty);
+    TypeIndex(types.len() - 1)
+}
+
+fn parse_type_signature(node: &Sexpr) -> Result<FunctionType<Vec<u8>>, WatError> {
+    let mut ty = FunctionType {
+        param_types: Vec::new(),
+        return_type: None
+    };
+
+    sexpr_match!(node;
+        (func *args) => {
+            for s in args {
+                sexpr_match!(s;
+                    (param *params) => {
+                        for p in params {
+                            ty.param_types.push(parse_type_expr(p)?.to_u8());
+                        }
+                    };
+                    (result &a) => {
+                        ty.return_type = Some(parse_type_expr(a)?);
+                    };
+                    _ => return Err(WatError::at(s, format!("unexpected signature part: {}", s)))
+                );
+            }
+            return Ok(ty);
+        };
+        _ => {}
+    );
+    Err(WatError::at(node, format!("expected a function signature: {}", node)))
+}
+
+/// Prints a module in the text format `parse_module` accepts, naming
+/// functions and locals from the `names` chunk where the names are usable
+/// as `$` identifiers.
+///
+/// Fails if a function body can't be written in folded form, which happens
+/// when an op takes its operands from outside its enclosing block.
+pub fn print<B: AsBytes>(module: &Module<B>) -> Result<String, WatError> {
+    let mut out = String::from("(module\n");
+
+    for ty in &module.types {
+        out.push_str(&format!("  (type (func{}))\n", signature(ty.param_types.as_bytes(), ty.return_type)));
+    }
+
+    for i in &module.imports {
+        out.push_str(&format!("  (import {} {} (type {}))\n",
+            quote(i.module_name.as_bytes()), quote(i.function_name.as_bytes()), i.function_type.0));
+    }
+
+    let function_names = unique_names((0..module.functions.len()).map(|i| {
+        module.names.get(i).map(|n| n.function_name.as_bytes())
+    }));
+    let function_ref = |index: FunctionIndex| {
+        match function_names.get(index.0) {
+            Some(&Some(ref name)) => format!("${}", name),
+            _ => format!("{}", index.0),
+        }
+    };
+
+    for (i, (ty_index, body)) in module.functions.iter().zip(&module.code).enumerate() {
+        let ty = match module.types.get(ty_index.0) {
+            Some(ty) => ty,
+            None => return Err(WatError::new(format!("function {} has no type {}", i, ty_index.0))),
+        };
+        let params = ty.param_types.as_bytes();
+        let mut locals = Vec::new();
+        for &(local_ty, count) in &body.locals {
+            for _ in 0..count {
+                locals.push(local_ty);
+            }
+        }
+        let local_names = unique_names((0..params.len() + locals.len()).map(|l| {
+            module.names.get(i).and_then(|n| n.local_names.get(l)).map(|n| n.as_bytes())
+        }));
+
+        out.push_str("  (func");
+        if let Some(ref name) = function_names[i] {
+            out.push_str(&format!(" ${}", name));
+        }
+        if let Some(name) = module.names.get(i).map(|n| n.function_name.as_bytes()) {
+            if name.len() > 0 {
+                out.push_str(&format!(" {}", quote(name)));
+            }
+        }
+        out.push_str(&format!(" (type {})", ty_index.0));
+        if params.len() > 0 {
+            out.push_str(" (param");
+            for (p, &param_ty) in params.iter().enumerate() {
+                out.push_str(&declaration(&local_names[p], Type::from_u8(param_ty)));
+            }
+            out.push_str(")");
+        }
+        if let Some(return_type) = ty.return_type {
+            out.push_str(&format!(" (result {})", return_type));
+        }
+        out.push_str("\n");
+        if locals.len() > 0 {
+            out.push_str("    (local");
+            for (l, &local_ty) in locals.iter().enumerate() {
+                out.push_str(&declaration(&local_names[params.len() + l], local_ty));
+            }
+            out.push_str(")\n");
+        }
+
+        let printer = BodyPrinter {
+            function_ref: &function_ref,
+            local_names: &local_names,
+        };
+        let ops = body.block_ops().collect::<Vec<_>>();
+        for e in printer.print_block(&ops, &mut Vec::new())? {
+            out.push_str("    ");
+            e.render(4, &mut out);
+            out.push_str("\n");
+        }
+        out.push_str("  )\n");
+    }
+
+    if module.table.len() > 0 {
+        let entries = module.table.iter().map(|&f| function_ref(f)).collect::<Vec<_>>();
+        out.push_str(&format!("  (table {})\n", entries.join(" ")));
+    }
+
+    let memory = &module.memory_info;
+    let has_memory = memory.initial_64k_pages > 0 || memory.maximum_64k_pages > 0 ||
+        memory.is_exported || module.memory_chunks.len() > 0;
+    if has_memory {
+        out.push_str(&format!("  (memory {} {}", memory.initial_64k_pages, memory.maximum_64k_pages));
+        for chunk in &module.memory_chunks {
+            out.push_str(&format!("\n    (segment {} {})", chunk.offset, quote(chunk.data.as_bytes())));
+        }
+        out.push_str(")\n");
+    }
+
+    for e in &module.exports {
+        out.push_str(&format!("  (export {} {})\n", quote(e.function_name.as_bytes()), function_ref(e.function_index)));
+    }
+    if module.memory_info.is_exported {
+        out.push_str("  (export \"memory\" memory)\n");
+    }
+
+    if let Some(start) = module.start_function_index {
+        out.push_str(&format!("  (start {})\n", function_ref(start)));
+    }
+
+    out.push_str(")\n");
+    Ok(out)
+}
+
+fn signature(params: &[u8], return_type: Option<Type>) -> String {
+    let mut text = String::new();
+    if params.len() > 0 {
+        text.push_str(" (param");
+        for &p in params {
+            text.push_str(&format!(" {}", Type::from_u8(p)));
+        }
+        text.push_str(")");
+    }
+    if let Some(ty) = return_type {
+        text.push_str(&format!(" (result {})", ty));
+    }
+    text
+}
+
+fn declaration(name: &Option<String>, ty: Type) -> String {
+    match name {
+        &Some(ref name) => format!(" ${} {}", name, ty),
+        &None => format!(" {}", ty),
+    }
+}
+
+fn quote(bytes: &[u8]) -> String {
+    let mut text = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' => text.push_str("\\\""),
+            b'\\' => text.push_str("\\\\"),
+            0x20...0x7e => text.push(b as char),
+            _ => text.push_str(&format!("\\{:02x}", b)),
+        }
+    }
+    text.push('"');
+    text
+}
+
+fn is_id_char(ch: u8) -> bool {
+    match ch {
+        b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' => true,
+        _ => b"!#$%&'*+-./:<=>?@\\^_`|~".contains(&ch),
+    }
+}
+
+/// Keeps the names that can be written as `$` identifiers and aren't used
+/// twice.
+fn unique_names<'a, I: Iterator<Item=Option<&'a [u8]>>>(names: I) -> Vec<Option<String>> {
+    let names = names.map(|n| n.and_then(|n| {
+        if n.len() > 0 && n.iter().all(|&ch| is_id_char(ch)) {
+            str::from_utf8(n).ok().map(|n| n.to_owned())
+        } else {
+            None
+        }
+    })).collect::<Vec<_>>();
+    let mut counts = HashMap::new();
+    for n in names.iter().filter_map(|n| n.as_ref()) {
+        *counts.entry(n.clone()).or_insert(0) += 1;
+    }
+    names.into_iter().map(|n| n.and_then(|n| if counts[&n] == 1 { Some(n) } else { None })).collect()
+}
+
+/// A folded expression: an op with its immediates, then its operands.
+struct Expr {
+    head: String,
+    operands: Vec<Expr>,
+    nested: bool,
+}
+
+impl Expr {
+    fn new(head: String, operands: Vec<Expr>) -> Expr {
+        let nested = operands.iter().any(|o| o.nested);
+        Expr {
+            head,
+            operands,
+            nested,
+        }
+    }
+
+    fn block(head: &str, body: Vec<Expr>) -> Expr {
+        Expr {
+            head: head.to_owned(),
+            operands: body,
+            nested: true,
+        }
+    }
+
+    /// Writes the expression on one line, or with one operand per line if it
+    /// contains a block.
+    fn render(&self, indent: usize, out: &mut String) {
+        out.push('(');
+        out.push_str(&self.head);
+        for o in &self.operands {
+            if self.nested {
+                out.push('\n');
+                for _ in 0..indent + 2 {
+                    out.push(' ');
+                }
+                o.render(indent + 2, out);
+            } else {
+                out.push(' ');
+                o.render(indent, out);
+            }
+        }
+        out.push(')');
+    }
+}
+
+struct BodyPrinter<'a> {
+    function_ref: &'a Fn(FunctionIndex) -> String,
+    local_names: &'a [Option<String>],
+}
+
+impl<'a> BodyPrinter<'a> {
+    /// Folds a block's ops into expressions. `labels` has an entry for each
+    /// enclosing label in the text, which is false for the blocks printed
+    /// around `if` arms that the binary doesn't have.
+    fn print_block(&self, ops: &[BlockOp], labels: &mut Vec<bool>) -> Result<Vec<Expr>, WatError> {
+        let mut stack: Vec<Expr> = Vec::new();
+        for op in ops {
+            let arity = match op {
+                &BlockOp::Normal(_, ref op) => op.arity(),
+                &BlockOp::Block(_, Block::If(..)) => 1,
+                &BlockOp::Block(..) => 0,
+            };
+            if stack.len() < arity {
+                return Err(WatError::new(format!("operand of {} comes from outside its block", op)));
+            }
+            let start = stack.len() - arity;
+            let operands = stack.split_off(start);
+            let e = match op {
+                &BlockOp::Normal(_, ref op) => Expr::new(self.head(op, labels)?, operands),
+                &BlockOp::Block(_, Block::Block(ref ops)) => {
+                    labels.push(true);
+                    let body = self.print_block(ops, labels)?;
+                    labels.pop();
+                    Expr::block("block", body)
+                }
+                &BlockOp::Block(_, Block::Loop(ref ops)) => {
+                    labels.push(true);
+                    labels.push(true);
+                    let body = self.print_block(ops, labels)?;
+                    labels.pop();
+                    labels.pop();
+                    Expr::block("loop", body)
+                }
+                &BlockOp::Block(_, Block::If(ref then, ref otherwise)) => {
+                    labels.push(true);
+                    let mut operands = operands;
+                    operands.push(self.print_arm("then", then, labels)?);
+                    if otherwise.len() > 0 {
+                        operands.push(self.print_arm("else", otherwise, labels)?);
+                    }
+                    labels.pop();
+                    Expr::block("if", operands)
+                }
+            };
+            stack.push(e);
+        }
+        Ok(stack)
+    }
+
+    /// An `if` arm is a single expression in the text, so an arm that folds
+    /// into any other number of them goes in a block of its own.
+    fn print_arm(&self, name: &str, ops: &[BlockOp], labels: &mut Vec<bool>) -> Result<Expr, WatError> {
+        let mut body = self.print_block(ops, labels)?;
+        if body.len() == 1 {
+            return Ok(body.pop().unwrap());
+        }
+        labels.push(false);
+        let body = self.print_block(ops, labels)?;
+        labels.pop();
+        Ok(Expr::block(name, body))
+    }
+
+    fn label(&self, relative_depth: u32, labels: &[bool]) -> u32 {
+        let mut remaining = relative_depth;
+        for (depth, &real) in labels.iter().rev().enumerate() {
+            if real {
+                if remaining == 0 {
+                    return depth as u32;
+                }
+                remaining -= 1;
+            }
+        }
+        labels.len() as u32 + remaining
+    }
+
+    fn local(&self, index: usize) -> String {
+        match self.local_names.get(index) {
+            Some(&Some(ref name)) => format!("${}", name),
+            _ => format!("{}", index),
+        }
+    }
+
+    fn head(&self, op: &NormalOp, labels: &[bool]) -> Result<String, WatError> {
+        Ok(match op {
+            &NormalOp::Nop => "nop".to_owned(),
+            &NormalOp::Select => "select".to_owned(),
+            &NormalOp::Br{relative_depth, ..} => format!("br {}", self.label(relative_depth, labels)),
+            &NormalOp::BrIf{relative_depth, ..} => format!("br_if {}", self.label(relative_depth, labels)),
+            &NormalOp::BrTable{target_data, default, ..} => {
+                let mut text = String::from("br_table");
+                for t in target_data.chunks(4) {
+                    if t.len() != 4 {
+                        return Err(WatError::new("truncated br_table".to_owned()));
+                    }
+                    let t = t[0] as u32 | (t[1] as u32) << 8 | (t[2] as u32) << 16 | (t[3] as u32) << 24;
+                    text.push_str(&format!(" {}", self.label(t, labels)));
+                }
+                text.push_str(&format!(" {}", self.label(default, labels)));
+                text
+            }
+            &NormalOp::Return{..} => "return".to_owned(),
+            &NormalOp::Unreachable => "unreachable".to_owned(),
+            &NormalOp::Drop => "drop".to_owned(),
+            &NormalOp::Const(value) => format!("{}.const {}", value.get_type(), print_const(value)),
+            &NormalOp::GetLocal(index) => format!("get_local {}", self.local(index)),
+            &NormalOp::SetLocal(index) => format!("set_local {}", self.local(index)),
+            &NormalOp::TeeLocal(index) => format!("tee_local {}", self.local(index)),
+            &NormalOp::Call{index, ..} => format!("call {}", (self.function_ref)(index)),
+            &NormalOp::CallIndirect{index, ..} => format!("call_indirect {}", index.0),
+            &NormalOp::CallImport{index, ..} => format!("call_import {}", index.0),
+            &NormalOp::IntLoad(ty, sign, size, memimm) => {
+                if size == ty.to_type().size() {
+                    format!("{}.load{}", ty, print_mem_imm(memimm, access_width(op)))
+                } else {
+                    format!("{}.load{}_{}{}", ty, size.to_int(), sign.text(), print_mem_imm(memimm, access_width(op)))
+                }
+            }
+            &NormalOp::FloatLoad(ty, memimm) => format!("{}.load{}", ty, print_mem_imm(memimm, access_width(op))),
+            &NormalOp::IntStore(ty, size, memimm) => {
+                if size == ty.to_type().size() {
+                    format!("{}.store{}", ty, print_mem_imm(memimm, access_width(op)))
+                } else {
+                    format!("{}.store{}{}", ty, size.to_int(), print_mem_imm(memimm, access_width(op)))
+                }
+            }
+            &NormalOp::FloatStore(ty, memimm) => format!("{}.store{}", ty, print_mem_imm(memimm, access_width(op))),
+            &NormalOp::CurrentMemory => "current_memory".to_owned(),
+            &NormalOp::GrowMemory => "grow_memory".to_owned(),
+            &NormalOp::IntBin(ty, op) => format!("{}.{}", ty, match op {
+                IntBinOp::Add => "add",
+                IntBinOp::Sub => "sub",
+                IntBinOp::Mul => "mul",
+                IntBinOp::DivS => "div_s",
+                IntBinOp::DivU => "div_u",
+                IntBinOp::RemS => "rem_s",
+                IntBinOp::RemU => "rem_u",
+                IntBinOp::And => "and",
+                IntBinOp::Or => "or",
+                IntBinOp::Xor => "xor",
+                IntBinOp::Shl => "shl",
+                IntBinOp::ShrU => "shr_u",
+                IntBinOp::ShrS => "shr_s",
+                IntBinOp::Rotr => "rotr",
+                IntBinOp::Rotl => "rotl",
+            }),
+            &NormalOp::IntCmp(ty, op) => format!("{}.{}", ty, match op {
+                IntCmpOp::Eq => "eq",
+                IntCmpOp::Ne => "ne",
+                IntCmpOp::LtS => "lt_s",
+                IntCmpOp::LeS => "le_s",
+                IntCmpOp::LtU => "lt_u",
+                IntCmpOp::LeU => "le_u",
+                IntCmpOp::GtS => "gt_s",
+                IntCmpOp::GeS => "ge_s",
+                IntCmpOp::GtU => "gt_u",
+                IntCmpOp::GeU => "ge_u",
+            }),
+            &NormalOp::IntUn(ty, op) => format!("{}.{}", ty, match op {
+                IntUnOp::Clz => "clz",
+                IntUnOp::Ctz => "ctz",
+                IntUnOp::Popcnt => "popcnt",
+            }),
+            &NormalOp::IntEqz(ty) => format!("{}.eqz", ty),
+            &NormalOp::FloatBin(ty, op) => format!("{}.{}", ty, match op {
+                FloatBinOp::Add => "add",
+                FloatBinOp::Sub => "sub",
+                FloatBinOp::Mul => "mul",
+                FloatBinOp::Div => "div",
+                FloatBinOp::Min => "min",
+                FloatBinOp::Max => "max",
+                FloatBinOp::Copysign => "copysign",
+            }),
+            &NormalOp::FloatUn(ty, op) => format!("{}.{}", ty, match op {
+                FloatUnOp::Abs => "abs",
+                FloatUnOp::Neg => "neg",
+                FloatUnOp::Ceil => "ceil",
+                FloatUnOp::Floor => "floor",
+                FloatUnOp::Trunc => "trunc",
+                FloatUnOp::Nearest => "nearest",
+                FloatUnOp::Sqrt => "sqrt",
+            }),
+            &NormalOp::FloatCmp(ty, op) => format!("{}.{}", ty, match op {
+                FloatCmpOp::Eq => "eq",
+                FloatCmpOp::Ne => "ne",
+                FloatCmpOp::Lt => "lt",
+                FloatCmpOp::Le => "le",
+                FloatCmpOp::Gt => "gt",
+                FloatCmpOp::Ge => "ge",
+            }),
+            &NormalOp::FloatToInt(float_type, int_type, sign) =>
+                format!("{}.trunc_{}/{}", int_type, sign.text(), float_type),
+            &NormalOp::IntExtend(sign) => format!("i64.extend_{}/i32", sign.text()),
+            &NormalOp::IntTruncate => "i32.wrap/i64".to_owned(),
+            &NormalOp::IntToFloat(int_type, sign, float_type) =>
+                format!("{}.convert_{}/{}", float_type, sign.text(), int_type),
+            &NormalOp::FloatConvert(FloatType::Float32) => "f32.demote/f64".to_owned(),
+            &NormalOp::FloatConvert(FloatType::Float64) => "f64.promote/f32".to_owned(),
+            &NormalOp::Reinterpret(from, to) => format!("{}.reinterpret/{}", to, from),
+        })
+    }
+}
+
+fn print_mem_imm(memimm: MemImm, width: u32) -> String {
+    let mut text = String::new();
+    if memimm.offset != 0 {
+        text.push_str(&format!(" offset={}", memimm.offset));
+    }
+    match 1u64.checked_shl(memimm.log_of_alignment) {
+        Some(align) if align == width as u64 => {}
+        Some(align) => text.push_str(&format!(" align={}", align)),
+        // Too large for any text alignment; show the exponent instead.
+        None => text.push_str(&format!(" align=2**{}", memimm.log_of_alignment)),
+    }
+    text
+}
+
+/// Prints a constant so that `parse_const` gives back the same bits.
+fn print_const(value: Dynamic) -> String {
+    match value {
+        Dynamic::Int32(v) => format!("{}", v.0 as i32),
+        Dynamic::Int64(v) => format!("{}", v.0 as i64),
+        Dynamic::Float32(_) |
+        Dynamic::Float64(_) => literal::format_float(value, FloatFormat::Hex),
+    }
+}
+
+#[test]
+fn test_parse_module() {
+    use interp::{Instance, InterpResult};
+
+    let m = parse_module("(module
+        (func $add (param $a i32) (param $b i32) (result i32)
+            (i32.add (get_local $a) (get_local $b)))
+        (export \"add\" $add))").unwrap();
+    let mut instance = Instance::new(&m, HashMap::new());
+    let add = m.find(b"add").unwrap();
+    assert_eq!(instance.invoke(add, &[Dynamic::from_u32(2), Dynamic::from_u32(3)]),
+        InterpResult::Value(Some(Dynamic::from_u32(5))));
+
+    match parse_module("(module (func (get_local $nope)))") {
+        Err(err) => assert_eq!(err.errors[0].message, "no local named nope"),
+        Ok(_) => panic!("expected an error"),
+    }
+    assert!(parse_module("(func)").is_err());
+    assert_eq!(parse_module("(module\n  (func (i32.ad)))").err().unwrap().to_string(),
+        "2:9: unexpected instr: (i32.ad)");
+    assert_eq!(parse_module("(module (func \"a\\q\"))").err().unwrap().to_string(), "1:17: unknown escape \\q");
+}
+
+#[test]
+fn test_print_round_trip() {
+    use interp::{Instance, InterpResult};
+
+    let text = "(module
+        (import $print \"spectest\" \"print\" (param i32))
+        (func $store \"store\" (param $p i32) (local $x i64)
+            (i32.store16 offset=4 align=1 (get_local $p) (i32.const -1))
+            (call_import $print (i32.load8_u (get_local $p))))
+        (func $id (param i32) (result i32) (get_local 0))
+        (table $id $store)
+        (memory 1 2 (segment 8 \"a\\\"\\\\\\00\\ff\"))
+        (export \"store\" $store)
+        (start $id))";
+    let mut m = parse_module(text).unwrap();
+    m.names[0].local_names = vec![b"p".to_vec(), b"x".to_vec()];
+    let printed = print(&m).unwrap();
+    assert!(printed.contains("(func $store \"store\" (type 0) (param $p i32)\n    (local $x i64)\n"));
+    assert!(printed.contains("(i32.store16 offset=4 align=1 (get_local $p) (i32.const -1))"));
+    assert!(printed.contains("(table 1 $store)"));
+    let m2 = parse_module(&printed).unwrap();
+    assert_eq!(m2.memory_chunks[0].data, m.memory_chunks[0].data);
+    for (a, b) in m.code.iter().zip(&m2.code) {
+        assert_eq!(a.ast, b.ast);
+    }
+
+    // An `if` arm of more than one expression gets a block, and branches
+    // out of it are renumbered to skip that block.
+    let mut m = Module::<Vec<u8>>::new();
+    m.types.push(FunctionType { param_types: vec![Type::Int32.to_u8()], return_type: Some(Type::Int32) });
+    m.functions.push(TypeIndex(0));
+    let mut f = FunctionBuilder::new();
+    f.ty_index = Some(TypeIndex(0));
+    f.write(LinearOp::Block);
+    f.write(LinearOp::Normal(NormalOp::GetLocal(0)));
+    f.write(LinearOp::If);
+    f.write(LinearOp::Normal(NormalOp::Const(Dynamic::from_u32(1))));
+    f.write(LinearOp::Normal(NormalOp::Const(Dynamic::from_u32(7))));
+    f.write(LinearOp::Normal(NormalOp::Br{has_arg: true, relative_depth: 1}));
+    f.write(LinearOp::End);
+    f.write(LinearOp::Normal(NormalOp::Const(Dynamic::from_u32(3))));
+    f.write(LinearOp::End);
+    m.code.push(f.build());
+    m.exports.push(Export { function_index: FunctionIndex(0), function_name: b"f".to_vec() });
+
+    let printed = print(&m).unwrap();
+    assert!(printed.contains("(br 2 (i32.const 7))"));
+    let m2 = parse_module(&printed).unwrap();
+    for &(arg, expected) in &[(0, 3), (1, 7)] {
+        let args = [Dynamic::from_u32(arg)];
+        let expected = InterpResult::Value(Some(Dynamic::from_u32(expected)));
+        assert_eq!(Instance::new(&m, HashMap::new()).invoke(FunctionIndex(0), &args), expected);
+        assert_eq!(Instance::new(&m2, HashMap::new()).invoke(FunctionIndex(0), &args), expected);
+    }
+
+    // No memory section for a module without a memory, and alignments too
+    // large to write out as a number don't overflow.
+    let printed = print(&parse_module("(module (func (nop)))").unwrap()).unwrap();
+    assert!(!printed.contains("(memory"));
+    let memimm = |log| MemImm { log_of_alignment: log, offset: 0 };
+    assert_eq!(print_mem_imm(memimm(63), 4), " align=9223372036854775808");
+    assert_eq!(print_mem_imm(memimm(64), 4), " align=2**64");
+}
+
+#[test]
+fn test_flat_syntax() {
+    use interp::{Instance, InterpResult};
+
+    let m = parse_module("(module
+        (func $add (param i32 i32) (result i32) get_local 0 get_local 1 i32.add)
+        (func $f (param $x i32) (result i32)
+            block $done i32
+                get_local $x
+                i32.eqz
+                if $zero
+                    i32.const 100
+                    br $done
+                else $zero
+                    nop
+                end $zero
+                (call $add (get_local $x) (i32.const 1))
+            end $done)
+        (func $sum (param $n i32) (result i32) (local $s i32)
+            loop $exit $cont
+                (br_if $exit (i32.eqz (get_local $n)))
+                get_local $s get_local $n i32.add set_local $s
+                get_local $n i32.const 1 i32.sub set_local $n
+                br $cont
+            end
+            get_local $s)
+        (func $folded (param i32 i32) (result i32) (i32.add (get_local 0) (get_local 1)))
+        (export \"f\" $f)
+        (export \"sum\" $sum))").unwrap();
+    assert_eq!(m.code[0].ast, m.code[3].ast);
+
+    let mut instance = Instance::new(&m, HashMap::new());
+    let mut call = |name: &[u8], arg: u32| instance.invoke(m.find(name).unwrap(), &[Dynamic::from_u32(arg)]);
+    assert_eq!(call(b"f", 0), InterpResult::Value(Some(Dynamic::from_u32(100))));
+    assert_eq!(call(b"f", 5), InterpResult::Value(Some(Dynamic::from_u32(6))));
+    assert_eq!(call(b"sum", 4), InterpResult::Value(Some(Dynamic::from_u32(10))));
+
+    let error = |text: &str| parse_module(text).err().unwrap().errors[0].message.clone();
+    assert_eq!(error("(module (func block nop))"), "block without an end");
+    assert_eq!(error("(module (func nop end))"), "end without a block");
+    assert_eq!(error("(module (func block $a end $b))"), "mismatched label $b on end");
+}
+
+#[test]
+fn test_memory_immediates() {
+    let body = |imm: &str| {
+        let text = format!("(module (memory 1) (func (i32.load16_u {} (i32.const 0))))", imm);
+        parse_module(&text).unwrap().code.remove(0).ast
+    };
+    assert_eq!(body("offset=16 align=1"), body("align=1 offset=0x10"));
+    assert_eq!(body(""), body("align=2"));
+    assert!(body("") != body("align=1"));
+
+    let error = |imm: &str| {
+        let text = format!("(module (memory 1) (func (i32.load16_u {} (i32.const 0))))", imm);
+        parse_module(&text).err().unwrap().errors[0].message.clone()
+    };
+    assert!(error("align=3").starts_with("alignment must be a power of two"));
+    assert!(error("align=4").starts_with("alignment must not be larger than the 2-byte access"));
+    assert!(error("offset=1 offset=2").starts_with("duplicate offset"));
+    assert!(error("offset=x").starts_with("bad offset"));
+}
+
+#[test]
+fn test_module_abbreviations() {
+    use interp::{Instance, InterpResult};
+
+    let m = parse_module("(module
+        (type $t (func (param i32) (result i32)))
+        (func $print (import \"spectest\" \"print\") (param $x i32))
+        (func $id (export \"id\") (export \"same\") (type $t) (get_local 0))
+        (func $seven (export \"seven\") (param i32) (result i32) (drop (get_local 0)) (i32.const 7))
+        (memory (export \"mem\") (data \"ab\" \"c\"))
+        (table anyfunc (elem $seven $id))
+        (data (i32.const 8) \"x\" \"yz\"))").unwrap();
+    assert_eq!(m.imports.len(), 1);
+    assert_eq!(m.imports[0].function_name, b"print");
+    assert_eq!(m.types[m.imports[0].function_type.0].param_types, vec![Type::Int32.to_u8()]);
+    assert_eq!(m.code.len(), 2);
+    let exports = m.exports.iter().map(|e| (e.function_name.as_slice(), e.function_index.0)).collect::<Vec<_>>();
+    assert_eq!(exports, vec![(&b"id"[..], 0), (&b"same"[..], 0), (&b"seven"[..], 1)]);
+    assert!(m.memory_info.is_exported);
+    assert_eq!(m.memory_info.initial_64k_pages, 1);
+    assert_eq!(m.memory_chunks[0].data, b"abc");
+    assert_eq!((m.memory_chunks[1].offset, m.memory_chunks[1].data.as_slice()), (8, &b"xyz"[..]));
+    assert_eq!(m.table.iter().map(|f| f.0).collect::<Vec<_>>(), vec![1, 0]);
+
+    let m = parse_module("(module
+        (func (export \"f\") (param i32) (result i32)
+            (drop (get_local 0))
+            get_local 0 drop
+            (i32.const 7)))").unwrap();
+    let result = Instance::new(&m, HashMap::new()).invoke(FunctionIndex(0), &[Dynamic::from_u32(3)]);
+    assert_eq!(result, InterpResult::Value(Some(Dynamic::from_u32(7))));
+
+    let m = parse_module("(module
+        (func $f (export \"f\") (import \"m\" \"n\") (param i32) (result i32))
+        (func $g (export \"g\") (param i32) (result i32) (call $f (get_local 0)))
+        (func $h (param i32) (result i32) get_local 0 call $f)
+        (func $i (param i32) (result i32) (call_import $f (get_local 0))))").unwrap();
+    assert_eq!(m.imports.len(), 1);
+    assert_eq!(m.code.len(), 4);
+    let exports = m.exports.iter().map(|e| (e.function_name.as_slice(), e.function_index.0)).collect::<Vec<_>>();
+    assert_eq!(exports, vec![(&b"f"[..], 0), (&b"g"[..], 1)]);
+    assert_eq!(m.functions[0].0, m.imports[0].function_type.0);
+    for body in &m.code[1..] {
+        assert_eq!(body.ast, m.code[0].ast);
+    }
+
+    let error = |text: &str| parse_module(text).err().unwrap().errors[0].message.clone();
+    assert!(error("(module (table anyfunc 1))").starts_with("expected an inline elem"));
+    assert!(error("(module (data (i64.const 0) \"\"))").starts_with("data offset must be an i32.const"));
+}
+
+#[test]
+fn test_diagnostics() {
+    let text = "(module\n  (func (get_local $nope))\n  (func (i32.add (i32.const 1)))\n  (func (i32.const 0x))\n  (func (i32.frob)))";
+    let errors = parse_module(text).err().unwrap();
+    let kinds = errors.errors.iter().map(|e| (e.span.unwrap().start.line, e.kind)).collect::<Vec<_>>();
+    assert_eq!(kinds, vec![(2, ErrorKind::UnknownName), (3, ErrorKind::Arity), (4, ErrorKind::Immediate),
+        (5, ErrorKind::UnknownName)]);
+    assert_eq!(errors.to_string().lines().next(), Some("2:20: no local named nope"));
+
+    let rendered = errors.render(text);
+    assert!(rendered.starts_with("error[unknown name]: no local named nope\n \
+        --> 2:20\n  |\n2 |   (func (get_local $nope))\n  |                    ^^^^^\n"));
+    assert!(rendered.contains("4 |   (func (i32.const 0x))\n  |                    ^^\n"));
+
+    // A bad declaration stops before the bodies, which would only repeat it.
+    let errors = parse_module("(module (func (type $t)) (func (call 0)))").err().unwrap();
+    assert_eq!(errors.errors.len(), 1);
+    assert_eq!(errors.errors[0].message, "no type named t");
+
+    let errors = parse_module("(module\n  (func $a)\n  (func $a)\n  (type $t (func)) (type $t (func)))").err().unwrap();
+    assert_eq!(errors.to_string(), "3:9: duplicate func $a\n4:26: duplicate type $t");
+    let errors = parse_module("(module (func (param $x i32) (param $x i32)) (func (param $y i32) (local $y i64)))")
+        .err().unwrap();
+    assert_eq!(errors.to_string(), "1:37: duplicate local $x\n1:74: duplicate local $y");
+}
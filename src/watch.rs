@@ -0,0 +1,122 @@
+use module::FunctionIndex;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, kind: Access) -> bool {
+        self == Access::ReadWrite || self == kind
+    }
+}
+
+/// A range of linear memory to watch, `len` bytes from `start`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub len: usize,
+    pub access: Access,
+}
+
+impl Watchpoint {
+    pub fn new(start: usize, len: usize, access: Access) -> Watchpoint {
+        Watchpoint { start, len, access }
+    }
+
+    /// Whether an access of `kind` to `width` bytes at `address` touches
+    /// this watchpoint.
+    pub fn hit(&self, address: usize, width: usize, kind: Access) -> bool {
+        self.access.covers(kind) && address < self.start + self.len && self.start < address + width
+    }
+}
+
+/// A load or store that touched a watchpoint. Values are the little-endian
+/// contents of the accessed bytes, zero-extended to 64 bits.
+#[derive(Copy, Clone)]
+pub struct MemoryAccess {
+    pub function: FunctionIndex,
+    pub offset: usize,
+    pub address: usize,
+    pub width: usize,
+    /// `Access::Read` or `Access::Write`.
+    pub kind: Access,
+    /// The memory contents before the access, i.e. the value read for loads.
+    pub old: u64,
+    /// The value being stored, for writes.
+    pub new: Option<u64>,
+}
+
+pub trait WatchHandler {
+    fn hit(&mut self, access: &MemoryAccess);
+}
+
+/// Calls a handler for loads and stores touching any of `watchpoints`,
+/// while attached to an `Instance` (see `Instance::watcher`). To pause
+/// instead, add watchpoints to the `Debugger`.
+pub struct Watcher {
+    pub watchpoints: Vec<Watchpoint>,
+    handler: Box<dyn WatchHandler>,
+}
+
+impl Watcher {
+    pub fn new(handler: Box<dyn WatchHandler>) -> Watcher {
+        Watcher {
+            watchpoints: Vec::new(),
+            handler,
+        }
+    }
+
+    pub fn watch(&mut self, start: usize, len: usize, access: Access) {
+        self.watchpoints.push(Watchpoint::new(start, len, access));
+    }
+
+    pub fn hit(&self, address: usize, width: usize, kind: Access) -> bool {
+        self.watchpoints.iter().any(|w| w.hit(address, width, kind))
+    }
+
+    pub fn report(&mut self, access: &MemoryAccess) {
+        self.handler.hit(access);
+    }
+}
+
+#[test]
+fn test_watcher() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use interp::Instance;
+
+    struct Log(Rc<RefCell<Vec<(Access, usize, usize, u64, Option<u64>)>>>);
+    impl WatchHandler for Log {
+        fn hit(&mut self, access: &MemoryAccess) {
+            self.0.borrow_mut().push((access.kind, access.address, access.width, access.old, access.new));
+        }
+    }
+
+    let m = ::wat::parse_module("(module (memory 1)
+        (func
+            (i32.store (i32.const 8) (i32.const 0x11223344))
+            (i32.store8 (i32.const 12) (i32.const 5))
+            (i32.store16 (i32.const 6) (i32.const 0xffff))
+            (drop (i32.load16_u (i32.const 11)))
+            (i32.store8 (i32.const 10) (i32.const 0xaa))
+            (drop (i32.load (i32.const 0)))
+            (i32.store8 (i32.const 3) (i32.const 1))))").unwrap();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut watcher = Watcher::new(Box::new(Log(log.clone())));
+    watcher.watch(10, 2, Access::ReadWrite);
+    watcher.watch(0, 4, Access::Write);
+    let mut instance = Instance::new(&m, HashMap::new());
+    instance.watcher = Some(watcher);
+    instance.invoke(FunctionIndex(0), &[]);
+
+    assert_eq!(*log.borrow(), vec![
+        (Access::Write, 8, 4, 0, Some(0x11223344)),
+        (Access::Read, 11, 2, 0x0511, None),
+        (Access::Write, 10, 1, 0x22, Some(0xaa)),
+        (Access::Write, 3, 1, 0, Some(1)),
+    ]);
+}